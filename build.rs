@@ -0,0 +1,23 @@
+//! Embeds the git commit and the target of the build, which `--version`
+//! prints for bug reports.
+
+use std::env;
+use std::process::Command;
+
+fn main() {
+    println!("cargo:rerun-if-changed=.git/HEAD");
+    println!("cargo:rerun-if-changed=.git/refs");
+    // Builds from a source tarball, as packages do, have no commit.
+    let commit = Command::new("git")
+        .args(["rev-parse", "--short=12", "HEAD"])
+        .output()
+        .ok()
+        .filter(|output| output.status.success())
+        .and_then(|output| String::from_utf8(output.stdout).ok());
+    if let Some(commit) = commit {
+        println!("cargo:rustc-env=DXVK_CACHE_TOOL_COMMIT={}", commit.trim());
+    }
+    if let Ok(target) = env::var("TARGET") {
+        println!("cargo:rustc-env=DXVK_CACHE_TOOL_TARGET={}", target);
+    }
+}
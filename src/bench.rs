@@ -0,0 +1,242 @@
+//! Timings of the work a merge does on synthetic caches, for comparing the
+//! performance of versions of the tool and of the machines running them.
+//!
+//! The caches are generated from a seed and kept in memory, so that the
+//! numbers measure the tool rather than the disk, and the same workload
+//! gives comparable numbers from one version to the next.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use linked_hash_map::LinkedHashMap;
+
+use crate::dxvk::*;
+use crate::error::Error;
+use crate::format::{self, Limits};
+use crate::merge::validate_entries;
+use crate::report::{BenchReport, Timing};
+
+/// Times each benchmark is run, reporting the fastest and the median run.
+pub const RUNS: usize = 5;
+
+/// The synthetic caches benchmarked. Two caches of `entries` entries are
+/// generated, sharing half of their entries, with the data of every entry
+/// drawn from `seed`.
+#[derive(Clone, Copy)]
+pub struct Workload {
+    pub entries:    usize,
+    /// Size of the data of an entry in bytes.
+    pub entry_size: usize,
+    pub seed:       u64
+}
+
+impl Default for Workload {
+    fn default() -> Self {
+        Workload { entries: 32768, entry_size: 1024, seed: 0 }
+    }
+}
+
+impl Workload {
+    /// Version of the generated caches, the newest one known.
+    pub fn version(&self) -> u32 {
+        CURRENT_VERSION
+    }
+
+    fn header(&self) -> DxvkStateCacheHeader {
+        DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version:    self.version(),
+            entry_size: 0
+        }
+    }
+
+    /// Generates the entries `first..first + self.entries`, every entry
+    /// the same whichever cache it is generated for.
+    fn generate(&self, first: usize) -> Vec<DxvkStateCacheEntry> {
+        (first..first + self.entries)
+            .map(|i| {
+                // One pipeline in eight is a compute pipeline, the rest
+                // have a vertex and a fragment shader.
+                let stage_mask = if i % 8 == 7 { STAGE_COMPUTE } else { 0x11 };
+                let mut entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+                    stage_mask,
+                    entry_size: self.entry_size as u32
+                });
+                let mut state = self.seed ^ (i as u64).wrapping_mul(0x9e37_79b9_7f4a_7c15);
+                for chunk in entry.data.chunks_mut(8) {
+                    let bytes = splitmix64(&mut state).to_le_bytes();
+                    chunk.copy_from_slice(bytes.get(..chunk.len()).unwrap_or_default());
+                }
+                entry.hash = entry.compute_hash(HashKind::from_version(self.version()));
+                entry
+            })
+            .collect()
+    }
+}
+
+/// Next number of the SplitMix64 generator, which is tiny and gives the
+/// same sequence everywhere.
+fn splitmix64(state: &mut u64) -> u64 {
+    *state = state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    let mut z = *state;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// Runs the benchmarks on `workload`, validating with `threads` threads:
+///
+/// - `parse`: reading and validating a cache
+/// - `merge`: validating two caches and deduplicating their entries
+/// - `master`: merging a session cache of one entry in a hundred into the
+///   first cache as its master, trusting the master's stored hashes as a
+///   merge into an input does
+/// - `master-revalidated`: the same, validating the master too, as with
+///   `ConfigBuilder::revalidate_master`
+/// - `serialize`: writing the merged cache
+pub fn run(workload: &Workload, threads: usize) -> Result<BenchReport, Error> {
+    let header = workload.header();
+    let kind = HashKind::from_version(header.version);
+    let first = workload.generate(0);
+    let second = workload.generate(workload.entries / 2);
+    let mut bytes = Vec::new();
+    format::write_cache_to(&mut bytes, &header, first.iter())?;
+
+    let parse = time("parse", bytes.len() as u64, || {
+        let (_, entries) = format::read_cache_from(bytes.as_slice(), &Limits::default())?;
+        black_box(validate_entries(&entries, kind, threads));
+        Ok(())
+    })?;
+
+    let merge_bytes = first.iter().chain(&second).map(|e| e.serialized_size() as u64).sum();
+    let merge = time("merge", merge_bytes, || {
+        black_box(merge_entries(&first, &second, kind, threads));
+        Ok(())
+    })?;
+
+    // Half of the session's entries are already in the master.
+    let session_entries = (workload.entries / 100).max(1);
+    let session = Workload { entries: session_entries, ..*workload }
+        .generate(workload.entries - session_entries / 2);
+    let mut session_bytes = Vec::new();
+    format::write_cache_to(&mut session_bytes, &header, session.iter())?;
+    let master_bytes = (bytes.len() + session_bytes.len()) as u64;
+    let master = time("master", master_bytes, || {
+        black_box(merge_into_master(&bytes, &session_bytes, kind, threads, false)?);
+        Ok(())
+    })?;
+    let revalidated = time("master-revalidated", master_bytes, || {
+        black_box(merge_into_master(&bytes, &session_bytes, kind, threads, true)?);
+        Ok(())
+    })?;
+
+    let merged = merge_entries(&first, &second, kind, threads);
+    let mut output = Vec::new();
+    let size = format::write_cache_to(&mut output, &header, merged.values().copied())?;
+    let serialize = time("serialize", size, || {
+        output.clear();
+        format::write_cache_to(&mut output, &header, merged.values().copied())?;
+        black_box(&output);
+        Ok(())
+    })?;
+
+    Ok(BenchReport {
+        workload: *workload,
+        threads,
+        runs: RUNS,
+        timings: vec![parse, merge, master, revalidated, serialize],
+        warnings: Vec::new()
+    })
+}
+
+/// The valid entries of both caches by hash, as a merge keeps them.
+fn merge_entries<'a>(
+    first: &'a [DxvkStateCacheEntry],
+    second: &'a [DxvkStateCacheEntry],
+    kind: HashKind,
+    threads: usize
+) -> LinkedHashMap<Sha1Hash, &'a DxvkStateCacheEntry> {
+    let mut entries = LinkedHashMap::with_capacity(first.len());
+    for input in [first, second] {
+        let valid = validate_entries(input, kind, threads);
+        for (entry, _) in input.iter().zip(valid).filter(|(_, valid)| *valid) {
+            entries.insert(entry.hash, entry);
+        }
+    }
+    entries
+}
+
+/// Reads the caches `master` and `session` and keeps the valid entries of
+/// both once, returning their number. The entries of `master` are taken
+/// as valid unless `revalidate`.
+fn merge_into_master(
+    master: &[u8],
+    session: &[u8],
+    kind: HashKind,
+    threads: usize,
+    revalidate: bool
+) -> Result<usize, Error> {
+    let (_, master) = format::read_cache_from(master, &Limits::default())?;
+    let (_, session) = format::read_cache_from(session, &Limits::default())?;
+    let trusted = match revalidate {
+        true => validate_entries(&master, kind, threads),
+        false => vec![true; master.len()]
+    };
+    let valid = validate_entries(&session, kind, threads);
+    let mut entries = LinkedHashMap::with_capacity(master.len() + session.len());
+    let inputs = master.iter().zip(trusted).chain(session.iter().zip(valid));
+    for (entry, _) in inputs.filter(|(_, valid)| *valid) {
+        entries.entry(entry.hash).or_insert(entry);
+    }
+    Ok(entries.len())
+}
+
+/// Runs `f` `RUNS` times, timing each run.
+fn time<F>(name: &'static str, bytes: u64, mut f: F) -> Result<Timing, Error>
+where
+    F: FnMut() -> Result<(), Error>
+{
+    let mut runs = Vec::with_capacity(RUNS);
+    for _ in 0..RUNS {
+        let started = Instant::now();
+        f()?;
+        runs.push(started.elapsed());
+    }
+    runs.sort();
+    Ok(Timing {
+        name,
+        bytes,
+        min: runs.first().copied().unwrap_or_default(),
+        median: runs.get(RUNS / 2).copied().unwrap_or_default()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn only_revalidating_drops_invalid_master_entries() {
+        let workload = Workload { entries: 8, entry_size: 64, seed: 1 };
+        let header = workload.header();
+        let mut master = workload.generate(0);
+        master[0].hash[0] ^= 1;
+        let session = Workload { entries: 4, ..workload }.generate(6);
+        let (mut master_bytes, mut session_bytes) = (Vec::new(), Vec::new());
+        format::write_cache_to(&mut master_bytes, &header, master.iter()).unwrap();
+        format::write_cache_to(&mut session_bytes, &header, session.iter()).unwrap();
+        let merge = |revalidate| {
+            merge_into_master(&master_bytes, &session_bytes, HashKind::Sha1, 2, revalidate)
+        };
+        assert_eq!(merge(false).unwrap(), 10);
+        assert_eq!(merge(true).unwrap(), 9);
+    }
+
+    #[test]
+    fn run_times_every_benchmark() {
+        let workload = Workload { entries: 16, entry_size: 32, seed: 0 };
+        let report = run(&workload, 1).unwrap();
+        let names: Vec<&str> = report.timings.iter().map(|t| t.name).collect();
+        assert_eq!(names, ["parse", "merge", "master", "master-revalidated", "serialize"]);
+    }
+}
@@ -0,0 +1,291 @@
+//! Estimates of how small the entry data of a cache gets compressed, to
+//! tell whether compressing a cache for sharing saves more than trimming
+//! it. The data is compressed with a fast zstd level, which needs the
+//! `zstd` feature; without it, estimating fails with
+//! `ErrorKind::FeatureNotEnabled`.
+
+use std::collections::BTreeMap;
+use std::convert::TryFrom;
+
+use crate::dxvk::DxvkStateCacheEntry;
+use crate::error::Error;
+#[cfg(not(feature = "zstd"))]
+use crate::error::ErrorKind;
+
+/// zstd level the data is compressed with, a fast one.
+pub const LEVEL: i32 = 1;
+
+/// Bytes of data a sampled estimate compresses at most per group.
+pub const SAMPLE_BYTES: u64 = 8 << 20;
+
+/// Entries in a run of consecutive entries sampled together, so that the
+/// estimate sees the likeness of neighbouring entries as compressing the
+/// whole cache does.
+const SAMPLE_RUN: usize = 32;
+
+/// Compressibility of some of the entries of a cache.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Group {
+    pub entries:    usize,
+    /// Bytes of entry data, without hashes and entry headers.
+    pub data_bytes: u64,
+    /// Bytes of data compressed for the estimate, all of them if exact.
+    pub sampled_bytes: u64,
+    /// Size of the sampled data once compressed.
+    pub compressed_bytes: u64
+}
+
+impl Group {
+    /// Estimated size of all the data of the group once compressed.
+    pub fn estimated_bytes(&self) -> u64 {
+        match self.sampled_bytes {
+            0 => 0,
+            sampled => {
+                let estimate = u128::from(self.data_bytes) * u128::from(self.compressed_bytes)
+                    / u128::from(sampled);
+                u64::try_from(estimate).unwrap_or(u64::MAX)
+            }
+        }
+    }
+
+    /// Estimated compressed size as a share of the data size, 1 for none.
+    pub fn ratio(&self) -> f64 {
+        match self.data_bytes {
+            0 => 1.0,
+            bytes => self.estimated_bytes() as f64 / bytes as f64
+        }
+    }
+
+    fn json_fields(&self) -> String {
+        format!(
+            "\"entries\":{},\"data_bytes\":{},\"sampled_bytes\":{},\"estimated_bytes\":{}",
+            self.entries,
+            self.data_bytes,
+            self.sampled_bytes,
+            self.estimated_bytes()
+        )
+    }
+}
+
+/// Compressibility of the entry data of a cache, overall and broken down
+/// by stage mask and by age.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Compressibility {
+    /// Whether all data was compressed instead of samples of it, as it is
+    /// when asked to or when there is no more than `SAMPLE_BYTES` of it.
+    pub exact:       bool,
+    pub total:       Group,
+    /// By stage mask, `None` for legacy entries, which have none.
+    pub stage_masks: BTreeMap<Option<u8>, Group>,
+    /// By quarter of the entries in file order, the oldest first, as DXVK
+    /// appends entries. Trimming the oldest entries saves what the first
+    /// quarters take.
+    pub quartiles:   [Group; 4]
+}
+
+impl Compressibility {
+    /// Formats the estimate as a JSON object, for embedding into the
+    /// report of `info`.
+    pub fn to_json(&self) -> String {
+        let stage_masks: Vec<String> = self
+            .stage_masks
+            .iter()
+            .map(|(mask, group)| {
+                let mask = mask.map_or("null".into(), |m| m.to_string());
+                format!("{{\"stage_mask\":{},{}}}", mask, group.json_fields())
+            })
+            .collect();
+        let quartiles: Vec<String> =
+            self.quartiles.iter().map(|q| format!("{{{}}}", q.json_fields())).collect();
+        format!(
+            "{{\"exact\":{},\"level\":{},{},\"stage_masks\":[{}],\"quartiles\":[{}]}}",
+            self.exact,
+            LEVEL,
+            self.total.json_fields(),
+            stage_masks.join(","),
+            quartiles.join(",")
+        )
+    }
+}
+
+/// Estimates the compressibility of `entries`, in file order, compressing
+/// all of their data if `exact` or runs of consecutive entries up to
+/// `SAMPLE_BYTES` per group otherwise.
+pub fn estimate(entries: &[DxvkStateCacheEntry], exact: bool) -> Result<Compressibility, Error> {
+    estimate_with(entries, exact, compressed_size)
+}
+
+#[cfg(feature = "zstd")]
+fn compressed_size(data: &[u8]) -> Result<u64, Error> {
+    Ok(zstd::bulk::compress(data, LEVEL)?.len() as u64)
+}
+
+#[cfg(not(feature = "zstd"))]
+fn compressed_size(_data: &[u8]) -> Result<u64, Error> {
+    Err(Error::new(
+        ErrorKind::FeatureNotEnabled,
+        "Estimating compressibility needs the zstd feature, which this build doesn't have"
+    ))
+}
+
+/// Like `estimate`, compressing with `compress`, which returns the
+/// compressed size of the data it is given.
+fn estimate_with<F>(
+    entries: &[DxvkStateCacheEntry],
+    exact: bool,
+    mut compress: F
+) -> Result<Compressibility, Error>
+where
+    F: FnMut(&[u8]) -> Result<u64, Error>
+{
+    let all: Vec<&DxvkStateCacheEntry> = entries.iter().collect();
+    let total = group(&all, exact, &mut compress)?;
+
+    let mut by_mask: BTreeMap<Option<u8>, Vec<&DxvkStateCacheEntry>> = BTreeMap::new();
+    for entry in entries {
+        by_mask.entry(entry.stage_mask()).or_default().push(entry);
+    }
+    let mut stage_masks = BTreeMap::new();
+    for (mask, entries) in by_mask {
+        stage_masks.insert(mask, group(&entries, exact, &mut compress)?);
+    }
+
+    let mut quartiles: [Group; 4] = Default::default();
+    for (i, quartile) in quartiles.iter_mut().enumerate() {
+        let range = entries.len() * i / 4..entries.len() * (i + 1) / 4;
+        let entries: Vec<&DxvkStateCacheEntry> =
+            entries.get(range).unwrap_or_default().iter().collect();
+        *quartile = group(&entries, exact, &mut compress)?;
+    }
+
+    let exact = total.sampled_bytes == total.data_bytes;
+    Ok(Compressibility { exact, total, stage_masks, quartiles })
+}
+
+/// Compresses the data of `entries` as a group, all of it if `exact` or
+/// evenly spaced runs of entries adding up to about `SAMPLE_BYTES`.
+fn group<F>(
+    entries: &[&DxvkStateCacheEntry],
+    exact: bool,
+    compress: &mut F
+) -> Result<Group, Error>
+where
+    F: FnMut(&[u8]) -> Result<u64, Error>
+{
+    let data_bytes: u64 = entries.iter().map(|e| e.data.len() as u64).sum();
+    let stride = match exact {
+        true => 1,
+        false => usize::try_from(data_bytes.div_ceil(SAMPLE_BYTES)).unwrap_or(usize::MAX).max(1)
+    };
+    let mut sample = Vec::new();
+    for run in entries.chunks(SAMPLE_RUN).step_by(stride) {
+        run.iter().for_each(|e| sample.extend_from_slice(&e.data));
+    }
+    let compressed_bytes = match sample.is_empty() {
+        true => 0,
+        false => compress(&sample)?
+    };
+    Ok(Group {
+        entries: entries.len(),
+        data_bytes,
+        sampled_bytes: sample.len() as u64,
+        compressed_bytes
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dxvk::{DxvkStateCacheEntryHeader, HASH_SIZE};
+
+    /// An entry of `size` bytes of `byte` with the stage mask `mask`, or a
+    /// legacy one without.
+    fn entry(byte: u8, size: usize, mask: Option<u8>) -> DxvkStateCacheEntry {
+        let mut entry = match mask {
+            Some(stage_mask) => DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+                stage_mask,
+                entry_size: size as u32
+            }),
+            None => DxvkStateCacheEntry::with_length(size + HASH_SIZE)
+        };
+        entry.data.iter_mut().for_each(|b| *b = byte);
+        entry
+    }
+
+    /// Compresses data to a quarter of its size, counting the bytes given.
+    fn quarter(given: &mut u64) -> impl FnMut(&[u8]) -> Result<u64, Error> + '_ {
+        move |data| {
+            *given += data.len() as u64;
+            Ok(data.len() as u64 / 4)
+        }
+    }
+
+    #[test]
+    fn breaks_down_by_stage_mask_and_quartile() {
+        let entries: Vec<DxvkStateCacheEntry> = (0..10)
+            .map(|i| entry(i, 64 * (i as usize + 1), Some(if i % 2 == 0 { 0x11 } else { 0x20 })))
+            .collect();
+        let mut given = 0;
+        let estimate = estimate_with(&entries, true, quarter(&mut given)).unwrap();
+        assert!(estimate.exact);
+        assert_eq!(estimate.total.entries, 10);
+        assert_eq!(estimate.total.data_bytes, 64 * 55);
+        assert_eq!(estimate.total.estimated_bytes(), 16 * 55);
+        assert_eq!(estimate.total.ratio(), 0.25);
+        let masks: Vec<(Option<u8>, usize, u64)> = estimate
+            .stage_masks
+            .iter()
+            .map(|(mask, group)| (*mask, group.entries, group.data_bytes))
+            .collect();
+        assert_eq!(masks, [(Some(0x11), 5, 64 * 25), (Some(0x20), 5, 64 * 30)]);
+        let quartiles: Vec<usize> = estimate.quartiles.iter().map(|q| q.entries).collect();
+        assert_eq!(quartiles, [2, 3, 2, 3]);
+        assert_eq!(estimate.quartiles[0].data_bytes, 64 * 3);
+        // The data is compressed once for the total, the stage masks and the
+        // quartiles each.
+        assert_eq!(given, 3 * 64 * 55);
+    }
+
+    #[test]
+    fn samples_runs_of_entries_of_large_groups() {
+        let size = 64 << 10;
+        let count = 4 * SAMPLE_BYTES as usize / size;
+        let entries: Vec<DxvkStateCacheEntry> =
+            (0..count).map(|i| entry(i as u8, size, None)).collect();
+        let mut given = 0;
+        let estimate = estimate_with(&entries, false, quarter(&mut given)).unwrap();
+        assert!(!estimate.exact);
+        let total = &estimate.total;
+        assert_eq!(total.data_bytes, 4 * SAMPLE_BYTES);
+        assert_eq!(total.sampled_bytes, SAMPLE_BYTES);
+        assert_eq!(total.estimated_bytes(), SAMPLE_BYTES);
+        assert_eq!(estimate.stage_masks.keys().collect::<Vec<_>>(), [&None]);
+        for quartile in &estimate.quartiles {
+            assert_eq!(quartile.sampled_bytes, quartile.data_bytes);
+        }
+    }
+
+    #[test]
+    fn empty_caches_estimate_nothing() {
+        let estimate = estimate_with(&[], false, |_| panic!("Compressed nothing")).unwrap();
+        assert_eq!(estimate.total, Group::default());
+        assert_eq!(estimate.total.ratio(), 1.0);
+        assert!(estimate.stage_masks.is_empty());
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn zstd_compresses_repeated_data() {
+        let entries: Vec<DxvkStateCacheEntry> =
+            (0..64).map(|_| entry(7, 1024, Some(0x11))).collect();
+        let estimate = estimate(&entries, true).unwrap();
+        assert!(estimate.total.ratio() < 0.1);
+    }
+
+    #[cfg(not(feature = "zstd"))]
+    #[test]
+    fn needs_the_zstd_feature() {
+        let error = estimate(&[entry(7, 1024, Some(0x11))], true).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::FeatureNotEnabled);
+    }
+}
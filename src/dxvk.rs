@@ -1,42 +1,441 @@
+use std::collections::{BTreeMap, HashSet};
+use std::convert::TryFrom;
+use std::fmt;
+
+use linked_hash_map::LinkedHashMap;
 use sha1::Sha1;
 
+use crate::error::{Error, ErrorKind};
+use crate::format::MAX_ENTRY_DATA;
+
 pub type Sha1Hash = [u8; HASH_SIZE];
 pub const LEGACY_VERSION: u32 = 7;
 pub const HASH_SIZE: usize = 20;
 pub const MAGIC_STRING: [u8; 4] = *b"DXVK";
+pub const HEADER_SIZE: usize = 12;
+pub const ENTRY_HEADER_SIZE: usize = 4;
+pub const STAGE_COMPUTE: u8 = 0x20;
+pub const STAGE_GRAPHICS: u8 = 0x1f;
+pub const FIRST_DXVK2_VERSION: u32 = 16;
+/// Trailing zeros that make an entry failing validation look unwritten
+/// rather than damaged.
+pub const ZERO_TAIL_SIZE: usize = 16;
 const SHA1_EMPTY: Sha1Hash = [
     218, 57, 163, 238, 94, 107, 75, 13, 50, 85, 191, 239, 149, 96, 24, 144, 175, 216, 7, 9
 ];
 
+/// State cache versions and the DXVK release that introduced each of them.
+pub const VERSIONS: &[(u32, &str)] = &[
+    (2, "0.80"),
+    (3, "0.90"),
+    (4, "0.94"),
+    (5, "1.0"),
+    (6, "1.2"),
+    (7, "1.3"),
+    (8, "1.4"),
+    (9, "1.5"),
+    (10, "1.6"),
+    (11, "1.7"),
+    (12, "1.8"),
+    (13, "1.9"),
+    (14, "1.10"),
+    (15, "1.10.2"),
+    (16, "2.0"),
+    (17, "2.2")
+];
+
+/// The newest version in `VERSIONS`.
+pub const CURRENT_VERSION: u32 = 17;
+const _: () = assert!(matches!(VERSIONS.last(), Some((CURRENT_VERSION, _))));
+
+/// Oldest version current DXVK still loads. Older caches merge fine but are
+/// rejected by the game.
+pub const MIN_USEFUL_VERSION: u32 = 7;
+
+pub fn dxvk_release(version: u32) -> Option<&'static str> {
+    VERSIONS.iter().find(|(v, _)| *v == version).map(|(_, release)| *release)
+}
+
+/// Returns the version DXVK `release`, like "2.3", writes: the newest one
+/// introduced by it or an earlier release. Releases after the newest one in
+/// `VERSIONS` are taken to write `CURRENT_VERSION`. Returns `None` for
+/// malformed releases and those older than the first state cache.
+pub fn release_version(release: &str) -> Option<u32> {
+    let parse = |s: &str| s.split('.').map(|n| n.parse().ok()).collect::<Option<Vec<u32>>>();
+    let release = parse(release)?;
+    VERSIONS
+        .iter()
+        .rev()
+        .find(|(_, introduced)| parse(introduced).is_some_and(|r| r <= release))
+        .map(|(version, _)| *version)
+}
+
+pub fn hash_to_hex(hash: &Sha1Hash) -> String {
+    hash.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// Parses a SHA1 hash from 40 hex digits, ignoring case.
+pub fn parse_hash(s: &str) -> Result<Sha1Hash, Error> {
+    if let Some((i, c)) = s.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid hash {}: unexpected character {:?} at position {}", s, c, i + 1)
+        ));
+    }
+    if s.len() != HASH_SIZE * 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid hash {}: expected {} hex digits, found {}",
+                s,
+                HASH_SIZE * 2,
+                s.len()
+            )
+        ));
+    }
+    Ok(hash_from_hex(s))
+}
+
+/// Converts hex digits to a hash, padding missing trailing digits with
+/// zeros. The digits must be valid.
+fn hash_from_hex(s: &str) -> Sha1Hash {
+    let mut hash = [0; HASH_SIZE];
+    for (b, pair) in hash.iter_mut().zip(s.as_bytes().chunks(2)) {
+        let digit = |i| pair.get(i).and_then(|&c| char::from(c).to_digit(16)).unwrap_or(0) as u8;
+        *b = digit(0) << 4 | digit(1);
+    }
+    hash
+}
+
+/// Shortest hash prefix accepted, in hex digits.
+pub const MIN_PREFIX_LEN: usize = 4;
+/// Most candidates reported for an ambiguous prefix.
+pub const MAX_CANDIDATES: usize = 10;
+
+/// Leading hex digits of an entry hash, stored in lower case.
+#[derive(Debug)]
+pub struct HashPrefix(String);
+
+impl HashPrefix {
+    pub fn matches(&self, hash: &Sha1Hash) -> bool {
+        hash_to_hex(hash).starts_with(&self.0)
+    }
+
+    /// Smallest hash starting with this prefix.
+    pub fn first(&self) -> Sha1Hash {
+        hash_from_hex(&self.0)
+    }
+}
+
+impl fmt::Display for HashPrefix {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+pub fn parse_hash_prefix(s: &str) -> Result<HashPrefix, Error> {
+    if let Some((i, c)) = s.char_indices().find(|(_, c)| !c.is_ascii_hexdigit()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("Invalid hash {}: unexpected character {:?} at position {}", s, c, i + 1)
+        ));
+    }
+    if s.len() < MIN_PREFIX_LEN {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Hash prefix {} is too short: at least {} hex digits are needed",
+                s, MIN_PREFIX_LEN
+            )
+        ));
+    }
+    if s.len() > HASH_SIZE * 2 {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!(
+                "Invalid hash {}: expected at most {} hex digits, found {}",
+                s,
+                HASH_SIZE * 2,
+                s.len()
+            )
+        ));
+    }
+    Ok(HashPrefix(s.to_ascii_lowercase()))
+}
+
+pub enum PrefixMatch {
+    Unique(Sha1Hash),
+    /// Up to `MAX_CANDIDATES` of the matching hashes.
+    Ambiguous(Vec<Sha1Hash>),
+    NotFound
+}
+
+/// Resolves `prefix` against `hashes`. Every command accepting hash
+/// prefixes goes through here, so they all agree on what is ambiguous.
+pub fn resolve_prefix<'a, I>(prefix: &HashPrefix, hashes: I) -> PrefixMatch
+where
+    I: IntoIterator<Item = &'a Sha1Hash>
+{
+    let matches: Vec<Sha1Hash> = hashes
+        .into_iter()
+        .filter(|h| prefix.matches(h))
+        .take(MAX_CANDIDATES)
+        .copied()
+        .collect();
+    match (matches.len(), matches.first().copied()) {
+        (1, Some(hash)) => PrefixMatch::Unique(hash),
+        (0, _) => PrefixMatch::NotFound,
+        _ => PrefixMatch::Ambiguous(matches)
+    }
+}
+
+/// Returns the sorted `hashes` that `present` is false for, such as the
+/// hashes of one cache missing from another.
+pub fn missing_hashes<'a, I, F>(hashes: I, present: F) -> Vec<&'a Sha1Hash>
+where
+    I: IntoIterator<Item = &'a Sha1Hash>,
+    F: Fn(&Sha1Hash) -> bool
+{
+    let mut missing: Vec<&Sha1Hash> = hashes.into_iter().filter(|h| !present(h)).collect();
+    missing.sort_unstable();
+    missing
+}
+
+/// Pipeline kind of an entry, derived from its stage mask.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Stages {
+    Graphics,
+    Compute,
+    /// Both or neither of the graphics and compute stages.
+    Ambiguous,
+    /// No stage mask at all, for legacy entries under
+    /// `LegacyStageHandling::TreatAsUnknown`.
+    Unknown
+}
+
+impl Stages {
+    pub fn from_mask(mask: u8) -> Self {
+        match (mask & STAGE_GRAPHICS != 0, mask & STAGE_COMPUTE != 0) {
+            (true, false) => Stages::Graphics,
+            (false, true) => Stages::Compute,
+            _ => Stages::Ambiguous
+        }
+    }
+}
+
+/// How stage-aware operations treat legacy entries, which have no stage
+/// mask.
+#[derive(Clone, Copy, PartialEq, Debug, Default)]
+pub enum LegacyStageHandling {
+    /// Fail with `ErrorKind::InvalidData`.
+    Reject,
+    /// Count them as graphics pipelines, which most of them are.
+    TreatAsGraphics,
+    /// Keep them apart as `Stages::Unknown`.
+    #[default]
+    TreatAsUnknown
+}
+
+impl LegacyStageHandling {
+    /// Returns the stages of an entry with the stage mask `mask`, or of a
+    /// legacy entry if `None`.
+    pub fn stages(self, mask: Option<u8>) -> Result<Stages, Error> {
+        match (mask, self) {
+            (Some(mask), _) => Ok(Stages::from_mask(mask)),
+            (None, LegacyStageHandling::TreatAsGraphics) => Ok(Stages::Graphics),
+            (None, LegacyStageHandling::TreatAsUnknown) => Ok(Stages::Unknown),
+            (None, LegacyStageHandling::Reject) => Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Entries of versions up to v{} have no stage mask to tell their stages",
+                    LEGACY_VERSION
+                )
+            ))
+        }
+    }
+}
+
 #[derive(PartialEq)]
 pub enum DxvkStateCacheEdition {
     Standard,
     Legacy
 }
 
+impl DxvkStateCacheEdition {
+    pub fn from_version(version: u32) -> Self {
+        if version > LEGACY_VERSION {
+            DxvkStateCacheEdition::Standard
+        } else {
+            DxvkStateCacheEdition::Legacy
+        }
+    }
+
+    /// Fields of each entry, in file order.
+    pub fn entry_fields(&self) -> &'static [Field] {
+        match self {
+            DxvkStateCacheEdition::Standard => STANDARD_ENTRY_FIELDS,
+            DxvkStateCacheEdition::Legacy => LEGACY_ENTRY_FIELDS
+        }
+    }
+
+    /// How the hash of an entry is computed, as `compute_hash` does.
+    pub fn hash_recipe(&self) -> String {
+        match self {
+            DxvkStateCacheEdition::Standard => "SHA1(data)".into(),
+            DxvkStateCacheEdition::Legacy => format!(
+                "SHA1(data || {}), the latter being SHA1 of nothing",
+                hash_to_hex(&SHA1_EMPTY)
+            ),
+        }
+    }
+}
+
+/// Algorithm behind the entry hashes of a cache version. Every version so
+/// far uses SHA1, but one switching to another algorithm would otherwise
+/// fail every entry, so validation goes through the kind of the version.
+#[derive(Clone, Copy, PartialEq, Debug)]
+pub enum HashKind {
+    Sha1
+}
+
+impl HashKind {
+    pub fn from_version(_version: u32) -> Self {
+        HashKind::Sha1
+    }
+}
+
+/// Size of a field in the on-disk format.
+#[derive(Clone, Copy)]
+pub enum FieldSize {
+    Fixed(usize),
+    /// The entry data, sized by the entry header, or for legacy versions by
+    /// the entry size in the cache header.
+    Data
+}
+
+/// A field of the on-disk format. All integers are little-endian.
+pub struct Field {
+    pub name:        &'static str,
+    pub size:        FieldSize,
+    pub description: &'static str
+}
+
+pub const HEADER_FIELDS: &[Field] = &[
+    Field {
+        name:        "magic",
+        size:        FieldSize::Fixed(MAGIC_STRING.len()),
+        description: "Magic string \"DXVK\""
+    },
+    Field {
+        name:        "version",
+        size:        FieldSize::Fixed(4),
+        description: "State cache version"
+    },
+    Field {
+        name:        "entry_size",
+        size:        FieldSize::Fixed(4),
+        description: "Size of legacy entries including the hash, unused since v8"
+    }
+];
+
+const STANDARD_ENTRY_FIELDS: &[Field] = &[
+    Field {
+        name:        "stage_mask",
+        size:        FieldSize::Fixed(1),
+        description: "Pipeline stages, 0x1f for graphics and 0x20 for compute"
+    },
+    Field {
+        name:        "entry_size",
+        size:        FieldSize::Fixed(ENTRY_HEADER_SIZE - 1),
+        description: "Size of the data"
+    },
+    Field {
+        name:        "hash",
+        size:        FieldSize::Fixed(HASH_SIZE),
+        description: "SHA1 hash of the data"
+    },
+    Field {
+        name:        "data",
+        size:        FieldSize::Data,
+        description: "Pipeline state"
+    }
+];
+
+const LEGACY_ENTRY_FIELDS: &[Field] = &[
+    Field {
+        name:        "data",
+        size:        FieldSize::Data,
+        description: "Pipeline state, the header entry_size minus the hash"
+    },
+    Field {
+        name:        "hash",
+        size:        FieldSize::Fixed(HASH_SIZE),
+        description: "SHA1 hash of the data"
+    }
+];
+
+const fn fixed_size(mut fields: &[Field]) -> usize {
+    let mut size = 0;
+    while let [field, rest @ ..] = fields {
+        if let FieldSize::Fixed(n) = field.size {
+            size += n;
+        }
+        fields = rest;
+    }
+    size
+}
+
+// The field tables describe what the reader and writer do, so they must
+// agree with the sizes those use.
+const _: () = assert!(fixed_size(HEADER_FIELDS) == HEADER_SIZE);
+const _: () = assert!(fixed_size(STANDARD_ENTRY_FIELDS) == ENTRY_HEADER_SIZE + HASH_SIZE);
+const _: () = assert!(fixed_size(LEGACY_ENTRY_FIELDS) == HASH_SIZE);
+
 pub struct DxvkStateCacheHeader {
     pub magic:      [u8; 4],
     pub version:    u32,
     pub entry_size: u32
 }
 
+#[derive(Clone, PartialEq)]
 pub struct DxvkStateCacheEntryHeader {
     pub stage_mask: u8,
     pub entry_size: u32
 }
 
+/// Where a merged entry came from: the position of its input among the
+/// inputs of the merge, and its position among the entries of that input.
+/// Kept in memory only, never written.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Debug)]
+pub struct Provenance {
+    pub input:   u32,
+    pub ordinal: u32
+}
+
+#[derive(Clone)]
 pub struct DxvkStateCacheEntry {
     pub header: Option<DxvkStateCacheEntryHeader>,
     pub hash:   [u8; HASH_SIZE],
-    pub data:   Vec<u8>
+    pub data:   Vec<u8>,
+    provenance: Option<Provenance>
+}
+
+/// Entries are equal by their contents, wherever they came from.
+impl PartialEq for DxvkStateCacheEntry {
+    fn eq(&self, other: &Self) -> bool {
+        self.header == other.header && self.hash == other.hash && self.data == other.data
+    }
 }
 
 impl DxvkStateCacheEntry {
+    /// Creates a legacy entry of `length` bytes including the hash. Shorter
+    /// lengths give empty data.
     pub fn with_length(length: usize) -> Self {
         DxvkStateCacheEntry {
-            data:   vec![0; length - HASH_SIZE],
+            data:   vec![0; length.saturating_sub(HASH_SIZE)],
             hash:   [0; HASH_SIZE],
-            header: None
+            header: None,
+            provenance: None
         }
     }
 
@@ -44,18 +443,510 @@ impl DxvkStateCacheEntry {
         DxvkStateCacheEntry {
             data:   vec![0; header.entry_size as usize],
             hash:   [0; HASH_SIZE],
-            header: Some(header)
+            header: Some(header),
+            provenance: None
         }
     }
 
-    pub fn is_valid(&self) -> bool {
-        let mut hasher = Sha1::default();
-        hasher.update(&self.data);
-        if self.header.is_none() {
-            hasher.update(&SHA1_EMPTY);
+    /// Returns where the entry came from, for entries of a merge. Entries
+    /// read from a single cache have none.
+    pub fn provenance(&self) -> Option<Provenance> {
+        self.provenance
+    }
+
+    pub(crate) fn set_provenance(&mut self, provenance: Provenance) {
+        self.provenance = Some(provenance);
+    }
+
+    pub fn stage_mask(&self) -> Option<u8> {
+        self.header.as_ref().map(|h| h.stage_mask)
+    }
+
+    /// Returns `None` for legacy entries, which have no stage mask.
+    pub fn stages(&self) -> Option<Stages> {
+        self.stage_mask().map(Stages::from_mask)
+    }
+
+    /// Like `stages`, with legacy entries treated by `handling`.
+    pub fn stages_with(&self, handling: LegacyStageHandling) -> Result<Stages, Error> {
+        handling.stages(self.stage_mask())
+    }
+
+    pub fn serialized_size(&self) -> usize {
+        match self.header {
+            Some(_) => ENTRY_HEADER_SIZE + HASH_SIZE + self.data.len(),
+            None => HASH_SIZE + self.data.len()
+        }
+    }
+
+    /// Returns the hash DXVK computes for the entry data with `kind`.
+    pub fn compute_hash(&self, kind: HashKind) -> Sha1Hash {
+        match kind {
+            HashKind::Sha1 => {
+                let mut hasher = Sha1::default();
+                hasher.update(&self.data);
+                if self.header.is_none() {
+                    hasher.update(&SHA1_EMPTY);
+                }
+                hasher.digest().bytes()
+            },
+        }
+    }
+
+    pub fn is_valid(&self, kind: HashKind) -> bool {
+        self.compute_hash(kind) == self.hash
+    }
+
+    /// Whether the bytes written last, the data or the hash of a legacy
+    /// entry, end in `ZERO_TAIL_SIZE` zeros or are all zero, as when a file
+    /// is extended before the entry is written out.
+    pub fn has_zero_tail(&self) -> bool {
+        let tail = match self.header {
+            Some(_) => self.data.as_slice(),
+            None => &self.hash
+        };
+        !tail.is_empty() && tail.iter().rev().take(ZERO_TAIL_SIZE).all(|&b| b == 0)
+    }
+}
+
+/// A state cache, with its entries in file order.
+pub struct DxvkStateCache {
+    pub header:  DxvkStateCacheHeader,
+    pub entries: LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>
+}
+
+impl DxvkStateCache {
+    pub fn new(header: DxvkStateCacheHeader) -> Self {
+        DxvkStateCache {
+            header,
+            entries: LinkedHashMap::new()
+        }
+    }
+
+    pub fn entries(&self) -> impl Iterator<Item = &DxvkStateCacheEntry> {
+        self.entries.values()
+    }
+
+    pub fn valid_entries(&self) -> impl Iterator<Item = &DxvkStateCacheEntry> {
+        self.entries().valid(HashKind::from_version(self.header.version))
+    }
+
+    pub fn by_stage(&self, mask: u8) -> impl Iterator<Item = &DxvkStateCacheEntry> {
+        self.entries().by_stage(mask)
+    }
+
+    /// Like `by_stage`, with legacy entries treated by `handling`: they
+    /// match `STAGE_GRAPHICS` as graphics pipelines, and never match as
+    /// unknown ones. Rejecting them fails for a legacy cache.
+    pub fn by_stage_with(
+        &self,
+        mask: u8,
+        handling: LegacyStageHandling
+    ) -> Result<impl Iterator<Item = &DxvkStateCacheEntry>, Error> {
+        if DxvkStateCacheEdition::from_version(self.header.version) == DxvkStateCacheEdition::Legacy
+        {
+            handling.stages(None)?;
+        }
+        Ok(self.entries().filter(move |e| match e.stage_mask() {
+            Some(m) => m & mask != 0,
+            None => handling == LegacyStageHandling::TreatAsGraphics && mask & STAGE_GRAPHICS != 0
+        }))
+    }
+
+    pub fn larger_than(&self, bytes: usize) -> impl Iterator<Item = &DxvkStateCacheEntry> {
+        self.entries().larger_than(bytes)
+    }
+
+    /// Passes the entries through `f` in file order, keeping the entries it
+    /// returns and dropping those it returns `None` for. The entries are
+    /// keyed by the hash they come back with: a transform changing `data`
+    /// must set `hash` to `compute_hash`, or the entry fails validation
+    /// and is skipped by DXVK. Of entries coming back with the same hash,
+    /// the first is kept. The size in the header of an entry is set to the
+    /// size of its data, which fails with `ErrorKind::EntryTooLarge` past
+    /// the 24 bits it has. Legacy entries must keep the size of the cache,
+    /// or mapping fails with `ErrorKind::InvalidData`.
+    pub fn map_entries<F>(self, mut f: F) -> Result<Self, Error>
+    where
+        F: FnMut(DxvkStateCacheEntry) -> Option<DxvkStateCacheEntry>
+    {
+        let legacy_size = self.header.entry_size as usize;
+        let mut entries = LinkedHashMap::with_capacity(self.entries.len());
+        for mut entry in self.entries.into_iter().filter_map(|(_, entry)| f(entry)) {
+            let size = entry.data.len();
+            match &mut entry.header {
+                Some(header) => {
+                    header.entry_size = match u32::try_from(size) {
+                        Ok(size) if size as usize <= MAX_ENTRY_DATA => size,
+                        _ => {
+                            return Err(Error::new(
+                                ErrorKind::EntryTooLarge,
+                                format!("Entry of {} bytes, the most is {}", size, MAX_ENTRY_DATA)
+                            ))
+                        },
+                    };
+                },
+                None if size + HASH_SIZE != legacy_size => {
+                    return Err(Error::new(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Legacy entry of {} bytes in a cache of {} byte entries",
+                            size + HASH_SIZE,
+                            legacy_size
+                        )
+                    ))
+                },
+                None => ()
+            }
+            entries.entry(entry.hash).or_insert(entry);
         }
-        let hash = hasher.digest().bytes();
+        Ok(DxvkStateCache { header: self.header, entries })
+    }
+
+    /// Appends the entries of `other` whose hash the cache lacks, returning
+    /// how many were added. Entries already present are kept as they are.
+    /// The caches should be of the same version, as entries aren't
+    /// converted.
+    pub fn extend(&mut self, other: Self) -> usize {
+        let len = self.entries.len();
+        for (hash, entry) in other.entries {
+            self.entries.entry(hash).or_insert(entry);
+        }
+        self.entries.len() - len
+    }
+
+    /// Like `extend`, but borrows `other`, cloning only the entries added.
+    /// For merging one cache into several others.
+    pub fn extend_ref(&mut self, other: &DxvkStateCache) -> usize {
+        let len = self.entries.len();
+        for (hash, entry) in &other.entries {
+            if !self.entries.contains_key(hash) {
+                self.entries.insert(*hash, entry.clone());
+            }
+        }
+        self.entries.len() - len
+    }
+
+    /// The hashes of the entries, for set operations between caches that
+    /// don't need the entry data.
+    pub fn hash_set(&self) -> HashSet<Sha1Hash> {
+        self.entries.keys().copied().collect()
+    }
+
+    /// Removes the entries whose hash isn't in `keep`, keeping the others
+    /// in order, and returns how many were removed.
+    pub fn retain_hashes(&mut self, keep: &HashSet<Sha1Hash>) -> usize {
+        self.remove_where(|hash| !keep.contains(hash))
+    }
+
+    /// Removes the entries whose hash is in `drop`, returning how many were
+    /// removed.
+    pub fn remove_hashes(&mut self, drop: &HashSet<Sha1Hash>) -> usize {
+        self.remove_where(|hash| drop.contains(hash))
+    }
+
+    fn remove_where<F: Fn(&Sha1Hash) -> bool>(&mut self, remove: F) -> usize {
+        let hashes: Vec<Sha1Hash> = self.entries.keys().filter(|h| remove(h)).copied().collect();
+        for hash in &hashes {
+            self.entries.remove(hash);
+        }
+        hashes.len()
+    }
+
+    /// Computes the statistics of the cache, validating every entry.
+    pub fn stats(&self) -> CacheStats {
+        let kind = HashKind::from_version(self.header.version);
+        let valid: Vec<bool> = self.entries().map(|e| e.is_valid(kind)).collect();
+        CacheStats::from_entries(self.entries(), Some(&valid))
+    }
+
+    /// Like `stats`, but trusts the stored hashes instead of validating
+    /// them, counting every entry as valid.
+    pub fn stats_fast(&self) -> CacheStats {
+        CacheStats::from_entries(self.entries(), None)
+    }
+}
+
+/// Statistics of the entries of a cache. Apart from the entry counts, they
+/// only cover valid entries, which are the ones DXVK uses.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CacheStats {
+    pub entries:    usize,
+    /// Entries failing validation, or `None` if they weren't validated.
+    pub invalid:    Option<usize>,
+    /// Bytes of entry data, without hashes and entry headers.
+    pub data_bytes: u64,
+    /// Entries by stage mask. Empty for legacy caches, which have none.
+    pub stage_masks: BTreeMap<u8, usize>,
+    /// Entries without a stage mask, i.e. legacy entries.
+    pub without_mask: usize,
+    /// Entries by data size: bucket 0 counts empty entries, and bucket `i`
+    /// those of at least 2^(i-1) and less than 2^i bytes.
+    pub size_buckets: Vec<usize>
+}
+
+impl CacheStats {
+    /// Computes the statistics of `entries`, where `valid` tells which of
+    /// them are valid, like `merge::validate_entries` does. Without it,
+    /// all entries count as valid.
+    pub fn from_entries<'a, I>(entries: I, valid: Option<&[bool]>) -> Self
+    where
+        I: IntoIterator<Item = &'a DxvkStateCacheEntry>
+    {
+        let mut stats = CacheStats {
+            invalid: valid.map(|_| 0),
+            ..Default::default()
+        };
+        for (i, entry) in entries.into_iter().enumerate() {
+            stats.entries += 1;
+            if let Some(invalid) = &mut stats.invalid {
+                if !valid.and_then(|v| v.get(i)).copied().unwrap_or(true) {
+                    *invalid += 1;
+                    continue;
+                }
+            }
+            let len = entry.data.len();
+            stats.data_bytes += len as u64;
+            match entry.stage_mask() {
+                Some(mask) => *stats.stage_masks.entry(mask).or_insert(0) += 1,
+                None => stats.without_mask += 1
+            }
+            let bucket = (usize::BITS - len.leading_zeros()) as usize;
+            if stats.size_buckets.len() <= bucket {
+                stats.size_buckets.resize(bucket + 1, 0);
+            }
+            if let Some(count) = stats.size_buckets.get_mut(bucket) {
+                *count += 1;
+            }
+        }
+        stats
+    }
+
+    /// Valid entries with the given stages, with legacy entries treated by
+    /// `handling`.
+    pub fn stages(&self, stages: Stages, handling: LegacyStageHandling) -> Result<usize, Error> {
+        let mut count = self
+            .stage_masks
+            .iter()
+            .filter(|(mask, _)| Stages::from_mask(**mask) == stages)
+            .map(|(_, count)| count)
+            .sum();
+        if self.without_mask > 0 && handling.stages(None)? == stages {
+            count += self.without_mask;
+        }
+        Ok(count)
+    }
+}
+
+/// Filters for iterators over entries, so they can be chained, as in
+/// `cache.valid_entries().by_stage(STAGE_COMPUTE).larger_than(4096)`.
+pub trait EntryFilters<'a>: Iterator<Item = &'a DxvkStateCacheEntry> + Sized {
+    /// Entries whose hash of `kind` matches their data.
+    fn valid(self, kind: HashKind) -> impl Iterator<Item = &'a DxvkStateCacheEntry> {
+        self.filter(move |e| e.is_valid(kind))
+    }
+
+    /// Entries with any of the stages in `mask`. Legacy entries have no
+    /// stage mask and never match, as under
+    /// `LegacyStageHandling::TreatAsUnknown`.
+    fn by_stage(self, mask: u8) -> impl Iterator<Item = &'a DxvkStateCacheEntry> {
+        self.filter(move |e| e.stage_mask().is_some_and(|m| m & mask != 0))
+    }
+
+    /// Entries with more than `bytes` bytes of data.
+    fn larger_than(self, bytes: usize) -> impl Iterator<Item = &'a DxvkStateCacheEntry> {
+        self.filter(move |e| e.data.len() > bytes)
+    }
+}
+
+impl<'a, I: Iterator<Item = &'a DxvkStateCacheEntry>> EntryFilters<'a> for I {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &str = "aa26e22988cf43e8778663e36492ec75c5d7714e";
+
+    #[test]
+    fn parse_hash_accepts_either_case() {
+        let hash = parse_hash(HASH).unwrap();
+        assert_eq!(hash_to_hex(&hash), HASH);
+        assert_eq!(parse_hash(&HASH.to_uppercase()).unwrap(), hash);
+    }
+
+    #[test]
+    fn parse_hash_rejects_short_hashes() {
+        let error = parse_hash(&HASH[..39]).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().ends_with("expected 40 hex digits, found 39"));
+        assert!(parse_hash("").is_err());
+    }
+
+    #[test]
+    fn parse_hash_rejects_long_hashes() {
+        let error = parse_hash(&format!("{}0", HASH)).unwrap_err();
+        assert!(error.to_string().ends_with("expected 40 hex digits, found 41"));
+    }
+
+    #[test]
+    fn parse_hash_rejects_other_characters() {
+        let error = parse_hash(&HASH.replacen('e', "g", 1)).unwrap_err();
+        assert!(error.to_string().ends_with("unexpected character 'g' at position 5"));
+        let error = parse_hash(&format!(" {}", &HASH[1..])).unwrap_err();
+        assert!(error.to_string().ends_with("unexpected character ' ' at position 1"));
+        assert!(parse_hash(&HASH.replacen('a', "é", 1)).is_err());
+    }
+
+    #[test]
+    fn parse_hash_prefix_rejects_malformed_prefixes() {
+        assert_eq!(parse_hash_prefix("AA26").unwrap().to_string(), "aa26");
+        let error = parse_hash_prefix("aa2").unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert!(error.to_string().contains("too short"));
+        assert!(parse_hash_prefix("").is_err());
+        let error = parse_hash_prefix(&format!("{}0", HASH)).unwrap_err();
+        assert!(error.to_string().ends_with("expected at most 40 hex digits, found 41"));
+        let error = parse_hash_prefix("aa2x").unwrap_err();
+        assert!(error.to_string().ends_with("unexpected character 'x' at position 4"));
+        assert!(parse_hash_prefix("aa\u{0}6").is_err());
+        assert!(parse_hash_prefix("ааааа").is_err());
+    }
+
+    #[test]
+    fn resolve_prefix_tells_unique_ambiguous_and_missing_apart() {
+        let hash = parse_hash(HASH).unwrap();
+        let mut other = hash;
+        other[HASH_SIZE - 1] ^= 1;
+        let hashes = [hash, other];
+        let resolve = |prefix| resolve_prefix(&parse_hash_prefix(prefix).unwrap(), &hashes);
+        assert!(matches!(resolve(HASH), PrefixMatch::Unique(h) if h == hash));
+        assert!(matches!(resolve("aa26"), PrefixMatch::Ambiguous(h) if h == hashes));
+        assert!(matches!(resolve("0000"), PrefixMatch::NotFound));
+        let none: [Sha1Hash; 0] = [];
+        let prefix = parse_hash_prefix("aa26").unwrap();
+        assert!(matches!(resolve_prefix(&prefix, &none), PrefixMatch::NotFound));
+    }
+
+    #[test]
+    fn resolve_prefix_caps_the_candidates() {
+        let hashes: Vec<Sha1Hash> = (0..=MAX_CANDIDATES as u8)
+            .map(|i| {
+                let mut hash = [0xaa; HASH_SIZE];
+                hash[HASH_SIZE - 1] = i;
+                hash
+            })
+            .collect();
+        let prefix = parse_hash_prefix("aaaa").unwrap();
+        match resolve_prefix(&prefix, &hashes) {
+            PrefixMatch::Ambiguous(candidates) => assert_eq!(candidates.len(), MAX_CANDIDATES),
+            _ => panic!("Expected an ambiguous prefix")
+        }
+    }
+
+    #[test]
+    fn with_length_shorter_than_a_hash_has_no_data() {
+        assert!(DxvkStateCacheEntry::with_length(0).data.is_empty());
+        assert!(DxvkStateCacheEntry::with_length(5).data.is_empty());
+        assert!(DxvkStateCacheEntry::with_length(HASH_SIZE).data.is_empty());
+        assert_eq!(DxvkStateCacheEntry::with_length(HASH_SIZE + 3).data.len(), 3);
+    }
+
+    /// A legacy cache of three entries with 32 bytes of data.
+    fn legacy_cache() -> DxvkStateCache {
+        let mut cache = DxvkStateCache::new(DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version:    LEGACY_VERSION,
+            entry_size: (32 + HASH_SIZE) as u32
+        });
+        for i in 0..3 {
+            let mut entry = DxvkStateCacheEntry::with_length(32 + HASH_SIZE);
+            entry.data.fill(i);
+            entry.hash = entry.compute_hash(HashKind::Sha1);
+            cache.entries.insert(entry.hash, entry);
+        }
+        cache
+    }
+
+    #[test]
+    fn legacy_entries_must_keep_their_size_when_mapped() {
+        let kept = legacy_cache().map_entries(|mut entry| {
+            entry.data.reverse();
+            entry.hash = entry.compute_hash(HashKind::Sha1);
+            Some(entry)
+        });
+        assert_eq!(kept.unwrap().entries().count(), 3);
+        let grown = legacy_cache().map_entries(|mut entry| {
+            entry.data.push(0);
+            Some(entry)
+        });
+        assert_eq!(grown.err().map(|e| e.kind()), Some(ErrorKind::InvalidData));
+    }
+
+    #[test]
+    fn legacy_entries_are_graphics_under_treat_as_graphics() {
+        let cache = legacy_cache();
+        let handling = LegacyStageHandling::TreatAsGraphics;
+        let stats = CacheStats::from_entries(cache.entries(), None);
+        assert_eq!(stats.stages(Stages::Graphics, handling).unwrap(), 3);
+        assert_eq!(stats.stages(Stages::Unknown, handling).unwrap(), 0);
+        assert_eq!(cache.by_stage_with(STAGE_GRAPHICS, handling).unwrap().count(), 3);
+        assert_eq!(cache.by_stage_with(STAGE_COMPUTE, handling).unwrap().count(), 0);
+        assert!(cache.entries().all(|e| e.stages_with(handling).ok() == Some(Stages::Graphics)));
+    }
+
+    #[test]
+    fn legacy_entries_are_unknown_under_treat_as_unknown() {
+        let cache = legacy_cache();
+        let handling = LegacyStageHandling::TreatAsUnknown;
+        assert!(handling == LegacyStageHandling::default());
+        let stats = CacheStats::from_entries(cache.entries(), None);
+        assert_eq!(stats.stages(Stages::Unknown, handling).unwrap(), 3);
+        assert_eq!(stats.stages(Stages::Graphics, handling).unwrap(), 0);
+        assert_eq!(cache.by_stage_with(STAGE_GRAPHICS, handling).unwrap().count(), 0);
+        assert_eq!(cache.by_stage(STAGE_GRAPHICS).count(), 0);
+        assert!(cache.entries().all(|e| e.stages_with(handling).ok() == Some(Stages::Unknown)));
+    }
+
+    #[test]
+    fn legacy_entries_fail_under_reject() {
+        let cache = legacy_cache();
+        let handling = LegacyStageHandling::Reject;
+        let stats = CacheStats::from_entries(cache.entries(), None);
+        let error = stats.stages(Stages::Graphics, handling).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+        assert!(cache.by_stage_with(STAGE_GRAPHICS, handling).is_err());
+        assert!(cache.entries().all(|e| e.stages_with(handling).is_err()));
+    }
+
+    #[test]
+    fn entries_with_a_stage_mask_ignore_the_handling() {
+        let handlings = [
+            LegacyStageHandling::Reject,
+            LegacyStageHandling::TreatAsGraphics,
+            LegacyStageHandling::TreatAsUnknown
+        ];
+        for handling in handlings {
+            assert!(handling.stages(Some(0x11)).ok() == Some(Stages::Graphics));
+            assert!(handling.stages(Some(STAGE_COMPUTE)).ok() == Some(Stages::Compute));
+            assert!(handling.stages(Some(0x21)).ok() == Some(Stages::Ambiguous));
+            assert!(handling.stages(Some(0)).ok() == Some(Stages::Ambiguous));
+        }
+    }
 
-        hash == self.hash
+    #[test]
+    fn release_version_finds_the_version_of_a_release() {
+        assert_eq!(release_version("2.2"), Some(17));
+        // Releases newer than the table write the newest version.
+        assert_eq!(release_version("2.3"), Some(17));
+        assert_eq!(release_version("9.9"), Some(17));
+        assert_eq!(release_version("2.0"), Some(16));
+        assert_eq!(release_version("1.10.1"), Some(14));
+        assert_eq!(release_version("1.10.2"), Some(15));
+        assert_eq!(release_version("1.10"), Some(14));
+        assert_eq!(release_version("0.80"), Some(2));
+        assert_eq!(release_version("0.7"), None);
+        assert_eq!(release_version("two"), None);
+        assert_eq!(release_version("2."), None);
+        assert_eq!(release_version(""), None);
     }
 }
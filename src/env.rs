@@ -0,0 +1,154 @@
+//! The environment variables DXVK reads for its state cache, which users
+//! know from the DXVK documentation.
+
+use std::env;
+use std::path::{Path, PathBuf};
+
+/// Turns the state cache off when set to `0`.
+pub const STATE_CACHE_VAR: &str = "DXVK_STATE_CACHE";
+/// Directory DXVK keeps state caches in, instead of next to the executable.
+pub const STATE_CACHE_PATH_VAR: &str = "DXVK_STATE_CACHE_PATH";
+
+/// Whether `DXVK_STATE_CACHE=0` is set, so that DXVK started from this
+/// environment neither reads nor writes state caches.
+pub fn state_cache_disabled() -> bool {
+    env::var_os(STATE_CACHE_VAR).is_some_and(|value| value == "0")
+}
+
+/// The directory set by `DXVK_STATE_CACHE_PATH`, unless unset or empty.
+pub fn state_cache_path() -> Option<PathBuf> {
+    env::var_os(STATE_CACHE_PATH_VAR).filter(|value| !value.is_empty()).map(PathBuf::from)
+}
+
+/// Hint for a state cache not found at `path`: a cache of the same name in
+/// `DXVK_STATE_CACHE_PATH` if there is one, otherwise where DXVK keeps its
+/// caches. Returns `None` for files not named like a state cache.
+pub fn missing_cache_hint(path: &Path) -> Option<String> {
+    if path.extension()? != "dxvk-cache" {
+        return None;
+    }
+    let mut hint = match state_cache_path() {
+        Some(dir) => match path.file_name().map(|name| dir.join(name)) {
+            Some(found) if found.is_file() && found != path => {
+                format!("{} has a cache of that name: {}", STATE_CACHE_PATH_VAR, found.display())
+            },
+            _ => format!(
+                "{} is set, so DXVK keeps its caches in {}",
+                STATE_CACHE_PATH_VAR,
+                dir.display()
+            )
+        },
+        None => format!(
+            "DXVK keeps its caches next to the game executable, or in {} if set",
+            STATE_CACHE_PATH_VAR
+        )
+    };
+    if state_cache_disabled() {
+        hint.push_str(&format!(". {}=0 is set, so DXVK writes none", STATE_CACHE_VAR));
+    }
+    Some(hint)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ffi::OsString;
+    use std::fs;
+    use std::sync::{Mutex, MutexGuard};
+
+    /// Held by the tests changing the environment, which the tests running
+    /// in parallel share.
+    static ENV_LOCK: Mutex<()> = Mutex::new(());
+
+    /// Sets the DXVK variables for the scope of a test, restoring them when
+    /// dropped.
+    struct ScopedEnv {
+        saved: Vec<(&'static str, Option<OsString>)>,
+        _lock: MutexGuard<'static, ()>
+    }
+
+    impl ScopedEnv {
+        fn new(vars: &[(&'static str, Option<&str>)]) -> Self {
+            let lock = ENV_LOCK.lock().unwrap_or_else(|e| e.into_inner());
+            let saved = [STATE_CACHE_VAR, STATE_CACHE_PATH_VAR]
+                .iter()
+                .map(|&name| (name, env::var_os(name)))
+                .collect();
+            for name in [STATE_CACHE_VAR, STATE_CACHE_PATH_VAR] {
+                match vars.iter().find(|(n, _)| *n == name).and_then(|(_, value)| *value) {
+                    Some(value) => env::set_var(name, value),
+                    None => env::remove_var(name)
+                }
+            }
+            ScopedEnv { saved, _lock: lock }
+        }
+    }
+
+    impl Drop for ScopedEnv {
+        fn drop(&mut self) {
+            for (name, value) in &self.saved {
+                match value {
+                    Some(value) => env::set_var(name, value),
+                    None => env::remove_var(name)
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn state_cache_is_disabled_only_by_zero() {
+        let scope = ScopedEnv::new(&[(STATE_CACHE_VAR, Some("0"))]);
+        assert!(state_cache_disabled());
+        drop(scope);
+        for value in [None, Some("1"), Some(""), Some("00")] {
+            let _env = ScopedEnv::new(&[(STATE_CACHE_VAR, value)]);
+            assert!(!state_cache_disabled(), "{:?}", value);
+        }
+    }
+
+    #[test]
+    fn empty_state_cache_path_is_unset() {
+        let scope = ScopedEnv::new(&[(STATE_CACHE_PATH_VAR, Some(""))]);
+        assert_eq!(state_cache_path(), None);
+        drop(scope);
+        let _env = ScopedEnv::new(&[(STATE_CACHE_PATH_VAR, Some("/caches"))]);
+        assert_eq!(state_cache_path(), Some(PathBuf::from("/caches")));
+    }
+
+    #[test]
+    fn missing_cache_hint_only_covers_caches() {
+        let _env = ScopedEnv::new(&[]);
+        assert_eq!(missing_cache_hint(Path::new("list.txt")), None);
+        assert_eq!(missing_cache_hint(Path::new("game")), None);
+        let hint = missing_cache_hint(Path::new("game.dxvk-cache")).unwrap();
+        assert!(hint.starts_with("DXVK keeps its caches next to the game executable"));
+    }
+
+    #[test]
+    fn missing_cache_hint_points_to_the_state_cache_path() {
+        let dir = env::temp_dir().join("dxvk-cache-tool-test-env-hint");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        fs::write(dir.join("game.dxvk-cache"), b"").unwrap();
+        let _env = ScopedEnv::new(&[
+            (STATE_CACHE_PATH_VAR, dir.to_str()),
+            (STATE_CACHE_VAR, Some("0"))
+        ]);
+
+        let hint = missing_cache_hint(Path::new("game.dxvk-cache")).unwrap();
+        let found = dir.join("game.dxvk-cache");
+        let expected =
+            format!("{} has a cache of that name: {}", STATE_CACHE_PATH_VAR, found.display());
+        assert!(hint.starts_with(&expected));
+        assert!(hint.ends_with("DXVK_STATE_CACHE=0 is set, so DXVK writes none"));
+
+        let hint = missing_cache_hint(Path::new("other.dxvk-cache")).unwrap();
+        let expected = format!(
+            "{} is set, so DXVK keeps its caches in {}. {}=0 is set, so DXVK writes none",
+            STATE_CACHE_PATH_VAR,
+            dir.display(),
+            STATE_CACHE_VAR
+        );
+        assert_eq!(hint, expected);
+    }
+}
@@ -1,36 +1,232 @@
+use std::fmt;
 use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::json;
+use crate::warning::WarningId;
 
 #[derive(Clone, Copy, PartialEq, Debug)]
 pub enum ErrorKind {
     IoError(io::ErrorKind),
     InvalidInput,
-    InvalidData
+    InvalidData,
+    VersionMismatch { expected: u32, found: u32 },
+    /// A cache is larger than `Limits::max_file_size`.
+    FileTooLarge,
+    /// An entry is larger than `Limits::max_entry_size`.
+    EntryTooLarge,
+    /// A cache has more entries than `Limits::max_entry_count`.
+    TooManyEntries,
+    /// Reading took longer than `Limits::time_budget`. Unlike the other
+    /// limits, this depends on the load of the machine, not just the input.
+    TimedOut,
+    /// The output would replace a cache of the same version with more
+    /// entries.
+    OutputRegression,
+    /// A warning is denied, as configured with `Deny`.
+    DeniedWarning(WarningId),
+    /// An option needs support this build was made without.
+    FeatureNotEnabled
+}
+
+impl ErrorKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ErrorKind::IoError(_) => "io_error",
+            ErrorKind::InvalidInput => "invalid_input",
+            ErrorKind::InvalidData => "invalid_data",
+            ErrorKind::VersionMismatch { .. } => "version_mismatch",
+            ErrorKind::FileTooLarge => "file_too_large",
+            ErrorKind::EntryTooLarge => "entry_too_large",
+            ErrorKind::TooManyEntries => "too_many_entries",
+            ErrorKind::TimedOut => "timed_out",
+            ErrorKind::OutputRegression => "output_regression",
+            ErrorKind::DeniedWarning(_) => "denied_warning",
+            ErrorKind::FeatureNotEnabled => "feature_not_enabled"
+        }
+    }
 }
 
 #[derive(Debug)]
 pub struct Error {
     kind:    ErrorKind,
-    message: String
+    message: String,
+    path:    Option<PathBuf>,
+    offset:  Option<u64>,
+    transient: bool
 }
 
 impl Error {
     pub fn new<S: Into<String>>(kind: ErrorKind, message: S) -> Self {
         Error {
             kind,
-            message: message.into()
+            message: message.into(),
+            path: None,
+            offset: None,
+            transient: false
+        }
+    }
+
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        if self.path.is_none() {
+            self.path = Some(path.as_ref().to_path_buf());
+        }
+        self
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        if self.offset.is_none() {
+            self.offset = Some(offset);
         }
+        self
     }
 
     pub fn kind(&self) -> ErrorKind {
         self.kind
     }
+
+    /// The message, without the path and offset.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+
+    /// The file the error is about, if any.
+    pub fn path(&self) -> Option<&Path> {
+        self.path.as_deref()
+    }
+
+    /// Whether the I/O error may go away by trying again, like the timeouts
+    /// and I/O errors of network file systems. Missing files, denied access
+    /// and invalid data never do.
+    pub fn is_transient(&self) -> bool {
+        self.transient
+    }
+
+    /// The error as a JSON document, its details in an `error` object, e.g.
+    /// `{"schema_version":1,"path_style":"posix","error":{"kind":"invalid_data",…}}`.
+    pub fn to_json(&self) -> String {
+        format!(
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"error\":{}}}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            self.details_json()
+        )
+    }
+
+    /// Formats the details of the error as a JSON object, for embedding
+    /// into other documents.
+    pub fn details_json(&self) -> String {
+        let mut s = format!(
+            "{{\"kind\":{},\"message\":{}",
+            json::string(self.kind.as_str()),
+            json::string(&self.message)
+        );
+        if let ErrorKind::VersionMismatch { expected, found } = self.kind {
+            s.push_str(&format!(",\"expected\":{},\"found\":{}", expected, found));
+        }
+        if let ErrorKind::DeniedWarning(id) = self.kind {
+            s.push_str(&format!(",\"warning\":{}", json::string(id.code())));
+        }
+        if let Some(path) = &self.path {
+            s.push_str(&format!(",\"path\":{}", json::path(path)));
+        }
+        if let Some(offset) = self.offset {
+            s.push_str(&format!(",\"offset\":{}", offset));
+        }
+        s.push('}');
+        s
+    }
 }
 
+/// OS errors worth retrying: EIO on Unix; ERROR_UNEXP_NET_ERR,
+/// ERROR_NETNAME_DELETED and ERROR_SEM_TIMEOUT on Windows.
+#[cfg(unix)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[5];
+#[cfg(windows)]
+const TRANSIENT_OS_ERRORS: &[i32] = &[59, 64, 121];
+#[cfg(not(any(unix, windows)))]
+const TRANSIENT_OS_ERRORS: &[i32] = &[];
+
 impl From<io::Error> for Error {
     fn from(error: io::Error) -> Self {
+        let transient = match error.kind() {
+            io::ErrorKind::TimedOut
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::WouldBlock
+            | io::ErrorKind::ConnectionReset
+            | io::ErrorKind::ConnectionAborted => true,
+            _ => error.raw_os_error().is_some_and(|e| TRANSIENT_OS_ERRORS.contains(&e))
+        };
         Error {
-            kind:    ErrorKind::IoError(error.kind()),
-            message: error.to_string()
+            transient,
+            ..Error::new(ErrorKind::IoError(error.kind()), error.to_string())
+        }
+    }
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path.display())?;
         }
+        write!(f, "{}", self.message)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for Error {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::json::Value;
+
+    const KINDS: &[ErrorKind] = &[
+        ErrorKind::IoError(io::ErrorKind::NotFound),
+        ErrorKind::InvalidInput,
+        ErrorKind::InvalidData,
+        ErrorKind::VersionMismatch { expected: 17, found: 15 },
+        ErrorKind::FileTooLarge,
+        ErrorKind::EntryTooLarge,
+        ErrorKind::TooManyEntries,
+        ErrorKind::TimedOut,
+        ErrorKind::OutputRegression,
+        ErrorKind::DeniedWarning(WarningId::OutputRegression),
+        ErrorKind::FeatureNotEnabled
+    ];
+
+    #[test]
+    fn every_kind_is_in_the_schema() {
+        let schema = json::parse(json::SCHEMA).unwrap();
+        let kinds = ["$defs", "error_details", "properties", "kind", "enum"]
+            .iter()
+            .try_fold(&schema, |value, key| value.get(key))
+            .and_then(Value::as_array)
+            .unwrap();
+        let kinds: Vec<&str> = kinds.iter().filter_map(Value::as_str).collect();
+        let names: Vec<&str> = KINDS.iter().map(ErrorKind::as_str).collect();
+        assert_eq!(names, kinds);
+        let unique: std::collections::HashSet<&str> = names.iter().copied().collect();
+        assert_eq!(unique.len(), KINDS.len());
+    }
+
+    #[test]
+    fn to_json_nests_the_details() {
+        let error = Error::new(ErrorKind::VersionMismatch { expected: 17, found: 15 }, "Mismatch")
+            .with_path("dir/game.dxvk-cache")
+            .with_offset(12);
+        let document = json::parse(&error.to_json()).unwrap();
+        assert_eq!(document.get("schema_version").and_then(Value::as_u64), Some(1));
+        let details = document.get("error").unwrap();
+        assert_eq!(details.get("kind").and_then(Value::as_str), Some("version_mismatch"));
+        assert_eq!(details.get("message").and_then(Value::as_str), Some("Mismatch"));
+        assert_eq!(details.get("expected").and_then(Value::as_u64), Some(17));
+        assert_eq!(details.get("found").and_then(Value::as_u64), Some(15));
+        assert_eq!(details.get("path").and_then(Value::as_str), Some("dir/game.dxvk-cache"));
+        assert_eq!(details.get("offset").and_then(Value::as_u64), Some(12));
+        assert!(document.get("kind").is_none());
     }
 }
@@ -0,0 +1,1306 @@
+//! Reading and writing state cache files.
+
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter, SeekFrom};
+use std::path::Path;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use crate::dxvk::*;
+use crate::error::{Error, ErrorKind};
+use crate::util;
+
+/// Number of entries between two progress reports.
+pub const PROGRESS_INTERVAL: usize = 4096;
+/// Capacity of the buffers for reading and writing caches. Larger than the
+/// 8 KiB of std since caches are read and written sequentially in one go.
+pub const DEFAULT_BUFFER_SIZE: usize = 256 << 10;
+
+/// Delay before the first retry of a transient I/O error by default.
+pub const DEFAULT_RETRY_DELAY: Duration = Duration::from_millis(500);
+
+/// Retrying reads and writes failing with transient I/O errors, as network
+/// file systems do now and then. See `Error::is_transient`. By default,
+/// nothing is retried.
+#[derive(Clone, Copy, Default)]
+pub struct Retry {
+    /// Retries of a failing read or write.
+    pub attempts: u32,
+    /// Delay before the first retry, doubling with every further one.
+    pub delay:    Duration
+}
+
+impl Retry {
+    /// Waits before retrying `error`, or returns it when it isn't transient
+    /// or `attempt` is past the retries. Retried errors go to `retried`.
+    fn wait(&self, error: Error, attempt: &mut u32, retried: &mut Vec<Error>) -> Result<(), Error> {
+        if !error.is_transient() || *attempt >= self.attempts {
+            return Err(error);
+        }
+        thread::sleep(self.delay.saturating_mul(1 << (*attempt).min(16)));
+        *attempt += 1;
+        retried.push(error);
+        Ok(())
+    }
+}
+
+/// Caps for reading untrusted caches, such as uploads to a server. Every
+/// limit defaults to `None`, unlimited, which is what the command line tool
+/// uses. Each limit fails with its own `ErrorKind`.
+#[derive(Clone, Copy, Default)]
+pub struct Limits {
+    /// Size of a cache file in bytes.
+    pub max_file_size:   Option<u64>,
+    /// Size of the data of a single entry in bytes.
+    pub max_entry_size:  Option<usize>,
+    /// Number of entries in a cache.
+    pub max_entry_count: Option<usize>,
+    /// Wall-clock time, checked every `PROGRESS_INTERVAL` entries.
+    pub time_budget:     Option<Duration>
+}
+
+impl Limits {
+    /// Returns the limits left after running since `started`, failing once
+    /// the time budget is used up.
+    pub fn after(&self, started: Instant) -> Result<Limits, Error> {
+        let time_budget = match self.time_budget {
+            Some(budget) => Some(budget.checked_sub(started.elapsed()).ok_or_else(|| {
+                Error::new(
+                    ErrorKind::TimedOut,
+                    format!("Exceeded the time budget of {:.1}s", budget.as_secs_f64())
+                )
+            })?),
+            None => None
+        };
+        Ok(Limits { time_budget, ..*self })
+    }
+
+    pub fn check_file_size(&self, size: u64) -> Result<(), Error> {
+        match self.max_file_size {
+            Some(max) if size > max => Err(Error::new(
+                ErrorKind::FileTooLarge,
+                format!("File is larger than the limit of {} bytes", max)
+            )),
+            _ => Ok(())
+        }
+    }
+
+    fn check_entry_size(&self, size: usize) -> Result<(), Error> {
+        match self.max_entry_size {
+            Some(max) if size > max => Err(Error::new(
+                ErrorKind::EntryTooLarge,
+                format!("Entry of {} bytes is larger than the limit of {} bytes", size, max)
+            )),
+            _ => Ok(())
+        }
+    }
+
+    fn check_entry_count(&self, count: usize) -> Result<(), Error> {
+        match self.max_entry_count {
+            Some(max) if count > max => Err(Error::new(
+                ErrorKind::TooManyEntries,
+                format!("Cache has more than the limit of {} entries", max)
+            )),
+            _ => Ok(())
+        }
+    }
+}
+
+impl<R: Read> ReadEx for BufReader<R> {}
+/// Little-endian integer reads.
+pub trait ReadEx: Read {
+    fn read_u32(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 4];
+        self.read_exact(&mut buf)?;
+        Ok(u32::from_le_bytes(buf))
+    }
+
+    fn read_u24(&mut self) -> io::Result<u32> {
+        let mut buf = [0; 3];
+        self.read_exact(&mut buf)?;
+        let [a, b, c] = buf;
+        Ok(u32::from_le_bytes([a, b, c, 0]))
+    }
+
+    fn read_u8(&mut self) -> io::Result<u8> {
+        let mut buf = [0; 1];
+        self.read_exact(&mut buf)?;
+        Ok(u8::from_le_bytes(buf))
+    }
+}
+
+impl<W: Write> WriteEx for BufWriter<W> {}
+/// Little-endian integer writes.
+pub trait WriteEx: Write {
+    fn write_u32(&mut self, n: u32) -> io::Result<()> {
+        self.write_all(&n.to_le_bytes())
+    }
+
+    fn write_u24(&mut self, n: u32) -> io::Result<()> {
+        let [a, b, c, _] = n.to_le_bytes();
+        self.write_all(&[a, b, c])
+    }
+
+    fn write_u8(&mut self, n: u8) -> io::Result<()> {
+        self.write_all(&[n])
+    }
+}
+
+/// Reads a cache as `version` if given, otherwise as the version in its
+/// header. The returned header carries the version the entries were read as.
+///
+/// Transient errors are retried by `retry`, reopening the file at the entry
+/// that failed, and collected in `retried`.
+pub fn read_cache(
+    path: &Path,
+    version: Option<u32>,
+    limits: &Limits,
+    retry: Retry,
+    retried: &mut Vec<Error>,
+    buffer_size: usize,
+    progress: &mut dyn FnMut(u64, usize)
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>), Error> {
+    let started = Instant::now();
+    let mut attempt = 0;
+    let file = open_at(path, 0, retry, &mut attempt, retried)?;
+    let metadata = file.metadata()?;
+    if metadata.is_file() {
+        limits.check_file_size(metadata.len())?;
+    }
+    let mut reader = BufReader::with_capacity(buffer_size, file);
+
+    let mut header = loop {
+        match read_header(&mut reader) {
+            Ok(header) => break header,
+            Err(e) => {
+                retry.wait(e, &mut attempt, retried)?;
+                let file = open_at(path, 0, retry, &mut attempt, retried)?;
+                reader = BufReader::with_capacity(buffer_size, file);
+            }
+        }
+    };
+    attempt = 0;
+    if header.magic != MAGIC_STRING {
+        return Err(magic_mismatch(header.magic));
+    }
+    if let Some(version) = version {
+        header.version = version;
+    }
+
+    let edition = DxvkStateCacheEdition::from_version(header.version);
+    if edition == DxvkStateCacheEdition::Legacy && header.entry_size as usize <= HASH_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid entry size {} for version v{}", header.entry_size, header.version)
+        ));
+    }
+    if edition == DxvkStateCacheEdition::Legacy {
+        limits.check_entry_size(header.entry_size as usize - HASH_SIZE)?;
+    }
+    // Legacy entries have a fixed size, so the entry count of a regular file
+    // is known up front and the entries can be read without reallocating.
+    let capacity = if edition == DxvkStateCacheEdition::Legacy && metadata.is_file() {
+        let count =
+            metadata.len().saturating_sub(HEADER_SIZE as u64) / u64::from(header.entry_size);
+        (count as usize).min(limits.max_entry_count.unwrap_or(usize::MAX))
+    } else {
+        0
+    };
+    let mut entries = Vec::with_capacity(capacity);
+    let mut offset = HEADER_SIZE as u64;
+    loop {
+        let res = match edition {
+            DxvkStateCacheEdition::Standard => read_entry(&mut reader, limits),
+            DxvkStateCacheEdition::Legacy => {
+                read_entry_legacy(&mut reader, header.entry_size as usize)
+            },
+        };
+        match res {
+            Ok(e) => {
+                attempt = 0;
+                offset += e.serialized_size() as u64;
+                entries.push(e);
+                limits.check_file_size(offset)?;
+                limits.check_entry_count(entries.len())?;
+                if entries.len() % PROGRESS_INTERVAL == 0 {
+                    limits.after(started)?;
+                    progress(offset, entries.len());
+                }
+            },
+            Err(ref e) if e.kind() == ErrorKind::IoError(io::ErrorKind::UnexpectedEof) => break,
+            Err(e) => {
+                retry.wait(e.with_offset(offset), &mut attempt, retried)?;
+                let file = open_at(path, offset, retry, &mut attempt, retried)
+                    .map_err(|e| e.with_offset(offset))?;
+                reader = BufReader::with_capacity(buffer_size, file);
+            }
+        }
+    }
+
+    Ok((header, entries))
+}
+
+/// Reads a cache from `reader` as the version in its header, without the
+/// retries and progress of `read_cache`. For caches already in memory.
+pub fn read_cache_from<R: Read>(
+    reader: R,
+    limits: &Limits
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>), Error> {
+    let mut reader = BufReader::with_capacity(DEFAULT_BUFFER_SIZE, reader);
+    let header = read_header(&mut reader)?;
+    if header.magic != MAGIC_STRING {
+        return Err(magic_mismatch(header.magic));
+    }
+    let edition = DxvkStateCacheEdition::from_version(header.version);
+    if edition == DxvkStateCacheEdition::Legacy && header.entry_size as usize <= HASH_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Invalid entry size {} for version v{}", header.entry_size, header.version)
+        ));
+    }
+    let mut entries = Vec::new();
+    loop {
+        let res = match edition {
+            DxvkStateCacheEdition::Standard => read_entry(&mut reader, limits),
+            DxvkStateCacheEdition::Legacy => {
+                read_entry_legacy(&mut reader, header.entry_size as usize)
+            },
+        };
+        match res {
+            Ok(e) => {
+                entries.push(e);
+                limits.check_entry_count(entries.len())?;
+            },
+            Err(ref e) if e.kind() == ErrorKind::IoError(io::ErrorKind::UnexpectedEof) => break,
+            Err(e) => return Err(e)
+        }
+    }
+
+    Ok((header, entries))
+}
+
+/// Opens `path` at `offset`, retrying transient errors.
+fn open_at(
+    path: &Path,
+    offset: u64,
+    retry: Retry,
+    attempt: &mut u32,
+    retried: &mut Vec<Error>
+) -> Result<File, Error> {
+    loop {
+        let file = File::open(path).and_then(|mut file| {
+            file.seek(SeekFrom::Start(offset))?;
+            Ok(file)
+        });
+        match file {
+            Ok(file) => return Ok(file),
+            Err(e) => retry.wait(e.into(), attempt, retried)?
+        }
+    }
+}
+
+/// Outcome of salvaging a cache.
+pub struct Salvage {
+    /// Bytes after the header that are not part of a recovered entry.
+    pub skipped_bytes: u64,
+    pub recovered:     usize
+}
+
+/// Recovers the valid entries of a cache whose header or parts of whose
+/// entries are corrupt, reading it as `version`. The header is ignored,
+/// except for the entry size of legacy versions, and every offset is tried
+/// as the start of an entry whose hash matches its data. Entries failing
+/// validation are lost along with the corrupt stretches.
+pub fn salvage_cache(
+    path: &Path,
+    version: u32,
+    limits: &Limits
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>, Salvage), Error> {
+    limits.check_file_size(fs::metadata(path)?.len())?;
+    let bytes = fs::read(path)?;
+    let declared_size = match bytes.get(8..HEADER_SIZE) {
+        Some(&[a, b, c, d]) => u32::from_le_bytes([a, b, c, d]),
+        _ => 0
+    };
+    let edition = DxvkStateCacheEdition::from_version(version);
+    let kind = HashKind::from_version(version);
+    if edition == DxvkStateCacheEdition::Legacy && declared_size as usize <= HASH_SIZE {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!(
+                "Invalid entry size {} in the header; entries of version v{} can't be told \
+                 apart without it",
+                declared_size, version
+            )
+        ));
+    }
+
+    let mut entries = Vec::new();
+    let mut skipped_bytes = 0;
+    let mut offset = HEADER_SIZE;
+    while offset < bytes.len() {
+        let rest = bytes.get(offset..).unwrap_or_default();
+        let entry = match edition {
+            DxvkStateCacheEdition::Standard => salvage_entry(rest, kind, limits),
+            DxvkStateCacheEdition::Legacy => {
+                salvage_entry_legacy(rest, kind, declared_size as usize)
+            },
+        };
+        match entry {
+            Some(entry) => {
+                offset += entry.serialized_size();
+                entries.push(entry);
+                limits.check_entry_count(entries.len())?;
+            },
+            None => {
+                offset += 1;
+                skipped_bytes += 1;
+            }
+        }
+    }
+
+    let header = DxvkStateCacheHeader {
+        magic: MAGIC_STRING,
+        version,
+        entry_size: declared_size
+    };
+    let salvage = Salvage {
+        skipped_bytes,
+        recovered: entries.len()
+    };
+    Ok((header, entries, salvage))
+}
+
+/// Returns the valid entry at the start of `bytes`, if any. Only stage
+/// masks DXVK writes are considered, which rules out most offsets without
+/// hashing anything.
+fn salvage_entry(bytes: &[u8], kind: HashKind, limits: &Limits) -> Option<DxvkStateCacheEntry> {
+    parse_entry(bytes, limits).filter(|e| e.is_valid(kind))
+}
+
+/// Returns the entry at the start of `bytes` if its header is plausible, a
+/// stage mask DXVK writes and a size within the limits, and it fits into
+/// `bytes`. It isn't validated.
+fn parse_entry(bytes: &[u8], limits: &Limits) -> Option<DxvkStateCacheEntry> {
+    let (&stage_mask, rest) = bytes.split_first()?;
+    if stage_mask == 0 || stage_mask & !(STAGE_GRAPHICS | STAGE_COMPUTE) != 0 {
+        return None;
+    }
+    let size = match rest.get(..3)? {
+        &[a, b, c] => u32::from_le_bytes([a, b, c, 0]),
+        _ => return None
+    };
+    limits.check_entry_size(size as usize).ok()?;
+    let hash = rest.get(3..3 + HASH_SIZE)?;
+    let data = rest.get(3 + HASH_SIZE..3 + HASH_SIZE + size as usize)?;
+    let mut entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+        stage_mask,
+        entry_size: size
+    });
+    entry.hash.copy_from_slice(hash);
+    entry.data.copy_from_slice(data);
+    Some(entry)
+}
+
+/// Returns the valid legacy entry of `size` bytes at the start of `bytes`.
+fn salvage_entry_legacy(
+    bytes: &[u8],
+    kind: HashKind,
+    size: usize
+) -> Option<DxvkStateCacheEntry> {
+    let bytes = bytes.get(..size)?;
+    let (data, hash) = bytes.split_at(size - HASH_SIZE);
+    let mut entry = DxvkStateCacheEntry::with_length(size);
+    entry.data.copy_from_slice(data);
+    entry.hash.copy_from_slice(hash);
+    Some(entry).filter(|e| e.is_valid(kind))
+}
+
+/// Bytes scanned at most for the next entry after one that desynchronizes
+/// `read_cache_resync`.
+pub const RESYNC_SCAN_LIMIT: usize = 1 << 20;
+
+/// Outcome of reading a cache with `read_cache_resync`.
+pub struct Resync {
+    /// Offsets and lengths of the stretches skipped to find the next entry.
+    pub skipped: Vec<(u64, u64)>,
+    /// Offset at which reading stopped, the file size unless the cache ends
+    /// in an entry cut short.
+    pub end:     u64
+}
+
+/// Reads a cache like `read_cache`, but resynchronizes with the entries
+/// after one whose header is corrupt. An entry is kept as long as it is
+/// valid or followed by a plausible header, as `salvage_entry` checks one.
+/// Otherwise the next offset holding a valid entry is looked for within
+/// `RESYNC_SCAN_LIMIT` bytes and reading resumes there, skipping the bytes
+/// in between. Reading stops where none is found, skipping the rest of the
+/// file, or quietly at a plausible entry cut short by the end of the file.
+/// Legacy entries all have the size in the header, so legacy caches are
+/// read as they are.
+pub fn read_cache_resync(
+    path: &Path,
+    version: Option<u32>,
+    limits: &Limits
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>, Resync), Error> {
+    limits.check_file_size(fs::metadata(path)?.len())?;
+    let bytes = fs::read(path)?;
+    let mut header = read_header(&mut BufReader::new(bytes.as_slice()))?;
+    if header.magic != MAGIC_STRING {
+        return Err(magic_mismatch(header.magic));
+    }
+    if let Some(version) = version {
+        header.version = version;
+    }
+    if DxvkStateCacheEdition::from_version(header.version) == DxvkStateCacheEdition::Legacy {
+        let (header, entries) = read_cache(
+            path,
+            Some(header.version),
+            limits,
+            Retry::default(),
+            &mut Vec::new(),
+            DEFAULT_BUFFER_SIZE,
+            &mut |_, _| {}
+        )?;
+        let end = entries.iter().map(|e| e.serialized_size() as u64).sum::<u64>();
+        let resync = Resync {
+            skipped: Vec::new(),
+            end:     HEADER_SIZE as u64 + end
+        };
+        return Ok((header, entries, resync));
+    }
+
+    let kind = HashKind::from_version(header.version);
+    let plausible = |offset: usize| {
+        offset == bytes.len()
+            || parse_entry(bytes.get(offset..).unwrap_or_default(), limits).is_some()
+    };
+    let mut entries = Vec::new();
+    let mut skipped = Vec::new();
+    let mut offset = HEADER_SIZE;
+    while offset < bytes.len() {
+        let rest = bytes.get(offset..).unwrap_or_default();
+        if let Some(entry) = parse_entry(rest, limits) {
+            let next = offset + entry.serialized_size();
+            if entry.is_valid(kind) || plausible(next) {
+                offset = next;
+                entries.push(entry);
+                limits.check_entry_count(entries.len())?;
+                continue;
+            }
+        }
+        let scan_end = bytes.len().min(offset.saturating_add(RESYNC_SCAN_LIMIT));
+        let found = (offset + 1..scan_end).find_map(|start| {
+            salvage_entry(bytes.get(start..).unwrap_or_default(), kind, limits)
+                .map(|e| (start, e))
+        });
+        match found {
+            Some((start, entry)) => {
+                skipped.push((offset as u64, (start - offset) as u64));
+                offset = start + entry.serialized_size();
+                entries.push(entry);
+                limits.check_entry_count(entries.len())?;
+            },
+            None if is_cut_short(rest) => break,
+            None => {
+                skipped.push((offset as u64, (bytes.len() - offset) as u64));
+                offset = bytes.len();
+            }
+        }
+    }
+
+    let resync = Resync {
+        skipped,
+        end: offset as u64
+    };
+    Ok((header, entries, resync))
+}
+
+/// Whether `bytes` start with an entry header DXVK writes whose entry ends
+/// past them, or too few bytes to tell.
+fn is_cut_short(bytes: &[u8]) -> bool {
+    match *bytes {
+        [stage_mask, a, b, c, ..] => {
+            let size = u32::from_le_bytes([a, b, c, 0]) as usize;
+            stage_mask != 0
+                && stage_mask & !(STAGE_GRAPHICS | STAGE_COMPUTE) == 0
+                && bytes.len() < ENTRY_HEADER_SIZE + HASH_SIZE + size
+        },
+        _ => true
+    }
+}
+
+/// Describes a magic string other than `DXVK`. Near misses, differing in
+/// case or in a single byte, are quoted as hex and ASCII since they hint at
+/// corruption rather than at some other kind of file.
+fn magic_mismatch(magic: [u8; 4]) -> Error {
+    let differing = magic.iter().zip(&MAGIC_STRING).filter(|(a, b)| a != b).count();
+    if !magic.eq_ignore_ascii_case(&MAGIC_STRING) && differing > 1 {
+        return Error::new(ErrorKind::InvalidData, "Magic string mismatch");
+    }
+    let hex: Vec<String> = magic.iter().map(|b| format!("{:02x}", b)).collect();
+    let ascii: String = magic
+        .iter()
+        .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+        .collect();
+    Error::new(
+        ErrorKind::InvalidData,
+        format!(
+            "Magic string mismatch: found {} (\"{}\") instead of DXVK, the file may be corrupt",
+            hex.join(" "),
+            ascii
+        )
+    )
+}
+
+/// Writes a cache, returning its size in bytes.
+pub fn write_cache<'a, I>(
+    path: &Path,
+    header: &DxvkStateCacheHeader,
+    entries: I,
+    buffer_size: usize
+) -> Result<u64, Error>
+where
+    I: Iterator<Item = &'a DxvkStateCacheEntry> + Clone
+{
+    let retry = Retry::default();
+    let progress = &mut |_, _| {};
+    write_cache_with_progress(path, header, entries, retry, &mut Vec::new(), buffer_size, progress)
+}
+
+/// Like `write_cache`, reporting the number of bytes and entries written so
+/// far to `progress` every `PROGRESS_INTERVAL` entries.
+///
+/// The cache is written to a temporary file that then replaces `path`, so a
+/// failed write leaves an existing file at `path` intact. The file and the
+/// rename are flushed to disk before returning, so that a crash leaves
+/// either the old or the new cache, and the new one gets the permissions
+/// of the file it replaces. On transient errors, the temporary file is
+/// written anew as `retry` allows, collecting the errors in `retried`. The
+/// temporary file never outlives a failure, and errors a user can act on,
+/// like a full disk, say so.
+pub fn write_cache_with_progress<'a, I>(
+    path: &Path,
+    header: &DxvkStateCacheHeader,
+    entries: I,
+    retry: Retry,
+    retried: &mut Vec<Error>,
+    buffer_size: usize,
+    progress: &mut dyn FnMut(u64, usize)
+) -> Result<u64, Error>
+where
+    I: Iterator<Item = &'a DxvkStateCacheEntry> + Clone
+{
+    let mut attempt = 0;
+    loop {
+        let temp = util::temp_path(path);
+        let result = replace_with(&temp, path, header, entries.clone(), buffer_size, progress);
+        match result {
+            Ok(size) => return Ok(size),
+            Err(e) => {
+                let _ = fs::remove_file(&temp);
+                retry
+                    .wait(e, &mut attempt, retried)
+                    .map_err(|e| describe_write_error(e, entries.clone()).with_path(path))?;
+            }
+        }
+    }
+}
+
+/// Writes a cache to `temp` and renames it to `path`, for
+/// `write_cache_with_progress`.
+fn replace_with<'a, I>(
+    temp: &Path,
+    path: &Path,
+    header: &DxvkStateCacheHeader,
+    entries: I,
+    buffer_size: usize,
+    progress: &mut dyn FnMut(u64, usize)
+) -> Result<u64, Error>
+where
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    let mut file = File::create(temp)?;
+    if let Ok(metadata) = fs::metadata(path) {
+        file.set_permissions(metadata.permissions())?;
+    }
+    let size = write_entries(&mut file, header, entries, buffer_size, progress)?;
+    file.sync_all()?;
+    drop(file);
+    fs::rename(temp, path)?;
+    util::sync_parent_dir(path)?;
+    Ok(size)
+}
+
+/// Rewords the errors of writing `entries` that call for freeing space or
+/// writing elsewhere instead of a raw OS error.
+fn describe_write_error<'a, I>(error: Error, entries: I) -> Error
+where
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    let (count, size) = entries.fold((0, HEADER_SIZE as u64), |(count, size), e| {
+        (count + 1, size + e.serialized_size() as u64)
+    });
+    let message = match error.kind() {
+        ErrorKind::IoError(io::ErrorKind::PermissionDenied) => format!(
+            "Permission denied writing {} entries; the directory is read-only or owned by \
+             another user, write the output elsewhere",
+            count
+        ),
+        ErrorKind::IoError(io::ErrorKind::ReadOnlyFilesystem) => format!(
+            "Read-only file system, can't write {} entries; write the output to another file \
+             system",
+            count
+        ),
+        ErrorKind::IoError(io::ErrorKind::StorageFull) => format!(
+            "No space left on device while writing {} entries ({}); free up space or write \
+             the output to another disk",
+            count,
+            util::format_bytes(size)
+        ),
+        ErrorKind::IoError(io::ErrorKind::QuotaExceeded) => format!(
+            "Disk quota exceeded while writing {} entries ({})",
+            count,
+            util::format_bytes(size)
+        ),
+        _ => return error
+    };
+    Error::new(error.kind(), message)
+}
+
+/// Writes a cache to `writer`, returning its size in bytes. For caches kept
+/// in memory; files are written by `write_cache`, which replaces them
+/// safely.
+pub fn write_cache_to<'a, W, I>(
+    writer: W,
+    header: &DxvkStateCacheHeader,
+    entries: I
+) -> Result<u64, Error>
+where
+    W: Write,
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    write_entries(writer, header, entries, DEFAULT_BUFFER_SIZE, &mut |_, _| {})
+}
+
+/// Appends `entries` to a cache written to `writer` with `header` before,
+/// returning the number of bytes appended. The entries of a legacy cache
+/// must be as large as the ones already written.
+pub fn append_cache_to<'a, W, I>(
+    writer: W,
+    header: &DxvkStateCacheHeader,
+    entries: I
+) -> Result<u64, Error>
+where
+    W: Write,
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    let mut entries = entries.peekable();
+    let header = legacy_header(header, entries.peek().copied());
+    let mut writer = BufWriter::new(writer);
+    let size = write_body(&mut writer, &header, entries, 0, &mut |_, _| {})?;
+    writer.flush()?;
+
+    Ok(size)
+}
+
+fn write_entries<'a, W, I>(
+    writer: W,
+    header: &DxvkStateCacheHeader,
+    entries: I,
+    buffer_size: usize,
+    progress: &mut dyn FnMut(u64, usize)
+) -> Result<u64, Error>
+where
+    W: Write,
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    let mut entries = entries.peekable();
+    let header = legacy_header(header, entries.peek().copied());
+    let mut writer = BufWriter::with_capacity(buffer_size, writer);
+    write_header(&mut writer, &header)?;
+    let size = write_body(&mut writer, &header, entries, HEADER_SIZE as u64, progress)?;
+    writer.flush()?;
+
+    Ok(size)
+}
+
+/// Writes `entries` of a cache with `header` from `offset` on, returning
+/// the offset after them.
+fn write_body<'a, W, I>(
+    writer: &mut BufWriter<W>,
+    header: &DxvkStateCacheHeader,
+    entries: I,
+    mut offset: u64,
+    progress: &mut dyn FnMut(u64, usize)
+) -> Result<u64, Error>
+where
+    W: Write,
+    I: Iterator<Item = &'a DxvkStateCacheEntry>
+{
+    let edition = DxvkStateCacheEdition::from_version(header.version);
+    for (i, entry) in entries.enumerate() {
+        match edition {
+            DxvkStateCacheEdition::Standard => write_entry(writer, entry)?,
+            DxvkStateCacheEdition::Legacy => {
+                check_legacy_size(entry, header.entry_size)?;
+                write_entry_legacy(writer, entry)?
+            }
+        };
+        offset += entry.serialized_size() as u64;
+        if (i + 1) % PROGRESS_INTERVAL == 0 {
+            progress(offset, i + 1);
+        }
+    }
+
+    Ok(offset)
+}
+
+/// The header to write: legacy caches declare the size of their entries,
+/// which is taken from the first entry, as the header of a cache put
+/// together entry by entry may declare none or a stale one. DXVK rejects a
+/// legacy cache whose entries don't match the declared size.
+fn legacy_header(
+    header: &DxvkStateCacheHeader,
+    first: Option<&DxvkStateCacheEntry>
+) -> DxvkStateCacheHeader {
+    let edition = DxvkStateCacheEdition::from_version(header.version);
+    let entry_size = match (edition, first) {
+        (DxvkStateCacheEdition::Legacy, Some(entry)) => entry.serialized_size() as u32,
+        _ => header.entry_size
+    };
+    DxvkStateCacheHeader { magic: header.magic, version: header.version, entry_size }
+}
+
+fn check_legacy_size(entry: &DxvkStateCacheEntry, entry_size: u32) -> Result<(), Error> {
+    if entry.serialized_size() == entry_size as usize {
+        return Ok(());
+    }
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        format!(
+            "Entry {} of {} bytes doesn't match the entry size {} of the legacy cache",
+            hash_to_hex(&entry.hash),
+            entry.serialized_size(),
+            entry_size
+        )
+    ))
+}
+
+impl DxvkStateCacheHeader {
+    /// Reads just the header of the cache at `path` and checks its magic
+    /// string, telling the version of a cache without parsing its entries.
+    pub fn peek<P: AsRef<Path>>(path: P) -> Result<Self, Error> {
+        let path = path.as_ref();
+        let header = File::open(path)
+            .map_err(Error::from)
+            .and_then(|file| read_header(&mut BufReader::new(file)))
+            .map_err(|e| e.with_path(path))?;
+        if header.magic != MAGIC_STRING {
+            return Err(magic_mismatch(header.magic).with_path(path));
+        }
+        Ok(header)
+    }
+}
+
+impl DxvkStateCache {
+    /// Writes the cache to `path` the way the command line tool writes its
+    /// output, with `write_cache`: to a temporary file next to `path` that
+    /// then replaces it, so that a failed write never leaves a partial
+    /// cache at `path`. Renaming only replaces a file atomically within a
+    /// file system, which is why the temporary file is created in the
+    /// directory of `path` rather than in a temporary directory. The new
+    /// cache and the rename are flushed to disk before returning, so after
+    /// a crash `path` holds either the old or the new cache.
+    pub fn write_to_path_atomic<P: AsRef<Path>>(&self, path: P) -> Result<(), Error> {
+        write_cache(path.as_ref(), &self.header, self.entries.values(), DEFAULT_BUFFER_SIZE)?;
+        Ok(())
+    }
+}
+
+/// Counts the complete entries of the cache at `path` by their sizes alone,
+/// without reading or validating their data. Returns the header as well.
+pub fn count_entries(path: &Path) -> Result<(DxvkStateCacheHeader, usize), Error> {
+    let header = DxvkStateCacheHeader::peek(path)?;
+    let len = fs::metadata(path).map_err(|e| Error::from(e).with_path(path))?.len();
+    let body = len.saturating_sub(HEADER_SIZE as u64);
+    if DxvkStateCacheEdition::from_version(header.version) == DxvkStateCacheEdition::Legacy {
+        let count = body.checked_div(u64::from(header.entry_size)).unwrap_or(0);
+        return Ok((header, count as usize));
+    }
+
+    let mut reader = BufReader::new(File::open(path)?);
+    reader.seek(SeekFrom::Start(HEADER_SIZE as u64))?;
+    let mut offset = HEADER_SIZE as u64;
+    let mut count = 0;
+    while offset + (ENTRY_HEADER_SIZE as u64) <= len {
+        reader.read_u8()?;
+        let size = (HASH_SIZE as u64) + u64::from(reader.read_u24()?);
+        offset += ENTRY_HEADER_SIZE as u64 + size;
+        if offset > len {
+            break;
+        }
+        reader.seek_relative(size as i64)?;
+        count += 1;
+    }
+    Ok((header, count))
+}
+
+/// Largest data of a standard entry, whose size is stored in 24 bits.
+pub const MAX_ENTRY_DATA: usize = (1 << 24) - 1;
+
+/// Reads an entry dumped to a file of its own, to be added to a cache with
+/// `header`. The dump holds either the entry as stored in such a cache or
+/// just its data, told apart by size: the size in the entry header of a
+/// standard entry, or the entry size of a legacy cache. Data alone is given
+/// `stage_mask` in a standard cache, which also replaces the stage mask of
+/// a stored entry. Either way the hash is computed for the cache version.
+pub fn read_entry_dump(
+    path: &Path,
+    header: &DxvkStateCacheHeader,
+    stage_mask: Option<u8>
+) -> Result<DxvkStateCacheEntry, Error> {
+    let bytes = fs::read(path).map_err(|e| Error::from(e).with_path(path))?;
+    let len = bytes.len();
+    let invalid = |kind, message: String| Error::new(kind, message).with_path(path);
+    let mut entry = match DxvkStateCacheEdition::from_version(header.version) {
+        DxvkStateCacheEdition::Legacy => {
+            if stage_mask.is_some() {
+                return Err(invalid(
+                    ErrorKind::InvalidInput,
+                    format!("Entries of version v{} have no stage mask", header.version)
+                ));
+            }
+            let size = header.entry_size as usize;
+            if len == size && len >= HASH_SIZE {
+                read_entry_legacy(&mut BufReader::new(bytes.as_slice()), size)
+                    .map_err(|e| e.with_path(path))?
+            } else if len.checked_add(HASH_SIZE) == Some(size) {
+                let mut entry = DxvkStateCacheEntry::with_length(size);
+                entry.data = bytes;
+                entry
+            } else {
+                return Err(invalid(
+                    ErrorKind::InvalidData,
+                    format!(
+                        "Dump of {} bytes doesn't fit version v{}, whose entries are {} bytes, \
+                         or {} bytes of data",
+                        len,
+                        header.version,
+                        size,
+                        size.saturating_sub(HASH_SIZE)
+                    )
+                ));
+            }
+        },
+        DxvkStateCacheEdition::Standard => {
+            let stored = match bytes.get(1..ENTRY_HEADER_SIZE) {
+                Some(&[a, b, c]) => Some(u32::from_le_bytes([a, b, c, 0]) as usize),
+                _ => None
+            };
+            let data = len.checked_sub(ENTRY_HEADER_SIZE + HASH_SIZE);
+            if stored.is_some() && stored == data && data != Some(0) {
+                let mut reader = BufReader::new(bytes.as_slice());
+                let mut entry =
+                    read_entry(&mut reader, &Limits::default()).map_err(|e| e.with_path(path))?;
+                if let (Some(mask), Some(header)) = (stage_mask, entry.header.as_mut()) {
+                    header.stage_mask = mask;
+                }
+                entry
+            } else {
+                let stage_mask = stage_mask.ok_or_else(|| {
+                    let message = match (stored, data) {
+                        (Some(stored), Some(data)) => format!(
+                            "Dump of {} bytes isn't a stored entry, whose header gives {} bytes \
+                             of data instead of {}, and data alone needs a stage mask",
+                            len, stored, data
+                        ),
+                        _ => format!(
+                            "Dump of {} bytes is too short for a stored entry, and data alone \
+                             needs a stage mask",
+                            len
+                        )
+                    };
+                    invalid(ErrorKind::InvalidInput, message)
+                })?;
+                if len == 0 || len > MAX_ENTRY_DATA {
+                    return Err(invalid(
+                        ErrorKind::InvalidData,
+                        format!(
+                            "Dump of {} bytes doesn't fit an entry, which holds 1 to {} bytes",
+                            len, MAX_ENTRY_DATA
+                        )
+                    ));
+                }
+                let mut entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+                    stage_mask,
+                    entry_size: len as u32
+                });
+                entry.data = bytes;
+                entry
+            }
+        }
+    };
+    entry.hash = entry.compute_hash(HashKind::from_version(header.version));
+    Ok(entry)
+}
+
+fn read_header<R: Read>(reader: &mut BufReader<R>) -> Result<DxvkStateCacheHeader, Error> {
+    Ok(DxvkStateCacheHeader {
+        magic:      {
+            let mut magic = [0; 4];
+            reader.read_exact(&mut magic)?;
+            magic
+        },
+        version:    reader.read_u32()?,
+        entry_size: reader.read_u32()?
+    })
+}
+
+fn read_entry<R: Read>(
+    reader: &mut BufReader<R>,
+    limits: &Limits
+) -> Result<DxvkStateCacheEntry, Error> {
+    let header = DxvkStateCacheEntryHeader {
+        stage_mask: reader.read_u8()?,
+        entry_size: reader.read_u24()?
+    };
+    limits.check_entry_size(header.entry_size as usize)?;
+    let mut entry = DxvkStateCacheEntry::with_header(header);
+    reader.read_exact(&mut entry.hash)?;
+    reader.read_exact(&mut entry.data)?;
+
+    Ok(entry)
+}
+
+fn read_entry_legacy<R: Read>(
+    reader: &mut BufReader<R>,
+    size: usize
+) -> Result<DxvkStateCacheEntry, Error> {
+    let mut entry = DxvkStateCacheEntry::with_length(size);
+    reader.read_exact(&mut entry.data)?;
+    reader.read_exact(&mut entry.hash)?;
+
+    Ok(entry)
+}
+
+fn write_header<W: Write>(
+    writer: &mut BufWriter<W>,
+    header: &DxvkStateCacheHeader
+) -> Result<(), Error> {
+    writer.write_all(&MAGIC_STRING)?;
+    writer.write_u32(header.version)?;
+    writer.write_u32(header.entry_size)?;
+
+    Ok(())
+}
+
+fn write_entry<W: Write>(
+    writer: &mut BufWriter<W>,
+    entry: &DxvkStateCacheEntry
+) -> Result<(), Error> {
+    if let Some(h) = &entry.header {
+        writer.write_u8(h.stage_mask)?;
+        writer.write_u24(h.entry_size)?;
+    }
+    writer.write_all(&entry.hash)?;
+    writer.write_all(&entry.data)?;
+
+    Ok(())
+}
+
+fn write_entry_legacy<W: Write>(
+    writer: &mut BufWriter<W>,
+    entry: &DxvkStateCacheEntry
+) -> Result<(), Error> {
+    writer.write_all(&entry.data)?;
+    writer.write_all(&entry.hash)?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use sha1::Sha1;
+
+    use super::*;
+
+    /// Writes a v17 cache of `count` entries with `size` bytes of data each
+    /// to a file of its own for the test `name`.
+    fn cache(name: &str, count: usize, size: usize) -> std::path::PathBuf {
+        let entries: Vec<DxvkStateCacheEntry> = (0..count)
+            .map(|i| {
+                let mut entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+                    stage_mask: 0x11,
+                    entry_size: size as u32
+                });
+                entry.data.iter_mut().zip(i.to_le_bytes()).for_each(|(b, i)| *b = i);
+                let mut hasher = Sha1::default();
+                hasher.update(&entry.data);
+                entry.hash = hasher.digest().bytes();
+                entry
+            })
+            .collect();
+        let header = DxvkStateCacheHeader {
+            magic: MAGIC_STRING,
+            version: 17,
+            entry_size: 0
+        };
+        let path = std::env::temp_dir().join(format!("dxvk-cache-tool-test-format-{}", name));
+        write_cache(&path, &header, entries.iter(), DEFAULT_BUFFER_SIZE).unwrap();
+        path
+    }
+
+    fn read(path: &Path, limits: &Limits) -> Result<Vec<DxvkStateCacheEntry>, Error> {
+        let retry = Retry::default();
+        read_cache(path, None, limits, retry, &mut Vec::new(), DEFAULT_BUFFER_SIZE, &mut |_, _| {})
+            .map(|(_, entries)| entries)
+    }
+
+    fn kind<T>(result: Result<T, Error>) -> Option<ErrorKind> {
+        result.err().map(|e| e.kind())
+    }
+
+    #[test]
+    fn caches_within_the_limits_are_read() {
+        let path = cache("within-limits", 3, 32);
+        let limits = Limits {
+            max_file_size:   Some(fs::metadata(&path).unwrap().len()),
+            max_entry_size:  Some(32),
+            max_entry_count: Some(3),
+            time_budget:     Some(Duration::from_secs(60))
+        };
+        assert_eq!(read(&path, &limits).unwrap().len(), 3);
+    }
+
+    #[test]
+    fn max_file_size_fails_with_file_too_large() {
+        let path = cache("file-too-large", 3, 32);
+        let limits = Limits {
+            max_file_size: Some(fs::metadata(&path).unwrap().len() - 1),
+            ..Limits::default()
+        };
+        assert_eq!(kind(read(&path, &limits)), Some(ErrorKind::FileTooLarge));
+    }
+
+    #[test]
+    fn max_entry_size_fails_with_entry_too_large() {
+        let limits = Limits {
+            max_entry_size: Some(31),
+            ..Limits::default()
+        };
+        let path = cache("entry-too-large", 3, 32);
+        assert_eq!(kind(read(&path, &limits)), Some(ErrorKind::EntryTooLarge));
+    }
+
+    #[test]
+    fn max_entry_count_fails_with_too_many_entries() {
+        let limits = Limits {
+            max_entry_count: Some(2),
+            ..Limits::default()
+        };
+        let path = cache("too-many-entries", 3, 32);
+        assert_eq!(kind(read(&path, &limits)), Some(ErrorKind::TooManyEntries));
+    }
+
+    #[test]
+    fn time_budget_fails_with_timed_out() {
+        let limits = Limits {
+            time_budget: Some(Duration::ZERO),
+            ..Limits::default()
+        };
+        let path = cache("timed-out", PROGRESS_INTERVAL, 4);
+        assert_eq!(kind(read(&path, &limits)), Some(ErrorKind::TimedOut));
+        let started = Instant::now() - Duration::from_secs(2);
+        let limits = Limits {
+            time_budget: Some(Duration::from_secs(1)),
+            ..Limits::default()
+        };
+        assert_eq!(kind(limits.after(started)), Some(ErrorKind::TimedOut));
+    }
+
+    #[test]
+    fn retry_stops_after_the_attempts() {
+        let retry = Retry {
+            attempts: 2,
+            delay:    Duration::ZERO
+        };
+        let transient = || Error::from(io::Error::from(io::ErrorKind::TimedOut));
+        let (mut attempt, mut retried) = (0, Vec::new());
+        assert!(retry.wait(transient(), &mut attempt, &mut retried).is_ok());
+        assert!(retry.wait(transient(), &mut attempt, &mut retried).is_ok());
+        let error = retry.wait(transient(), &mut attempt, &mut retried).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::IoError(io::ErrorKind::TimedOut));
+        assert_eq!((attempt, retried.len()), (2, 2));
+    }
+
+    #[test]
+    fn retry_passes_on_errors_that_are_not_transient() {
+        let retry = Retry {
+            attempts: 2,
+            delay:    Duration::ZERO
+        };
+        let (mut attempt, mut retried) = (0, Vec::new());
+        let error = Error::from(io::Error::from(io::ErrorKind::NotFound));
+        assert!(retry.wait(error, &mut attempt, &mut retried).is_err());
+        let error = Error::new(ErrorKind::InvalidData, "Invalid");
+        assert!(retry.wait(error, &mut attempt, &mut retried).is_err());
+        assert_eq!((attempt, retried.len()), (0, 0));
+        let error = Error::from(io::Error::from(io::ErrorKind::Interrupted));
+        assert!(Retry::default().wait(error, &mut attempt, &mut retried).is_err());
+    }
+
+    /// Writes `bytes` to a file of its own for the test `name`.
+    fn temp_file(name: &str, bytes: &[u8]) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("dxvk-cache-tool-test-format-{}", name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Hashes of the entries of `bytes` at the given indices, as written by
+    /// `cache`.
+    fn hashes_at(bytes: &[u8], indices: &[usize]) -> Vec<Sha1Hash> {
+        let (_, entries) = read_cache_from(bytes, &Limits::default()).unwrap();
+        indices.iter().map(|&i| entries[i].hash).collect()
+    }
+
+    #[test]
+    fn resync_skips_an_entry_with_a_corrupt_size() {
+        let bytes = fs::read(cache("resync-corrupt-size", 5, 32)).unwrap();
+        let mut corrupt = bytes.clone();
+        // The size of the third entry, after its stage mask.
+        let third = HEADER_SIZE + 2 * 56;
+        corrupt[third + 1..third + 4].copy_from_slice(&[0xff, 0xff, 0x7f]);
+        let path = temp_file("resync-corrupt-size", &corrupt);
+
+        let (_, entries, resync) = read_cache_resync(&path, None, &Limits::default()).unwrap();
+        let hashes: Vec<Sha1Hash> = entries.iter().map(|e| e.hash).collect();
+        assert_eq!(hashes, hashes_at(&bytes, &[0, 1, 3, 4]));
+        assert_eq!(resync.skipped, [(third as u64, 56)]);
+        assert_eq!(resync.end, bytes.len() as u64);
+    }
+
+    #[test]
+    fn resync_skips_garbage_between_entries_and_at_the_end() {
+        let bytes = fs::read(cache("resync-garbage", 3, 32)).unwrap();
+        let second = HEADER_SIZE + 56;
+        let mut corrupt = bytes[..second].to_vec();
+        corrupt.extend_from_slice(&[0xab; 13]);
+        corrupt.extend_from_slice(&bytes[second..]);
+        corrupt.extend_from_slice(&[0xff; 9]);
+        let path = temp_file("resync-garbage", &corrupt);
+
+        let (_, entries, resync) = read_cache_resync(&path, None, &Limits::default()).unwrap();
+        let hashes: Vec<Sha1Hash> = entries.iter().map(|e| e.hash).collect();
+        assert_eq!(hashes, hashes_at(&bytes, &[0, 1, 2]));
+        let end = bytes.len() as u64 + 13;
+        assert_eq!(resync.skipped, [(second as u64, 13), (end, 9)]);
+        assert_eq!(resync.end, corrupt.len() as u64);
+    }
+
+    #[test]
+    fn resync_stops_at_an_entry_cut_short() {
+        let bytes = fs::read(cache("resync-cut-short", 3, 32)).unwrap();
+        let path = temp_file("resync-cut-short", &bytes[..bytes.len() - 10]);
+        let (_, entries, resync) = read_cache_resync(&path, None, &Limits::default()).unwrap();
+        assert_eq!(entries.len(), 2);
+        assert!(resync.skipped.is_empty());
+        assert_eq!(resync.end, (HEADER_SIZE + 2 * 56) as u64);
+    }
+
+    #[test]
+    fn resync_reads_intact_caches_like_read_cache() {
+        let path = cache("resync-intact", 4, 32);
+        let bytes = fs::read(&path).unwrap();
+        let (_, entries, resync) = read_cache_resync(&path, None, &Limits::default()).unwrap();
+        assert!(entries == read(&path, &Limits::default()).unwrap());
+        assert!(resync.skipped.is_empty());
+        assert_eq!(resync.end, bytes.len() as u64);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn write_cache_keeps_the_permissions_of_the_replaced_file() {
+        let path = cache("keep-permissions", 1, 32);
+        util::set_file_mode(&path, 0o640).unwrap();
+        assert_eq!(cache("keep-permissions", 2, 32), path);
+        assert_eq!(util::file_mode(&path).unwrap(), 0o640);
+        assert_eq!(read(&path, &Limits::default()).unwrap().len(), 2);
+    }
+
+    #[test]
+    fn write_to_path_atomic_leaves_no_temporary_file() {
+        let dir = std::env::temp_dir().join("dxvk-cache-tool-test-format-atomic-dir");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("atomic.dxvk-cache");
+        fs::copy(cache("atomic", 3, 32), &path).unwrap();
+        let (header, entries) =
+            read_cache_from(File::open(&path).unwrap(), &Limits::default()).unwrap();
+        let mut cache = DxvkStateCache::new(header);
+        cache.entries.extend(entries.into_iter().skip(1).map(|e| (e.hash, e)));
+        cache.write_to_path_atomic(&path).unwrap();
+        assert_eq!(read(&path, &Limits::default()).unwrap().len(), 2);
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn appended_entries_read_back_as_if_written_at_once() {
+        let path = cache("append", 5, 32);
+        let (header, entries) =
+            read_cache_from(File::open(&path).unwrap(), &Limits::default()).unwrap();
+        let mut whole = Vec::new();
+        write_cache_to(&mut whole, &header, entries.iter()).unwrap();
+
+        let mut bytes = Vec::new();
+        let written = write_cache_to(&mut bytes, &header, entries.iter().take(2)).unwrap();
+        let appended = append_cache_to(&mut bytes, &header, entries.iter().skip(2)).unwrap();
+        assert_eq!(written + appended, bytes.len() as u64);
+        assert_eq!(bytes, whole);
+    }
+
+    #[test]
+    fn entries_mapped_to_another_size_read_back() {
+        let path = cache("map-size", 3, 32);
+        let (header, entries) =
+            read_cache_from(File::open(&path).unwrap(), &Limits::default()).unwrap();
+        let mut cache = DxvkStateCache::new(header);
+        cache.entries.extend(entries.into_iter().map(|e| (e.hash, e)));
+        let cache = cache
+            .map_entries(|mut entry| {
+                entry.data.extend_from_slice(&[7; 16]);
+                entry.hash = entry.compute_hash(HashKind::Sha1);
+                Some(entry)
+            })
+            .unwrap();
+
+        let mut bytes = Vec::new();
+        write_cache_to(&mut bytes, &cache.header, cache.entries()).unwrap();
+        let (_, read) = read_cache_from(bytes.as_slice(), &Limits::default()).unwrap();
+        assert_eq!(read.len(), 3);
+        for entry in &read {
+            assert_eq!(entry.data.len(), 48);
+            assert_eq!(entry.header.as_ref().map(|h| h.entry_size), Some(48));
+            assert_eq!(entry.hash, entry.compute_hash(HashKind::Sha1));
+        }
+    }
+
+    #[test]
+    fn entries_mapped_past_24_bits_are_too_large() {
+        let mut cache = DxvkStateCache::new(DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version:    17,
+            entry_size: 0
+        });
+        let entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+            stage_mask: 0x11,
+            entry_size: 4
+        });
+        cache.entries.insert(entry.hash, entry);
+        let grown = cache.map_entries(|mut entry| {
+            entry.data.resize(MAX_ENTRY_DATA + 1, 0);
+            Some(entry)
+        });
+        assert_eq!(grown.err().map(|e| e.kind()), Some(ErrorKind::EntryTooLarge));
+    }
+}
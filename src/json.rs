@@ -0,0 +1,344 @@
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::util;
+
+/// Version of the JSON documents printed by the tool, found in their
+/// `schema_version` field. Raised only on breaking changes; adding fields
+/// is not one. Sample documents of each version are kept under
+/// `tests/fixtures/schema` and checked against `SCHEMA`.
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// JSON Schema describing the documents of `SCHEMA_VERSION`.
+pub const SCHEMA: &str = include_str!("schema.json");
+
+/// Platform the paths of the documents come from, in their `path_style`
+/// field: `windows` paths may start with a drive letter and compare
+/// without case. Either way, they are written with forward slashes.
+pub const PATH_STYLE: &str = util::PathStyle::NATIVE.as_str();
+
+/// Quotes `path` as a JSON string literal, with forward slashes.
+pub fn path(path: &Path) -> String {
+    string(&util::portable_path(path))
+}
+
+/// Quotes and escapes `s` as a JSON string literal.
+pub fn string(s: &str) -> String {
+    let mut out = String::with_capacity(s.len() + 2);
+    out.push('"');
+    for c in s.chars() {
+        match c {
+            '"' => out.push_str("\\\""),
+            '\\' => out.push_str("\\\\"),
+            '\n' => out.push_str("\\n"),
+            '\r' => out.push_str("\\r"),
+            '\t' => out.push_str("\\t"),
+            c if (c as u32) < 0x20 => {
+                let _ = write!(out, "\\u{:04x}", c as u32);
+            },
+            c => out.push(c)
+        }
+    }
+    out.push('"');
+    out
+}
+
+/// A parsed JSON value, for reading back documents the tool wrote.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Number(f64),
+    String(String),
+    Array(Vec<Value>),
+    /// Members in document order.
+    Object(Vec<(String, Value)>)
+}
+
+impl Value {
+    /// Returns the member `key` of an object.
+    pub fn get(&self, key: &str) -> Option<&Value> {
+        match self {
+            Value::Object(members) => members.iter().find(|(k, _)| k == key).map(|(_, v)| v),
+            _ => None
+        }
+    }
+
+    pub fn as_str(&self) -> Option<&str> {
+        match self {
+            Value::String(s) => Some(s),
+            _ => None
+        }
+    }
+
+    /// Returns a number that is a non-negative integer.
+    pub fn as_u64(&self) -> Option<u64> {
+        match *self {
+            Value::Number(n) if n >= 0.0 && n.fract() == 0.0 && n < u64::MAX as f64 => {
+                Some(n as u64)
+            },
+            _ => None
+        }
+    }
+
+    pub fn as_array(&self) -> Option<&[Value]> {
+        match self {
+            Value::Array(values) => Some(values),
+            _ => None
+        }
+    }
+}
+
+/// Nesting depth beyond which documents are rejected.
+const MAX_DEPTH: usize = 64;
+
+/// Parses a JSON document, returning `None` if it is malformed.
+pub fn parse(s: &str) -> Option<Value> {
+    let mut parser = Parser { chars: s.chars().peekable() };
+    let value = parser.value(0)?;
+    parser.skip_whitespace();
+    match parser.chars.next() {
+        None => Some(value),
+        Some(_) => None
+    }
+}
+
+struct Parser<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>
+}
+
+impl Parser<'_> {
+    fn skip_whitespace(&mut self) {
+        while self.chars.next_if(|c| matches!(c, ' ' | '\t' | '\n' | '\r')).is_some() {}
+    }
+
+    fn expect(&mut self, word: &str) -> Option<()> {
+        word.chars().try_for_each(|c| self.chars.next_if_eq(&c).map(|_| ()))
+    }
+
+    fn value(&mut self, depth: usize) -> Option<Value> {
+        if depth > MAX_DEPTH {
+            return None;
+        }
+        self.skip_whitespace();
+        match *self.chars.peek()? {
+            'n' => self.expect("null").map(|_| Value::Null),
+            't' => self.expect("true").map(|_| Value::Bool(true)),
+            'f' => self.expect("false").map(|_| Value::Bool(false)),
+            '"' => self.string().map(Value::String),
+            '[' => {
+                self.chars.next();
+                let mut values = Vec::new();
+                self.list(']', |parser| {
+                    values.push(parser.value(depth + 1)?);
+                    Some(())
+                })?;
+                Some(Value::Array(values))
+            },
+            '{' => {
+                self.chars.next();
+                let mut members = Vec::new();
+                self.list('}', |parser| {
+                    parser.skip_whitespace();
+                    let key = parser.string()?;
+                    parser.skip_whitespace();
+                    parser.expect(":")?;
+                    members.push((key, parser.value(depth + 1)?));
+                    Some(())
+                })?;
+                Some(Value::Object(members))
+            },
+            _ => self.number().map(Value::Number)
+        }
+    }
+
+    /// Parses the comma separated items of an array or object with `item`,
+    /// up to and including `end`.
+    fn list<F>(&mut self, end: char, mut item: F) -> Option<()>
+    where
+        F: FnMut(&mut Self) -> Option<()>
+    {
+        self.skip_whitespace();
+        if self.chars.next_if_eq(&end).is_some() {
+            return Some(());
+        }
+        loop {
+            item(self)?;
+            self.skip_whitespace();
+            match self.chars.next()? {
+                ',' => (),
+                c if c == end => return Some(()),
+                _ => return None
+            }
+        }
+    }
+
+    fn string(&mut self) -> Option<String> {
+        self.expect("\"")?;
+        let mut s = String::new();
+        loop {
+            match self.chars.next()? {
+                '"' => return Some(s),
+                '\\' => match self.chars.next()? {
+                    '"' => s.push('"'),
+                    '\\' => s.push('\\'),
+                    '/' => s.push('/'),
+                    'b' => s.push('\u{8}'),
+                    'f' => s.push('\u{c}'),
+                    'n' => s.push('\n'),
+                    'r' => s.push('\r'),
+                    't' => s.push('\t'),
+                    'u' => {
+                        let high = self.hex4()?;
+                        let code = if (0xd800..0xdc00).contains(&high) {
+                            self.expect("\\u")?;
+                            let low = self.hex4()?;
+                            if !(0xdc00..0xe000).contains(&low) {
+                                return None;
+                            }
+                            0x10000 + ((high - 0xd800) << 10) + (low - 0xdc00)
+                        } else {
+                            high
+                        };
+                        s.push(char::from_u32(code)?);
+                    },
+                    _ => return None
+                },
+                c if (c as u32) < 0x20 => return None,
+                c => s.push(c)
+            }
+        }
+    }
+
+    fn hex4(&mut self) -> Option<u32> {
+        (0..4).try_fold(0, |code, _| Some(code << 4 | self.chars.next()?.to_digit(16)?))
+    }
+
+    fn number(&mut self) -> Option<f64> {
+        let mut s = String::new();
+        let numeric = |c: &char| matches!(c, '0'..='9' | '-' | '+' | '.' | 'e' | 'E');
+        while let Some(c) = self.chars.next_if(numeric) {
+            s.push(c);
+        }
+        s.parse().ok().filter(|n: &f64| n.is_finite())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Documents printed at schema version 1, each with the definition of
+    /// `SCHEMA` it follows. They are kept as they were when the version is
+    /// raised, so that breaking changes show.
+    const V1_SAMPLES: &[(&str, &str)] = &[
+        ("merge", include_str!("../tests/fixtures/schema/v1/merge.json")),
+        ("repair", include_str!("../tests/fixtures/schema/v1/repair.json")),
+        ("info", include_str!("../tests/fixtures/schema/v1/info.json")),
+        ("info", include_str!("../tests/fixtures/schema/v1/info-compressibility.json")),
+        ("equal", include_str!("../tests/fixtures/schema/v1/equal.json")),
+        ("bench", include_str!("../tests/fixtures/schema/v1/bench.json")),
+        ("version", include_str!("../tests/fixtures/schema/v1/version.json")),
+        ("error", include_str!("../tests/fixtures/schema/v1/error.json")),
+        ("provenance", include_str!("../tests/fixtures/schema/v1/provenance.dxvk-cache.meta.json")),
+        ("health", include_str!("../tests/fixtures/schema/v1/health.json")),
+        ("health", include_str!("../tests/fixtures/schema/v1/health-failed.json"))
+    ];
+
+    /// Checks `value` against the schema `def`, with the keywords `SCHEMA`
+    /// uses, returning where it first fails.
+    fn check(schema: &Value, def: &Value, value: &Value, at: &str) -> Result<(), String> {
+        if let Some(reference) = def.get("$ref").and_then(Value::as_str) {
+            let name = reference.trim_start_matches("#/$defs/");
+            let def = schema.get("$defs").and_then(|defs| defs.get(name)).unwrap();
+            check(schema, def, value, at)?;
+        }
+        if let Some(expected) = def.get("const") {
+            if value != expected {
+                return Err(format!("{}: expected {:?}", at, expected));
+            }
+        }
+        if let Some(values) = def.get("enum").and_then(Value::as_array) {
+            if !values.contains(value) {
+                return Err(format!("{}: {:?} is not listed", at, value));
+            }
+        }
+        if let Some(types) = def.get("type") {
+            let types = match types {
+                Value::Array(types) => types.iter().filter_map(Value::as_str).collect(),
+                types => vec![types.as_str().unwrap()]
+            };
+            let matches = |name: &str| match (name, value) {
+                ("null", Value::Null) | ("boolean", Value::Bool(_)) => true,
+                ("number", Value::Number(_)) | ("string", Value::String(_)) => true,
+                ("array", Value::Array(_)) | ("object", Value::Object(_)) => true,
+                ("integer", Value::Number(n)) => n.fract() == 0.0,
+                _ => false
+            };
+            if !types.into_iter().any(matches) {
+                return Err(format!("{}: {:?} is not of type {:?}", at, value, def.get("type")));
+            }
+        }
+        if let Some(defs) = def.get("allOf").and_then(Value::as_array) {
+            defs.iter().try_for_each(|def| check(schema, def, value, at))?;
+        }
+        if let Some(defs) = def.get("oneOf").and_then(Value::as_array) {
+            let matching = defs.iter().filter(|def| check(schema, def, value, at).is_ok()).count();
+            if matching != 1 {
+                return Err(format!("{}: {} definitions of oneOf match", at, matching));
+            }
+        }
+        if let Value::Object(members) = value {
+            let required = def.get("required").and_then(Value::as_array).unwrap_or(&[]);
+            for key in required.iter().filter_map(Value::as_str) {
+                if value.get(key).is_none() {
+                    return Err(format!("{}: {} is missing", at, key));
+                }
+            }
+            for (key, member) in members {
+                if let Some(def) = def.get("properties").and_then(|p| p.get(key)) {
+                    check(schema, def, member, &format!("{}.{}", at, key))?;
+                }
+            }
+        }
+        if let (Value::Array(items), Some(def)) = (value, def.get("items")) {
+            for (i, item) in items.iter().enumerate() {
+                check(schema, def, item, &format!("{}[{}]", at, i))?;
+            }
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn previous_version_samples_follow_the_schema() {
+        let schema = parse(SCHEMA).unwrap();
+        for (name, sample) in V1_SAMPLES {
+            let document = parse(sample).unwrap();
+            assert_eq!(document.get("schema_version").and_then(Value::as_u64), Some(1));
+            let def = schema.get("$defs").and_then(|defs| defs.get(name)).unwrap();
+            assert_eq!(check(&schema, def, &document, name), Ok(()));
+        }
+    }
+
+    #[test]
+    fn previous_version_samples_match_one_definition() {
+        let schema = parse(SCHEMA).unwrap();
+        // Sidecars and health files are no --json output.
+        let printed = |name: &&str| !["provenance", "health"].contains(name);
+        for (name, sample) in V1_SAMPLES.iter().filter(|(name, _)| printed(name)) {
+            let document = parse(sample).unwrap();
+            assert_eq!(check(&schema, &schema, &document, name), Ok(()), "{}", name);
+        }
+    }
+
+    #[test]
+    fn check_finds_breaking_changes() {
+        let schema = parse(SCHEMA).unwrap();
+        let def = schema.get("$defs").and_then(|defs| defs.get("repair")).unwrap();
+        let renamed = V1_SAMPLES[1].1.replace("\"stored\"", "\"old\"");
+        let error = check(&schema, def, &parse(&renamed).unwrap(), "repair");
+        assert_eq!(error, Err("repair.entries[0]: stored is missing".to_string()));
+        let bumped = V1_SAMPLES[1].1.replace("\"schema_version\":1", "\"schema_version\":2");
+        assert!(check(&schema, def, &parse(&bumped).unwrap(), "repair").is_err());
+    }
+}
@@ -0,0 +1,22 @@
+//! Reading, validating and merging DXVK state caches.
+
+#![deny(clippy::unwrap_used, clippy::expect_used, clippy::indexing_slicing, clippy::panic)]
+
+pub mod bench;
+pub mod compression;
+pub mod dxvk;
+pub mod env;
+pub mod error;
+pub mod format;
+pub mod json;
+pub mod merge;
+pub mod progress;
+pub mod provenance;
+pub mod report;
+pub mod resume;
+pub mod snapshot;
+pub mod template;
+pub mod util;
+pub mod version;
+pub mod warning;
+pub mod watch;
@@ -1,316 +1,3762 @@
-mod dxvk;
-mod error;
+mod table;
+mod terminal;
 
+use std::cell::{Cell, RefCell};
+use std::cmp::Ordering;
+use std::collections::{BTreeMap, HashMap, HashSet};
 use std::env;
-use std::ffi::OsStr;
-use std::fs::File;
-use std::io::{self, prelude::*, BufReader, BufWriter};
-use std::path::PathBuf;
+use std::ffi::{OsStr, OsString};
+use std::fs::{self, File};
+use std::io::{self, prelude::*, BufReader, BufWriter, SeekFrom};
+use std::iter;
+use std::ops::RangeInclusive;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, SystemTime};
 
-use dxvk::*;
-use error::{Error, ErrorKind};
+use dxvk_cache_tool::bench::{self, Workload};
+use dxvk_cache_tool::compression::{self, Compressibility};
+use dxvk_cache_tool::dxvk::*;
+use dxvk_cache_tool::error::{Error, ErrorKind};
+use dxvk_cache_tool::format::{
+    self, Limits, ReadEx, Resync, Retry, WriteEx, DEFAULT_BUFFER_SIZE, DEFAULT_RETRY_DELAY,
+    MAX_ENTRY_DATA
+};
+use dxvk_cache_tool::json;
+use dxvk_cache_tool::merge::{
+    self, validate_entries, ConfigBuilder, ConflictPolicy, DedupKey, EvictPolicy, Event, SortOrder
+};
+use dxvk_cache_tool::provenance::{self, Provenance};
+use dxvk_cache_tool::report::{
+    self, Comparison, MergeReport, PhaseTimings, RepairReport, RepairedEntry
+};
+use dxvk_cache_tool::resume::{self, MergeState};
+use dxvk_cache_tool::snapshot::Snapshot;
+use dxvk_cache_tool::template::{self, OutputTemplate};
+use dxvk_cache_tool::util::{self, FileUse};
+use dxvk_cache_tool::version::VersionInfo;
+use dxvk_cache_tool::warning::{self, Deny, Warning, WarningId};
+use dxvk_cache_tool::watch::{self, PollFiles, SystemClock};
 use linked_hash_map::LinkedHashMap;
+use table::{Align, Table};
+use terminal::ProgressLine;
+
+/// Prints human readable output, which `--json` replaces with a report and
+/// `--quiet` turns off.
+macro_rules! info {
+    ($config:expr, $($arg:tt)*) => {
+        if !$config.json && $config.verbosity >= Verbosity::Normal {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Prints the result of a command, which only `--quiet-unless-error` turns
+/// off.
+macro_rules! output {
+    ($config:expr, $($arg:tt)*) => {
+        if $config.verbosity > Verbosity::Silent {
+            println!($($arg)*);
+        }
+    };
+}
+
+/// Environment variable holding the default for `--output-version`.
+const VERSION_VAR: &str = "DXVK_CACHE_TOOL_VERSION";
+const INDEX_MAGIC_STRING: [u8; 4] = *b"DXIX";
+const INDEX_VERSION: u32 = 1;
+const INDEX_HEADER_SIZE: u64 = 12;
+/// Width file names are cut to in aligned tables.
+const FILE_NAME_WIDTH: usize = 48;
+/// Bytes of data `--print-entry` dumps unless `--max-dump` says otherwise.
+const DEFAULT_MAX_DUMP: usize = 256;
 
 struct Config {
+    command:    Command,
     files:      Vec<PathBuf>,
-    output:     PathBuf,
-    entry_size: u32,
+    prefixes:   Vec<HashPrefix>,
+    print_entry: Option<HashPrefix>,
+    max_dump:   usize,
+    compressibility: bool,
+    exact:      bool,
+    output:     Option<PathBuf>,
+    output_template: Option<OutputTemplate>,
+    metrics:    Option<PathBuf>,
+    tee:        Option<PathBuf>,
+    compare_to: Option<PathBuf>,
+    install_prefix: Option<PathBuf>,
+    resume:     Option<PathBuf>,
+    journal:    Option<PathBuf>,
+    output_mode: Option<OutputMode>,
     version:    u32,
-    edition:    DxvkStateCacheEdition
+    assume_version: Option<u32>,
+    required_versions: Vec<RangeInclusive<u32>>,
+    salvage:    bool,
+    resync:     bool,
+    strict:     bool,
+    skip_mismatched: bool,
+    verbosity:  Verbosity,
+    json:       bool,
+    snapshot:   bool,
+    all_matching: bool,
+    bytes:      bool,
+    dry_run:    bool,
+    verify_only: bool,
+    merge_by_version: bool,
+    allow_empty: bool,
+    drop_partial_tail: bool,
+    revalidate_master: bool,
+    write_meta: bool,
+    strip_to_hashes: bool,
+    deny:       Deny,
+    /// Warnings of the run, except those a merge adds to its report.
+    warnings:   RefCell<Vec<Warning>>,
+    /// All warnings of the run, for the summary.
+    warning_count: Cell<usize>,
+    ignore_space_check: bool,
+    force_overwrite: bool,
+    force_live_replace: bool,
+    no_regression_check: bool,
+    remove_merged_inputs: bool,
+    no_table:   bool,
+    profile:    bool,
+    threads:    usize,
+    buffer_size: usize,
+    retry:      Retry,
+    time_limit: Option<Duration>,
+    max_bytes:  Option<u64>,
+    max_entries: Option<usize>,
+    evict:      EvictPolicy,
+    sort:       SortOrder,
+    conflict_prefer: Option<ConflictPolicy>,
+    dedup_by:   DedupKey,
+    legacy_stages: LegacyStageHandling,
+    stage_mask: Option<u8>,
+    limit_per_file: Option<usize>,
+    min_entry_size: Option<usize>,
+    max_entry_size: Option<usize>,
+    min_sources: Option<usize>,
+    keep_hashes: Option<PathBuf>,
+    drop_hashes: Option<PathBuf>,
+    only_stages: Option<Stages>,
+    group_by:   GroupBy,
+    csv:        bool,
+    workload:   Workload,
+    watch:      watch::Options
+}
+
+impl Config {
+    fn verbose(&self) -> bool {
+        self.verbosity == Verbosity::Verbose
+    }
+
+    /// Prints a warning on stderr, unless printing a JSON report or
+    /// nothing at all, and counts it.
+    fn print_warning(&self, warning: &Warning) {
+        if !self.json && self.verbosity > Verbosity::Silent {
+            eprintln!("Warning: {}", warning);
+        }
+        self.warning_count.set(self.warning_count.get() + 1);
+    }
+
+    /// Adds a warning to the run, failing instead if `--deny` denies it.
+    fn warn(&self, warning: Warning) -> Result<(), Error> {
+        let warning = self.deny.check(warning)?;
+        self.print_warning(&warning);
+        self.warnings.borrow_mut().push(warning);
+        Ok(())
+    }
+
+    /// The warnings of the run so far as a JSON array.
+    fn warnings_json(&self) -> String {
+        warning::to_json(&self.warnings.borrow())
+    }
+
+    /// Formats a size for summaries, exactly with `--verbose`. JSON reports
+    /// always give sizes in bytes.
+    fn size(&self, bytes: u64) -> String {
+        if self.verbose() {
+            util::format_bytes_exact(bytes)
+        } else {
+            util::format_bytes(bytes)
+        }
+    }
+
+    fn table_style(&self) -> table::Style {
+        table::Style::detect(self.no_table)
+    }
+}
+
+/// How much is printed besides errors.
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+enum Verbosity {
+    /// Nothing at all, for cron jobs relying on the exit status.
+    Silent,
+    /// Only the results of commands, such as the report of `info`.
+    Quiet,
+    Normal,
+    Verbose
+}
+
+/// Permissions given to the output, on Unix.
+#[derive(Clone, Copy)]
+#[cfg_attr(not(unix), allow(dead_code))]
+enum OutputMode {
+    Mode(u32),
+    /// The mode of the first input.
+    Preserve
+}
+
+/// Time buckets of `stats-timeline`, by the modification time of the
+/// inputs in UTC, so that the buckets don't depend on the time zone.
+#[derive(Clone, Copy, PartialEq)]
+enum GroupBy {
+    Day,
+    /// ISO 8601 weeks, starting on Monday.
+    Week,
+    Month
+}
+
+impl GroupBy {
+    /// Labels the bucket of `time` as `2020-01-31`, `2020-W05` or `2020-01`,
+    /// so that labels sort like their buckets.
+    fn label(self, time: SystemTime) -> String {
+        let days = util::days_since_epoch(time);
+        match self {
+            GroupBy::Day => {
+                let (year, month, day) = util::civil_from_days(days);
+                format!("{:04}-{:02}-{:02}", year, month, day)
+            },
+            GroupBy::Week => {
+                // The epoch was a Thursday, and a week belongs to the year
+                // of its Thursday.
+                let thursday = days - (days + 3).rem_euclid(7) + 3;
+                let (year, _, _) = util::civil_from_days(thursday);
+                let week = (thursday - util::days_from_civil(year, 1, 1)) / 7 + 1;
+                format!("{:04}-W{:02}", year, week)
+            },
+            GroupBy::Month => {
+                let (year, month, _) = util::civil_from_days(days);
+                format!("{:04}-{:02}", year, month)
+            }
+        }
+    }
+}
+
+enum Action {
+    Run(Box<Config>),
+    PrintHelp,
+    PrintVersion { json: bool },
+    PrintFrog,
+    PrintSchema
+}
+
+/// Outcome of a command that ran without errors. Commands checking
+/// something, like `equal`, fail when it doesn't hold, which `main` turns
+/// into exit status 1 without printing an error.
+#[derive(Clone, Copy, PartialEq)]
+enum Outcome {
+    Passed,
+    Failed
+}
+
+#[derive(Clone, Copy, PartialEq)]
+enum Command {
+    Merge,
+    SplitStages,
+    BuildIndex,
+    HasHash,
+    Canonicalize,
+    Info,
+    Repair,
+    ExplainFormat,
+    Covers,
+    Equal,
+    ImportEntry,
+    Bench,
+    Passthrough,
+    StatsTimeline,
+    Redact,
+    Watch
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Config {
+            command:    Command::Merge,
+            files:      Vec::new(),
+            prefixes:   Vec::new(),
+            print_entry: None,
+            max_dump:   DEFAULT_MAX_DUMP,
+            compressibility: false,
+            exact:      false,
+            output:     None,
+            output_template: None,
+            metrics:    None,
+            tee:        None,
+            compare_to: None,
+            install_prefix: None,
+            resume:     None,
+            journal:    None,
+            output_mode: None,
+            version:    0,
+            assume_version: None,
+            required_versions: Vec::new(),
+            salvage:    false,
+            resync:     false,
+            strict:     false,
+            skip_mismatched: false,
+            verbosity:  Verbosity::Normal,
+            json:       false,
+            snapshot:   false,
+            all_matching: false,
+            bytes:      false,
+            dry_run:    false,
+            verify_only: false,
+            merge_by_version: false,
+            allow_empty: false,
+            drop_partial_tail: false,
+            revalidate_master: false,
+            write_meta: false,
+            strip_to_hashes: false,
+            deny:       Deny::default(),
+            warnings:   RefCell::new(Vec::new()),
+            warning_count: Cell::new(0),
+            ignore_space_check: false,
+            force_overwrite: false,
+            force_live_replace: false,
+            no_regression_check: false,
+            remove_merged_inputs: false,
+            no_table:   false,
+            profile:    false,
+            threads:    thread::available_parallelism().map_or(1, |n| n.get()),
+            buffer_size: DEFAULT_BUFFER_SIZE,
+            retry:      Retry::default(),
+            time_limit: None,
+            max_bytes:  None,
+            max_entries: None,
+            evict:      EvictPolicy::Oldest,
+            sort:       SortOrder::None,
+            conflict_prefer: None,
+            dedup_by:   DedupKey::Hash,
+            legacy_stages: LegacyStageHandling::default(),
+            stage_mask: None,
+            limit_per_file: None,
+            min_entry_size: None,
+            max_entry_size: None,
+            min_sources: None,
+            keep_hashes: None,
+            drop_hashes: None,
+            only_stages: None,
+            group_by:   GroupBy::Day,
+            csv:        false,
+            workload:   Workload::default(),
+            watch:      watch::Options::default()
+        }
+    }
+}
+
+fn print_help() {
+    println!("Standalone dxvk-cache merger");
+    println!("USAGE:\n\tdxvk-cache-tool [OPTION]... <FILEs>...");
+    println!("\tdxvk-cache-tool split-stages <FILE>");
+    println!("\tdxvk-cache-tool build-index <FILE>");
+    println!("\tdxvk-cache-tool has-hash <INDEX> <HASH>");
+    println!("\tdxvk-cache-tool canonicalize <FILE>");
+    println!("\tdxvk-cache-tool info <FILE>");
+    println!("\tdxvk-cache-tool repair [--check] <FILE>");
+    println!("\tdxvk-cache-tool explain-format [VERSION]");
+    println!("\tdxvk-cache-tool covers <CANDIDATE> <EXISTING>");
+    println!("\tdxvk-cache-tool equal [--bytes] <FILE> <FILE>");
+    println!("\tdxvk-cache-tool import-entry [--stage-mask MASK] <FILE> <DUMP>...");
+    println!("\tdxvk-cache-tool bench [--entries N] [--entry-size SIZE] [--seed X]");
+    println!("\tdxvk-cache-tool passthrough [-o OUTPUT] <FILE>");
+    println!("\tdxvk-cache-tool stats-timeline [--group-by mtime:UNIT] [--csv] <DIR>");
+    println!("\tdxvk-cache-tool redact [-o OUTPUT] <FILE> <HASHES>");
+    println!("\tdxvk-cache-tool watch -o MASTER [--interval SECONDS] <FILEs>...\n");
+    println!("COMMANDS:");
+    println!("\tsplit-stages\t\tSplit a cache into FILE.graphics and FILE.compute caches");
+    println!("\tbuild-index\t\tWrite a sorted index of the entry hashes to FILE.idx");
+    println!("\thas-hash\t\tCheck whether an index contains a hash or hash prefix");
+    println!("\tcanonicalize\t\tRewrite a cache without duplicate, invalid or empty entries");
+    println!("\tinfo\t\t\tShow the version and the graphics and compute entries of a cache");
+    println!("\trepair\t\t\tRewrite the hashes of entries failing validation");
+    println!("\texplain-format\t\tDescribe the file layout of a version, by default the newest");
+    println!("\tcovers\t\t\tCheck whether CANDIDATE contains every entry of EXISTING");
+    println!("\tequal\t\t\tCheck whether two caches hold the same entries");
+    println!("\timport-entry\t\tAdd entries dumped to files of their own to a cache");
+    println!("\tbench\t\t\tTime parsing, merging and writing synthetic caches in memory");
+    println!("\tpassthrough\t\tCheck that writing a cache back reproduces it byte for byte");
+    println!("\tstats-timeline\t\tTell what the caches of DIR added over time, by their mtime");
+    println!("\tredact\t\t\tRemove the entries listed in HASHES, leaving the others as they are");
+    println!("\twatch\t\t\tMerge the inputs into MASTER again whenever they change\n");
+    println!("OPTIONS:");
+    println!("\t-o, --output FILE\tSet output file name");
+    println!("\t--output-template T\tName the output after merging by a template with");
+    println!("\t\t\t\t{}", template::PLACEHOLDERS);
+    println!("\t--output-version V\tMerge into version V instead of the first input's version");
+    println!("\t\t\t\tV is a number, latest or a DXVK release like dxvk-2.3");
+    println!("\t--assume-version V\tRead the inputs as version V whatever their headers say");
+    println!("\t--require-version V\tFail unless the caches have version V or one in a range");
+    println!("\t\t\t\tlike 15..=17, given any number of times");
+    println!("\t--require-dxvk X.Y\tSame as --require-version for the version of DXVK X.Y");
+    println!("\t--salvage\t\tRecover the valid entries of corrupt inputs, with --assume-version");
+    println!("\t--resync\t\tSkip to the next valid entry after one with a corrupt header");
+    println!("\t--skip-mismatched\tSkip inputs whose version differs from the output version");
+    println!("\t--fail-on-invalid\tFail instead of omitting entries with invalid hashes");
+    println!("\t--limit-per-file N\tTake at most the N newest entries of each input");
+    println!("\t--min-entry-size SIZE\tSkip the entries with less data than SIZE, e.g. 1K");
+    println!("\t--max-entry-size SIZE\tSkip the entries with more data than SIZE, e.g. 4M");
+    println!("\t--min-sources N\t\tKeep only the entries found in at least N inputs");
+    println!("\t--keep-hashes FILE\tKeep only the entries whose hash FILE lists");
+    println!("\t--drop-hashes FILE\tDrop the entries whose hash FILE lists");
+    println!("\t--only-stages S\t\tKeep only the graphics or compute entries");
+    println!("\t--max-bytes SIZE\tLimit the output file size, e.g. 512M");
+    println!("\t--max-entries N\t\tLimit the number of output entries");
+    println!("\t--evict POLICY\t\tEntries to evict over the limits: oldest (default), largest");
+    println!("\t--trim-policy POLICY\tSame as --evict");
+    println!(
+        "\t--order ORDER\t\tOrder of the output entries: preserve (default), by-input, \
+         by-hash, by-size"
+    );
+    println!("\t--sort ORDER\t\tSame as --order, also taking none and hash");
+    println!("\t--legacy-stages P\tStages of legacy entries: unknown (default), graphics, reject");
+    println!("\t--conflict-prefer P\tInput winning conflicts: newest, oldest, largest-file");
+    println!("\t--dedup-by KEY\t\tKeep entries once by hash (default) or by data");
+    println!("\t--allow-empty\t\tWrite a cache without entries instead of failing");
+    println!("\t--drop-partial-tail\tDrop a last entry still being written, not as invalid");
+    println!("\t--revalidate-master\tValidate the entries of an input that is also the output");
+    println!("\t--write-meta\t\tWrite how the output was made to OUTPUT.meta.json");
+    println!("\t--strip-to-hashes\tSort by hash and name the inputs in OUTPUT.meta.json by hash");
+    println!("\t--snapshot\t\tRead copies of the inputs, for caches in use by a game");
+    println!("\t--check, --dry-run\tReport what a merge or repair would write without writing");
+    println!("\t--merge-by-version\tMerge the inputs into a cache per version in the -o directory");
+    println!("\t--verify-only\t\tCheck that the inputs merge cleanly without writing");
+    println!("\t--print-entry HASH\tDump the entry of info with the hash or prefix HASH");
+    println!("\t--max-dump SIZE\t\tBytes of data --print-entry dumps, 256 by default, 0 for all");
+    println!("\t--compressibility\tEstimate how well the entries of info compress");
+    println!("\t--exact\t\t\tCompress every entry with --compressibility instead of a sample");
+    println!("\t--all-matching\t\tUse every entry matching an ambiguous hash prefix");
+    println!("\t--bytes\t\t\tCompare the files byte by byte with equal");
+    println!("\t--stage-mask MASK\tStage mask of the entries imported by import-entry, e.g. 0x11");
+    println!("\t--entries N\t\tEntries of the caches of bench, 32768 by default");
+    println!("\t--entry-size SIZE\tData size of the entries of bench, 1K by default");
+    println!("\t--seed X\t\tSeed of the entry data of bench, 0 by default");
+    println!("\t--group-by mtime:UNIT\tGroup stats-timeline by UTC day (default), week or month");
+    println!("\t--csv\t\t\tPrint the table of stats-timeline as CSV");
+    println!("\t--time-limit SECONDS\tStop merging after SECONDS, writing what is merged so far");
+    println!("\t--resume STATEFILE\tContinue a merge stopped by --time-limit");
+    println!("\t--journal FILE\t\tRecord the progress of a merge in FILE to resume it if");
+    println!("\t\t\t\tinterrupted");
+    println!("\t--interval SECONDS\tCheck the inputs of watch for changes every SECONDS, 10 by");
+    println!("\t\t\t\tdefault");
+    println!("\t--max-runtime SECONDS\tStop watching after SECONDS");
+    println!("\t--health-file FILE\tWrite the state of watch to FILE as JSON after every check");
+    println!("\t--retry N[,DELAY_MS]\tRetry transient I/O errors N times, after 500 ms by default");
+    println!("\t-j, --threads N\t\tNumber of threads validating entries");
+    println!("\t--io-buffer KB\t\tSize of the read and write buffers, 256 by default");
+    println!("\t--profile\t\tPrint the time each phase of a merge takes on stderr");
+    println!("\t-v, --verbose\t\tPrint additional details");
+    println!("\t-q, --quiet\t\tOnly print the results of commands, warnings and errors");
+    println!("\t--quiet-unless-error\tPrint nothing but errors, for cron jobs");
+    println!("\t--force-overwrite\tReplace an output with more entries than the merge");
+    println!("\t--force-live-replace\tReplace a cache in DXVK's place even if a game has it open");
+    println!("\t--no-regression-check\tDon't count the entries of the output being replaced");
+    println!("\t--ignore-space-check\tWrite the output even if it may not fit on the disk");
+    println!("\t--json\t\t\tPrint a JSON report, and errors as JSON objects on stderr");
+    println!("\t--deny W[,W...]\t\tFail on the warnings W, such as W_INVALID_ENTRIES, or all");
+    println!("\t--no-table\t\tPrint tables tab separated even on a terminal");
+    println!("\t--output-mode OCTAL\tSet the permissions of the output, on Unix");
+    println!("\t--preserve-mode\t\tGive the output the permissions of the first input, on Unix");
+    println!("\t--tee FILE\t\tList the hashes of the entries written to FILE, - for stderr");
+    println!("\t--compare-to FILE\tCompare the merged entries with FILE, failing if they differ");
+    println!("\t--install-into-prefix PATH");
+    println!("\t\t\t\tWrite the output into the DXVK_state_cache directory of PATH");
+    println!("\t--remove-merged-inputs\tRemove the inputs fully merged into the output");
+    println!("\t--metrics FILE\t\tWrite the merge outcome as Prometheus metrics to FILE");
+    println!("\t--json-schema\t\tPrint the JSON Schema of the --json output and exit");
+    println!("\t-h, --help\t\tDisplay this help and exit");
+    println!("\t-V, --version\t\tOutput version information and exit, as JSON with --json\n");
+    println!("ENVIRONMENT:");
+    println!("\t{}\tDefault for --output-version", VERSION_VAR);
+    println!("\tDXVK_STATE_CACHE_PATH\tDirectory of the merge output when no -o is given");
+    println!("\tDXVK_STATE_CACHE\tWarns when set to 0, as DXVK then ignores caches");
+}
+
+fn parse_size(s: &str) -> Result<u64, Error> {
+    let (digits, multiplier) = match s.char_indices().find(|(_, c)| !c.is_ascii_digit()) {
+        Some((i, _)) => {
+            let multiplier = match s[i..].to_ascii_uppercase().as_ref() {
+                "K" | "KB" | "KIB" => 1 << 10,
+                "M" | "MB" | "MIB" => 1 << 20,
+                "G" | "GB" | "GIB" => 1 << 30,
+                _ => 0
+            };
+            (&s[..i], multiplier)
+        },
+        None => (s, 1)
+    };
+    match digits.parse::<u64>() {
+        Ok(n) if multiplier > 0 => n.checked_mul(multiplier).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("Size is too large: {}", s))
+        }),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid size: {}", s)))
+    }
+}
+
+/// The error for giving both `--output-mode` and `--preserve-mode`.
+fn conflicting_modes() -> Error {
+    Error::new(ErrorKind::InvalidInput, "--output-mode can't be combined with --preserve-mode")
+}
+
+/// Parses `N[,DELAY_MS]` for `--retry`.
+fn parse_retry(s: &str) -> Result<Retry, Error> {
+    let invalid = || Error::new(ErrorKind::InvalidInput, format!("Invalid retry: {}", s));
+    let (attempts, delay) = match s.split_once(',') {
+        Some((attempts, delay)) => {
+            (attempts, Duration::from_millis(delay.parse().map_err(|_| invalid())?))
+        },
+        None => (s, DEFAULT_RETRY_DELAY)
+    };
+    Ok(Retry { attempts: attempts.parse().map_err(|_| invalid())?, delay })
+}
+
+/// Parses a stage mask such as `0x11` or `17`.
+fn parse_stage_mask(s: &str) -> Result<u8, Error> {
+    let mask = match s.strip_prefix("0x").or_else(|| s.strip_prefix("0X")) {
+        Some(hex) => u8::from_str_radix(hex, 16),
+        None => s.parse()
+    };
+    match mask {
+        Ok(mask) if mask > 0 => Ok(mask),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid stage mask: {}", s)))
+    }
+}
+
+/// Parses an order of `--order`, or of `--sort` by its older names.
+fn parse_sort_order(s: &str) -> Result<SortOrder, Error> {
+    match s {
+        "preserve" | "none" => Ok(SortOrder::None),
+        "by-input" => Ok(SortOrder::Input),
+        "by-hash" | "hash" => Ok(SortOrder::Hash),
+        "by-size" => Ok(SortOrder::Size),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Unknown sort order: {}", s)))
+    }
+}
+
+/// The transformation recorded in provenance for sorting by `order`.
+fn sort_transformation(order: SortOrder) -> Option<&'static str> {
+    match order {
+        SortOrder::None => None,
+        SortOrder::Input => Some(provenance::SORT_BY_INPUT),
+        SortOrder::Hash => Some(provenance::SORT_BY_HASH),
+        SortOrder::Size => Some(provenance::SORT_BY_SIZE)
+    }
+}
+
+/// Parses a version as a number like "v15", "latest" or a DXVK release
+/// like "dxvk-2.3".
+fn parse_version(s: &str) -> Result<u32, Error> {
+    if s.eq_ignore_ascii_case("latest") {
+        return Ok(CURRENT_VERSION);
+    }
+    let prefix = s.get(..5).filter(|p| p.eq_ignore_ascii_case("dxvk-"));
+    if let (Some(_), Some(release)) = (prefix, s.get(5..)) {
+        return release_version(release).ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, format!("Unknown DXVK release: {}", release))
+        });
+    }
+    match s.strip_prefix('v').unwrap_or(s).parse() {
+        Ok(version) if version > 0 => Ok(version),
+        _ => Err(Error::new(ErrorKind::InvalidInput, format!("Invalid version: {}", s)))
+    }
+}
+
+/// Parses a version or a range of versions, like `15..=17` or `15..18`,
+/// for `--require-version`.
+fn parse_version_range(s: &str) -> Result<RangeInclusive<u32>, Error> {
+    let range = if let Some((start, end)) = s.split_once("..=") {
+        parse_version(start)?..=parse_version(end)?
+    } else if let Some((start, end)) = s.split_once("..") {
+        parse_version(start)?..=parse_version(end)?.saturating_sub(1)
+    } else {
+        let version = parse_version(s)?;
+        version..=version
+    };
+    if range.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, format!("Empty version range: {}", s)));
+    }
+    Ok(range)
+}
+
+/// Returns the value following the option at `args[i]`.
+fn option_value<'a>(args: &'a [OsString], i: usize, option: &str) -> Result<&'a OsStr, Error> {
+    args.get(i + 1).map(OsString::as_os_str).ok_or_else(|| {
+        Error::new(ErrorKind::InvalidInput, format!("Option {} requires a value", option))
+    })
+}
+
+fn utf8(s: &OsStr) -> Result<&str, Error> {
+    s.to_str().ok_or_else(|| {
+        Error::new(ErrorKind::InvalidInput, format!("Invalid UTF-8 in {}", s.to_string_lossy()))
+    })
 }
 
-impl Default for Config {
-    fn default() -> Self {
-        Config {
-            files:      Vec::new(),
-            output:     PathBuf::from("output.dxvk-cache"),
-            entry_size: 0,
-            version:    0,
-            edition:    DxvkStateCacheEdition::Standard
+fn process_args(mut args: Vec<OsString>) -> Result<Action, Error> {
+    let mut config = Config::default();
+    for (i, arg) in args.clone().iter().enumerate().rev() {
+        let arg = arg.to_str().unwrap_or_default();
+        match arg {
+            "-h" | "--help" => return Ok(Action::PrintHelp),
+            "-o" | "--output" => {
+                let output = util::expand_path(option_value(&args, i, arg)?);
+                // Arguments are processed last to first, so a later output
+                // is already set.
+                if let Some(later) = config.output.as_ref().filter(|later| **later != output) {
+                    return Err(Error::new(
+                        ErrorKind::InvalidInput,
+                        format!(
+                            "Conflicting outputs {} and {}, give -o once",
+                            output.display(),
+                            later.display()
+                        )
+                    ));
+                }
+                config.output = Some(output);
+                args.drain(i..=i + 1);
+            },
+            "--output-template" => {
+                config.output_template =
+                    Some(OutputTemplate::parse(utf8(option_value(&args, i, arg)?)?)?);
+                args.drain(i..=i + 1);
+            },
+            "--output-mode" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                let mode = match u32::from_str_radix(value, 8) {
+                    Ok(mode) if mode <= 0o7777 => mode,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid mode: {}", value)
+                        ))
+                    },
+                };
+                if let Some(OutputMode::Preserve) = config.output_mode {
+                    return Err(conflicting_modes());
+                }
+                config.output_mode = Some(OutputMode::Mode(mode));
+                args.drain(i..=i + 1);
+            },
+            "--preserve-mode" => {
+                if let Some(OutputMode::Mode(_)) = config.output_mode {
+                    return Err(conflicting_modes());
+                }
+                config.output_mode = Some(OutputMode::Preserve);
+                args.remove(i);
+            },
+            "--tee" => {
+                config.tee = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--install-into-prefix" => {
+                config.install_prefix = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--remove-merged-inputs" => {
+                config.remove_merged_inputs = true;
+                args.remove(i);
+            },
+            "--compare-to" => {
+                config.compare_to = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--metrics" => {
+                config.metrics = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--output-version" => {
+                config.version = parse_version(utf8(option_value(&args, i, arg)?)?)?;
+                args.drain(i..=i + 1);
+            },
+            "--assume-version" => {
+                config.assume_version = Some(parse_version(utf8(option_value(&args, i, arg)?)?)?);
+                args.drain(i..=i + 1);
+            },
+            "--require-version" => {
+                let range = parse_version_range(utf8(option_value(&args, i, arg)?)?)?;
+                config.required_versions.push(range);
+                args.drain(i..=i + 1);
+            },
+            "--require-dxvk" => {
+                let release = utf8(option_value(&args, i, arg)?)?;
+                let version = release_version(release).ok_or_else(|| {
+                    let message = format!("Unknown DXVK release: {}", release);
+                    Error::new(ErrorKind::InvalidInput, message)
+                })?;
+                config.required_versions.push(version..=version);
+                args.drain(i..=i + 1);
+            },
+            "--max-bytes" => {
+                config.max_bytes = Some(parse_size(utf8(option_value(&args, i, arg)?)?)?);
+                args.drain(i..=i + 1);
+            },
+            "--max-entries" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.max_entries = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid entry limit: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--evict" | "--trim-policy" => {
+                config.evict = match utf8(option_value(&args, i, arg)?)? {
+                    "oldest" => EvictPolicy::Oldest,
+                    "largest" => EvictPolicy::Largest,
+                    p => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown eviction policy: {}", p)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--dedup-by" => {
+                config.dedup_by = match utf8(option_value(&args, i, arg)?)? {
+                    "hash" => DedupKey::Hash,
+                    "data" => DedupKey::Data,
+                    k => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown dedup key: {}", k)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--legacy-stages" => {
+                config.legacy_stages = match utf8(option_value(&args, i, arg)?)? {
+                    "reject" => LegacyStageHandling::Reject,
+                    "graphics" => LegacyStageHandling::TreatAsGraphics,
+                    "unknown" => LegacyStageHandling::TreatAsUnknown,
+                    p => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown legacy stage handling: {}", p)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--stage-mask" => {
+                config.stage_mask = Some(parse_stage_mask(utf8(option_value(&args, i, arg)?)?)?);
+                args.drain(i..=i + 1);
+            },
+            "--entries" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.workload.entries = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid number of entries: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--entry-size" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.workload.entry_size = match parse_size(value)? {
+                    n if n > 0 && n <= MAX_ENTRY_DATA as u64 => n as usize,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "Invalid entry size: {}, expected 1 to {} bytes",
+                                value, MAX_ENTRY_DATA
+                            )
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--seed" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.workload.seed = value.parse().map_err(|_| {
+                    Error::new(ErrorKind::InvalidInput, format!("Invalid seed: {}", value))
+                })?;
+                args.drain(i..=i + 1);
+            },
+            "--group-by" => {
+                config.group_by = match utf8(option_value(&args, i, arg)?)? {
+                    "mtime:day" => GroupBy::Day,
+                    "mtime:week" => GroupBy::Week,
+                    "mtime:month" => GroupBy::Month,
+                    g => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!(
+                                "Unknown grouping: {}, expected mtime:day, mtime:week or \
+                                 mtime:month",
+                                g
+                            )
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--order" | "--sort" => {
+                config.sort = parse_sort_order(utf8(option_value(&args, i, arg)?)?)?;
+                args.drain(i..=i + 1);
+            },
+            "--conflict-prefer" => {
+                config.conflict_prefer = match utf8(option_value(&args, i, arg)?)? {
+                    "newest" => Some(ConflictPolicy::Newest),
+                    "oldest" => Some(ConflictPolicy::Oldest),
+                    "largest-file" => Some(ConflictPolicy::LargestFile),
+                    p => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown conflict policy: {}", p)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--limit-per-file" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.limit_per_file = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid entry limit per file: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--min-entry-size" | "--max-entry-size" => {
+                let size = parse_size(utf8(option_value(&args, i, arg)?)?)?;
+                let size = size.min(usize::MAX as u64) as usize;
+                if arg == "--min-entry-size" {
+                    config.min_entry_size = Some(size);
+                } else {
+                    config.max_entry_size = Some(size);
+                }
+                args.drain(i..=i + 1);
+            },
+            "--min-sources" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.min_sources = match value.parse() {
+                    Ok(n) if n > 0 => Some(n),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid number of sources: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--keep-hashes" => {
+                config.keep_hashes = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--drop-hashes" => {
+                config.drop_hashes = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--only-stages" => {
+                config.only_stages = match utf8(option_value(&args, i, arg)?)? {
+                    "graphics" => Some(Stages::Graphics),
+                    "compute" => Some(Stages::Compute),
+                    s => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown stages: {}", s)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--io-buffer" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                let size = value.parse::<usize>().ok().and_then(|n| n.checked_mul(1024));
+                config.buffer_size = match size {
+                    Some(n) if n > 0 => n,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid buffer size: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--time-limit" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.time_limit = match value.parse() {
+                    Ok(seconds) if seconds > 0 => Some(Duration::from_secs(seconds)),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid time limit: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--resume" => {
+                config.resume = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--journal" => {
+                config.journal = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--interval" | "--max-runtime" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                let seconds = match value.parse() {
+                    Ok(seconds) if seconds > 0 => Duration::from_secs(seconds),
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid number of seconds for {}: {}", arg, value)
+                        ))
+                    },
+                };
+                match arg {
+                    "--interval" => config.watch.interval = seconds,
+                    _ => config.watch.max_runtime = Some(seconds)
+                }
+                args.drain(i..=i + 1);
+            },
+            "--health-file" => {
+                config.watch.health_file = Some(util::expand_path(option_value(&args, i, arg)?));
+                args.drain(i..=i + 1);
+            },
+            "--retry" => {
+                config.retry = parse_retry(utf8(option_value(&args, i, arg)?)?)?;
+                args.drain(i..=i + 1);
+            },
+            "-j" | "--threads" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                config.threads = match value.parse() {
+                    Ok(n) if n > 0 => n,
+                    _ => {
+                        return Err(Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Invalid number of threads: {}", value)
+                        ))
+                    },
+                };
+                args.drain(i..=i + 1);
+            },
+            "--salvage" => {
+                config.salvage = true;
+                args.remove(i);
+            },
+            "--resync" => {
+                config.resync = true;
+                args.remove(i);
+            },
+            "--skip-mismatched" => {
+                config.skip_mismatched = true;
+                args.remove(i);
+            },
+            "--fail-on-invalid" => {
+                config.strict = true;
+                args.remove(i);
+            },
+            // The quieter of several options wins, whatever their order.
+            "-v" | "--verbose" => {
+                if config.verbosity == Verbosity::Normal {
+                    config.verbosity = Verbosity::Verbose;
+                }
+                args.remove(i);
+            },
+            "-q" | "--quiet" => {
+                config.verbosity = config.verbosity.min(Verbosity::Quiet);
+                args.remove(i);
+            },
+            "--quiet-unless-error" => {
+                config.verbosity = Verbosity::Silent;
+                args.remove(i);
+            },
+            "--ignore-space-check" => {
+                config.ignore_space_check = true;
+                args.remove(i);
+            },
+            "--force-overwrite" => {
+                config.force_overwrite = true;
+                args.remove(i);
+            },
+            "--force-live-replace" => {
+                config.force_live_replace = true;
+                args.remove(i);
+            },
+            "--no-regression-check" => {
+                config.no_regression_check = true;
+                args.remove(i);
+            },
+            "--check" | "--dry-run" => {
+                config.dry_run = true;
+                args.remove(i);
+            },
+            "--verify-only" => {
+                config.verify_only = true;
+                args.remove(i);
+            },
+            "--merge-by-version" => {
+                config.merge_by_version = true;
+                args.remove(i);
+            },
+            "--allow-empty" => {
+                config.allow_empty = true;
+                args.remove(i);
+            },
+            "--deny" => {
+                let value = utf8(option_value(&args, i, arg)?)?;
+                for code in value.split(',') {
+                    if code.eq_ignore_ascii_case("all") {
+                        config.deny = Deny::all();
+                        continue;
+                    }
+                    let id = WarningId::from_code(code).ok_or_else(|| {
+                        let codes: Vec<_> = WarningId::ALL.iter().map(|id| id.code()).collect();
+                        let codes = codes.join(", ");
+                        Error::new(
+                            ErrorKind::InvalidInput,
+                            format!("Unknown warning: {}, expected all or {}", code, codes)
+                        )
+                    })?;
+                    config.deny.add(id);
+                }
+                args.drain(i..=i + 1);
+            },
+            "--drop-partial-tail" => {
+                config.drop_partial_tail = true;
+                args.remove(i);
+            },
+            "--revalidate-master" => {
+                config.revalidate_master = true;
+                args.remove(i);
+            },
+            "--write-meta" => {
+                config.write_meta = true;
+                args.remove(i);
+            },
+            "--strip-to-hashes" => {
+                config.strip_to_hashes = true;
+                args.remove(i);
+            },
+            "--print-entry" => {
+                config.print_entry = Some(parse_hash_prefix(utf8(option_value(&args, i, arg)?)?)?);
+                args.drain(i..=i + 1);
+            },
+            "--max-dump" => {
+                let size = parse_size(utf8(option_value(&args, i, arg)?)?)?;
+                config.max_dump = size.min(usize::MAX as u64) as usize;
+                args.drain(i..=i + 1);
+            },
+            "--compressibility" => {
+                config.compressibility = true;
+                args.remove(i);
+            },
+            "--exact" => {
+                config.exact = true;
+                args.remove(i);
+            },
+            "--all-matching" => {
+                config.all_matching = true;
+                args.remove(i);
+            },
+            "--bytes" => {
+                config.bytes = true;
+                args.remove(i);
+            },
+            "--csv" => {
+                config.csv = true;
+                args.remove(i);
+            },
+            "--no-table" => {
+                config.no_table = true;
+                args.remove(i);
+            },
+            "--profile" => {
+                config.profile = true;
+                args.remove(i);
+            },
+            "--snapshot" => {
+                config.snapshot = true;
+                args.remove(i);
+            },
+            "--json" => {
+                config.json = true;
+                args.remove(i);
+            },
+            "-V" | "--version" => {
+                let json = config.json || args.iter().any(|arg| arg == "--json");
+                return Ok(Action::PrintVersion { json });
+            },
+            "--frog" => return Ok(Action::PrintFrog),
+            "--json-schema" => return Ok(Action::PrintSchema),
+            _ => ()
+        }
+    }
+    if config.version == 0 {
+        if let Ok(version) = env::var(VERSION_VAR) {
+            config.version = parse_version(&version).map_err(|_| {
+                Error::new(
+                    ErrorKind::InvalidInput,
+                    format!("Invalid version in {}: {}", VERSION_VAR, version)
+                )
+            })?;
+        }
+    }
+    if args.len() <= 1 {
+        return Ok(Action::PrintHelp);
+    }
+    args.remove(0);
+    let command = match args.first().and_then(|a| a.to_str()).unwrap_or_default() {
+        "split-stages" => Some((Command::SplitStages, 1..=1)),
+        "build-index" => Some((Command::BuildIndex, 1..=1)),
+        "has-hash" => Some((Command::HasHash, 2..=2)),
+        "canonicalize" => Some((Command::Canonicalize, 1..=1)),
+        "info" => Some((Command::Info, 1..=1)),
+        "repair" => Some((Command::Repair, 1..=1)),
+        "covers" => Some((Command::Covers, 2..=2)),
+        "equal" => Some((Command::Equal, 2..=2)),
+        "import-entry" => Some((Command::ImportEntry, 2..=usize::MAX)),
+        "explain-format" => Some((Command::ExplainFormat, 0..=1)),
+        "bench" => Some((Command::Bench, 0..=0)),
+        "passthrough" => Some((Command::Passthrough, 1..=1)),
+        "stats-timeline" => Some((Command::StatsTimeline, 1..=1)),
+        "redact" => Some((Command::Redact, 2..=2)),
+        "watch" => Some((Command::Watch, 1..=usize::MAX)),
+        _ => None
+    };
+    if let Some((command, count)) = command {
+        let name = args.remove(0);
+        if !count.contains(&args.len()) {
+            let expected = if count.start() == count.end() {
+                count.start().to_string()
+            } else if *count.end() == usize::MAX {
+                format!("at least {}", count.start())
+            } else {
+                format!("{} to {}", count.start(), count.end())
+            };
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!(
+                    "{} expects {} arguments, found {}",
+                    name.to_string_lossy(),
+                    expected,
+                    args.len()
+                )
+            ));
+        }
+        if command == Command::ExplainFormat {
+            if let Some(version) = args.pop() {
+                config.version = parse_version(utf8(&version)?)?;
+            }
+        }
+        if command == Command::HasHash {
+            if let Some(hash) = args.pop() {
+                config.prefixes.push(parse_hash_prefix(utf8(&hash)?)?);
+            }
+        }
+        config.command = command;
+    }
+    if config.salvage && config.assume_version.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--salvage requires --assume-version, as the header can't be trusted"
+        ));
+    }
+    if config.salvage && config.resync {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--resync can't be combined with --salvage, which scans the whole input anyway"
+        ));
+    }
+    if let Some(template) = &config.output_template {
+        if config.output.is_some() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--output-template can't be combined with -o"
+            ));
+        }
+        if config.merge_by_version && !template.has_version() {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "--output-template needs {version} with --merge-by-version"
+            ));
+        }
+    } else if config.merge_by_version && config.output.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--merge-by-version requires -o DIR or --output-template"
+        ));
+    }
+    if config.merge_by_version && config.tee.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--tee lists a single output and can't be combined with --merge-by-version"
+        ));
+    }
+    if config.merge_by_version && config.compare_to.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--compare-to compares a single output and can't be combined with --merge-by-version"
+        ));
+    }
+    if config.json && config.print_entry.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--print-entry prints a hex dump and can't be combined with --json"
+        ));
+    }
+    if config.exact && !config.compressibility {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--exact only applies to --compressibility"
+        ));
+    }
+    if config.json && config.verbosity == Verbosity::Silent {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--quiet-unless-error prints no report and can't be combined with --json"
+        ));
+    }
+    if config.remove_merged_inputs && config.install_prefix.is_none() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--remove-merged-inputs requires --install-into-prefix"
+        ));
+    }
+    if config.install_prefix.is_some() {
+        let conflict = if config.merge_by_version {
+            Some("--merge-by-version")
+        } else if config.output_template.is_some() {
+            Some("--output-template")
+        } else if config.time_limit.is_some() {
+            Some("--time-limit")
+        } else if config.resume.is_some() {
+            Some("--resume")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("--install-into-prefix can't be combined with {}", conflict)
+            ));
+        }
+        if config.output.as_ref().and_then(|o| o.parent()).is_some_and(|p| p != Path::new("")) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                "With --install-into-prefix, -o only names the installed file"
+            ));
+        }
+    }
+    if config.journal.is_some() && (config.time_limit.is_some() || config.resume.is_some()) {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--journal can't be combined with --time-limit or --resume"
+        ));
+    }
+    if config.time_limit.is_some() || config.resume.is_some() || config.journal.is_some() {
+        // Resuming merges the previous output first, as if it were an input,
+        // which these would treat differently from its original inputs.
+        let conflict = if config.merge_by_version {
+            Some("--merge-by-version")
+        } else if config.output_template.is_some() {
+            Some("--output-template")
+        } else if config.conflict_prefer.is_some() {
+            Some("--conflict-prefer")
+        } else if config.limit_per_file.is_some() {
+            Some("--limit-per-file")
+        } else if config.min_sources.is_some() {
+            Some("--min-sources")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("--time-limit, --resume and --journal can't be combined with {}", conflict)
+            ));
+        }
+    }
+    if config.command == Command::Watch {
+        if config.output.is_none() {
+            return Err(Error::new(ErrorKind::InvalidInput, "watch requires -o MASTER"));
+        }
+        // Each merge replaces the master, which the next one merges again.
+        let conflict = if config.merge_by_version {
+            Some("--merge-by-version")
+        } else if config.output_template.is_some() {
+            Some("--output-template")
+        } else if config.install_prefix.is_some() {
+            Some("--install-into-prefix")
+        } else if config.time_limit.is_some() || config.resume.is_some() {
+            Some("--time-limit and --resume")
+        } else if config.journal.is_some() {
+            Some("--journal")
+        } else if config.dry_run || config.verify_only {
+            Some("--dry-run and --verify-only")
+        } else if config.compare_to.is_some() {
+            Some("--compare-to")
+        } else {
+            None
+        };
+        if let Some(conflict) = conflict {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("watch can't be combined with {}", conflict)
+            ));
+        }
+    } else if config.watch.max_runtime.is_some() || config.watch.health_file.is_some() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            "--max-runtime and --health-file only apply to watch"
+        ));
+    }
+    if config.strip_to_hashes {
+        // Merge order tells the order pipelines were compiled in.
+        config.sort = SortOrder::Hash;
+        config.write_meta = true;
+    }
+    for arg in args {
+        config.files.push(util::expand_path(&arg));
+    }
+    Ok(Action::Run(Box::new(config)))
+}
+
+fn main() {
+    terminal::reset_sigpipe();
+    let json = env::args_os().any(|arg| arg == "--json");
+    match run() {
+        Ok(Outcome::Passed) => (),
+        Ok(Outcome::Failed) => std::process::exit(1),
+        Err(e) => {
+            if json {
+                eprintln!("{}", e.to_json());
+            } else {
+                eprintln!("Error: {}", e);
+                if e.kind() == ErrorKind::IoError(io::ErrorKind::NotFound) {
+                    let hint = e.path().and_then(dxvk_cache_tool::env::missing_cache_hint);
+                    if let Some(hint) = hint {
+                        eprintln!("Hint: {}", hint);
+                    }
+                }
+            }
+            std::process::exit(if e.kind() == ErrorKind::InvalidInput { 2 } else { 1 });
+        }
+    }
+}
+
+fn run() -> Result<Outcome, Error> {
+    let config = match process_args(env::args_os().collect())? {
+        Action::Run(config) => *config,
+        Action::PrintHelp => {
+            print_help();
+            return Ok(Outcome::Passed);
+        },
+        Action::PrintVersion { json } => {
+            let info = VersionInfo::current();
+            if json {
+                println!("{}", info.to_json());
+            } else {
+                println!("{}", info);
+            }
+            return Ok(Outcome::Passed);
+        },
+        Action::PrintSchema => {
+            print!("{}", json::SCHEMA);
+            return Ok(Outcome::Passed);
+        },
+        Action::PrintFrog => {
+            println!("🐸");
+            return Ok(Outcome::Passed);
+        }
+    };
+    let writes_cache = match config.command {
+        Command::Merge => !config.dry_run && !config.verify_only,
+        Command::Watch => true,
+        Command::Repair => !config.dry_run,
+        Command::SplitStages | Command::Canonicalize | Command::ImportEntry | Command::Redact => {
+            true
+        },
+        _ => false
+    };
+    if writes_cache && dxvk_cache_tool::env::state_cache_disabled() {
+        config.warn(Warning::new(
+            WarningId::StateCacheDisabled,
+            format!(
+                "{}=0 is set, so DXVK started from this environment won't use the caches written",
+                dxvk_cache_tool::env::STATE_CACHE_VAR
+            )
+        ))?;
+    }
+    check_required_versions(&config)?;
+    let passed = |result: Result<(), Error>| result.map(|()| Outcome::Passed);
+    let result = match config.command {
+        Command::Merge => merge(&config),
+        Command::SplitStages => passed(split_stages(&config)),
+        Command::BuildIndex => passed(build_index(&config)),
+        Command::HasHash => has_hash(&config),
+        Command::Canonicalize => passed(canonicalize(&config)),
+        Command::Info => passed(info(&config)),
+        Command::Repair => passed(repair(&config)),
+        Command::ExplainFormat => passed(explain_format(&config)),
+        Command::Covers => covers(&config),
+        Command::Equal => equal(&config),
+        Command::ImportEntry => passed(import_entry(&config)),
+        Command::Bench => passed(bench(&config)),
+        Command::Passthrough => passthrough(&config),
+        Command::StatsTimeline => passed(stats_timeline(&config)),
+        Command::Redact => passed(redact(&config)),
+        Command::Watch => passed(watch(&config))
+    };
+    let count = config.warning_count.get();
+    if result.is_ok() && count > 0 && !config.json && config.verbosity > Verbosity::Silent {
+        eprintln!("Finished with {} warnings", count);
+    }
+    result
+}
+
+/// Fails with `ErrorKind::VersionMismatch` unless the caches a command
+/// works on have a version allowed by `--require-version` and
+/// `--require-dxvk`, before anything is parsed beyond the headers. With an
+/// output version, only that is checked, as inputs of other versions are
+/// either skipped or fail the merge anyway. Otherwise every input is checked.
+fn check_required_versions(config: &Config) -> Result<(), Error> {
+    if config.required_versions.is_empty() {
+        return Ok(());
+    }
+    let inputs = match config.command {
+        Command::Merge
+        | Command::Watch
+        | Command::SplitStages
+        | Command::BuildIndex
+        | Command::Canonicalize
+        | Command::Info
+        | Command::Repair
+        | Command::Covers
+        | Command::Equal
+        | Command::Passthrough => &config.files[..],
+        // The other files are entry dumps and hash lists.
+        Command::ImportEntry | Command::Redact => config.files.get(..1).unwrap_or_default(),
+        Command::HasHash | Command::ExplainFormat | Command::Bench | Command::StatsTimeline => {
+            return Ok(())
+        }
+    };
+    let check = |version: u32, path: Option<&Path>| {
+        if config.required_versions.iter().any(|range| range.contains(&version)) {
+            return Ok(());
+        }
+        // The nearest of the versions allowed.
+        let expected = config
+            .required_versions
+            .iter()
+            .map(|range| version.clamp(*range.start(), *range.end()))
+            .min_by_key(|allowed| allowed.abs_diff(version))
+            .unwrap_or(version);
+        let allowed: Vec<String> = config
+            .required_versions
+            .iter()
+            .map(|range| {
+                if range.start() == range.end() {
+                    format!("v{}", range.start())
+                } else {
+                    format!("v{} to v{}", range.start(), range.end())
+                }
+            })
+            .collect();
+        let message = format!(
+            "State cache version v{} is not allowed, expected {}",
+            version,
+            allowed.join(" or ")
+        );
+        let error = Error::new(ErrorKind::VersionMismatch { expected, found: version }, message);
+        Err(match path {
+            Some(path) => error.with_path(path),
+            None => error
+        })
+    };
+    if config.command == Command::Merge && config.version != 0 {
+        return check(config.version, None);
+    }
+    for path in inputs {
+        let version = match config.assume_version {
+            Some(version) => version,
+            None => DxvkStateCacheHeader::peek(path)?.version
+        };
+        check(version, Some(path))?;
+    }
+    Ok(())
+}
+
+fn merge(config: &Config) -> Result<Outcome, Error> {
+    if config.merge_by_version {
+        return merge_by_version(config);
+    }
+    let mut files = config.files.clone();
+    let mut output = config.output.clone();
+    let mut previous = None;
+    let mut stamps = HashMap::new();
+    if let Some(prefix) = &config.install_prefix {
+        let target = install_target(config, prefix)?;
+        check_live_replace(config, &target)?;
+        back_up(config, &target)?;
+        if config.remove_merged_inputs {
+            stamps = files.iter().filter_map(|f| Some((f.clone(), file_stamp(f)?))).collect();
+        }
+        output = Some(target);
+    }
+    if output.is_none() && config.output_template.is_none() {
+        output = state_cache_output(config);
+        if let Some(output) = &output {
+            check_live_replace(config, output)?;
+        }
+    }
+    if let Some(path) = &config.resume {
+        let state = resume_state(config, path)?;
+        output = Some(state.output().to_path_buf());
+        if let Some(changed) = state.changed() {
+            config.warn(
+                Warning::new(
+                    WarningId::InputChanged,
+                    "Changed since the merge stopped, merging all inputs again"
+                )
+                .with_path(changed)
+            )?;
+        } else {
+            info!(
+                config,
+                "Resuming the merge into {}, which has {} inputs merged",
+                util::file_name(state.output()),
+                state.inputs().count()
+            );
+            let pending = config.files.iter().filter(|path| !state.contains(path)).cloned();
+            files = iter::once(state.output().to_path_buf()).chain(pending).collect();
+            previous = Some(state);
+        }
+    }
+    let report = merge_files(config, &files, output.as_deref(), config.version)?;
+    if config.time_limit.is_some() || config.resume.is_some() {
+        save_progress(config, previous.as_ref(), &report)?;
+    }
+    if config.json {
+        output!(config, "{}", report.to_json());
+    }
+    if let Some(path) = &config.metrics {
+        write_metrics(path, std::slice::from_ref(&report))?;
+    }
+    if config.verify_only && !verdict(config, &report) {
+        return Ok(Outcome::Failed);
+    }
+    if let Some(comparison) = &report.comparison {
+        print_comparison(config, comparison);
+        if !comparison.is_equal() {
+            return Ok(Outcome::Failed);
+        }
+    }
+    if config.remove_merged_inputs {
+        remove_merged_inputs(config, &report, &stamps)?;
+    }
+    info!(config, "Finished");
+
+    Ok(Outcome::Passed)
+}
+
+/// Merges the inputs into the master again whenever they change, until
+/// the maximum runtime passes. Merges that fail are reported and retried on
+/// the next change, without ending the watch.
+fn watch(config: &Config) -> Result<(), Error> {
+    let master = config
+        .output
+        .as_deref()
+        .ok_or_else(|| Error::new(ErrorKind::InvalidInput, "watch requires -o MASTER"))?;
+    info!(
+        config,
+        "Watching {} inputs every {} s for changes to merge into {}",
+        config.files.len(),
+        config.watch.interval.as_secs(),
+        util::file_name(master)
+    );
+    let mut events = PollFiles::new(config.files.clone());
+    let health = watch::watch(&config.watch, &mut SystemClock::new(), &mut events, || {
+        // The master merges as trusted, as long as it exists.
+        let master_file = master.is_file().then(|| master.to_path_buf());
+        let files: Vec<PathBuf> = master_file.into_iter().chain(config.files.clone()).collect();
+        match merge_files(config, &files, Some(master), config.version) {
+            Ok(report) => {
+                if config.json {
+                    output!(config, "{}", report.to_json());
+                }
+                info!(config, "Merged, {} entries in the master", report.entries);
+                Ok(report.entries)
+            },
+            Err(e) => {
+                if config.json {
+                    eprintln!("{}", e.to_json());
+                } else {
+                    eprintln!("Error: {}", e);
+                }
+                Err(e)
+            }
+        }
+    })?;
+    info!(
+        config,
+        "Stopped after the maximum runtime, with {} merges of which {} failed",
+        health.merges,
+        health.failures
+    );
+    Ok(())
+}
+
+/// Returns the default output in the directory of `DXVK_STATE_CACHE_PATH`,
+/// if set, named after the first input as DXVK would name the cache.
+fn state_cache_output(config: &Config) -> Option<PathBuf> {
+    let dir = dxvk_cache_tool::env::state_cache_path()?;
+    let output = dir.join(config.files.first()?.file_name()?);
+    info!(
+        config,
+        "Using {} from {} as the output directory, as no -o is given",
+        dir.display(),
+        dxvk_cache_tool::env::STATE_CACHE_PATH_VAR
+    );
+    Some(output)
+}
+
+/// Returns where `--install-into-prefix` installs the output: the
+/// `DXVK_state_cache` directory of the prefix, created unless on a dry run,
+/// under the name given by `-o` or else the name of the first input.
+fn install_target(config: &Config, prefix: &Path) -> Result<PathBuf, Error> {
+    if !fs::metadata(prefix).map_err(|e| Error::from(e).with_path(prefix))?.is_dir() {
+        return Err(Error::new(ErrorKind::InvalidInput, "The prefix is not a directory")
+            .with_path(prefix));
+    }
+    let name = match config.output.as_deref().or_else(|| config.files.first().map(|f| &**f)) {
+        Some(path) => path.file_name().ok_or_else(|| {
+            Error::new(ErrorKind::InvalidInput, "No file name for the installed cache")
+                .with_path(path)
+        })?,
+        None => return Err(Error::new(ErrorKind::InvalidInput, "No input files"))
+    };
+    let dir = prefix.join("DXVK_state_cache");
+    if !config.dry_run && !config.verify_only {
+        fs::create_dir_all(&dir).map_err(|e| Error::from(e).with_path(&dir))?;
+    }
+    Ok(dir.join(name))
+}
+
+/// Refuses to replace a cache in DXVK's place while another process, most
+/// likely the game, has it open, as DXVK writes to it until the game exits
+/// and the merged entries would be lost. Where that can't be told, warns
+/// and goes on.
+fn check_live_replace(config: &Config, target: &Path) -> Result<(), Error> {
+    if config.force_live_replace || !target.is_file() {
+        return Ok(());
+    }
+    match util::file_use(target) {
+        FileUse::Unused => Ok(()),
+        FileUse::InUse(users) => {
+            let users =
+                if users.is_empty() { "another process".to_string() } else { users.join(", ") };
+            Err(Error::new(
+                ErrorKind::IoError(io::ErrorKind::Other),
+                format!(
+                    "Open by {}, which may overwrite the merge when the game exits; close the \
+                     game first or pass --force-live-replace",
+                    users
+                )
+            )
+            .with_path(target))
+        },
+        FileUse::Unknown(reason) => config.warn(
+            Warning::new(
+                WarningId::LiveReplaceUnchecked,
+                format!("Can't tell whether a running game has the cache open: {}", reason)
+            )
+            .with_path(target)
+        )
+    }
+}
+
+/// Keeps the cache an install replaces as `NAME.bak`, linked rather than
+/// copied where the file system allows so that it takes no space.
+fn back_up(config: &Config, target: &Path) -> Result<(), Error> {
+    if !target.is_file() {
+        return Ok(());
+    }
+    let mut backup = target.to_path_buf().into_os_string();
+    backup.push(".bak");
+    let backup = PathBuf::from(backup);
+    if config.dry_run || config.verify_only {
+        info!(config, "Would back up {} to {}", target.display(), util::file_name(&backup));
+        return Ok(());
+    }
+    match fs::remove_file(&backup) {
+        Err(e) if e.kind() != io::ErrorKind::NotFound => {
+            return Err(Error::from(e).with_path(&backup))
+        },
+        _ => ()
+    }
+    fs::hard_link(target, &backup)
+        .or_else(|_| fs::copy(target, &backup).map(|_| ()))
+        .map_err(|e| Error::from(e).with_path(&backup))?;
+    info!(config, "Backed up {} to {}", target.display(), util::file_name(&backup));
+    Ok(())
+}
+
+/// Size and modification time of a file, telling whether it changed.
+fn file_stamp(path: &Path) -> Option<(u64, SystemTime)> {
+    let metadata = fs::metadata(path).ok()?;
+    Some((metadata.len(), metadata.modified().ok()?))
+}
+
+/// Removes the inputs all of whose entries the report shows in the output,
+/// unless they changed since their `stamps` were taken before the merge.
+/// Nothing is removed after a merge that evicted or dropped entries or
+/// resolved conflicts, as which inputs lost entries is unknown.
+fn remove_merged_inputs(
+    config: &Config,
+    report: &MergeReport,
+    stamps: &HashMap<PathBuf, (u64, SystemTime)>
+) -> Result<(), Error> {
+    let dry_run = config.dry_run || config.verify_only;
+    let dropped = report.evicted + report.too_few_sources + report.filtered;
+    if !report.pending.is_empty() || dropped > 0 || report.conflicts > 0 {
+        info!(config, "Keeping the inputs, as the output lacks some of their entries");
+        return Ok(());
+    }
+    let output = (util::file_id(&report.output), fs::canonicalize(&report.output).ok());
+    let mut removed = 0;
+    let mut reclaimed = 0;
+    for input in &report.inputs {
+        let path = &input.path;
+        let is_output = (output.0.is_some() && util::file_id(path) == output.0)
+            || (output.1.is_some() && fs::canonicalize(path).ok() == output.1);
+        if input.skipped || is_output {
+            continue;
+        }
+        if input.taken < input.entries
+            || input.invalid > 0
+            || input.truncated > 0
+            || input.out_of_range > 0
+        {
+            info!(config, "Keeping {}, as the output lacks some of its entries", path.display());
+            continue;
+        }
+        let stamp = match (stamps.get(path), file_stamp(path)) {
+            (Some(before), Some(now)) if *before == now => now,
+            _ => {
+                info!(config, "Keeping {}, as it changed during the merge", path.display());
+                continue;
+            }
+        };
+        if dry_run {
+            info!(config, "Would remove {} ({})", path.display(), config.size(stamp.0));
+        } else {
+            fs::remove_file(path).map_err(|e| Error::from(e).with_path(path))?;
+            info!(config, "Removed {} ({})", path.display(), config.size(stamp.0));
+        }
+        removed += 1;
+        reclaimed += stamp.0;
+    }
+    info!(
+        config,
+        "{} {} merged inputs, reclaiming {}",
+        if dry_run { "Would remove" } else { "Removed" },
+        removed,
+        util::format_bytes(reclaimed)
+    );
+    Ok(())
+}
+
+/// Reads the state of a merge to resume, which must write to the output
+/// given, if any.
+fn resume_state(config: &Config, path: &Path) -> Result<MergeState, Error> {
+    let state = MergeState::read(path)?;
+    if let Some(output) = &config.output {
+        if fs::canonicalize(output).ok().as_deref() != Some(state.output()) {
+            return Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("The merge to resume writes to {}", state.output().display())
+            )
+            .with_path(path));
+        }
+    }
+    Ok(state)
+}
+
+/// Records the inputs merged so far when the time limit passed, in the
+/// state file given to `--resume` or next to the output. A merge resumed
+/// to the end removes its state file.
+fn save_progress(
+    config: &Config,
+    previous: Option<&MergeState>,
+    report: &MergeReport
+) -> Result<(), Error> {
+    if config.dry_run || config.verify_only {
+        return Ok(());
+    }
+    let path = config.resume.clone().unwrap_or_else(|| {
+        let mut path = report.output.clone().into_os_string();
+        path.push(".state");
+        PathBuf::from(path)
+    });
+    if report.pending.is_empty() {
+        if config.resume.is_some() {
+            match fs::remove_file(&path) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(Error::from(e).with_path(&path))
+                },
+                _ => info!(config, "Merge complete, removed {}", path.display())
+            }
+        }
+        return Ok(());
+    }
+    // The previous output is the first input of a resumed merge.
+    let merged = report.inputs.iter().skip(previous.map_or(0, |_| 1)).map(|f| f.path.as_path());
+    let state = MergeState::record(
+        &report.output,
+        previous.into_iter().flat_map(|s| s.inputs()).chain(merged)
+    )?;
+    state.write(&path)?;
+    info!(
+        config,
+        "{} inputs are left, continue with --resume {}",
+        report.pending.len(),
+        path.display()
+    );
+    Ok(())
+}
+
+/// Merges the inputs into a cache per version, named `merged.vN.dxvk-cache`
+/// in the output directory or by the output template. Versions whose
+/// inputs contain no entries at all are skipped, and so are all but the
+/// output version if one is given.
+fn merge_by_version(config: &Config) -> Result<Outcome, Error> {
+    let dir = config.output.as_deref().unwrap_or(Path::new("."));
+    let mut groups: BTreeMap<u32, Vec<PathBuf>> = BTreeMap::new();
+    for path in &config.files {
+        let version = match config.assume_version {
+            Some(version) => version,
+            None => DxvkStateCacheHeader::peek(path).map_err(|e| e.with_path(path))?.version
+        };
+        groups.entry(version).or_default().push(path.clone());
+    }
+    if config.output_template.is_none() && !config.dry_run && !config.verify_only {
+        fs::create_dir_all(dir).map_err(|e| Error::from(e).with_path(dir))?;
+    }
+
+    let mut rows = Vec::new();
+    let mut go = true;
+    for (&version, files) in &groups {
+        if config.version != 0 && config.version != version {
+            info!(config, "Skipping v{}: not the output version v{}", version, config.version);
+            rows.push((version, files.len(), None));
+            continue;
+        }
+        let is_empty =
+            |path: &PathBuf| fs::metadata(path).is_ok_and(|m| m.len() <= HEADER_SIZE as u64);
+        if files.iter().all(is_empty) {
+            info!(config, "Skipping v{}: all {} inputs are empty", version, files.len());
+            rows.push((version, files.len(), None));
+            continue;
+        }
+        let output = dir.join(format!("merged.v{}.dxvk-cache", version));
+        let output = Some(&*output).filter(|_| config.output_template.is_none());
+        let report = merge_files(config, files, output, version)?;
+        if config.json {
+            output!(config, "{}", report.to_json());
+        }
+        if config.verify_only {
+            go &= verdict(config, &report);
+        }
+        rows.push((version, files.len(), Some(report)));
+    }
+
+    let mut table = Table::new()
+        .column("Version", Align::Left)
+        .column("Inputs", Align::Right)
+        .column("Entries", Align::Right)
+        .column("Size", Align::Right)
+        .column("Output", Align::Left)
+        .max_width(FILE_NAME_WIDTH);
+    for (version, inputs, report) in &rows {
+        let (entries, size, output) = match report {
+            Some(report) => (
+                report.entries.to_string(),
+                util::format_bytes(report.output_size),
+                report.output.display().to_string()
+            ),
+            None => ("-".to_string(), "-".to_string(), "skipped".to_string())
+        };
+        table.row(vec![format!("v{}", version), inputs.to_string(), entries, size, output]);
+    }
+    info!(config, "{}", table.render(config.table_style()));
+    if let Some(path) = &config.metrics {
+        let reports: Vec<_> = rows.into_iter().filter_map(|(_, _, report)| report).collect();
+        write_metrics(path, &reports)?;
+    }
+    if !go {
+        return Ok(Outcome::Failed);
+    }
+    info!(config, "Finished");
+
+    Ok(Outcome::Passed)
+}
+
+/// Merges `files` as configured, into `output` or the default output, and
+/// in `version` unless it is 0.
+fn merge_files(
+    config: &Config,
+    files: &[PathBuf],
+    output: Option<&Path>,
+    version: u32
+) -> Result<MergeReport, Error> {
+    let mut builder = ConfigBuilder::new()
+        .skip_mismatched(config.skip_mismatched || config.verify_only)
+        .fail_on_invalid(config.strict)
+        .snapshot(config.snapshot)
+        .threads(config.threads)
+        .buffer_size(config.buffer_size)
+        .retry(config.retry)
+        .evict(config.evict)
+        .sort(config.sort)
+        .dedup_by(config.dedup_by)
+        .force(config.ignore_space_check)
+        .regression_check(!config.no_regression_check)
+        .force_overwrite(config.force_overwrite)
+        .allow_empty(config.allow_empty)
+        .drop_partial_tail(config.drop_partial_tail)
+        .revalidate_master(config.revalidate_master)
+        .deny(config.deny.clone())
+        .dry_run(config.dry_run || config.verify_only);
+    for path in files {
+        builder = builder.add_file(path);
+    }
+    if let Some(output) = output {
+        builder = builder.output(output);
+    }
+    if let Some(template) = &config.output_template {
+        builder = builder.output_template(template.clone());
+    }
+    if version != 0 {
+        builder = builder.version(version);
+    }
+    if let Some(version) = config.assume_version {
+        builder = builder.assume_version(version).salvage(config.salvage);
+    }
+    builder = builder.resync(config.resync);
+    if let Some(max_bytes) = config.max_bytes {
+        builder = builder.max_bytes(max_bytes);
+    }
+    if let Some(max_entries) = config.max_entries {
+        builder = builder.max_entries(max_entries);
+    }
+    if let Some(policy) = config.conflict_prefer {
+        builder = builder.conflict_prefer(policy);
+    }
+    if let Some(limit) = config.limit_per_file {
+        builder = builder.limit_per_file(limit);
+    }
+    if let Some(bytes) = config.min_entry_size {
+        builder = builder.min_entry_size(bytes);
+    }
+    if let Some(bytes) = config.max_entry_size {
+        builder = builder.max_entry_size(bytes);
+    }
+    if let Some(min_sources) = config.min_sources {
+        builder = builder.min_sources(min_sources);
+    }
+    if let Some(list) = &config.keep_hashes {
+        builder = builder.keep_hashes(read_hash_list(list)?);
+    }
+    if let Some(list) = &config.drop_hashes {
+        builder = builder.drop_hashes(read_hash_list(list)?);
+    }
+    if let Some(stages) = config.only_stages {
+        builder = builder.only_stages(stages, config.legacy_stages);
+    }
+    if let Some(limit) = config.time_limit {
+        builder = builder.time_limit(limit);
+    }
+    if let Some(journal) = &config.journal {
+        builder = builder.journal(journal);
+    }
+    if let Some(reference) = &config.compare_to {
+        builder = builder.compare_to(reference);
+    }
+    let merge_config = builder.build()?;
+
+    // Hashed before merging, as the output may replace an input.
+    let mut hashes = HashMap::new();
+    if config.write_meta && !config.dry_run && !config.verify_only {
+        for path in files {
+            let hash = resume::file_hash(path).map_err(|e| Error::from(e).with_path(path))?;
+            hashes.insert(path.as_path(), hash);
+        }
+    }
+
+    let names: Vec<_> = files.iter().map(|p| util::file_name(p)).collect();
+    info!(config, "Merging files {}", names.join(" "));
+    let file_count = files.len();
+    let mut written = Vec::new();
+    let mut progress_line = ProgressLine::new(config.verbosity >= Verbosity::Normal);
+    let result = merge::merge_files_with(&merge_config, &mut |event| {
+        if let Event::Progress(progress) = event {
+            progress_line.update(progress);
+            return;
+        }
+        progress_line.clear();
+        match *event {
+            Event::Progress(_) => (),
+            Event::Snapshot { path, taken_at } => info!(
+                config,
+                "Using a snapshot of {} taken at {}",
+                util::file_name(path),
+                util::format_timestamp(taken_at)
+            ),
+            Event::Salvaged { path, salvage } => info!(
+                config,
+                "Salvaged {} entries from {}, skipping {}",
+                util::format_count(salvage.recovered as u64),
+                util::file_name(path),
+                config.size(salvage.skipped_bytes)
+            ),
+            Event::Resynced { path, resync } => print_resync(config, path, resync),
+            Event::DetectedVersion(version) => {
+                info!(config, "Detected state cache version v{}", version)
+            },
+            Event::Duplicate { index, path, first } => info!(
+                config,
+                "Skipping {} ({}/{}): same file as {}",
+                util::file_name(path),
+                index + 1,
+                file_count,
+                first.display()
+            ),
+            Event::Mismatched { index, path, version, expected } => info!(
+                config,
+                "Skipping {} ({}/{}): version v{} does not match v{}",
+                util::file_name(path),
+                index + 1,
+                file_count,
+                version,
+                expected
+            ),
+            Event::Trusted { index, path } => info!(
+                config,
+                "Trusting the hashes of {} ({}/{}), the output, without validating them",
+                util::file_name(path),
+                index + 1,
+                file_count
+            ),
+            Event::EntrySize { path, found, expected } => {
+                if config.verbose() {
+                    info!(
+                        config,
+                        "Note: {} declares entry size {} instead of {}",
+                        util::file_name(path),
+                        found,
+                        expected
+                    );
+                }
+            },
+            Event::Warning(warning) => config.print_warning(warning),
+            Event::Retried { path, errors } => print_retries(config, path, errors),
+            Event::ResumedJournal { journal, inputs } => info!(
+                config,
+                "Resuming the merge recorded in {}, which has {} inputs merged",
+                journal.display(),
+                inputs
+            ),
+            Event::Checkpoint { journal, inputs } => {
+                if config.verbose() {
+                    info!(config, "Recorded {} inputs merged in {}", inputs, journal.display());
+                }
+            },
+            Event::TimeLimit { pending } => {
+                info!(config, "Time limit reached, leaving {} inputs for later", pending.len())
+            },
+            Event::Invalid { invalid, .. } => {
+                if config.verbose() {
+                    info!(config, "Failed SHA1 validation:");
+                    for hash in invalid {
+                        info!(config, "\t{}", hash_to_hex(hash));
+                    }
+                }
+            },
+            Event::Merged { index, report, omitted } => {
+                let taken = if report.taken < report.entries {
+                    format!(
+                        "{} of {} entries taken, ",
+                        util::format_count(report.taken as u64),
+                        util::format_count(report.entries as u64)
+                    )
+                } else {
+                    String::new()
+                };
+                info!(
+                    config,
+                    "Merging {} ({}/{})... {}{} new, {} duplicate, {} invalid",
+                    util::file_name(&report.path),
+                    index + 1,
+                    file_count,
+                    taken,
+                    util::format_count(report.new as u64),
+                    util::format_count(report.duplicates as u64),
+                    util::format_count(report.invalid as u64)
+                );
+                if report.data_duplicates > 0 {
+                    info!(
+                        config,
+                        "{} duplicates have the data of an entry with another hash",
+                        util::format_count(report.data_duplicates as u64)
+                    );
+                }
+                if report.truncated > 0 {
+                    info!(config, "The last entry is dropped, as it is still being written");
+                }
+                if report.out_of_range > 0 {
+                    info!(
+                        config,
+                        "Skipped {} entries outside the entry size range",
+                        util::format_count(report.out_of_range as u64)
+                    );
+                }
+                if !omitted.is_empty() && config.verbose() {
+                    info!(config, "Omitted as invalid:");
+                    for hash in omitted {
+                        info!(config, "\t{}", hash_to_hex(hash));
+                    }
+                }
+            },
+            Event::Evicted { hashes, .. } => {
+                let entries = |n: usize| format!("{} entries", util::format_count(n as u64));
+                let limits: Vec<String> = config
+                    .max_bytes
+                    .map(|n| config.size(n))
+                    .into_iter()
+                    .chain(config.max_entries.map(entries))
+                    .collect();
+                info!(
+                    config,
+                    "Evicted {} {} entries to fit into {}",
+                    util::format_count(hashes.len() as u64),
+                    match config.evict {
+                        EvictPolicy::Oldest => "oldest",
+                        EvictPolicy::Largest => "largest"
+                    },
+                    limits.join(" and ")
+                );
+                if config.verbose() {
+                    for hash in hashes {
+                        info!(config, "\t{}", hash_to_hex(hash));
+                    }
+                }
+            },
+            Event::Filtered { pass, hashes } => {
+                info!(
+                    config,
+                    "Dropped {} entries {}",
+                    util::format_count(hashes.len() as u64),
+                    if pass == "stages" { "of other stages" } else { "by the hash lists" }
+                );
+                if config.verbose() {
+                    for hash in hashes {
+                        info!(config, "\t{}", hash_to_hex(hash));
+                    }
+                }
+            },
+            Event::TooFewSources { min_sources, hashes } => {
+                info!(
+                    config,
+                    "Dropped {} entries found in fewer than {} inputs",
+                    util::format_count(hashes.len() as u64),
+                    min_sources
+                );
+                if config.verbose() {
+                    for hash in hashes {
+                        info!(config, "\t{}", hash_to_hex(hash));
+                    }
+                }
+            },
+            Event::Writing { path, entries, size, .. } => {
+                if config.tee.is_some() {
+                    written = entries.keys().copied().collect();
+                }
+                info!(
+                    config,
+                    "{} {} entries ({}) to file {}",
+                    if config.dry_run || config.verify_only { "Would write" } else { "Writing" },
+                    util::format_count(entries.len() as u64),
+                    config.size(size),
+                    util::file_name(path)
+                );
+                if config.verbose() {
+                    let memory = merge::memory_usage(entries);
+                    info!(
+                        config,
+                        "\tAbout {} in memory: {} of entry data, {} for the map",
+                        util::format_bytes(memory.data + memory.overhead),
+                        util::format_bytes(memory.data),
+                        util::format_bytes(memory.overhead)
+                    );
+                    let mut counts = vec![0; files.len()];
+                    for provenance in entries.values().filter_map(|e| e.provenance()) {
+                        if let Some(count) = counts.get_mut(provenance.input as usize) {
+                            *count += 1;
+                        }
+                    }
+                    let mut table = Table::new()
+                        .column("Entries", Align::Right)
+                        .column("From", Align::Left)
+                        .max_width(FILE_NAME_WIDTH);
+                    for (path, count) in files.iter().zip(counts).filter(|&(_, n)| n > 0) {
+                        table.row(vec![count.to_string(), util::file_name(path).into_owned()]);
+                    }
+                    if !table.is_empty() {
+                        info!(config, "{}", table.render(config.table_style()));
+                    }
+                }
+                // Rejecting legacy entries only leaves out this summary.
+                let stages = entries.values().try_fold((0, 0), |(compute, unknown), e| {
+                    e.stages_with(config.legacy_stages).map(|stages| match stages {
+                        Stages::Compute => (compute + 1, unknown),
+                        Stages::Unknown => (compute, unknown + 1),
+                        _ => (compute, unknown)
+                    })
+                });
+                match stages {
+                    Ok((compute, 0)) if config.verbose() => info!(
+                        config,
+                        "\t{} graphics and {} compute entries",
+                        util::format_count((entries.len() - compute) as u64),
+                        util::format_count(compute as u64)
+                    ),
+                    Ok((_, unknown)) if config.verbose() => info!(
+                        config,
+                        "\t{} entries of unknown stages",
+                        util::format_count(unknown as u64)
+                    ),
+                    _ => ()
+                }
+            },
+            Event::ReplacingInput(input) => {
+                info!(config, "Note: the output replaces the input {}", input.display())
+            },
+        }
+    });
+    progress_line.clear();
+    let mut report = result?;
+    if report.passes.len() > 1 {
+        let passes: Vec<String> = report
+            .passes
+            .iter()
+            .map(|(pass, entries)| format!("{} {}", pass, util::format_count(*entries as u64)))
+            .collect();
+        info!(config, "Entries after each pass: {}", passes.join(", "));
+    }
+    if config.profile {
+        print_profile(config, &report.timings);
+    }
+    if let Some(tee) = &config.tee {
+        write_listing(tee, &written).map_err(|e| Error::from(e).with_path(tee))?;
+    }
+
+    if let (Some(mode), Some(first)) = (config.output_mode, files.first()) {
+        if !config.dry_run && !config.verify_only {
+            set_output_mode(config, mode, first, &report.output)?;
+        }
+    }
+    if !hashes.is_empty() {
+        let merged = report.inputs.iter().filter(|input| !input.skipped).filter_map(|input| {
+            hashes.get(input.path.as_path()).map(|hash| (input.path.as_path(), *hash))
+        });
+        let mut provenance = Provenance::record(&report.output, merged, report.entries)?;
+        if let Some(transformation) = sort_transformation(config.sort) {
+            provenance.transformations.push(transformation.to_string());
+        }
+        if config.strip_to_hashes {
+            provenance.strip_input_names();
+        }
+        provenance.write(&report.output)?;
+        info!(
+            config,
+            "Wrote provenance metadata to {}",
+            util::file_name(&provenance::sidecar_path(&report.output))
+        );
+    }
+    // The report of each merge also carries the warnings of the run.
+    report.warnings.splice(0..0, config.warnings.borrow().iter().cloned());
+    Ok(report)
+}
+
+/// Prints the phase timings of a merge for `--profile` as a table on
+/// stderr, so that it doesn't mix with the output of `--json`.
+fn print_profile(config: &Config, timings: &PhaseTimings) {
+    let total: Duration = timings.phases().iter().map(|(_, d)| *d).sum();
+    let mut table = Table::new()
+        .column("Phase", Align::Left)
+        .column("Time", Align::Right)
+        .column("Share", Align::Right);
+    let ms = |d: Duration| format!("{:.3} ms", d.as_secs_f64() * 1e3);
+    for (phase, duration) in timings.phases().iter().copied().chain([("Total", total)]) {
+        let share = duration.as_secs_f64() / total.as_secs_f64().max(f64::MIN_POSITIVE);
+        table.row(vec![phase.to_string(), ms(duration), format!("{:.1}%", share * 100.0)]);
+    }
+    eprintln!("{}", table.render(config.table_style()));
+}
+
+/// Applies `--output-mode` or `--preserve-mode` to a written output.
+#[cfg(unix)]
+fn set_output_mode(
+    config: &Config,
+    mode: OutputMode,
+    first_input: &Path,
+    output: &Path
+) -> Result<(), Error> {
+    let mode = match mode {
+        OutputMode::Mode(mode) => mode,
+        OutputMode::Preserve => {
+            util::file_mode(first_input).map_err(|e| Error::from(e).with_path(first_input))?
+        }
+    };
+    util::set_file_mode(output, mode).map_err(|e| Error::from(e).with_path(output))?;
+    if config.verbose() {
+        info!(config, "Set the mode of {} to {:04o}", util::file_name(output), mode);
+    }
+    Ok(())
+}
+
+#[cfg(not(unix))]
+fn set_output_mode(config: &Config, _: OutputMode, _: &Path, _: &Path) -> Result<(), Error> {
+    config.warn(Warning::new(
+        WarningId::ModeIgnored,
+        "--output-mode and --preserve-mode only apply on Unix"
+    ))
+}
+
+/// Writes `--metrics`, replacing the file at once so that the textfile
+/// collector never reads a partial file.
+fn write_metrics(path: &Path, reports: &[MergeReport]) -> Result<(), Error> {
+    let metrics = report::to_metrics(reports);
+    util::write_atomic(path, metrics.as_bytes()).map_err(|e| Error::from(e).with_path(path))
+}
+
+/// Lists `hashes` for `--tee`, a hex hash per line, on stderr for `-`.
+fn write_listing(path: &Path, hashes: &[Sha1Hash]) -> io::Result<()> {
+    let mut writer: Box<dyn Write> = if path == Path::new("-") {
+        Box::new(io::stderr().lock())
+    } else {
+        Box::new(BufWriter::new(File::create(path)?))
+    };
+    for hash in hashes {
+        writeln!(writer, "{}", hash_to_hex(hash))?;
+    }
+    writer.flush()
+}
+
+/// Prints the outcome of `--compare-to`, listing the hashes with `--verbose`.
+fn print_comparison(config: &Config, comparison: &Comparison) {
+    let reference = util::file_name(&comparison.reference);
+    if comparison.is_equal() {
+        info!(config, "The merge has the same entries as {}", reference);
+        return;
+    }
+    info!(config, "The merge differs from {}", reference);
+    let sides = [
+        ("only in the merge", &comparison.only_in_merge),
+        ("only in the reference", &comparison.only_in_reference)
+    ];
+    for (side, hashes) in sides.iter().filter(|(_, hashes)| !hashes.is_empty()) {
+        info!(config, "\t{} entries {}", util::format_count(hashes.len() as u64), side);
+        if config.verbose() {
+            for hash in hashes.iter() {
+                info!(config, "\t\t{}", hash_to_hex(hash));
+            }
+        }
+    }
+}
+
+/// Concludes `--verify-only`: a go unless an input doesn't match the output
+/// version or contains invalid entries.
+fn verdict(config: &Config, report: &MergeReport) -> bool {
+    let mismatched =
+        report.inputs.iter().filter(|f| f.skipped && f.duplicate_of.is_none()).count();
+    let invalid: usize = report.inputs.iter().map(|f| f.invalid).sum();
+    if mismatched == 0 && invalid == 0 {
+        info!(
+            config,
+            "Go: {} inputs merge into {} entries of version v{}",
+            report.inputs.len(),
+            util::format_count(report.entries as u64),
+            report.version
+        );
+        return true;
+    }
+    info!(
+        config,
+        "No-go: {} inputs of another version than v{}, {} invalid entries",
+        mismatched,
+        report.version,
+        util::format_count(invalid as u64)
+    );
+    false
+}
+
+fn split_stages(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let (header, entries) = open_cache(path, config)?;
+    let handling = config.legacy_stages;
+    if DxvkStateCacheEdition::from_version(header.version) == DxvkStateCacheEdition::Legacy {
+        handling.stages(None).map_err(|e| e.with_path(path))?;
+    }
+
+    let mut graphics = Vec::new();
+    let mut compute = Vec::new();
+    let mut unknown = Vec::new();
+    let mut ambiguous = 0;
+    let mut omitted = 0;
+    let valid = validate_entries(&entries, HashKind::from_version(header.version), config.threads);
+    for (entry, valid) in entries.into_iter().zip(valid) {
+        if !valid {
+            omitted += 1;
+            continue;
+        }
+        match entry.stages_with(handling)? {
+            Stages::Compute => compute.push(entry),
+            Stages::Graphics => graphics.push(entry),
+            Stages::Unknown => unknown.push(entry),
+            Stages::Ambiguous => {
+                ambiguous += 1;
+                graphics.push(entry);
+            }
+        }
+    }
+    if omitted > 0 {
+        output!(config, "{} entries are omitted as invalid", util::format_count(omitted as u64));
+    }
+    if ambiguous > 0 {
+        config.warn(Warning::new(
+            WarningId::AmbiguousStages,
+            format!("{} entries with an ambiguous stage mask are written as graphics", ambiguous)
+        ))?;
+    }
+
+    let mut outputs = vec![("graphics", &graphics), ("compute", &compute)];
+    if !unknown.is_empty() {
+        config.warn(Warning::new(
+            WarningId::UnknownStages,
+            format!("{} entries without a stage mask are written apart", unknown.len())
+        ))?;
+        outputs.push(("unknown", &unknown));
+    }
+
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    for (kind, entries) in outputs {
+        let output = path.with_file_name(format!("{}.{}.dxvk-cache", stem, kind));
+        output!(
+            config,
+            "Writing {} {} entries to file {}",
+            util::format_count(entries.len() as u64),
+            kind,
+            util::file_name(&output)
+        );
+        format::write_cache(&output, &header, entries.iter(), config.buffer_size)?;
+    }
+
+    output!(config, "Finished");
+
+    Ok(())
+}
+
+fn info(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let (header, entries) = open_cache(path, config)?;
+    let valid = validate_entries(&entries, HashKind::from_version(header.version), config.threads);
+    if let Some(prefix) = &config.print_entry {
+        return print_entry(config, path, prefix, &entries, &valid);
+    }
+    let compressibility = match config.compressibility {
+        true => Some(compression::estimate(&entries, config.exact).map_err(|e| e.with_path(path))?),
+        false => None
+    };
+    let stats = CacheStats::from_entries(&entries, Some(&valid));
+    let invalid = stats.invalid.unwrap_or(0);
+    let handling = config.legacy_stages;
+    let count = |stages| stats.stages(stages, handling).map_err(|e| e.with_path(path));
+    let (graphics, compute) = (count(Stages::Graphics)?, count(Stages::Compute)?);
+    let (ambiguous, unknown) = (count(Stages::Ambiguous)?, count(Stages::Unknown)?);
+    // Legacy entries are either all graphics or all unknown.
+    let staged = DxvkStateCacheEdition::from_version(header.version)
+        == DxvkStateCacheEdition::Standard
+        || handling == LegacyStageHandling::TreatAsGraphics;
+    let provenance = read_provenance(config, path)?;
+
+    if config.json {
+        let mut report = format!(
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"path\":{},\"version\":{},\
+             \"release\":{},\"entries\":{},\"invalid\":{},\"data_bytes\":{}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            json::path(path),
+            header.version,
+            dxvk_release(header.version).map_or("null".into(), json::string),
+            stats.entries,
+            invalid,
+            stats.data_bytes
+        );
+        if staged {
+            report.push_str(&format!(
+                ",\"graphics\":{},\"compute\":{},\"ambiguous\":{}",
+                graphics, compute, ambiguous
+            ));
+        } else {
+            report.push_str(&format!(",\"unknown\":{}", unknown));
+        }
+        let provenance = match &provenance {
+            Some((provenance, matches)) => {
+                format!("{{{},\"matches\":{}}}", provenance.json_fields(), matches)
+            },
+            None => "null".to_string()
+        };
+        if let Some(compressibility) = &compressibility {
+            report.push_str(&format!(",\"compressibility\":{}", compressibility.to_json()));
+        }
+        output!(
+            config,
+            "{},\"provenance\":{},\"warnings\":{}}}",
+            report,
+            provenance,
+            config.warnings_json()
+        );
+        return Ok(());
+    }
+
+    match dxvk_release(header.version) {
+        Some(release) => output!(config, "Version: v{} (DXVK {})", header.version, release),
+        None => output!(config, "Version: v{}", header.version)
+    }
+    let count = |n: usize| util::format_count(n as u64);
+    output!(config, "Entries: {}", count(stats.entries));
+    if staged {
+        output!(config, "\tGraphics: {}", count(graphics));
+        output!(config, "\tCompute: {}", count(compute));
+        if ambiguous > 0 {
+            output!(config, "\tAmbiguous stage mask: {}", count(ambiguous));
+        }
+    } else {
+        output!(
+            config,
+            "\tUnknown stages: {}, not recorded by version v{}",
+            count(unknown),
+            header.version
+        );
+    }
+    output!(config, "\tInvalid: {}", count(invalid));
+    output!(config, "Data: {}", config.size(stats.data_bytes));
+    match &provenance {
+        Some((provenance, matches)) => {
+            output!(
+                config,
+                "Provenance: merged from {} inputs by {} {} at {}",
+                provenance.inputs.len(),
+                env!("CARGO_PKG_NAME"),
+                provenance.tool_version,
+                provenance.created
+            );
+            output!(
+                config,
+                "\tChecksum: {}",
+                if *matches { "matches the cache" } else { "does not match the cache" }
+            );
+            if !provenance.transformations.is_empty() {
+                output!(config, "\tTransformations: {}", provenance.transformations.join(", "));
+            }
+            if config.verbose() {
+                for (name, hash) in &provenance.inputs {
+                    output!(config, "\t{} {}", hash_to_hex(hash), name);
+                }
+            }
+        },
+        None => output!(config, "Provenance: no provenance metadata")
+    }
+    if config.verbose() {
+        let mut masks = Table::new().column("", Align::Left).column("", Align::Right);
+        for (mask, count) in &stats.stage_masks {
+            masks.row(vec![format!("{:#04x}", mask), count.to_string()]);
+        }
+        if !masks.is_empty() {
+            output!(config, "Stage masks:");
+            output!(config, "{}", masks.render(config.table_style()));
+        }
+        let mut sizes = Table::new().column("", Align::Right).column("", Align::Right);
+        for (i, count) in stats.size_buckets.iter().enumerate().filter(|(_, c)| **c > 0) {
+            let range = match i {
+                0 => "0 bytes".to_string(),
+                _ => format!("{} to {} bytes", 1u64 << (i - 1), (1u64 << i) - 1)
+            };
+            sizes.row(vec![range, count.to_string()]);
         }
+        output!(config, "Entry sizes:");
+        output!(config, "{}", sizes.render(config.table_style()));
     }
+    if let Some(compressibility) = &compressibility {
+        print_compressibility(config, compressibility);
+    }
+
+    Ok(())
 }
 
-impl<R: Read> ReadEx for BufReader<R> {}
-trait ReadEx: Read {
-    fn read_u32(&mut self) -> io::Result<u32> {
-        let mut buf = [0; 4];
-        match self.read_exact(&mut buf) {
-            Ok(_) => Ok((u32::from(buf[0]))
-                + (u32::from(buf[1]) << 8)
-                + (u32::from(buf[2]) << 16)
-                + (u32::from(buf[3]) << 24)),
-            Err(e) => Err(e)
+/// Prints the compressibility estimate of `info` as a table of the entries
+/// overall, by stage mask and by age.
+fn print_compressibility(config: &Config, compressibility: &Compressibility) {
+    output!(
+        config,
+        "Compressibility: {}, zstd level {}",
+        if compressibility.exact { "all data compressed" } else { "estimated from samples" },
+        compression::LEVEL
+    );
+    let mut table = Table::new()
+        .column("Entries", Align::Left)
+        .column("Count", Align::Right)
+        .column("Data", Align::Right)
+        .column("Compressed", Align::Right)
+        .column("Ratio", Align::Right);
+    let mut row = |name: String, group: &compression::Group| {
+        table.row(vec![
+            name,
+            util::format_count(group.entries as u64),
+            config.size(group.data_bytes),
+            config.size(group.estimated_bytes()),
+            format!("{:.0}%", group.ratio() * 100.0)
+        ]);
+    };
+    row("All".to_string(), &compressibility.total);
+    for (mask, group) in &compressibility.stage_masks {
+        match mask {
+            Some(mask) => row(format!("Stage mask {:#04x}", mask), group),
+            None => row("No stage mask".to_string(), group)
         }
     }
+    let ages = ["Oldest quarter", "Second quarter", "Third quarter", "Newest quarter"];
+    for (age, group) in ages.iter().zip(&compressibility.quartiles) {
+        row(age.to_string(), group);
+    }
+    output!(config, "{}", table.render(config.table_style()));
+}
 
-    fn read_u24(&mut self) -> io::Result<u32> {
-        let mut buf = [0; 3];
-        match self.read_exact(&mut buf) {
-            Ok(_) => Ok((u32::from(buf[0])) + (u32::from(buf[1]) << 8) + (u32::from(buf[2]) << 16)),
-            Err(e) => Err(e)
+/// Reads the provenance sidecar of `path` if there is one, along with
+/// whether the cache still matches it. A malformed sidecar or a cache that
+/// changed since is a warning, as the sidecar is never required.
+fn read_provenance(config: &Config, path: &Path) -> Result<Option<(Provenance, bool)>, Error> {
+    let sidecar = provenance::sidecar_path(path);
+    let provenance = match Provenance::read(path) {
+        Ok(Some(provenance)) => provenance,
+        Ok(None) => return Ok(None),
+        Err(e) => {
+            let warning = Warning::new(WarningId::ProvenanceMismatch, e.message());
+            config.warn(warning.with_path(&sidecar))?;
+            return Ok(None);
         }
+    };
+    let matches = provenance.matches(path)?;
+    if !matches {
+        config.warn(
+            Warning::new(
+                WarningId::ProvenanceMismatch,
+                "The cache changed or was copied partially since the metadata was written"
+            )
+            .with_path(&sidecar)
+        )?;
     }
+    Ok(Some((provenance, matches)))
+}
+
+/// Adds entries dumped to files of their own to a copy of a cache.
+fn import_entry(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let (header, file_entries) = open_cache(path, config)?;
+    let count = util::format_count(file_entries.len() as u64);
+    info!(config, "Read {} entries from {}", count, path.display());
 
-    fn read_u8(&mut self) -> io::Result<u8> {
-        let mut buf = [0; 1];
-        match self.read_exact(&mut buf) {
-            Ok(_) => Ok(buf[0]),
-            Err(e) => Err(e)
+    let mut entries = LinkedHashMap::with_capacity(file_entries.len() + config.files.len() - 1);
+    for entry in file_entries {
+        entries.entry(entry.hash).or_insert(entry);
+    }
+    for dump in &config.files[1..] {
+        let entry = format::read_entry_dump(dump, &header, config.stage_mask)?;
+        let stage_mask =
+            entry.stage_mask().map_or(String::new(), |m| format!(", stage mask {:#04x}", m));
+        info!(
+            config,
+            "Imported {} as {}, {}{}",
+            util::file_name(dump),
+            hash_to_hex(&entry.hash),
+            config.size(entry.data.len() as u64),
+            stage_mask
+        );
+        if entries.contains_key(&entry.hash) {
+            config.warn(
+                Warning::new(WarningId::EntryReplaced, "Replaces the entry with the same hash")
+                    .with_path(dump)
+            )?;
         }
+        entries.insert(entry.hash, entry);
     }
+
+    let output = config.output.clone().unwrap_or_else(|| PathBuf::from("output.dxvk-cache"));
+    let count = util::format_count(entries.len() as u64);
+    info!(config, "Writing {} entries to file {}", count, util::file_name(&output));
+    format::write_cache(&output, &header, entries.values(), config.buffer_size)?;
+    info!(config, "Finished");
+
+    Ok(())
 }
 
-impl<W: Write> WriteEx for BufWriter<W> {}
-trait WriteEx: Write {
-    fn write_u32(&mut self, n: u32) -> io::Result<()> {
-        let mut buf = [0; 4];
-        buf[0] = n as u8;
-        buf[1] = (n >> 8) as u8;
-        buf[2] = (n >> 16) as u8;
-        buf[3] = (n >> 24) as u8;
-        self.write_all(&buf)
+/// Times parsing, merging and writing synthetic caches, for numbers users
+/// can compare across versions and machines.
+fn bench(config: &Config) -> Result<(), Error> {
+    let workload = &config.workload;
+    info!(
+        config,
+        "Benchmarking caches of {} entries of {}, {} runs on {} threads",
+        util::format_count(workload.entries as u64),
+        config.size(workload.entry_size as u64),
+        bench::RUNS,
+        config.threads
+    );
+    let mut report = bench::run(workload, config.threads)?;
+    if config.json {
+        report.warnings = config.warnings.borrow().clone();
+        output!(config, "{}", report.to_json());
+        return Ok(());
+    }
+
+    output!(
+        config,
+        "Workload: {} entries of {}, seed {}, version v{}",
+        util::format_count(workload.entries as u64),
+        config.size(workload.entry_size as u64),
+        workload.seed,
+        workload.version()
+    );
+    output!(config, "Threads: {}, runs: {}", report.threads, report.runs);
+    let mut table = Table::new()
+        .column("Benchmark", Align::Left)
+        .column("Size", Align::Right)
+        .column("Min", Align::Right)
+        .column("Median", Align::Right)
+        .column("Best MB/s", Align::Right)
+        .column("Median MB/s", Align::Right);
+    let ms = |d: Duration| format!("{:.3} ms", d.as_secs_f64() * 1e3);
+    for t in &report.timings {
+        table.row(vec![
+            t.name.to_string(),
+            util::format_bytes(t.bytes),
+            ms(t.min),
+            ms(t.median),
+            format!("{:.1}", t.throughput(t.min)),
+            format!("{:.1}", t.throughput(t.median))
+        ]);
     }
+    output!(config, "{}", table.render(config.table_style()));
+
+    Ok(())
+}
 
-    fn write_u24(&mut self, n: u32) -> io::Result<()> {
-        let mut buf = [0; 3];
-        buf[0] = n as u8;
-        buf[1] = (n >> 8) as u8;
-        buf[2] = (n >> 16) as u8;
-        self.write_all(&buf)
+/// Gives entries failing validation the hash computed from their data. With
+/// `--check`, only reports the entries that would change.
+fn repair(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let (header, file_entries) = open_cache(path, config)?;
+    let kind = HashKind::from_version(header.version);
+    let valid = validate_entries(&file_entries, kind, config.threads);
+
+    let mut taken: HashSet<Sha1Hash> =
+        file_entries.iter().zip(&valid).filter(|(_, &v)| v).map(|(e, _)| e.hash).collect();
+    let mut report = RepairReport {
+        dry_run:  config.dry_run,
+        entries:  Vec::new(),
+        warnings: Vec::new()
+    };
+    let mut entries = LinkedHashMap::with_capacity(file_entries.len());
+    for (mut entry, valid) in file_entries.into_iter().zip(valid) {
+        if !valid {
+            let computed = entry.compute_hash(kind);
+            report.entries.push(RepairedEntry {
+                stored: entry.hash,
+                computed,
+                size: entry.data.len(),
+                stage_mask: entry.stage_mask(),
+                collides: !taken.insert(computed)
+            });
+            entry.hash = computed;
+        }
+        entries.entry(entry.hash).or_insert(entry);
     }
 
-    fn write_u8(&mut self, n: u8) -> io::Result<()> {
-        let mut buf = [0; 1];
-        buf[0] = n;
-        self.write_all(&buf)
+    if config.json {
+        report.warnings = config.warnings.borrow().clone();
+        output!(config, "{}", report.to_json());
+    } else {
+        let count = util::format_count(report.entries.len() as u64);
+        output!(config, "{} entries fail validation", count);
+        let mut table = Table::new()
+            .column("Stored", Align::Left)
+            .column("Computed", Align::Left)
+            .column("Bytes", Align::Right)
+            .column("Stage mask", Align::Left)
+            .column("Note", Align::Left);
+        for e in &report.entries {
+            table.row(vec![
+                hash_to_hex(&e.stored),
+                hash_to_hex(&e.computed),
+                e.size.to_string(),
+                e.stage_mask.map_or(String::new(), |m| format!("{:#04x}", m)),
+                if e.collides { "collides with another entry" } else { "" }.to_string()
+            ]);
+        }
+        if !table.is_empty() {
+            output!(config, "{}", table.render(config.table_style()));
+        }
+    }
+    if config.dry_run || report.entries.is_empty() {
+        return Ok(());
     }
+
+    let output = config.output.clone().unwrap_or_else(|| PathBuf::from("output.dxvk-cache"));
+    info!(
+        config,
+        "Writing {} entries to file {}",
+        util::format_count(entries.len() as u64),
+        util::file_name(&output)
+    );
+    let header = DxvkStateCacheHeader {
+        magic:      MAGIC_STRING,
+        version:    header.version,
+        entry_size: header.entry_size
+    };
+    format::write_cache(&output, &header, entries.values(), config.buffer_size)?;
+    info!(config, "Finished");
+
+    Ok(())
 }
 
-fn print_help() {
-    println!("Standalone dxvk-cache merger");
-    println!("USAGE:\n\tdxvk-cache-tool [OPTION]... <FILEs>...\n");
-    println!("OPTIONS:");
-    println!("\t-o, --output FILE\tSet output file name");
-    println!("\t-h, --help\t\tDisplay this help and exit");
-    println!("\t-V, --version\t\tOutput version information and exit");
+/// Prints the layout of a state cache version from the field tables the
+/// reader and writer follow.
+fn explain_format(config: &Config) -> Result<(), Error> {
+    let version = match config.version {
+        0 => CURRENT_VERSION,
+        v => v
+    };
+    let edition = DxvkStateCacheEdition::from_version(version);
+    match dxvk_release(version) {
+        Some(release) => output!(config, "State cache version v{} (DXVK {})", version, release),
+        None => output!(config, "State cache version v{} (unknown DXVK release)", version)
+    }
+    output!(config, "All integers are little-endian.\n");
+    output!(config, "Header:");
+    print_fields(config, HEADER_FIELDS);
+    output!(config, "\nEntries, repeated until the end of the file:");
+    print_fields(config, edition.entry_fields());
+    output!(config, "\nEntry hash: {}", edition.hash_recipe());
+
+    Ok(())
 }
 
-fn process_args() -> Config {
-    let mut config = Config::default();
-    let mut args: Vec<String> = env::args().collect();
-    for (i, arg) in env::args().enumerate().rev() {
-        match arg.as_ref() {
-            "-h" | "--help" => {
-                print_help();
-                std::process::exit(0);
-            },
-            "-o" | "--output" => {
-                config.output = PathBuf::from(&args[i + 1]);
-                args.drain(i..=i + 1);
-            },
-            "-V" | "--version" => {
-                println!("{} {}", env!("CARGO_PKG_NAME"), env!("CARGO_PKG_VERSION"));
-                std::process::exit(0);
-            },
-            "--frog" => {
-                println!("🐸");
-                std::process::exit(0);
+/// Prints a table of fields. Offsets following the data field are relative
+/// to its size N.
+fn print_fields(config: &Config, fields: &[Field]) {
+    let mut table = Table::new()
+        .column("Offset", Align::Left)
+        .column("Size", Align::Right)
+        .column("Field", Align::Left)
+        .column("Description", Align::Left);
+    let mut offset = 0;
+    let mut after_data = false;
+    for field in fields {
+        let offset_text = match (after_data, offset) {
+            (false, _) => offset.to_string(),
+            (true, 0) => "N".into(),
+            (true, _) => format!("N+{}", offset)
+        };
+        let size = match field.size {
+            FieldSize::Fixed(size) => {
+                offset += size;
+                size.to_string()
             },
-            _ => ()
+            FieldSize::Data => {
+                after_data = true;
+                offset = 0;
+                "N".into()
+            }
+        };
+        table.row(vec![offset_text, size, field.name.into(), field.description.into()]);
+    }
+    output!(config, "{}", table.render(config.table_style()));
+}
+
+fn canonicalize(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let (header, file_entries) = open_cache(path, config)?;
+    let input_size = fs::metadata(path)?.len();
+    let count = file_entries.len();
+    let kind = HashKind::from_version(header.version);
+    let valid = validate_entries(&file_entries, kind, config.threads);
+
+    let mut entries = LinkedHashMap::with_capacity(count);
+    let mut invalid = 0;
+    let mut empty = 0;
+    let mut duplicates = 0;
+    for (entry, valid) in file_entries.into_iter().zip(valid) {
+        if !valid {
+            invalid += 1;
+        } else if entry.data.is_empty() {
+            empty += 1;
+        } else if entries.insert(entry.hash, entry).is_some() {
+            duplicates += 1;
         }
     }
-    if args.len() <= 1 {
-        print_help();
-        std::process::exit(0);
+    merge::sort_entries(&mut entries, config.sort);
+
+    let format_count = |n: usize| util::format_count(n as u64);
+    info!(config, "Read {} entries from {}", format_count(count), path.display());
+    info!(config, "Removed {} invalid entries", format_count(invalid));
+    info!(config, "Removed {} empty entries", format_count(empty));
+    info!(config, "Removed {} duplicate entries", format_count(duplicates));
+    match config.sort {
+        SortOrder::Hash => info!(config, "Sorted entries by hash"),
+        SortOrder::Size => info!(config, "Sorted entries by size"),
+        SortOrder::None | SortOrder::Input => ()
     }
-    args.remove(0);
-    for arg in args {
-        config.files.push(PathBuf::from(arg));
+
+    let output = config.output.clone().unwrap_or_else(|| PathBuf::from("output.dxvk-cache"));
+    let written = format_count(entries.len());
+    info!(config, "Writing {} entries to file {}", written, util::file_name(&output));
+    let header = DxvkStateCacheHeader {
+        magic:      MAGIC_STRING,
+        version:    header.version,
+        entry_size: header.entry_size
+    };
+    let output_size =
+        format::write_cache(&output, &header, entries.values(), config.buffer_size)?;
+
+    info!(
+        config,
+        "{} \u{2192} {} entries, {} \u{2192} {}",
+        format_count(count),
+        format_count(entries.len()),
+        config.size(input_size),
+        config.size(output_size)
+    );
+    info!(config, "Finished");
+
+    Ok(())
+}
+
+/// Passes only if the candidate contains every valid entry of the
+/// existing cache, i.e. replacing the existing cache loses nothing.
+fn covers(config: &Config) -> Result<Outcome, Error> {
+    let (candidate_path, existing_path) = (&config.files[0], &config.files[1]);
+    let (candidate_version, candidate) = open_valid_entries(candidate_path, config)?;
+    let (existing_version, existing) = open_valid_entries(existing_path, config)?;
+    if candidate_version != existing_version {
+        return Err(merge::version_mismatch(
+            candidate_version,
+            Some(candidate_path),
+            existing_version,
+            existing_path
+        ));
+    }
+
+    let missing = missing_hashes(existing.keys(), |h| candidate.contains_key(h));
+    let extra = missing_hashes(candidate.keys(), |h| existing.contains_key(h)).len();
+    if missing.is_empty() {
+        output!(
+            config,
+            "{} covers all {} entries of {} and adds {} new entries",
+            candidate_path.display(),
+            util::format_count(existing.len() as u64),
+            existing_path.display(),
+            util::format_count(extra as u64)
+        );
+        Ok(Outcome::Passed)
+    } else {
+        output!(
+            config,
+            "{} is missing {} of {} entries of {} and adds {} new entries",
+            candidate_path.display(),
+            util::format_count(missing.len() as u64),
+            util::format_count(existing.len() as u64),
+            existing_path.display(),
+            util::format_count(extra as u64)
+        );
+        if config.verbose() {
+            for hash in &missing {
+                output!(config, "\t{}", hash_to_hex(hash));
+            }
+        }
+        Ok(Outcome::Failed)
     }
-    config
 }
 
-fn main() -> Result<(), Error> {
-    let mut config = process_args();
+/// First difference found between two caches by `equal`.
+enum Difference {
+    /// With `--bytes`, the files differ in size.
+    Size(u64, u64),
+    /// With `--bytes`, the first offset at which the files differ.
+    Byte(u64),
+    Version(u32, u32),
+    /// Numbers of valid entries.
+    Count(usize, usize),
+    /// The lowest hash of a valid entry of one cache missing from the other,
+    /// along with the cache lacking it.
+    Missing(Sha1Hash, usize),
+    /// The lowest hash whose entries differ in stage mask or data.
+    Payload(Sha1Hash)
+}
 
-    print!("Merging files");
-    for path in config.files.iter() {
-        print!(" {}", path.file_name().and_then(OsStr::to_str).unwrap());
+impl Difference {
+    fn kind(&self) -> &'static str {
+        match self {
+            Difference::Size(..) => "size",
+            Difference::Byte(_) => "byte",
+            Difference::Version(..) => "version",
+            Difference::Count(..) => "count",
+            Difference::Missing(..) => "missing",
+            Difference::Payload(_) => "payload"
+        }
     }
-    println!();
-    let mut entries = LinkedHashMap::new();
-    for (i, path) in config.files.iter().enumerate() {
-        if path.extension().and_then(OsStr::to_str) != Some("dxvk-cache") {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                "File extension mismatch: expected .dxvk-cache"
-            ));
+
+    fn describe(&self, files: &[PathBuf]) -> String {
+        match self {
+            Difference::Size(first, second) => {
+                format!("Sizes differ: {} vs {} bytes", first, second)
+            },
+            Difference::Byte(offset) => format!("Files differ at byte {}", offset),
+            Difference::Version(first, second) => {
+                format!("Versions differ: v{} vs v{}", first, second)
+            },
+            Difference::Count(first, second) => {
+                format!("Entry counts differ: {} vs {}", first, second)
+            },
+            Difference::Missing(hash, lacking) => format!(
+                "Entry {} is missing from {}",
+                hash_to_hex(hash),
+                files[*lacking].display()
+            ),
+            Difference::Payload(hash) => format!("Payload mismatch at hash {}", hash_to_hex(hash))
         }
+    }
 
-        let file = File::open(path)?;
-        let mut reader = BufReader::new(file);
+    fn to_json(&self, files: &[PathBuf]) -> String {
+        let details = match self {
+            Difference::Size(first, second) => {
+                format!(",\"first\":{},\"second\":{}", first, second)
+            },
+            Difference::Byte(offset) => format!(",\"offset\":{}", offset),
+            Difference::Version(first, second) => {
+                format!(",\"first\":{},\"second\":{}", first, second)
+            },
+            Difference::Count(first, second) => {
+                format!(",\"first\":{},\"second\":{}", first, second)
+            },
+            Difference::Missing(hash, lacking) => format!(
+                ",\"hash\":\"{}\",\"path\":{}",
+                hash_to_hex(hash),
+                json::path(&files[*lacking])
+            ),
+            Difference::Payload(hash) => format!(",\"hash\":\"{}\"", hash_to_hex(hash))
+        };
+        format!(
+            "{{\"kind\":\"{}\",\"message\":{}{}}}",
+            self.kind(),
+            json::string(&self.describe(files)),
+            details
+        )
+    }
+}
 
-        let header = read_header(&mut reader)?;
+/// Reads a cache and writes it back in memory, checking that the writer
+/// reproduces every byte read, as DXVK wrote them. Fails if not,
+/// telling where the bytes differ. Bytes after the last entry aren't read,
+/// so they count as a difference too. With `-o`, also writes the cache.
+fn passthrough(config: &Config) -> Result<Outcome, Error> {
+    let path = &config.files[0];
+    let input = fs::read(path).map_err(|e| Error::from(e).with_path(path))?;
+    let (header, entries) = format::read_cache_from(input.as_slice(), &Limits::default())
+        .map_err(|e| e.with_path(path))?;
+    let mut written = Vec::with_capacity(input.len());
+    format::write_cache_to(&mut written, &header, entries.iter())?;
+    if let Some(output) = &config.output {
+        format::write_cache(output, &header, entries.iter(), config.buffer_size)?;
+        info!(config, "Wrote {} entries to file {}", entries.len(), util::file_name(output));
+    }
 
-        if header.magic != MAGIC_STRING {
-            return Err(Error::new(ErrorKind::InvalidData, "Magic string mismatch"));
-        }
+    let differing = input.iter().zip(&written).filter(|(a, b)| a != b).count()
+        + input.len().abs_diff(written.len());
+    if differing == 0 {
+        output!(
+            config,
+            "Round trip of {} entries reproduces all {} bytes of {}",
+            util::format_count(entries.len() as u64),
+            util::format_count(input.len() as u64),
+            path.display()
+        );
+        return Ok(Outcome::Passed);
+    }
 
-        if config.version == 0 {
-            config.version = header.version;
-            config.edition = if header.version > LEGACY_VERSION {
-                DxvkStateCacheEdition::Standard
-            } else {
-                DxvkStateCacheEdition::Legacy
-            };
-            config.entry_size = header.entry_size;
-            println!("Detected state cache version v{}", header.version);
+    // Entries start at the same offsets in both up to the first difference.
+    let mut offset = HEADER_SIZE;
+    let ranges: Vec<_> = entries
+        .iter()
+        .enumerate()
+        .map(|(i, entry)| {
+            let range = offset..offset + entry.serialized_size();
+            offset = range.end;
+            (i, entry, range)
+        })
+        .collect();
+    let first = input.iter().zip(&written).position(|(a, b)| a != b);
+    let first = first.unwrap_or_else(|| input.len().min(written.len()));
+    let location = if first < HEADER_SIZE {
+        "in the header".to_string()
+    } else {
+        match ranges.iter().find(|(_, _, range)| range.contains(&first)) {
+            Some((i, entry, _)) => format!("in entry {} ({})", i, hash_to_hex(&entry.hash)),
+            None => "after the last entry".to_string()
         }
-
-        if header.version != config.version {
-            return Err(Error::new(
-                ErrorKind::InvalidInput,
-                format!(
-                    "State cache version mismatch: expected v{}, found v{}",
-                    config.version, header.version
-                )
-            ));
+    };
+    if input.len() != written.len() {
+        let (read, written) = (input.len() as u64, written.len() as u64);
+        output!(
+            config,
+            "Read {} bytes but wrote {}",
+            util::format_count(read),
+            util::format_count(written)
+        );
+    }
+    let differing = util::format_count(differing as u64);
+    output!(config, "{} bytes differ, the first at offset {} {}", differing, first, location);
+    if config.verbose() {
+        for (i, entry, range) in &ranges {
+            if input.get(range.clone()) != written.get(range.clone()) {
+                let hash = hash_to_hex(&entry.hash);
+                output!(config, "\tEntry {} at offset {}: {}", i, range.start, hash);
+            }
         }
+    }
+    Ok(Outcome::Failed)
+}
 
-        let mut omitted = 0;
-        let entries_len = entries.len();
-        print!(
-            "Merging {} ({}/{})... ",
-            path.file_name().and_then(OsStr::to_str).unwrap(),
-            i + 1,
-            config.files.len()
+/// Groups the caches in a directory, such as those kept of successive
+/// sessions of a game, into buckets by when they were last modified, and
+/// tells bucket by bucket how many valid entries they added to those of the
+/// buckets before, by stages, and how many there are so far.
+fn stats_timeline(config: &Config) -> Result<(), Error> {
+    let dir = &config.files[0];
+    let mut buckets: BTreeMap<String, Vec<PathBuf>> = BTreeMap::new();
+    let entries = fs::read_dir(dir).map_err(|e| Error::from(e).with_path(dir))?;
+    for entry in entries {
+        let path = entry.map_err(|e| Error::from(e).with_path(dir))?.path();
+        if path.extension() != Some(OsStr::new("dxvk-cache")) || !path.is_file() {
+            continue;
+        }
+        let modified = fs::metadata(&path).and_then(|m| m.modified());
+        let modified = modified.map_err(|e| Error::from(e).with_path(&path))?;
+        buckets.entry(config.group_by.label(modified)).or_default().push(path);
+    }
+    if buckets.is_empty() {
+        return Err(
+            Error::new(ErrorKind::InvalidInput, "No .dxvk-cache files in the directory")
+                .with_path(dir)
         );
-        loop {
-            let res = match config.edition {
-                DxvkStateCacheEdition::Standard => read_entry(&mut reader),
-                DxvkStateCacheEdition::Legacy => {
-                    read_entry_legacy(&mut reader, header.entry_size as usize)
-                },
-            };
-            match res {
-                Ok(e) => {
-                    if e.is_valid() {
-                        entries.insert(e.hash, e);
-                    } else {
-                        omitted += 1;
-                    }
-                },
-                Err(ref e) if e.kind() == ErrorKind::IoError(io::ErrorKind::UnexpectedEof) => break,
-                Err(e) => return Err(e)
+    }
+
+    let mut table = Table::new()
+        .column("Bucket", Align::Left)
+        .column("Files", Align::Right)
+        .column("Added", Align::Right)
+        .column("Graphics", Align::Right)
+        .column("Compute", Align::Right)
+        .column("Other", Align::Right)
+        .column("Total", Align::Right);
+    let mut seen = HashSet::new();
+    for (label, files) in &mut buckets {
+        files.sort();
+        let (mut graphics, mut compute, mut other) = (0, 0, 0);
+        for path in files.iter() {
+            let (_, entries) = open_valid_entries(path, config)?;
+            for (hash, entry) in entries {
+                if !seen.insert(hash) {
+                    continue;
+                }
+                match entry.stages_with(config.legacy_stages).map_err(|e| e.with_path(path))? {
+                    Stages::Graphics => graphics += 1,
+                    Stages::Compute => compute += 1,
+                    Stages::Ambiguous | Stages::Unknown => other += 1
+                }
             }
         }
-        println!("{} new entries", entries.len() - entries_len);
-        if omitted > 0 {
-            println!("{} entries are omitted as invalid", omitted);
+        table.row(vec![
+            label.clone(),
+            files.len().to_string(),
+            (graphics + compute + other).to_string(),
+            graphics.to_string(),
+            compute.to_string(),
+            other.to_string(),
+            seen.len().to_string()
+        ]);
+    }
+    let style = if config.csv { table::Style::Csv } else { config.table_style() };
+    output!(config, "{}", table.render(style));
+    Ok(())
+}
+
+/// Reads a list of hashes, one per line, skipping empty lines and those
+/// starting with `#`.
+fn read_hash_list(list: &Path) -> Result<HashSet<Sha1Hash>, Error> {
+    let lines = util::read_lines(list).map_err(|e| Error::from(e).with_path(list))?;
+    let mut hashes = HashSet::new();
+    for (i, line) in lines.iter().enumerate() {
+        if line.is_empty() || line.starts_with('#') {
+            continue;
         }
+        let hash = parse_hash(line).map_err(|e| {
+            Error::new(ErrorKind::InvalidInput, format!("Line {}: {}", i + 1, e.message()))
+                .with_path(list)
+        })?;
+        hashes.insert(hash);
     }
+    Ok(hashes)
+}
 
-    if entries.is_empty() {
-        return Err(Error::new(
-            ErrorKind::InvalidData,
-            "No valid state cache entries found"
-        ));
+/// Removes the entries whose hashes are listed in a file, one per line,
+/// leaving the others as they are, invalid and duplicate entries included.
+/// Nothing is validated or hashed, so the entries left are written back
+/// byte for byte, unless `--strip-to-hashes` sorts them.
+fn redact(config: &Config) -> Result<(), Error> {
+    let (path, list) = (&config.files[0], &config.files[1]);
+    let hashes = read_hash_list(list)?;
+    // Hashed before writing, as the output may replace the input.
+    let input_hash = if config.write_meta {
+        Some(resume::file_hash(path).map_err(|e| Error::from(e).with_path(path))?)
+    } else {
+        None
+    };
+
+    let (header, mut entries) = open_cache(path, config)?;
+    let count = entries.len();
+    entries.retain(|entry| !hashes.contains(&entry.hash));
+    let removed = count - entries.len();
+    let format_count = |n: usize| util::format_count(n as u64);
+    info!(
+        config,
+        "Removed {} of {} entries of {}",
+        format_count(removed),
+        format_count(count),
+        path.display()
+    );
+    match config.sort {
+        SortOrder::Hash => {
+            entries.sort_by_key(|entry| entry.hash);
+            info!(config, "Sorted entries by hash");
+        },
+        SortOrder::Size => {
+            entries.sort_by_key(|entry| entry.data.len());
+            info!(config, "Sorted entries by size");
+        },
+        SortOrder::None | SortOrder::Input => ()
     }
 
-    println!(
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let output = config
+        .output
+        .clone()
+        .unwrap_or_else(|| path.with_file_name(format!("{}.redacted.dxvk-cache", stem)));
+    info!(
+        config,
         "Writing {} entries to file {}",
-        entries.len(),
-        config.output.file_name().and_then(OsStr::to_str).unwrap()
+        format_count(entries.len()),
+        util::file_name(&output)
     );
+    format::write_cache(&output, &header, entries.iter(), config.buffer_size)?;
+    if let Some(hash) = input_hash {
+        let mut provenance = Provenance::record(&output, [(path.as_path(), hash)], entries.len())?;
+        provenance.transformations.push(provenance::REDACT.to_string());
+        if let Some(transformation) = sort_transformation(config.sort) {
+            provenance.transformations.push(transformation.to_string());
+        }
+        if config.strip_to_hashes {
+            provenance.strip_input_names();
+        }
+        provenance.write(&output)?;
+        info!(
+            config,
+            "Wrote provenance metadata to {}",
+            util::file_name(&provenance::sidecar_path(&output))
+        );
+    }
+    info!(config, "Finished");
+    Ok(())
+}
 
-    let header = DxvkStateCacheHeader {
-        magic:      MAGIC_STRING,
-        version:    config.version,
-        entry_size: config.entry_size
+/// Passes only if two caches are equal. By default they are equal when
+/// they have the same version and the same valid entries in any order, as
+/// DXVK would load the same pipelines from both. With `--bytes`, only
+/// identical files are equal.
+fn equal(config: &Config) -> Result<Outcome, Error> {
+    let difference = if config.bytes {
+        byte_difference(&config.files, config.buffer_size)?
+    } else {
+        entry_difference(config)?
     };
 
-    let file = File::create(&config.output)?;
-    let mut writer = BufWriter::new(file);
-    wrtie_header(&mut writer, header)?;
-    for (_, entry) in &entries {
-        match config.edition {
-            DxvkStateCacheEdition::Standard => write_entry(&mut writer, entry)?,
-            DxvkStateCacheEdition::Legacy => write_entry_legacy(&mut writer, entry)?
-        };
+    if config.json {
+        output!(
+            config,
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"paths\":[{},{}],\"mode\":\"{}\",\
+             \"equal\":{},\"difference\":{},\"warnings\":{}}}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            json::path(&config.files[0]),
+            json::path(&config.files[1]),
+            if config.bytes { "bytes" } else { "entries" },
+            difference.is_none(),
+            difference.as_ref().map_or("null".into(), |d| d.to_json(&config.files)),
+            config.warnings_json()
+        );
+    } else if let Some(difference) = &difference {
+        info!(config, "{}", difference.describe(&config.files));
+    } else if config.bytes {
+        info!(config, "Files are identical");
+    } else {
+        info!(config, "Caches hold the same entries");
     }
 
-    println!("Finished");
+    Ok(if difference.is_some() { Outcome::Failed } else { Outcome::Passed })
+}
 
-    Ok(())
+/// Streams both files, comparing them buffer by buffer.
+fn byte_difference(files: &[PathBuf], buffer_size: usize) -> Result<Option<Difference>, Error> {
+    let open = |path: &PathBuf| -> Result<(u64, BufReader<File>), Error> {
+        let file = File::open(path).map_err(|e| Error::from(e).with_path(path))?;
+        let size = file.metadata().map_err(|e| Error::from(e).with_path(path))?.len();
+        Ok((size, BufReader::with_capacity(buffer_size, file)))
+    };
+    let (first_size, mut first) = open(&files[0])?;
+    let (second_size, mut second) = open(&files[1])?;
+    if first_size != second_size {
+        return Ok(Some(Difference::Size(first_size, second_size)));
+    }
+
+    let mut offset = 0;
+    loop {
+        let a = first.fill_buf().map_err(|e| Error::from(e).with_path(&files[0]))?;
+        let b = second.fill_buf().map_err(|e| Error::from(e).with_path(&files[1]))?;
+        let len = a.len().min(b.len());
+        if len == 0 {
+            // Only if a file changed size while reading it.
+            return Ok((a.len() != b.len()).then_some(Difference::Byte(offset)));
+        }
+        if let Some(i) = a[..len].iter().zip(&b[..len]).position(|(a, b)| a != b) {
+            return Ok(Some(Difference::Byte(offset + i as u64)));
+        }
+        first.consume(len);
+        second.consume(len);
+        offset += len as u64;
+    }
 }
 
-fn read_header<R: Read>(reader: &mut BufReader<R>) -> Result<DxvkStateCacheHeader, Error> {
-    Ok(DxvkStateCacheHeader {
-        magic:      {
-            let mut magic = [0; 4];
-            reader.read_exact(&mut magic)?;
-            magic
-        },
-        version:    reader.read_u32()?,
-        entry_size: reader.read_u32()?
-    })
+/// Compares the valid entries of both caches as sets, like `covers` does.
+fn entry_difference(config: &Config) -> Result<Option<Difference>, Error> {
+    let (first_version, first) = open_valid_entries(&config.files[0], config)?;
+    let (second_version, second) = open_valid_entries(&config.files[1], config)?;
+    if first_version != second_version {
+        return Ok(Some(Difference::Version(first_version, second_version)));
+    }
+    if first.len() != second.len() {
+        return Ok(Some(Difference::Count(first.len(), second.len())));
+    }
+    // With equal counts, an entry missing on one side means another is
+    // missing on the other side too.
+    if let Some(hash) = missing_hashes(first.keys(), |h| second.contains_key(h)).first() {
+        return Ok(Some(Difference::Missing(**hash, 1)));
+    }
+    let mut hashes: Vec<&Sha1Hash> = first.keys().collect();
+    hashes.sort_unstable();
+    let mismatch = hashes.into_iter().find(|h| first.get(*h) != second.get(*h));
+    Ok(mismatch.map(|h| Difference::Payload(*h)))
+}
+
+/// Reads the version and the valid entries of a cache by their hash.
+fn open_valid_entries(
+    path: &Path,
+    config: &Config
+) -> Result<(u32, HashMap<Sha1Hash, DxvkStateCacheEntry>), Error> {
+    let (header, entries) = open_cache(path, config)?;
+    let valid = validate_entries(&entries, HashKind::from_version(header.version), config.threads);
+    let entries = entries
+        .into_iter()
+        .zip(valid)
+        .filter(|(_, valid)| *valid)
+        .map(|(e, _)| (e.hash, e))
+        .collect();
+    Ok((header.version, entries))
 }
 
-fn read_entry<R: Read>(reader: &mut BufReader<R>) -> Result<DxvkStateCacheEntry, Error> {
-    let header = DxvkStateCacheEntryHeader {
-        stage_mask: reader.read_u8()?,
-        entry_size: reader.read_u24()? as u32
+/// Index files list the hashes of all valid entries of a cache, sorted so
+/// that lookups can binary search them:
+///
+/// magic "DXIX" | u32 index version | u32 hash count | sorted 20-byte hashes
+///
+/// An index is not updated with its cache and has to be rebuilt whenever the
+/// cache changes.
+fn build_index(config: &Config) -> Result<(), Error> {
+    let path = &config.files[0];
+    let output = match &config.output {
+        Some(output) => output.clone(),
+        None => path.with_extension("idx")
     };
-    let mut entry = DxvkStateCacheEntry::with_header(header);
-    reader.read_exact(&mut entry.hash)?;
-    reader.read_exact(&mut entry.data)?;
+    let (header, entries) = open_cache(path, config)?;
+    let valid = validate_entries(&entries, HashKind::from_version(header.version), config.threads);
+    let mut hashes: Vec<Sha1Hash> = entries
+        .into_iter()
+        .zip(valid)
+        .filter(|(_, valid)| *valid)
+        .map(|(e, _)| e.hash)
+        .collect();
+    hashes.sort_unstable();
+    hashes.dedup();
+
+    output!(
+        config,
+        "Writing {} hashes to file {}",
+        hashes.len(),
+        util::file_name(&output)
+    );
+    write_index(&output, &hashes).map_err(|e| Error::from(e).with_path(&output))?;
+
+    output!(config, "Finished");
 
-    Ok(entry)
+    Ok(())
 }
 
-fn read_entry_legacy<R: Read>(
-    reader: &mut BufReader<R>,
-    size: usize
-) -> Result<DxvkStateCacheEntry, Error> {
-    let mut entry = DxvkStateCacheEntry::with_length(size);
-    reader.read_exact(&mut entry.data)?;
-    reader.read_exact(&mut entry.hash)?;
+fn write_index(path: &Path, hashes: &[Sha1Hash]) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut writer = BufWriter::new(file);
+    writer.write_all(&INDEX_MAGIC_STRING)?;
+    writer.write_u32(INDEX_VERSION)?;
+    writer.write_u32(hashes.len() as u32)?;
+    for hash in hashes {
+        writer.write_all(hash)?;
+    }
+    writer.flush()
+}
 
-    Ok(entry)
+fn has_hash(config: &Config) -> Result<Outcome, Error> {
+    let path = &config.files[0];
+    let prefix = &config.prefixes[0];
+    let matches = search_index(path, prefix).map_err(|e| e.with_path(path))?;
+    match resolve_prefix(prefix, &matches) {
+        PrefixMatch::Unique(hash) => output!(config, "{} found", hash_to_hex(&hash)),
+        PrefixMatch::NotFound => {
+            output!(config, "{} not found", prefix);
+            return Ok(Outcome::Failed);
+        },
+        PrefixMatch::Ambiguous(_) if config.all_matching => {
+            for hash in &matches {
+                output!(config, "{} found", hash_to_hex(hash));
+            }
+        },
+        PrefixMatch::Ambiguous(candidates) => return Err(ambiguous_prefix(prefix, &candidates))
+    }
+    Ok(Outcome::Passed)
 }
 
-fn wrtie_header<W: Write>(
-    writer: &mut BufWriter<W>,
-    header: DxvkStateCacheHeader
+/// Prints the stage mask, the size and a hex dump of the data of the entry
+/// whose hash starts with `prefix`, cut after `--max-dump` bytes, or of
+/// every entry matching with `--all-matching`.
+fn print_entry(
+    config: &Config,
+    path: &Path,
+    prefix: &HashPrefix,
+    entries: &[DxvkStateCacheEntry],
+    valid: &[bool]
 ) -> Result<(), Error> {
-    writer.write_all(&MAGIC_STRING)?;
-    writer.write_u32(header.version)?;
-    writer.write_u32(header.entry_size as u32)?;
-
+    // A cache may hold an entry more than once, which isn't ambiguous.
+    let hashes: BTreeMap<&Sha1Hash, _> =
+        entries.iter().zip(valid).rev().map(|(e, valid)| (&e.hash, (e, *valid))).collect();
+    let matches: Vec<_> = match resolve_prefix(prefix, hashes.keys().copied()) {
+        PrefixMatch::Unique(hash) => hashes.get(&hash).into_iter().collect(),
+        PrefixMatch::Ambiguous(_) if config.all_matching => {
+            hashes.iter().filter(|(h, _)| prefix.matches(h)).map(|(_, e)| e).collect()
+        },
+        PrefixMatch::Ambiguous(candidates) => return Err(ambiguous_prefix(prefix, &candidates)),
+        PrefixMatch::NotFound => Vec::new()
+    };
+    if matches.is_empty() {
+        return Err(Error::new(
+            ErrorKind::InvalidInput,
+            format!("No entry has the hash {}", prefix)
+        )
+        .with_path(path));
+    }
+    for (i, &&(entry, valid)) in matches.iter().enumerate() {
+        if i > 0 {
+            output!(config, "");
+        }
+        output!(config, "Hash: {}", hash_to_hex(&entry.hash));
+        match entry.stage_mask() {
+            Some(mask) => output!(config, "Stage mask: {:#04x}", mask),
+            None => output!(config, "Stage mask: none, legacy entries have none")
+        }
+        output!(config, "Size: {}", util::format_bytes_exact(entry.data.len() as u64));
+        let valid = if valid { "yes" } else { "no, the hash doesn't match the data" };
+        output!(config, "Valid: {}", valid);
+        let shown = match config.max_dump {
+            0 => entry.data.len(),
+            max => max.min(entry.data.len())
+        };
+        output!(config, "Data:");
+        if shown > 0 {
+            output!(config, "{}", util::hex_dump(entry.data.get(..shown).unwrap_or_default()));
+        }
+        if shown < entry.data.len() {
+            output!(
+                config,
+                "... {} more bytes, shown with --max-dump 0",
+                util::format_count((entry.data.len() - shown) as u64)
+            );
+        }
+    }
     Ok(())
 }
 
-fn write_entry<W: Write>(
-    writer: &mut BufWriter<W>,
-    entry: &DxvkStateCacheEntry
-) -> Result<(), Error> {
-    if let Some(h) = &entry.header {
-        writer.write_u8(h.stage_mask)?;
-        writer.write_u24(h.entry_size)?;
+fn ambiguous_prefix(prefix: &HashPrefix, candidates: &[Sha1Hash]) -> Error {
+    let mut message = format!("Hash prefix {} is ambiguous, it matches", prefix);
+    for hash in candidates {
+        message.push_str(&format!("\n\t{}", hash_to_hex(hash)));
     }
-    writer.write_all(&entry.hash)?;
-    writer.write_all(&entry.data)?;
+    if candidates.len() == MAX_CANDIDATES {
+        message.push_str("\n\t...");
+    }
+    message.push_str("\nUse a longer prefix, or --all-matching to use every match");
+    Error::new(ErrorKind::InvalidInput, message)
+}
 
-    Ok(())
+/// Returns the hashes in an index starting with `prefix`, in order.
+fn search_index(path: &Path, prefix: &HashPrefix) -> Result<Vec<Sha1Hash>, Error> {
+    let file = File::open(path)?;
+    let mut reader = BufReader::new(file);
+    let mut magic = [0; 4];
+    reader.read_exact(&mut magic)?;
+    if magic != INDEX_MAGIC_STRING {
+        return Err(Error::new(ErrorKind::InvalidData, "Index magic string mismatch"));
+    }
+    let version = reader.read_u32()?;
+    if version != INDEX_VERSION {
+        return Err(Error::new(
+            ErrorKind::InvalidData,
+            format!("Unsupported index version {}", version)
+        ));
+    }
+    let count = u64::from(reader.read_u32()?);
+
+    // Binary search for the first hash not below the prefix, then read
+    // on while the hashes match.
+    let needle = prefix.first();
+    let (mut low, mut high) = (0, count);
+    let mut hash = [0; HASH_SIZE];
+    while low < high {
+        let mid = low + (high - low) / 2;
+        let offset = INDEX_HEADER_SIZE + mid * HASH_SIZE as u64;
+        reader.seek(SeekFrom::Start(offset))?;
+        reader.read_exact(&mut hash).map_err(|e| Error::from(e).with_offset(offset))?;
+        match hash.cmp(&needle) {
+            Ordering::Less => low = mid + 1,
+            Ordering::Greater | Ordering::Equal => high = mid
+        }
+    }
+    let mut matches = Vec::new();
+    let offset = INDEX_HEADER_SIZE + low * HASH_SIZE as u64;
+    reader.seek(SeekFrom::Start(offset))?;
+    for i in low..count {
+        let offset = INDEX_HEADER_SIZE + i * HASH_SIZE as u64;
+        reader.read_exact(&mut hash).map_err(|e| Error::from(e).with_offset(offset))?;
+        if !prefix.matches(&hash) {
+            break;
+        }
+        matches.push(hash);
+    }
+    Ok(matches)
 }
 
-fn write_entry_legacy<W: Write>(
-    writer: &mut BufWriter<W>,
-    entry: &DxvkStateCacheEntry
-) -> Result<(), Error> {
-    writer.write_all(&entry.data)?;
-    writer.write_all(&entry.hash)?;
+/// Reads all entries of a cache, or of a snapshot of it with `--snapshot`.
+/// With `--assume-version`, the entries are read as that version whatever
+/// the header says, and with `--salvage` also whatever the file contains
+/// between them. `--resync` skips past entries with a corrupt header.
+fn open_cache(
+    path: &Path,
+    config: &Config
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>), Error> {
+    let snapshot = if config.snapshot {
+        let snapshot = Snapshot::take(path).map_err(|e| e.with_path(path))?;
+        info!(
+            config,
+            "Using a snapshot of {} taken at {}",
+            util::file_name(path),
+            util::format_timestamp(snapshot.taken_at)
+        );
+        Some(snapshot)
+    } else {
+        None
+    };
+    let source = snapshot.as_ref().map_or(path, |s| s.path());
+    let limits = Limits::default();
+    if let (true, Some(version)) = (config.salvage, config.assume_version) {
+        let (header, entries, salvage) =
+            format::salvage_cache(source, version, &limits).map_err(|e| e.with_path(path))?;
+        info!(
+            config,
+            "Salvaged {} entries from {}, skipping {}",
+            salvage.recovered,
+            util::file_name(path),
+            util::format_bytes(salvage.skipped_bytes)
+        );
+        return Ok((header, entries));
+    }
+    if config.resync {
+        let (header, entries, resync) =
+            format::read_cache_resync(source, config.assume_version, &limits)
+                .map_err(|e| e.with_path(path))?;
+        print_resync(config, path, &resync);
+        return Ok((header, entries));
+    }
+    let mut retried = Vec::new();
+    let (version, retry, buffer_size) = (config.assume_version, config.retry, config.buffer_size);
+    let progress = &mut |_, _| {};
+    let result =
+        format::read_cache(source, version, &limits, retry, &mut retried, buffer_size, progress)
+            .map_err(|e| e.with_path(path));
+    if !retried.is_empty() {
+        print_retries(config, path, &retried);
+    }
+    result
+}
 
-    Ok(())
+/// Reports the bytes of `path` skipped to resynchronize with its entries,
+/// each stretch only with `--verbose`.
+fn print_resync(config: &Config, path: &Path, resync: &Resync) {
+    if resync.skipped.is_empty() {
+        return;
+    }
+    let total = resync.skipped.iter().map(|(_, len)| len).sum();
+    info!(
+        config,
+        "Skipped {} of {} to resynchronize with its entries",
+        config.size(total),
+        util::file_name(path)
+    );
+    if config.verbose() {
+        for (offset, len) in &resync.skipped {
+            info!(config, "\tskipped {} at offset {:#x}", util::format_bytes_exact(*len), offset);
+        }
+    }
+}
+
+/// Reports the transient errors retried on `path`, each of them only with
+/// `--verbose`.
+fn print_retries(config: &Config, path: &Path, errors: &[Error]) {
+    info!(config, "Retried {} transient I/O errors on {}", errors.len(), util::file_name(path));
+    if config.verbose() {
+        for error in errors {
+            info!(config, "\t{}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::{Duration, UNIX_EPOCH};
+
+    fn day(year: i64, month: u32, day: u32) -> SystemTime {
+        let days = util::days_from_civil(year, month, day) as u64;
+        UNIX_EPOCH + Duration::from_secs(days * 86400 + 43200)
+    }
+
+    #[test]
+    fn group_by_labels_sort_like_their_buckets() {
+        assert_eq!(GroupBy::Day.label(day(2024, 2, 29)), "2024-02-29");
+        assert_eq!(GroupBy::Month.label(day(2024, 2, 29)), "2024-02");
+        assert_eq!(GroupBy::Day.label(UNIX_EPOCH), "1970-01-01");
+    }
+
+    #[test]
+    fn group_by_week_follows_iso_weeks() {
+        assert_eq!(GroupBy::Week.label(day(2024, 1, 1)), "2024-W01");
+        assert_eq!(GroupBy::Week.label(day(2024, 1, 7)), "2024-W01");
+        assert_eq!(GroupBy::Week.label(day(2024, 1, 8)), "2024-W02");
+        // Weeks belong to the year of their Thursday.
+        assert_eq!(GroupBy::Week.label(day(2024, 12, 30)), "2025-W01");
+        assert_eq!(GroupBy::Week.label(day(2021, 1, 3)), "2020-W53");
+        assert_eq!(GroupBy::Week.label(UNIX_EPOCH), "1970-W01");
+    }
+
+    #[test]
+    fn parse_version_range_accepts_versions_and_ranges() {
+        assert_eq!(parse_version_range("17").unwrap(), 17..=17);
+        assert_eq!(parse_version_range("15..=17").unwrap(), 15..=17);
+        assert_eq!(parse_version_range("15..18").unwrap(), 15..=17);
+        assert_eq!(parse_version_range("v15..=latest").unwrap(), 15..=CURRENT_VERSION);
+        assert_eq!(parse_version_range("dxvk-2.0..=17").unwrap(), 16..=17);
+        assert_eq!(parse_version_range("16..17").unwrap(), 16..=16);
+    }
+
+    #[test]
+    fn parse_version_range_rejects_empty_and_malformed_ranges() {
+        let ranges = ["", "0", "17..=15", "15..15", "15...17", "15..", "..=17", "a..=b", "15-17"];
+        for s in ranges {
+            let error = parse_version_range(s).unwrap_err();
+            assert!(error.kind() == ErrorKind::InvalidInput, "{}", s);
+        }
+        let error = parse_version_range("17..=15").unwrap_err();
+        assert_eq!(error.message(), "Empty version range: 17..=15");
+        let error = parse_version_range("dxvk-0.1..=17").unwrap_err();
+        assert_eq!(error.message(), "Unknown DXVK release: 0.1");
+    }
+
+    #[test]
+    fn parse_size_accepts_binary_suffixes() {
+        assert_eq!(parse_size("0").unwrap(), 0);
+        assert_eq!(parse_size("512").unwrap(), 512);
+        assert_eq!(parse_size("4K").unwrap(), 4 << 10);
+        assert_eq!(parse_size("4kib").unwrap(), 4 << 10);
+        assert_eq!(parse_size("2MB").unwrap(), 2 << 20);
+        assert_eq!(parse_size("1G").unwrap(), 1 << 30);
+        for s in ["", "K", "1T", "1.5M", "-1", "1 M"] {
+            assert!(parse_size(s).is_err(), "{}", s);
+        }
+        let error = parse_size("99999999999999G").unwrap_err();
+        assert_eq!(error.message(), "Size is too large: 99999999999999G");
+    }
 }
@@ -0,0 +1,2481 @@
+//! Merging state caches, the engine behind the command line tool.
+//!
+//! A merge is described by a `Config` made with `ConfigBuilder`:
+//!
+//! ```no_run
+//! use dxvk_cache_tool::merge::{self, ConfigBuilder};
+//!
+//! let config = ConfigBuilder::new()
+//!     .add_file("a.dxvk-cache")
+//!     .add_file("b.dxvk-cache")
+//!     .output("merged.dxvk-cache")
+//!     .build()?;
+//! let report = merge::merge_files(&config)?;
+//! println!("{} entries", report.entries);
+//! # Ok::<(), dxvk_cache_tool::error::Error>(())
+//! ```
+//!
+//! Whatever order the options are set in, a merge runs the same passes
+//! over the entries in memory, in this order:
+//!
+//! 1. Merging the valid entries of the inputs, resolving conflicts between
+//!    entries with the same hash as they are found.
+//! 2. Keeping and dropping entries by hash, by `keep_hashes` and
+//!    `drop_hashes`.
+//! 3. Keeping the entries of some stages, by `only_stages`.
+//! 4. Dropping the entries found in too few inputs, by `min_sources`.
+//! 5. Evicting entries over the limits, by `max_bytes` and `max_entries`.
+//! 6. Sorting, by `sort`.
+//! 7. Writing the output.
+//!
+//! The report counts the entries left after each pass that runs.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Read};
+use std::mem;
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+use linked_hash_map::LinkedHashMap;
+
+use crate::dxvk::*;
+use crate::error::{Error, ErrorKind};
+use crate::format::{self, Limits, Resync, Retry, Salvage, DEFAULT_BUFFER_SIZE};
+use crate::progress::{Phase, Progress};
+use crate::report::{Comparison, FileReport, MergeReport};
+use crate::resume::MergeJournal;
+use crate::snapshot::Snapshot;
+use crate::template::OutputTemplate;
+use crate::util;
+use crate::warning::{Deny, Warning, WarningId};
+
+/// Order of the entries in the output.
+#[derive(Clone, Copy, PartialEq)]
+pub enum SortOrder {
+    /// The order the entries were first seen in, the default: an entry
+    /// found again in a later input keeps its place. It follows the order
+    /// DXVK appended them in, the order pipelines were compiled in.
+    None,
+    /// By the input each entry was taken from, in merge order, and in file
+    /// order within an input. An entry found again in a later input moves
+    /// to where that input has it.
+    Input,
+    /// By hash, the canonical order.
+    Hash,
+    /// By data size, the smallest first, in first-seen order among those of
+    /// equal size.
+    Size
+}
+
+/// Entries evicted when the output exceeds its size limit.
+#[derive(Clone, Copy, PartialEq)]
+pub enum EvictPolicy {
+    /// The entries of the earliest provenance: from the first inputs, and
+    /// the first entries within an input. An entry kept from a later input
+    /// counts as coming from there.
+    Oldest,
+    /// The largest entries, the oldest first among those of equal size.
+    Largest
+}
+
+/// Input whose entry is kept when inputs contain differing entries with the
+/// same hash.
+#[derive(Clone, Copy, PartialEq)]
+pub enum ConflictPolicy {
+    /// The input modified last.
+    Newest,
+    /// The input modified first.
+    Oldest,
+    /// The largest input file.
+    LargestFile
+}
+
+/// Key telling entries found more than once apart from distinct ones.
+#[derive(Clone, Copy, PartialEq)]
+pub enum DedupKey {
+    /// The stored hash, as DXVK does, the default.
+    Hash,
+    /// The hash the data should have, so that entries with the same data
+    /// are kept once even if their stored hashes differ. Validated entries
+    /// have that hash anyway, so only the trusted entries of an input that
+    /// is also the output can differ, like a stale copy of an entry.
+    Data
+}
+
+/// A validated merge configuration, made with `ConfigBuilder`.
+pub struct Config {
+    files:           Vec<PathBuf>,
+    output:          PathBuf,
+    output_template: Option<OutputTemplate>,
+    version:         Option<u32>,
+    assume_version:  Option<u32>,
+    salvage:         bool,
+    resync:          bool,
+    skip_mismatched: bool,
+    strict:          bool,
+    snapshot:        bool,
+    threads:         usize,
+    max_bytes:       Option<u64>,
+    max_entries:     Option<usize>,
+    evict:           EvictPolicy,
+    sort:            SortOrder,
+    conflict_prefer: Option<ConflictPolicy>,
+    dedup_by:        DedupKey,
+    limit_per_file:  Option<usize>,
+    min_entry_size:  usize,
+    max_entry_size:  Option<usize>,
+    min_sources:     usize,
+    keep_hashes:     Option<HashSet<Sha1Hash>>,
+    drop_hashes:     HashSet<Sha1Hash>,
+    only_stages:     Option<(Stages, LegacyStageHandling)>,
+    force:           bool,
+    dry_run:         bool,
+    regression_check: bool,
+    force_overwrite: bool,
+    allow_empty:     bool,
+    drop_partial_tail: bool,
+    revalidate_master: bool,
+    deny:            Deny,
+    compare_to:      Option<PathBuf>,
+    limits:          Limits,
+    time_limit:      Option<Duration>,
+    journal:         Option<PathBuf>,
+    retry:           Retry,
+    buffer_size:     usize
+}
+
+impl Config {
+    /// Whether passes after merging the inputs may drop entries, so that
+    /// entries over the limits are only evicted once they are done.
+    fn drops_after_merging(&self) -> bool {
+        self.keep_hashes.is_some()
+            || !self.drop_hashes.is_empty()
+            || self.only_stages.is_some()
+            || self.min_sources > 1
+    }
+
+    /// The inputs in merge order.
+    pub fn files(&self) -> &[PathBuf] {
+        &self.files
+    }
+
+    /// The output, unless an output template names it after the merge.
+    pub fn output(&self) -> &Path {
+        &self.output
+    }
+}
+
+/// Builds a `Config`. Unset options default to merging into
+/// `output.dxvk-cache` in the version of the first input.
+pub struct ConfigBuilder {
+    config: Config
+}
+
+impl Default for ConfigBuilder {
+    fn default() -> Self {
+        ConfigBuilder {
+            config: Config {
+                files:           Vec::new(),
+                output:          PathBuf::from("output.dxvk-cache"),
+                output_template: None,
+                version:         None,
+                assume_version:  None,
+                salvage:         false,
+                resync:          false,
+                skip_mismatched: false,
+                strict:          false,
+                snapshot:        false,
+                threads:         thread::available_parallelism().map_or(1, |n| n.get()),
+                max_bytes:       None,
+                max_entries:     None,
+                evict:           EvictPolicy::Oldest,
+                sort:            SortOrder::None,
+                conflict_prefer: None,
+                dedup_by:        DedupKey::Hash,
+                limit_per_file:  None,
+                min_entry_size:  0,
+                max_entry_size:  None,
+                min_sources:     1,
+                keep_hashes:     None,
+                drop_hashes:     HashSet::new(),
+                only_stages:     None,
+                force:           false,
+                dry_run:         false,
+                regression_check: true,
+                force_overwrite: false,
+                allow_empty:     false,
+                drop_partial_tail: false,
+                revalidate_master: false,
+                deny:            Deny::default(),
+                compare_to:      None,
+                limits:          Limits::default(),
+                time_limit:      None,
+                journal:         None,
+                retry:           Retry::default(),
+                buffer_size:     DEFAULT_BUFFER_SIZE
+            }
+        }
+    }
+}
+
+impl ConfigBuilder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds an input. Of entries found in several inputs, the one of the
+    /// last input is kept unless `conflict_prefer` says otherwise.
+    pub fn add_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.files.push(path.into());
+        self
+    }
+
+    pub fn output(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.output = path.into();
+        self
+    }
+
+    /// Names the output by `template` once the merge is done, instead of
+    /// `output`.
+    pub fn output_template(mut self, template: OutputTemplate) -> Self {
+        self.config.output_template = Some(template);
+        self
+    }
+
+    /// Merges into `version` instead of the version of the first input.
+    pub fn version(mut self, version: u32) -> Self {
+        self.config.version = Some(version);
+        self
+    }
+
+    /// Reads the inputs as `version` whatever their headers say.
+    pub fn assume_version(mut self, version: u32) -> Self {
+        self.config.assume_version = Some(version);
+        self
+    }
+
+    /// Recovers the valid entries of inputs with a corrupt header or
+    /// corrupt stretches, as described at `format::salvage_cache`. Requires
+    /// `assume_version`.
+    pub fn salvage(mut self, salvage: bool) -> Self {
+        self.config.salvage = salvage;
+        self
+    }
+
+    /// Resynchronizes with the entries of inputs after one whose header is
+    /// corrupt, as described at `format::read_cache_resync`, instead of
+    /// losing them.
+    pub fn resync(mut self, resync: bool) -> Self {
+        self.config.resync = resync;
+        self
+    }
+
+    /// Skips inputs of another version instead of failing.
+    pub fn skip_mismatched(mut self, skip: bool) -> Self {
+        self.config.skip_mismatched = skip;
+        self
+    }
+
+    /// Fails instead of omitting entries with invalid hashes.
+    pub fn fail_on_invalid(mut self, strict: bool) -> Self {
+        self.config.strict = strict;
+        self
+    }
+
+    /// Reads copies of the inputs, for caches in use by a game.
+    pub fn snapshot(mut self, snapshot: bool) -> Self {
+        self.config.snapshot = snapshot;
+        self
+    }
+
+    /// Number of threads validating entries, by default one per CPU.
+    pub fn threads(mut self, threads: usize) -> Self {
+        self.config.threads = threads;
+        self
+    }
+
+    /// Limits the output size, evicting entries by `evict` to fit.
+    pub fn max_bytes(mut self, max_bytes: u64) -> Self {
+        self.config.max_bytes = Some(max_bytes);
+        self
+    }
+
+    /// Limits the number of output entries, evicting entries by `evict` to
+    /// fit.
+    pub fn max_entries(mut self, max_entries: usize) -> Self {
+        self.config.max_entries = Some(max_entries);
+        self
+    }
+
+    pub fn evict(mut self, policy: EvictPolicy) -> Self {
+        self.config.evict = policy;
+        self
+    }
+
+    pub fn sort(mut self, order: SortOrder) -> Self {
+        self.config.sort = order;
+        self
+    }
+
+    /// Keeps the entry of the input preferred by `policy` when inputs
+    /// contain differing entries with the same hash, regardless of the
+    /// order of the inputs. Inputs tied by the policy fall back to it.
+    pub fn conflict_prefer(mut self, policy: ConflictPolicy) -> Self {
+        self.config.conflict_prefer = Some(policy);
+        self
+    }
+
+    /// Deduplicates the entries by `key`. Entries with the same key are
+    /// kept once, as entries with the same hash are by default, resolving
+    /// conflicts the same way.
+    pub fn dedup_by(mut self, key: DedupKey) -> Self {
+        self.config.dedup_by = key;
+        self
+    }
+
+    /// Takes at most `limit` entries from each input, the newest ones, so
+    /// that no input dominates the output.
+    pub fn limit_per_file(mut self, limit: usize) -> Self {
+        self.config.limit_per_file = Some(limit);
+        self
+    }
+
+    /// Skips the entries with less than `bytes` bytes of data, counting
+    /// them per input.
+    pub fn min_entry_size(mut self, bytes: usize) -> Self {
+        self.config.min_entry_size = bytes;
+        self
+    }
+
+    /// Skips the entries with more than `bytes` bytes of data, counting
+    /// them per input. Unlike `Limits::max_entry_size`, this doesn't reject
+    /// the input.
+    pub fn max_entry_size(mut self, bytes: usize) -> Self {
+        self.config.max_entry_size = Some(bytes);
+        self
+    }
+
+    /// Keeps only the entries whose hash is in at least `inputs` of the
+    /// inputs merged, dropping those too few inputs vouch for, such as
+    /// pipelines of a single user's mods or corrupt entries. Entries are
+    /// counted once per input, and an output merged into as an input counts
+    /// as one.
+    pub fn min_sources(mut self, inputs: usize) -> Self {
+        self.config.min_sources = inputs;
+        self
+    }
+
+    /// Keeps only the entries whose hash is in `hashes`, an allow list.
+    pub fn keep_hashes(mut self, hashes: HashSet<Sha1Hash>) -> Self {
+        self.config.keep_hashes = Some(hashes);
+        self
+    }
+
+    /// Drops the entries whose hash is in `hashes`, a block list, even if
+    /// `keep_hashes` has them too.
+    pub fn drop_hashes(mut self, hashes: HashSet<Sha1Hash>) -> Self {
+        self.config.drop_hashes = hashes;
+        self
+    }
+
+    /// Keeps only the entries of pipelines with `stages`. Legacy entries
+    /// have the stages `handling` gives them.
+    pub fn only_stages(mut self, stages: Stages, handling: LegacyStageHandling) -> Self {
+        self.config.only_stages = Some((stages, handling));
+        self
+    }
+
+    /// Writes the output even if the file system seems to lack the space.
+    pub fn force(mut self, force: bool) -> Self {
+        self.config.force = force;
+        self
+    }
+
+    /// Reports the merge without writing the output.
+    pub fn dry_run(mut self, dry_run: bool) -> Self {
+        self.config.dry_run = dry_run;
+        self
+    }
+
+    /// Checks whether the output would replace a cache of the same version
+    /// with more entries, which is likely missing from the inputs. On by
+    /// default; an output that is also an input is never checked.
+    pub fn regression_check(mut self, check: bool) -> Self {
+        self.config.regression_check = check;
+        self
+    }
+
+    /// Replaces an output with more entries instead of failing with
+    /// `ErrorKind::OutputRegression`. The regression is still reported.
+    pub fn force_overwrite(mut self, force: bool) -> Self {
+        self.config.force_overwrite = force;
+        self
+    }
+
+    /// Writes a cache of just the header instead of failing with
+    /// `ErrorKind::InvalidData` when no valid entries are found.
+    pub fn allow_empty(mut self, allow_empty: bool) -> Self {
+        self.config.allow_empty = allow_empty;
+        self
+    }
+
+    /// Drops the last entry of an input the game may still be writing, one
+    /// cut short by the end of the file or failing validation with a zero
+    /// tail, counting it as `truncated` instead of invalid. Invalid entries
+    /// anywhere else are still reported, and fail the merge with
+    /// `fail_on_invalid`.
+    pub fn drop_partial_tail(mut self, drop: bool) -> Self {
+        self.config.drop_partial_tail = drop;
+        self
+    }
+
+    /// Validates the entries of an input that is also the output, like
+    /// those of the other inputs. By default their stored hashes are
+    /// trusted, as the output was written by an earlier merge, which makes
+    /// merging a small cache into a large one much cheaper. Either way,
+    /// entries with the same hash are only kept once.
+    pub fn revalidate_master(mut self, revalidate: bool) -> Self {
+        self.config.revalidate_master = revalidate;
+        self
+    }
+
+    /// Fails with `ErrorKind::DeniedWarning` on the warnings `deny` denies,
+    /// before anything is written, instead of adding them to the report.
+    pub fn deny(mut self, deny: Deny) -> Self {
+        self.config.deny = deny;
+        self
+    }
+
+    /// Compares the merged entries with the valid entries of the cache at
+    /// `reference` before writing them, reporting the hashes only one of
+    /// them has as the `comparison` of the report. The reference must be of
+    /// the output version, and may be the output itself.
+    pub fn compare_to(mut self, reference: impl Into<PathBuf>) -> Self {
+        self.config.compare_to = Some(reference.into());
+        self
+    }
+
+    /// Caps reading the inputs, for merging untrusted caches. The time
+    /// budget covers the whole merge.
+    pub fn limits(mut self, limits: Limits) -> Self {
+        self.config.limits = limits;
+        self
+    }
+
+    /// Stops merging inputs once `limit` has passed, after the input being
+    /// merged, and writes what is merged so far. The inputs left are the
+    /// `pending` ones of the report. At least one input besides the output
+    /// is always merged, so that merging into the output bit by bit, with
+    /// the output as the first input, makes progress.
+    pub fn time_limit(mut self, limit: Duration) -> Self {
+        self.config.time_limit = Some(limit);
+        self
+    }
+
+    /// Records the progress of the merge in the journal at `path`, so that
+    /// an interrupted merge resumes where it was. At most every
+    /// `JOURNAL_INTERVAL`, the entries merged since are appended to the
+    /// partial output next to the journal, `path` with `.dxvk-cache`
+    /// appended, and the inputs they come from to the journal. A merge with
+    /// a journal left over merges the partial output first, in place of
+    /// those inputs. Both files are removed once the output is written.
+    pub fn journal(mut self, path: impl Into<PathBuf>) -> Self {
+        self.config.journal = Some(path.into());
+        self
+    }
+
+    /// Retries reading the inputs and writing the output on transient I/O
+    /// errors, as reported by `Event::Retried`.
+    pub fn retry(mut self, retry: Retry) -> Self {
+        self.config.retry = retry;
+        self
+    }
+
+    /// Capacity of the buffers reading the inputs and writing the output.
+    pub fn buffer_size(mut self, bytes: usize) -> Self {
+        self.config.buffer_size = bytes;
+        self
+    }
+
+    /// Checks the options, failing with `ErrorKind::InvalidInput` on a
+    /// configuration that could not merge anything. The inputs may be left
+    /// out for a `MergeSession`, which is given them one by one.
+    pub fn build(self) -> Result<Config, Error> {
+        let config = self.config;
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+        for path in &config.files {
+            if path.extension().and_then(OsStr::to_str) != Some("dxvk-cache") {
+                let mut message = String::from("File extension mismatch: expected .dxvk-cache");
+                if let Ok(header) = DxvkStateCacheHeader::peek(path) {
+                    message.push_str(&format!(", but this is a v{} state cache", header.version));
+                }
+                return Err(invalid(message).with_path(path));
+            }
+        }
+        if config.salvage && config.assume_version.is_none() {
+            return Err(invalid("Salvaging requires the version the inputs are read as".into()));
+        }
+        if config.salvage && config.resync {
+            return Err(invalid("Salvaging scans the whole input, so it can't resync".into()));
+        }
+        if let (Some(template), Some(first)) = (&config.output_template, config.files.first()) {
+            template.check(first)?;
+        }
+        if [config.version, config.assume_version].contains(&Some(0)) {
+            return Err(invalid("Invalid version: 0".into()));
+        }
+        if let (Some(version), Some(assumed)) = (config.version, config.assume_version) {
+            if version != assumed {
+                return Err(invalid(format!(
+                    "Inputs read as v{} never match the output version v{}",
+                    assumed, version
+                )));
+            }
+        }
+        if config.max_entries == Some(0) {
+            return Err(invalid("Entry limit of 0 leaves nothing to merge".into()));
+        }
+        if config.limit_per_file == Some(0) {
+            return Err(invalid("Invalid entry limit per file: 0".into()));
+        }
+        match config.max_entry_size {
+            Some(0) => return Err(invalid("Entry size limit of 0 leaves nothing to merge".into())),
+            Some(max) if max < config.min_entry_size => {
+                return Err(invalid(format!(
+                    "Entry size range is empty: at least {} bytes but at most {}",
+                    config.min_entry_size, max
+                )));
+            },
+            _ => {},
+        }
+        if config.min_sources == 0 {
+            return Err(invalid("Invalid number of sources: 0".into()));
+        }
+        if config.buffer_size == 0 {
+            return Err(invalid("Invalid buffer size: 0".into()));
+        }
+        if config.threads == 0 {
+            return Err(invalid("Invalid number of threads: 0".into()));
+        }
+        if let Some(max_bytes) = config.max_bytes {
+            if max_bytes < HEADER_SIZE as u64 {
+                return Err(invalid(format!(
+                    "Size limit of {} bytes is too small for any state cache",
+                    max_bytes
+                )));
+            }
+        }
+        Ok(config)
+    }
+}
+
+/// Something happening during a merge, passed to the callback of
+/// `merge_files_with`. Paths are the inputs as configured, and `index` is
+/// the position of an input among them.
+pub enum Event<'a> {
+    /// Progress reading or validating an input, or writing the output.
+    Progress(&'a Progress<'a>),
+    /// A snapshot of an input is taken.
+    Snapshot { path: &'a Path, taken_at: SystemTime },
+    /// Entries are salvaged from an input read as the assumed version.
+    Salvaged { path: &'a Path, salvage: &'a Salvage },
+    /// Bytes of an input are skipped to resynchronize with its entries.
+    Resynced { path: &'a Path, resync: &'a Resync },
+    /// The output version is taken from the first input.
+    DetectedVersion(u32),
+    /// An input is skipped as the same file as an earlier one.
+    Duplicate { index: usize, path: &'a Path, first: &'a Path },
+    /// An input is skipped for not matching the output version.
+    Mismatched { index: usize, path: &'a Path, version: u32, expected: u32 },
+    /// The stored hashes of an input that is also the output are trusted
+    /// instead of validating its entries.
+    Trusted { index: usize, path: &'a Path },
+    /// A modern input declares another entry size than the first input.
+    /// Only legacy caches depend on it, so this is merely a note.
+    EntrySize { path: &'a Path, found: u32, expected: u32 },
+    /// A warning is added to the report.
+    Warning(&'a Warning),
+    /// Transient I/O errors were retried reading an input or writing the
+    /// output.
+    Retried { path: &'a Path, errors: &'a [Error] },
+    /// A merge is resumed from the partial output of `journal`, which has
+    /// `inputs` inputs merged.
+    ResumedJournal { journal: &'a Path, inputs: usize },
+    /// The entries merged so far are written to the partial output, and
+    /// `journal` records the `inputs` inputs merged so far.
+    Checkpoint { journal: &'a Path, inputs: usize },
+    /// The time limit passed, leaving the `pending` inputs unmerged.
+    TimeLimit { pending: &'a [PathBuf] },
+    /// An input fails the merge with `fail_on_invalid` for holding the
+    /// `invalid` entries.
+    Invalid { index: usize, path: &'a Path, invalid: &'a [Sha1Hash] },
+    /// An input is merged, leaving out the `omitted` invalid entries.
+    Merged { index: usize, report: &'a FileReport, omitted: &'a [Sha1Hash] },
+    /// Entries are evicted after merging an input, to fit into the size
+    /// and entry limits.
+    Evicted { index: usize, hashes: &'a [Sha1Hash] },
+    /// Entries are dropped by the hash lists or the stages kept, once all
+    /// inputs are merged. `pass` is "hash lists" or "stages".
+    Filtered { pass: &'static str, hashes: &'a [Sha1Hash] },
+    /// Entries found in fewer than `min_sources` inputs are dropped, once
+    /// all inputs are merged.
+    TooFewSources { min_sources: usize, hashes: &'a [Sha1Hash] },
+    /// The output is about to be written, or would be on a dry run.
+    Writing {
+        path:    &'a Path,
+        version: u32,
+        entries: &'a LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+        size:    u64
+    },
+    /// The output is the same file as `input`. All inputs are read by now,
+    /// so replacing it is safe.
+    ReplacingInput(&'a Path)
+}
+
+/// Merges the inputs of `config` into its output.
+pub fn merge_files(config: &Config) -> Result<MergeReport, Error> {
+    merge_files_with(config, &mut |_| ())
+}
+
+/// Like `merge_files`, passing what happens to `events`. The inputs are
+/// fed to a `MergeSession` one by one.
+pub fn merge_files_with(
+    config: &Config,
+    events: &mut dyn FnMut(&Event)
+) -> Result<MergeReport, Error> {
+    if config.files.is_empty() {
+        return Err(Error::new(ErrorKind::InvalidInput, "No input files"));
+    }
+    let mut session = MergeSession::new(config);
+    if !config.skip_mismatched && config.assume_version.is_none() {
+        let phase = Instant::now();
+        check_versions(config)?;
+        session.report.timings.header_reads += phase.elapsed();
+    }
+    let mut journal = match &config.journal {
+        Some(path) => Some((path, open_journal(path, &mut session, events)?)),
+        None => None
+    };
+    let mut unrecorded = Vec::new();
+    let mut checkpoint = Instant::now();
+    for (i, path) in config.files.iter().enumerate() {
+        if let (Some(limit), Some(pending)) = (config.time_limit, config.files.get(i..)) {
+            if session.progressed && session.started.elapsed() >= limit {
+                session.report.pending = pending.to_vec();
+                events(&Event::TimeLimit { pending });
+                break;
+            }
+        }
+        if let Some((_, journal)) = &journal {
+            if journal.contains(path) {
+                continue;
+            }
+        }
+        session.add_file_with(path, events)?;
+        if let Some((journal_path, journal)) = &mut journal {
+            unrecorded.push(path.as_path());
+            if !config.dry_run && checkpoint.elapsed() >= JOURNAL_INTERVAL {
+                session.checkpoint(journal, journal_path, &mut unrecorded, events)?;
+                checkpoint = Instant::now();
+            }
+        }
+    }
+    let report = session.finish_with(events)?;
+    if let (Some((path, journal)), false) = (journal, config.dry_run) {
+        for file in [path.as_path(), journal.partial()] {
+            match fs::remove_file(file) {
+                Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                    return Err(Error::from(e).with_path(file))
+                },
+                _ => ()
+            }
+        }
+    }
+    Ok(report)
+}
+
+/// Time between the checkpoints of a merge with a journal, which trades
+/// the time of writing the partial output for the time lost to an
+/// interruption.
+pub const JOURNAL_INTERVAL: Duration = Duration::from_secs(60);
+
+/// Opens the journal at `path`, merging its partial output into `session`
+/// if one is left over from an interrupted merge. A journal whose partial
+/// output or inputs changed since is started over.
+fn open_journal(
+    path: &Path,
+    session: &mut MergeSession,
+    events: &mut dyn FnMut(&Event)
+) -> Result<MergeJournal, Error> {
+    let mut partial = path.as_os_str().to_os_string();
+    partial.push(".dxvk-cache");
+    let partial = PathBuf::from(partial);
+    if !path.exists() {
+        return Ok(MergeJournal::new(&partial));
+    }
+    let journal = MergeJournal::read(path)?;
+    if let Some(changed) = journal.changed() {
+        let warning = Warning::new(
+            WarningId::InputChanged,
+            "Changed since the merge was interrupted, merging all inputs again"
+        );
+        warn(warning.with_path(changed), session.config, &mut session.report, events)?;
+        return Ok(MergeJournal::new(&partial));
+    }
+    events(&Event::ResumedJournal { journal: path, inputs: journal.inputs().count() });
+    journal.cut_partial()?;
+    session.add_file_with(journal.partial(), events)?;
+    session.checkpointed = session.entries.len();
+    Ok(journal)
+}
+
+/// A merge fed one input at a time, for front ends that let the user add
+/// inputs one by one and show the running totals before writing anything.
+/// The inputs of the `Config` only serve to report progress against; the
+/// inputs merged are those added. `merge_files` merges through a session as
+/// well, so both behave the same.
+///
+/// An input that fails to merge, like one of another version, returns the
+/// error and leaves the session as it was, so that other inputs can still
+/// be added. Only a denied warning fails an input whose entries are
+/// already merged. Dropping the session cancels the merge without writing
+/// anything.
+pub struct MergeSession<'a> {
+    config:         &'a Config,
+    started:        Instant,
+    report:         MergeReport,
+    entries:        LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    /// Size of the output if it were written now.
+    total_size:     u64,
+    version:        Option<u32>,
+    entry_size:     Option<u32>,
+    /// The first input merged, which the output version is reported as
+    /// coming from.
+    version_source: Option<PathBuf>,
+    file_ids:       HashMap<util::FileId, PathBuf>,
+    /// Ranks of the inputs added by the conflict policy, if any.
+    ranks:          Vec<i128>,
+    output_id:      Option<Option<util::FileId>>,
+    /// Number of inputs having a valid entry, by hash.
+    sources:        HashMap<Sha1Hash, usize>,
+    bytes_done:     u64,
+    bytes_total:    Option<u64>,
+    /// Whether an input other than the output was added, which a time
+    /// limit always lets merge.
+    progressed:     bool,
+    /// Number of entries in the partial output of a journal, the first
+    /// ones of `entries`.
+    checkpointed:   usize,
+    /// Whether entries were replaced or evicted since the partial output
+    /// was written, so that appending to it won't do.
+    partial_stale:  bool
+}
+
+impl<'a> MergeSession<'a> {
+    pub fn new(config: &'a Config) -> Self {
+        let bytes_total = match config.files.is_empty() {
+            true => None,
+            false => config.files.iter().map(|p| fs::metadata(p).ok().map(|m| m.len())).sum()
+        };
+        MergeSession {
+            config,
+            started: Instant::now(),
+            report: MergeReport {
+                dry_run: config.dry_run,
+                ..Default::default()
+            },
+            entries: LinkedHashMap::new(),
+            total_size: HEADER_SIZE as u64,
+            version: config.version,
+            entry_size: None,
+            version_source: None,
+            file_ids: HashMap::new(),
+            ranks: Vec::new(),
+            output_id: config.output_template.is_none().then(|| util::file_id(&config.output)),
+            sources: HashMap::new(),
+            bytes_done: 0,
+            bytes_total,
+            progressed: false,
+            checkpointed: 0,
+            partial_stale: false
+        }
+    }
+
+    /// Merges the input at `path`, returning its report.
+    pub fn add_file(&mut self, path: &Path) -> Result<FileReport, Error> {
+        self.add_file_with(path, &mut |_| ())
+    }
+
+    /// Like `add_file`, passing what happens to `events`.
+    pub fn add_file_with(
+        &mut self,
+        path: &Path,
+        events: &mut dyn FnMut(&Event)
+    ) -> Result<FileReport, Error> {
+        let config = self.config;
+        let i = self.report.inputs.len();
+        let file_name = util::file_name(path);
+        let mut progress = Progress {
+            phase:        Phase::Reading,
+            file_index:   i,
+            file_count:   config.files.len().max(i + 1),
+            file_name:    &file_name,
+            bytes_done:   self.bytes_done,
+            bytes_total:  self.bytes_total,
+            entries_done: 0
+        };
+        let file_size = fs::metadata(path).map_or(0, |m| m.len());
+
+        // Paths differing only in case, links and the like lead to the same
+        // file, which must not be merged twice.
+        let file_id = util::file_id(path);
+        let is_output = file_id.is_some() && self.output_id == Some(file_id);
+        self.progressed |= !is_output;
+        if let Some(first) = file_id.and_then(|id| self.file_ids.get(&id)) {
+            events(&Event::Duplicate { index: i, path, first });
+            let report = FileReport {
+                duplicate_of: Some(first.clone()),
+                ..FileReport::skipped(path)
+            };
+            self.bytes_done += file_size;
+            return Ok(self.push_input(report, None, None));
+        }
+
+        // Once the version is known, the header alone tells inputs of
+        // another version apart, so they aren't parsed in vain.
+        if let (Some(expected), None) = (self.version, config.assume_version) {
+            let phase = Instant::now();
+            let found = DxvkStateCacheHeader::peek(path)?.version;
+            self.report.timings.header_reads += phase.elapsed();
+            if found != expected {
+                if !config.skip_mismatched {
+                    let source = self.version_source.as_deref();
+                    return Err(version_mismatch(expected, source, found, path));
+                }
+                events(&Event::Mismatched { index: i, path, version: found, expected });
+                self.bytes_done += file_size;
+                return Ok(self.push_input(FileReport::skipped(path), file_id, None));
+            }
+        }
+
+        let limits = config.limits.after(self.started)?;
+        let mut retried = Vec::new();
+        let phase = Instant::now();
+        let (header, file_entries, trailing) =
+            read_input(path, config, &limits, &mut retried, events, &mut progress)?;
+        self.report.timings.parsing += phase.elapsed();
+        progress.bytes_done = self.bytes_done + file_size;
+        let input = Input { index: i, path, header, is_output, retried: &retried };
+        let merged = self.merge_input(input, file_entries, trailing, &mut progress, events);
+        self.bytes_done += file_size;
+        let rank = self.rank(path);
+        let report = merged?;
+        Ok(self.push_input(report, file_id, rank))
+    }
+
+    /// Merges a cache read from `reader`, named `name` in reports, as the
+    /// version in its header. Unlike files, readers aren't checked for
+    /// being added twice, and rank lowest under a conflict policy.
+    pub fn add_reader<R: Read>(&mut self, name: &Path, reader: R) -> Result<FileReport, Error> {
+        self.add_reader_with(name, reader, &mut |_| ())
+    }
+
+    /// Like `add_reader`, passing what happens to `events`.
+    pub fn add_reader_with<R: Read>(
+        &mut self,
+        name: &Path,
+        reader: R,
+        events: &mut dyn FnMut(&Event)
+    ) -> Result<FileReport, Error> {
+        let i = self.report.inputs.len();
+        let limits = self.config.limits.after(self.started)?;
+        let phase = Instant::now();
+        let (header, file_entries) =
+            format::read_cache_from(reader, &limits).map_err(|e| e.with_path(name))?;
+        self.report.timings.parsing += phase.elapsed();
+        let file_name = util::file_name(name);
+        let mut progress = Progress {
+            phase:        Phase::Reading,
+            file_index:   i,
+            file_count:   self.config.files.len().max(i + 1),
+            file_name:    &file_name,
+            bytes_done:   self.bytes_done,
+            bytes_total:  self.bytes_total,
+            entries_done: 0
+        };
+        let input = Input { index: i, path: name, header, is_output: false, retried: &[] };
+        self.progressed = true;
+        let report = self.merge_input(input, file_entries, None, &mut progress, events)?;
+        let rank = self.config.conflict_prefer.map(|_| i128::MIN);
+        Ok(self.push_input(report, None, rank))
+    }
+
+    /// Statistics of the entries merged so far, without validating them
+    /// again.
+    pub fn current_stats(&self) -> CacheStats {
+        CacheStats::from_entries(self.entries.values(), None)
+    }
+
+    /// Size in bytes of the output if it were written now, before the
+    /// passes `finish` runs.
+    pub fn preview_output_size(&self) -> u64 {
+        self.total_size
+    }
+
+    /// Appends the entries merged since the last checkpoint to the partial
+    /// output of `journal`, then records the `unrecorded` inputs they come
+    /// from in the journal at `path`. The partial output is written anew if
+    /// entries in it were replaced or evicted since. Nothing is written
+    /// until an input is merged.
+    fn checkpoint<'p>(
+        &mut self,
+        journal: &mut MergeJournal,
+        path: &Path,
+        unrecorded: &mut Vec<&'p Path>,
+        events: &mut dyn FnMut(&Event)
+    ) -> Result<(), Error> {
+        let version = match self.version {
+            Some(version) => version,
+            None => return Ok(())
+        };
+        let header = DxvkStateCacheHeader {
+            magic: MAGIC_STRING,
+            version,
+            entry_size: self.entry_size.unwrap_or_default()
+        };
+        let restart = self.partial_stale || self.checkpointed == 0;
+        let skip = if restart { 0 } else { self.checkpointed };
+        let entries = self.entries.values().skip(skip);
+        journal.append_partial(restart, |writer| {
+            match restart {
+                true => format::write_cache_to(writer, &header, entries),
+                false => format::append_cache_to(writer, &header, entries)
+            }
+            .map(drop)
+        })?;
+        self.checkpointed = self.entries.len();
+        self.partial_stale = false;
+        journal.record(unrecorded.drain(..))?;
+        journal.write(path)?;
+        events(&Event::Checkpoint { journal: path, inputs: journal.inputs().count() });
+        Ok(())
+    }
+
+    /// Runs the passes after merging and writes the output, as described
+    /// in the module documentation, returning the report of the merge.
+    pub fn finish(self) -> Result<MergeReport, Error> {
+        self.finish_with(&mut |_| ())
+    }
+
+    /// Like `finish`, passing what happens to `events`.
+    pub fn finish_with(mut self, events: &mut dyn FnMut(&Event)) -> Result<MergeReport, Error> {
+        let config = self.config;
+        let report = &mut self.report;
+        let entries = &mut self.entries;
+        let total_size = &mut self.total_size;
+        let file_ids = &self.file_ids;
+        if report.conflicts > 0 {
+            let policy = match config.conflict_prefer {
+                Some(ConflictPolicy::Oldest) => "oldest",
+                Some(ConflictPolicy::LargestFile) => "largest",
+                _ => "newest"
+            };
+            let warning = Warning::new(
+                WarningId::ConflictsResolved,
+                format!(
+                    "{} entries differ between inputs, resolved in favor of the {} input",
+                    report.conflicts, policy
+                )
+            );
+            warn(warning, config, report, events)?;
+        }
+
+        let phase = Instant::now();
+        // Entries over the limits are evicted while merging unless later
+        // passes drop entries, so then the count covers both.
+        let limited = config.max_bytes.is_some() || config.max_entries.is_some();
+        let merge_pass =
+            if limited && !config.drops_after_merging() { "merge, limits" } else { "merge" };
+        report.passes.push((merge_pass, entries.len()));
+        if config.keep_hashes.is_some() || !config.drop_hashes.is_empty() {
+            let dropped =
+                filter_hashes(entries, config.keep_hashes.as_ref(), &config.drop_hashes);
+            remove_entries(entries, total_size, &dropped);
+            report.filtered += dropped.len();
+            report.passes.push(("hash lists", entries.len()));
+            if !dropped.is_empty() {
+                events(&Event::Filtered { pass: "hash lists", hashes: &dropped });
+            }
+        }
+        if let Some((stages, handling)) = config.only_stages {
+            let dropped = filter_stages(entries, stages, handling)?;
+            remove_entries(entries, total_size, &dropped);
+            report.filtered += dropped.len();
+            report.passes.push(("stages", entries.len()));
+            if !dropped.is_empty() {
+                events(&Event::Filtered { pass: "stages", hashes: &dropped });
+            }
+        }
+        if config.min_sources > 1 {
+            let sources = &self.sources;
+            let dropped: Vec<Sha1Hash> = entries
+                .keys()
+                .filter(|hash| sources.get(*hash).copied().unwrap_or(0) < config.min_sources)
+                .copied()
+                .collect();
+            remove_entries(entries, total_size, &dropped);
+            report.passes.push(("min sources", entries.len()));
+            report.too_few_sources = dropped.len();
+            if !dropped.is_empty() {
+                events(&Event::TooFewSources { min_sources: config.min_sources, hashes: &dropped });
+            }
+            if entries.is_empty() && !dropped.is_empty() && !config.allow_empty {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    format!("No entries found in at least {} inputs", config.min_sources)
+                ));
+            }
+        }
+        if config.drops_after_merging() {
+            let evicted = evict(entries, total_size, config);
+            report.evicted += evicted.len();
+            if limited {
+                report.passes.push(("limits", entries.len()));
+            }
+            if let (false, Some(index)) = (evicted.is_empty(), report.inputs.len().checked_sub(1))
+            {
+                events(&Event::Evicted { index, hashes: &evicted });
+            }
+        }
+        if report.filtered > 0 && entries.is_empty() && !config.allow_empty {
+            return Err(Error::new(ErrorKind::InvalidData, "No entries are left after filtering"));
+        }
+
+        let version = match self.version {
+            Some(version) if !entries.is_empty() || config.allow_empty => version,
+            _ => {
+                return Err(Error::new(
+                    ErrorKind::InvalidData,
+                    "No valid state cache entries found"
+                ))
+            },
+        };
+
+        sort_entries(entries, config.sort);
+        report.timings.merging += phase.elapsed();
+
+        let output = &match (&config.output_template, report.inputs.first()) {
+            (Some(template), Some(first)) => {
+                template.expand(&first.path, version, entries.len(), SystemTime::now())?
+            },
+            _ => config.output.clone()
+        };
+        let replaced_input = util::file_id(output).and_then(|id| file_ids.get(&id));
+        if replaced_input.is_none() && config.regression_check {
+            check_regression(output, version, entries.len(), config, report, events)?;
+        }
+        events(&Event::Writing { path: output, version, entries, size: *total_size });
+        if let Some(input) = replaced_input {
+            events(&Event::ReplacingInput(input));
+        }
+        if let Some(reference) = &config.compare_to {
+            let limits = config.limits.after(self.started)?;
+            report.comparison =
+                Some(compare(reference, version, entries, config, &limits, report, events)?);
+        }
+        report.version = version;
+        report.entries = entries.len();
+        report.output = output.clone();
+        report.output_size = *total_size;
+        if config.dry_run {
+            return Ok(self.report);
+        }
+        config.limits.after(self.started)?;
+        if !config.force {
+            check_space(output, *total_size)?;
+        }
+
+        let header = DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version,
+            entry_size: self.entry_size.unwrap_or_default()
+        };
+
+        let output_name = util::file_name(output);
+        let file_count = config.files.len().max(report.inputs.len());
+        let mut progress = Progress {
+            phase:        Phase::Writing,
+            file_index:   file_count.saturating_sub(1),
+            file_count,
+            file_name:    &output_name,
+            bytes_done:   0,
+            bytes_total:  Some(*total_size),
+            entries_done: 0
+        };
+        let mut report_progress = |offset, count| {
+            progress.bytes_done = offset;
+            progress.entries_done = count;
+            events(&Event::Progress(&progress));
+        };
+        let mut retried = Vec::new();
+        let phase = Instant::now();
+        format::write_cache_with_progress(
+            output,
+            &header,
+            entries.values(),
+            config.retry,
+            &mut retried,
+            config.buffer_size,
+            &mut report_progress
+        )?;
+        report.timings.writing = phase.elapsed();
+        if !retried.is_empty() {
+            events(&Event::Retried { path: output, errors: &retried });
+            report.retries += retried.len();
+        }
+
+        Ok(self.report)
+    }
+
+    /// Rank of the input at `path` by the conflict policy, if any.
+    fn rank(&self, path: &Path) -> Option<i128> {
+        self.config.conflict_prefer.map(|policy| rank_input(path, policy))
+    }
+
+    /// Records an input added, which is now known by `file_id` and ranks
+    /// `rank`, returning its report.
+    fn push_input(
+        &mut self,
+        report: FileReport,
+        file_id: Option<util::FileId>,
+        rank: Option<i128>
+    ) -> FileReport {
+        if let Some(id) = file_id {
+            self.file_ids.entry(id).or_insert_with(|| report.path.clone());
+        }
+        self.ranks.push(rank.unwrap_or(i128::MIN));
+        self.report.inputs.push(report.clone());
+        report
+    }
+
+    /// Merges the entries read from an input into the session, returning
+    /// the report of the input. `trailing` tells the bytes left over after
+    /// the last entry read.
+    fn merge_input(
+        &mut self,
+        input: Input,
+        mut file_entries: Vec<DxvkStateCacheEntry>,
+        trailing: Trailing,
+        progress: &mut Progress,
+        events: &mut dyn FnMut(&Event)
+    ) -> Result<FileReport, Error> {
+        let config = self.config;
+        let Input { index: i, path, header, is_output, retried } = input;
+        let kind = HashKind::from_version(header.version);
+        let cut = trailing.is_some();
+        if let (Some((offset, bytes)), false) = (trailing, config.drop_partial_tail) {
+            let warning = Warning::new(
+                WarningId::TrailingGarbage,
+                format!("{} bytes after the last entry are ignored", bytes)
+            );
+            warn(warning.with_path(path).with_offset(offset), config, &mut self.report, events)?;
+        }
+        // The entry cut short was dropped while reading, but one written
+        // as zeros so far is only told apart from damage by its position.
+        let truncated = if config.drop_partial_tail && !cut {
+            let partial =
+                file_entries.last().is_some_and(|e| e.has_zero_tail() && !e.is_valid(kind));
+            if partial {
+                file_entries.pop();
+            }
+            partial
+        } else {
+            cut && config.drop_partial_tail
+        };
+        if !retried.is_empty() {
+            events(&Event::Retried { path, errors: retried });
+            self.report.retries += retried.len();
+        }
+        let mut file_report = FileReport {
+            path:    path.to_path_buf(),
+            entries: file_entries.len(),
+            taken:   file_entries.len(),
+            evicted: 0,
+            new:     0,
+            duplicates: 0,
+            data_duplicates: 0,
+            invalid: 0,
+            truncated: truncated as usize,
+            out_of_range: 0,
+            retries: retried.len(),
+            skipped: false,
+            duplicate_of: None
+        };
+
+        let expected = match self.version {
+            Some(version) => version,
+            None => header.version
+        };
+        if header.version != expected {
+            if config.skip_mismatched {
+                events(&Event::Mismatched { index: i, path, version: header.version, expected });
+                file_report.skipped = true;
+                return Ok(file_report);
+            }
+            let source = self.version_source.as_deref();
+            return Err(version_mismatch(expected, source, header.version, path));
+        }
+        let advisory = if header.version < MIN_USEFUL_VERSION {
+            Some(Warning::new(
+                WarningId::VersionOutdated,
+                format!(
+                    "Version v{}{} is older than current DXVK loads (v{}), so the output may be \
+                     rejected by the game",
+                    header.version,
+                    dxvk_release(header.version)
+                        .map_or(String::new(), |r| format!(" (DXVK {})", r)),
+                    MIN_USEFUL_VERSION
+                )
+            ))
+        } else if header.version > CURRENT_VERSION {
+            Some(Warning::new(
+                WarningId::VersionUnknown,
+                format!(
+                    "Version v{} is newer than the versions known to this tool, up to v{}",
+                    header.version, CURRENT_VERSION
+                )
+            ))
+        } else {
+            None
+        };
+        let expected_size = self.entry_size.unwrap_or(header.entry_size);
+
+        // Modern entries carry their own size, so only legacy caches depend
+        // on the entry size declared in the header.
+        if header.entry_size != expected_size
+            && DxvkStateCacheEdition::from_version(expected) == DxvkStateCacheEdition::Legacy
+        {
+            return Err(Error::new(
+                ErrorKind::InvalidData,
+                format!(
+                    "Entry size mismatch: expected {}, found {}",
+                    expected_size, header.entry_size
+                )
+            )
+            .with_path(path));
+        }
+        if self.version.is_none() {
+            events(&Event::DetectedVersion(header.version));
+            self.version = Some(header.version);
+        }
+        if self.version_source.is_none() {
+            self.version_source = Some(path.to_path_buf());
+        }
+        self.entry_size = Some(expected_size);
+        if let Some(warning) = advisory {
+            warn(warning.with_path(path), config, &mut self.report, events)?;
+        }
+        if header.entry_size != expected_size {
+            events(&Event::EntrySize { path, found: header.entry_size, expected: expected_size });
+        }
+
+        // DXVK appends new entries, so the last ones of a file are the newest.
+        let mut excess = 0;
+        if let Some(limit) = config.limit_per_file {
+            excess = file_entries.len().saturating_sub(limit);
+            file_entries.drain(..excess);
+            file_report.taken = file_entries.len();
+        }
+
+        progress.phase = Phase::Validating;
+        progress.entries_done = 0;
+        events(&Event::Progress(progress));
+        let phase = Instant::now();
+        let trusted = is_output && !config.revalidate_master;
+        let valid = if trusted {
+            events(&Event::Trusted { index: i, path });
+            vec![true; file_entries.len()]
+        } else {
+            validate_entries(&file_entries, kind, config.threads)
+        };
+        self.report.timings.validation += phase.elapsed();
+        if config.strict && valid.contains(&false) {
+            let invalid = file_entries.iter().zip(&valid).filter(|(_, valid)| !**valid);
+            let invalid: Vec<Sha1Hash> = invalid.map(|(e, _)| e.hash).collect();
+            events(&Event::Invalid { index: i, path, invalid: &invalid });
+            let message = format!("{} entries failed SHA1 validation", invalid.len());
+            return Err(Error::new(ErrorKind::InvalidData, message).with_path(path));
+        }
+
+        let phase = Instant::now();
+        let rank = self.rank(path);
+        let entries = &mut self.entries;
+        let mut omitted = Vec::new();
+        let mut found = HashSet::new();
+        let entries_len = entries.len();
+        entries.reserve(file_entries.len());
+        for (ordinal, (mut e, valid)) in file_entries.into_iter().zip(valid).enumerate() {
+            if !valid {
+                omitted.push(e.hash);
+                continue;
+            }
+            if e.data.len() < config.min_entry_size
+                || config.max_entry_size.is_some_and(|max| e.data.len() > max)
+            {
+                file_report.out_of_range += 1;
+                continue;
+            }
+            // Validated entries have the hash of their data, so only trusted
+            // ones need hashing to be keyed by data.
+            let key = match (config.dedup_by, trusted) {
+                (DedupKey::Data, true) => e.compute_hash(kind),
+                _ => e.hash
+            };
+            if config.min_sources > 1 {
+                found.insert(key);
+            }
+            let size = e.serialized_size() as u64;
+            match entries.get(&key) {
+                None => self.total_size += size,
+                Some(existing) => {
+                    file_report.duplicates += 1;
+                    if existing.hash != e.hash {
+                        file_report.data_duplicates += 1;
+                    }
+                    // A stale hash never replaces the hash of the data.
+                    if e.hash != key {
+                        continue;
+                    }
+                    if let Some(rank) = rank {
+                        let origin = existing.provenance().map_or(i, |p| p.input as usize);
+                        if *existing != e {
+                            self.report.conflicts += 1;
+                        }
+                        if Some(&rank) < self.ranks.get(origin) {
+                            continue;
+                        }
+                    }
+                    self.total_size = self.total_size - existing.serialized_size() as u64 + size;
+                }
+            }
+            e.set_provenance(Provenance {
+                input:   i as u32,
+                ordinal: (excess + ordinal) as u32
+            });
+            // Replacing an entry keeps its place, so the map stays in the
+            // order the hashes were first seen in.
+            match entries.get_mut(&key) {
+                Some(existing) => {
+                    self.partial_stale |= *existing != e;
+                    *existing = e
+                },
+                None => {
+                    entries.insert(key, e);
+                }
+            }
+        }
+        file_report.new = entries.len() - entries_len;
+        file_report.invalid = omitted.len();
+        for hash in found {
+            *self.sources.entry(hash).or_default() += 1;
+        }
+
+        // Evicting after every input keeps memory bounded by the limits, and
+        // leaves the same entries as evicting from the final set would,
+        // unless later passes drop entries.
+        let evicted = if config.drops_after_merging() {
+            Vec::new()
+        } else {
+            evict(entries, &mut self.total_size, config)
+        };
+        self.report.timings.merging += phase.elapsed();
+        self.partial_stale |= !evicted.is_empty();
+        file_report.evicted = evicted.len();
+        self.report.evicted += evicted.len();
+        events(&Event::Merged { index: i, report: &file_report, omitted: &omitted });
+        if !omitted.is_empty() {
+            let warning = Warning::new(
+                WarningId::InvalidEntries,
+                format!("{} entries are omitted as invalid", omitted.len())
+            );
+            warn(warning.with_path(path), config, &mut self.report, events)?;
+        }
+        if !evicted.is_empty() {
+            events(&Event::Evicted { index: i, hashes: &evicted });
+        }
+        Ok(file_report)
+    }
+}
+
+/// An input read by a `MergeSession`, to be merged.
+struct Input<'p> {
+    index:     usize,
+    path:      &'p Path,
+    header:    DxvkStateCacheHeader,
+    is_output: bool,
+    /// Transient errors retried reading it.
+    retried:   &'p [Error]
+}
+
+/// Reads the header of every input and fails if they don't all have the
+/// output version, listing the versions found and the inputs of each, so
+/// that all mismatched inputs are told at once rather than one per run.
+/// Inputs whose header can't be read are left for the merge to fail on.
+fn check_versions(config: &Config) -> Result<(), Error> {
+    let mut versions: BTreeMap<u32, Vec<&Path>> = BTreeMap::new();
+    let mut first = None;
+    for path in &config.files {
+        if let Ok(header) = DxvkStateCacheHeader::peek(path) {
+            first.get_or_insert((header.version, path.as_path()));
+            versions.entry(header.version).or_default().push(path);
+        }
+    }
+    let (expected, source) = match (config.version, first) {
+        (Some(version), _) => (version, None),
+        (None, Some((version, path))) => (version, Some(path)),
+        (None, None) => return Ok(())
+    };
+    let mismatched = config.files.iter().find_map(|path| {
+        let (&version, _) = versions.iter().find(|(_, paths)| paths.contains(&path.as_path()))?;
+        (version != expected).then_some((version, path))
+    });
+    let (found, path) = match mismatched {
+        Some(mismatched) => mismatched,
+        None => return Ok(())
+    };
+
+    let error = version_mismatch(expected, source, found, path);
+    let mut message = error.message().to_string();
+    for (version, paths) in &versions {
+        let release = dxvk_release(*version).map_or(String::new(), |r| format!(" (DXVK {})", r));
+        let mut names: Vec<_> = paths.iter().take(5).map(|p| util::file_name(p)).collect();
+        if paths.len() > names.len() {
+            names.push(format!("and {} more", paths.len() - names.len()).into());
+        }
+        message.push_str(&format!(
+            "\n\t{} {} v{}{}: {}",
+            paths.len(),
+            if paths.len() == 1 { "input is" } else { "inputs are" },
+            version,
+            release,
+            names.join(", ")
+        ));
+    }
+    Err(Error::new(error.kind(), message).with_path(path))
+}
+
+/// Fails if `output` is a cache of `version` with more entries than the
+/// merge, unless forced or on a dry run. Anything else at `output` is
+/// replaced without asking.
+fn check_regression(
+    output: &Path,
+    version: u32,
+    entries: usize,
+    config: &Config,
+    report: &mut MergeReport,
+    events: &mut dyn FnMut(&Event)
+) -> Result<(), Error> {
+    let existing = match format::count_entries(output) {
+        Ok((header, existing)) if header.version == version && existing > entries => existing,
+        _ => return Ok(())
+    };
+    if config.force_overwrite || config.dry_run {
+        let warning = Warning::new(
+            WarningId::OutputRegression,
+            format!(
+                "The output has {} entries, more than the {} entries of the merge replacing it; \
+                 unless it is meant to shrink, add it as an input",
+                existing, entries
+            )
+        );
+        return warn(warning.with_path(output), config, report, events);
+    }
+    Err(Error::new(
+        ErrorKind::OutputRegression,
+        format!(
+            "The output has {} entries, more than the {} entries of the merge replacing it; add \
+             it as an input or pass --force-overwrite",
+            existing, entries
+        )
+    )
+    .with_path(output))
+}
+
+/// Compares the merged `entries` with the valid entries of `reference`.
+fn compare(
+    reference: &Path,
+    version: u32,
+    entries: &LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    config: &Config,
+    limits: &Limits,
+    report: &mut MergeReport,
+    events: &mut dyn FnMut(&Event)
+) -> Result<Comparison, Error> {
+    let mut retried = Vec::new();
+    let (header, reference_entries) = format::read_cache(
+        reference,
+        None,
+        limits,
+        config.retry,
+        &mut retried,
+        config.buffer_size,
+        &mut |_, _| ()
+    )
+    .map_err(|e| e.with_path(reference))?;
+    if !retried.is_empty() {
+        events(&Event::Retried { path: reference, errors: &retried });
+        report.retries += retried.len();
+    }
+    if header.version != version {
+        return Err(Error::new(
+            ErrorKind::VersionMismatch { expected: version, found: header.version },
+            format!(
+                "The reference is version v{} but the merge is v{}, so they can't be compared",
+                header.version, version
+            )
+        )
+        .with_path(reference));
+    }
+    let kind = HashKind::from_version(header.version);
+    let valid = validate_entries(&reference_entries, kind, config.threads);
+    let hashes: HashSet<Sha1Hash> =
+        reference_entries.iter().zip(valid).filter(|(_, v)| *v).map(|(e, _)| e.hash).collect();
+    Ok(Comparison {
+        reference:         reference.to_path_buf(),
+        only_in_merge:     missing_hashes(entries.keys(), |h| hashes.contains(h))
+            .into_iter()
+            .copied()
+            .collect(),
+        only_in_reference: missing_hashes(&hashes, |h| entries.contains_key(h))
+            .into_iter()
+            .copied()
+            .collect()
+    })
+}
+
+/// Ranks an input by `policy`, higher ranks being preferred. Inputs
+/// without metadata rank lowest.
+fn rank_input(path: &Path, policy: ConflictPolicy) -> i128 {
+    let metadata = match fs::metadata(path) {
+        Ok(metadata) => metadata,
+        Err(_) => return i128::MIN
+    };
+    let modified = || {
+        metadata
+            .modified()
+            .ok()
+            .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+            .map(|d| d.as_nanos() as i128)
+    };
+    match policy {
+        ConflictPolicy::Newest => modified().unwrap_or(i128::MIN),
+        ConflictPolicy::Oldest => modified().map_or(i128::MIN, |t| -t),
+        ConflictPolicy::LargestFile => i128::from(metadata.len())
+    }
+}
+
+/// Offset and count of the bytes after the last entry of an input, if any.
+type Trailing = Option<(u64, u64)>;
+
+/// Reads an input, or a snapshot of it if configured, reporting the bytes
+/// read on top of those already done in `progress`.
+fn read_input(
+    path: &Path,
+    config: &Config,
+    limits: &Limits,
+    retried: &mut Vec<Error>,
+    events: &mut dyn FnMut(&Event),
+    progress: &mut Progress
+) -> Result<(DxvkStateCacheHeader, Vec<DxvkStateCacheEntry>, Trailing), Error> {
+    let snapshot = if config.snapshot {
+        // Don't copy what can't be read anyway.
+        fs::metadata(path)
+            .map_err(Error::from)
+            .and_then(|m| limits.check_file_size(m.len()))
+            .map_err(|e| e.with_path(path))?;
+        let snapshot = Snapshot::take(path).map_err(|e| e.with_path(path))?;
+        events(&Event::Snapshot { path, taken_at: snapshot.taken_at });
+        Some(snapshot)
+    } else {
+        None
+    };
+    let source = snapshot.as_ref().map_or(path, |s| s.path());
+    if let (true, Some(version)) = (config.salvage, config.assume_version) {
+        let (header, entries, salvage) =
+            format::salvage_cache(source, version, limits).map_err(|e| e.with_path(path))?;
+        events(&Event::Salvaged { path, salvage: &salvage });
+        return Ok((header, entries, None));
+    }
+    if config.resync {
+        let (header, entries, resync) =
+            format::read_cache_resync(source, config.assume_version, limits)
+                .map_err(|e| e.with_path(path))?;
+        if !resync.skipped.is_empty() {
+            events(&Event::Resynced { path, resync: &resync });
+        }
+        let trailing = fs::metadata(source)
+            .ok()
+            .filter(|m| m.is_file() && m.len() > resync.end)
+            .map(|m| (resync.end, m.len() - resync.end));
+        return Ok((header, entries, trailing));
+    }
+    let bytes_done = progress.bytes_done;
+    let buffer_size = config.buffer_size;
+    let report_progress = &mut |offset, count| {
+        progress.bytes_done = bytes_done + offset;
+        progress.entries_done = count;
+        events(&Event::Progress(progress));
+    };
+    let version = config.assume_version;
+    let (header, entries) = format::read_cache(
+        source,
+        version,
+        limits,
+        config.retry,
+        retried,
+        buffer_size,
+        report_progress
+    )
+    .map_err(|e| e.with_path(path))?;
+
+    // Reading stops quietly at an entry cut short by the end of the file,
+    // whose bytes are left over: their offset and count.
+    let read = HEADER_SIZE as u64 + entries.iter().map(|e| e.serialized_size() as u64).sum::<u64>();
+    let trailing = fs::metadata(source)
+        .ok()
+        .filter(|m| m.is_file() && m.len() > read)
+        .map(|m| (read, m.len() - read));
+    Ok((header, entries, trailing))
+}
+
+/// Adds `warning` to the report and passes it to `events`, failing instead
+/// if it is denied.
+fn warn(
+    warning: Warning,
+    config: &Config,
+    report: &mut MergeReport,
+    events: &mut dyn FnMut(&Event)
+) -> Result<(), Error> {
+    let warning = config.deny.check(warning)?;
+    events(&Event::Warning(&warning));
+    report.warnings.push(warning);
+    Ok(())
+}
+
+/// Validates entries on up to `threads` threads. The results are in the
+/// order of `entries` regardless of the thread count, so callers keep the
+/// file order of the entries they insert.
+pub fn validate_entries(
+    entries: &[DxvkStateCacheEntry],
+    kind: HashKind,
+    threads: usize
+) -> Vec<bool> {
+    if threads <= 1 || entries.len() < 2 {
+        return entries.iter().map(|e| e.is_valid(kind)).collect();
+    }
+    let chunk_size = entries.len().div_ceil(threads);
+    thread::scope(|s| {
+        let handles: Vec<_> = entries
+            .chunks(chunk_size)
+            .map(|chunk| {
+                s.spawn(move || chunk.iter().map(|e| e.is_valid(kind)).collect::<Vec<_>>())
+            })
+            .collect();
+        handles
+            .into_iter()
+            .flat_map(|h| h.join().unwrap_or_else(|e| std::panic::resume_unwind(e)))
+            .collect()
+    })
+}
+
+/// Describes inputs not matching the `expected` version, which comes from
+/// `expected_path` or, if that is `None`, from the requested output version.
+pub fn version_mismatch(
+    expected: u32,
+    expected_path: Option<&Path>,
+    found: u32,
+    path: &Path
+) -> Error {
+    let describe = |version| match dxvk_release(version) {
+        Some(release) => format!("v{} (DXVK {})", version, release),
+        None => format!("v{}", version)
+    };
+    let message = if (expected >= FIRST_DXVK2_VERSION) != (found >= FIRST_DXVK2_VERSION) {
+        format!(
+            "State cache version mismatch: {} is {} but {} is {}. Caches written by DXVK 2.x \
+             are not interchangeable with caches from older releases; merge them separately or \
+             leave out the mismatched inputs with --skip-mismatched",
+            expected_path.map_or("the output version".into(), |p| p.display().to_string()),
+            describe(expected),
+            path.display(),
+            describe(found)
+        )
+    } else {
+        format!(
+            "State cache version mismatch: expected {}, found {}",
+            describe(expected),
+            describe(found)
+        )
+    };
+    Error::new(ErrorKind::VersionMismatch { expected, found }, message).with_path(path)
+}
+
+/// Evicts entries by the policy of `config` until they fit into its size
+/// and entry limits.
+fn evict(
+    entries: &mut LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    total_size: &mut u64,
+    config: &Config
+) -> Vec<Sha1Hash> {
+    let max_bytes = config.max_bytes.unwrap_or(u64::MAX);
+    let max_entries = config.max_entries.unwrap_or(usize::MAX);
+    let mut evicted = Vec::new();
+    match config.evict {
+        EvictPolicy::Oldest => {
+            if *total_size <= max_bytes && entries.len() <= max_entries {
+                return evicted;
+            }
+            // Replaced entries keep their place in the map, so it isn't in
+            // provenance order.
+            let mut by_age: Vec<(Option<Provenance>, usize, Sha1Hash)> =
+                entries.values().map(|e| (e.provenance(), e.serialized_size(), e.hash)).collect();
+            by_age.sort_by_key(|&(age, _, _)| age);
+            for (_, size, hash) in by_age {
+                if *total_size <= max_bytes && entries.len() <= max_entries {
+                    break;
+                }
+                entries.remove(&hash);
+                *total_size -= size as u64;
+                evicted.push(hash);
+            }
+        },
+        EvictPolicy::Largest => {
+            if *total_size <= max_bytes && entries.len() <= max_entries {
+                return evicted;
+            }
+            let mut by_size: Vec<(usize, Sha1Hash)> =
+                entries.values().map(|e| (e.serialized_size(), e.hash)).collect();
+            by_size.sort_by_key(|&(size, _)| std::cmp::Reverse(size));
+            for (size, hash) in by_size {
+                if *total_size <= max_bytes && entries.len() <= max_entries {
+                    break;
+                }
+                entries.remove(&hash);
+                *total_size -= size as u64;
+                evicted.push(hash);
+            }
+        }
+    }
+    evicted
+}
+
+/// Returns the hashes of the entries not in `keep`, if given, or in `drop`,
+/// leaving the entries as they are.
+pub fn filter_hashes(
+    entries: &LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    keep: Option<&HashSet<Sha1Hash>>,
+    drop: &HashSet<Sha1Hash>
+) -> Vec<Sha1Hash> {
+    entries
+        .keys()
+        .filter(|hash| keep.is_some_and(|keep| !keep.contains(*hash)) || drop.contains(*hash))
+        .copied()
+        .collect()
+}
+
+/// Returns the hashes of the entries of other stages than `stages`, with
+/// legacy entries treated by `handling`, leaving the entries as they are.
+pub fn filter_stages(
+    entries: &LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    stages: Stages,
+    handling: LegacyStageHandling
+) -> Result<Vec<Sha1Hash>, Error> {
+    let mut dropped = Vec::new();
+    for (hash, entry) in entries {
+        if handling.stages(entry.stage_mask())? != stages {
+            dropped.push(*hash);
+        }
+    }
+    Ok(dropped)
+}
+
+/// Removes the entries with the given hashes, keeping `total_size` up to
+/// date.
+fn remove_entries(
+    entries: &mut LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>,
+    total_size: &mut u64,
+    hashes: &[Sha1Hash]
+) {
+    for hash in hashes {
+        if let Some(entry) = entries.remove(hash) {
+            *total_size -= entry.serialized_size() as u64;
+        }
+    }
+}
+
+/// Approximate memory held by an entry map.
+pub struct MemoryUsage {
+    /// Data of the entries.
+    pub data:     u64,
+    /// The nodes of the map, holding the hashes, links and fixed-size parts
+    /// of the entries, and the slots of its hash table.
+    pub overhead: u64
+}
+
+/// Estimates the memory held by `entries`, for explaining the memory use
+/// of large merges. Allocator overhead is left out.
+pub fn memory_usage(entries: &LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>) -> MemoryUsage {
+    let node = 2 * mem::size_of::<usize>()
+        + mem::size_of::<Sha1Hash>()
+        + mem::size_of::<DxvkStateCacheEntry>();
+    let slot = 2 * mem::size_of::<usize>() + 1;
+    MemoryUsage {
+        data:     entries.values().map(|e| e.data.capacity() as u64).sum(),
+        overhead: (entries.len() * node + entries.capacity() * slot) as u64
+    }
+}
+
+/// Sorts entries by `order`, keeping their order for `SortOrder::None`.
+/// Entries without provenance, not merged by a `MergeSession`, keep their
+/// order for `SortOrder::Input`.
+pub fn sort_entries(entries: &mut LinkedHashMap<Sha1Hash, DxvkStateCacheEntry>, order: SortOrder) {
+    if order == SortOrder::None {
+        return;
+    }
+    let mut sorted = Vec::with_capacity(entries.len());
+    while let Some(entry) = entries.pop_front() {
+        sorted.push(entry);
+    }
+    match order {
+        SortOrder::None => (),
+        SortOrder::Input => {
+            sorted.sort_by_key(|(_, e)| e.provenance().map(|p| (p.input, p.ordinal)))
+        },
+        SortOrder::Hash => sorted.sort_unstable_by_key(|(hash, _)| *hash),
+        SortOrder::Size => sorted.sort_by_key(|(_, e)| e.data.len())
+    }
+    entries.extend(sorted);
+}
+
+/// Fails if `size` bytes won't fit on the file system of `output`. The
+/// output is written to a temporary file renamed over an existing output,
+/// which takes up its space until then, so that space doesn't count as
+/// available.
+fn check_space(output: &Path, size: u64) -> Result<(), Error> {
+    let dir = match output.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    match util::available_space(dir) {
+        Some(available) => check_fits(output, size, available),
+        None => Ok(())
+    }
+}
+
+/// Fails if `size` bytes exceed the `available` bytes for `output`.
+fn check_fits(output: &Path, size: u64, available: u64) -> Result<(), Error> {
+    if size > available {
+        return Err(Error::new(
+            ErrorKind::IoError(io::ErrorKind::Other),
+            format!(
+                "Not enough space for the output: {} bytes needed, {} bytes available; \
+                 use --ignore-space-check to write anyway",
+                size, available
+            )
+        )
+        .with_path(output));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use sha1::Sha1;
+
+    use super::*;
+
+    /// A valid v17 entry with `size` bytes of data, at least 4, which differ
+    /// for every `seed`.
+    fn entry(seed: u32, size: usize) -> DxvkStateCacheEntry {
+        let mut entry = DxvkStateCacheEntry::with_header(DxvkStateCacheEntryHeader {
+            stage_mask: 0x11,
+            entry_size: size as u32
+        });
+        for (i, b) in entry.data.iter_mut().enumerate() {
+            *b = seed.to_le_bytes().get(i).copied().unwrap_or(i as u8);
+        }
+        let mut hasher = Sha1::default();
+        hasher.update(&entry.data);
+        entry.hash = hasher.digest().bytes();
+        entry
+    }
+
+    #[test]
+    fn validate_entries_matches_a_sequential_pass() {
+        let entries: Vec<DxvkStateCacheEntry> = (0..1000)
+            .map(|i| {
+                let mut entry = entry(i, 16 + i as usize % 64);
+                if i % 3 == 0 || i % 7 == 0 {
+                    entry.hash[0] ^= 1;
+                }
+                entry
+            })
+            .collect();
+        let sequential: Vec<bool> = entries.iter().map(|e| e.is_valid(HashKind::Sha1)).collect();
+        assert!(sequential.contains(&true) && sequential.contains(&false));
+        for threads in [1, 2, 3, 8, 64, 2000] {
+            assert_eq!(validate_entries(&entries, HashKind::Sha1, threads), sequential);
+        }
+    }
+
+    /// An empty directory of its own for the test `name`.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("dxvk-cache-tool-test-merge-{}", name));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Writes a v17 cache holding `entries` to `path`.
+    fn write(path: &Path, entries: &[DxvkStateCacheEntry]) {
+        let header = DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version:    17,
+            entry_size: 0
+        };
+        format::write_cache(path, &header, entries.iter(), DEFAULT_BUFFER_SIZE).unwrap();
+    }
+
+    /// Like `write`, last modified `secs` after the epoch.
+    fn write_dated(path: &Path, entries: &[DxvkStateCacheEntry], secs: u64) {
+        write(path, entries);
+        let file = fs::File::options().write(true).open(path).unwrap();
+        file.set_modified(UNIX_EPOCH + Duration::from_secs(secs)).unwrap();
+    }
+
+    /// Merges caches holding `inputs` in order as a dry run of `builder`,
+    /// returning the hashes of the output entries in file order.
+    fn merge(
+        name: &str,
+        builder: ConfigBuilder,
+        inputs: &[Vec<DxvkStateCacheEntry>]
+    ) -> Vec<Sha1Hash> {
+        let dir = temp_dir(name);
+        let mut builder = builder.dry_run(true);
+        for (i, entries) in inputs.iter().enumerate() {
+            let path = dir.join(format!("{}.dxvk-cache", i));
+            write(&path, entries);
+            builder = builder.add_file(path);
+        }
+        let mut hashes = Vec::new();
+        merge_files_with(&builder.build().unwrap(), &mut |event| {
+            if let Event::Writing { entries, .. } = event {
+                hashes = entries.keys().copied().collect();
+            }
+        })
+        .unwrap();
+        hashes
+    }
+
+    /// Like `entry`, with the stage mask `mask`.
+    fn masked(seed: u32, size: usize, mask: u8) -> DxvkStateCacheEntry {
+        let mut entry = entry(seed, size);
+        entry.header = Some(DxvkStateCacheEntryHeader {
+            stage_mask: mask,
+            entry_size: size as u32
+        });
+        entry
+    }
+
+    /// Merges `inputs` as a dry run, returning the stage mask of the entry
+    /// of seed 1 in the output and the number of conflicts.
+    fn conflict_winner(inputs: &[PathBuf], policy: Option<ConflictPolicy>) -> (u8, usize) {
+        let mut builder = ConfigBuilder::new().dry_run(true);
+        if let Some(policy) = policy {
+            builder = builder.conflict_prefer(policy);
+        }
+        for input in inputs {
+            builder = builder.add_file(input);
+        }
+        let hash = entry(1, 32).hash;
+        let mut mask = None;
+        let report = merge_files_with(&builder.build().unwrap(), &mut |event| {
+            if let Event::Writing { entries, .. } = event {
+                mask = entries.get(&hash).and_then(|e| e.stage_mask());
+            }
+        })
+        .unwrap();
+        (mask.unwrap(), report.conflicts)
+    }
+
+    /// The larger `old` and the newer `new` both hold the entry of seed 1,
+    /// with the stage masks 0x01 and 0x10.
+    fn conflicting_inputs(name: &str, new_secs: u64) -> (PathBuf, PathBuf) {
+        let dir = temp_dir(name);
+        let (old, new) = (dir.join("old.dxvk-cache"), dir.join("new.dxvk-cache"));
+        write_dated(&old, &[masked(1, 32, 0x01), entry(2, 32), entry(3, 32)], 1000);
+        write_dated(&new, &[masked(1, 32, 0x10)], new_secs);
+        (old, new)
+    }
+
+    #[test]
+    fn last_input_wins_conflicts_without_a_policy() {
+        let (old, new) = conflicting_inputs("conflict-no-policy", 2000);
+        assert_eq!(conflict_winner(&[old.clone(), new.clone()], None).0, 0x10);
+        assert_eq!(conflict_winner(&[new, old], None).0, 0x01);
+    }
+
+    #[test]
+    fn conflict_policy_wins_over_the_input_order() {
+        let (old, new) = conflicting_inputs("conflict-policy", 2000);
+        let policies = [
+            (ConflictPolicy::Newest, 0x10),
+            (ConflictPolicy::Oldest, 0x01),
+            (ConflictPolicy::LargestFile, 0x01)
+        ];
+        for (policy, mask) in policies {
+            let inputs = [old.clone(), new.clone()];
+            assert_eq!(conflict_winner(&inputs, Some(policy)), (mask, 1));
+            let inputs = [new.clone(), old.clone()];
+            assert_eq!(conflict_winner(&inputs, Some(policy)), (mask, 1));
+        }
+    }
+
+    #[test]
+    fn input_order_breaks_conflict_policy_ties() {
+        let (old, new) = conflicting_inputs("conflict-tie", 1000);
+        for policy in [ConflictPolicy::Newest, ConflictPolicy::Oldest] {
+            let inputs = [old.clone(), new.clone()];
+            assert_eq!(conflict_winner(&inputs, Some(policy)).0, 0x10);
+            let inputs = [new.clone(), old.clone()];
+            assert_eq!(conflict_winner(&inputs, Some(policy)).0, 0x01);
+        }
+    }
+
+    /// Two inputs sharing the entry of seed 2, merged into 5 entries of
+    /// the seeds 1 to 5. The shared entry counts as coming from the second
+    /// input, where it's the fourth oldest.
+    fn evict_inputs() -> Vec<Vec<DxvkStateCacheEntry>> {
+        vec![
+            vec![entry(1, 32), entry(2, 64), entry(3, 32)],
+            vec![entry(4, 96), entry(2, 64), entry(5, 32)]
+        ]
+    }
+
+    #[test]
+    fn eviction_keeps_exactly_the_expected_entries() {
+        let sizes = [(1, 32), (2, 64), (3, 32), (4, 96), (5, 32)];
+        let seeds = |seeds: &[u32]| -> Vec<Sha1Hash> {
+            let size = |seed| sizes.iter().find(|(s, _)| *s == seed).unwrap().1;
+            seeds.iter().map(|&seed| entry(seed, size(seed)).hash).collect()
+        };
+        let inputs = evict_inputs();
+        let merge = |builder| merge("evict", builder, &inputs);
+        assert_eq!(merge(ConfigBuilder::new()), seeds(&[1, 2, 3, 4, 5]));
+
+        let builder = ConfigBuilder::new().max_entries(3).evict(EvictPolicy::Oldest);
+        assert_eq!(merge(builder), seeds(&[2, 4, 5]));
+        let builder = ConfigBuilder::new().max_entries(3).evict(EvictPolicy::Largest);
+        assert_eq!(merge(builder), seeds(&[1, 3, 5]));
+
+        // Entries of 32 bytes take 56 bytes in the file, after the header.
+        // Evicting after the first input already drops an entry here.
+        let max_bytes = HEADER_SIZE as u64 + 3 * 56;
+        let builder = ConfigBuilder::new().max_bytes(max_bytes).evict(EvictPolicy::Largest);
+        assert_eq!(merge(builder), seeds(&[1, 3, 5]));
+        let builder = ConfigBuilder::new().max_bytes(max_bytes).evict(EvictPolicy::Oldest);
+        assert_eq!(merge(builder), seeds(&[2, 5]));
+    }
+
+    #[test]
+    fn eviction_applies_before_sorting() {
+        let inputs = evict_inputs();
+        let merge = |builder| merge("evict-sort", builder, &inputs);
+        for policy in [EvictPolicy::Oldest, EvictPolicy::Largest] {
+            let mut expected = merge(ConfigBuilder::new().max_entries(3).evict(policy));
+            expected.sort_unstable();
+            let builder = ConfigBuilder::new().max_entries(3).evict(policy).sort(SortOrder::Hash);
+            assert_eq!(merge(builder), expected);
+        }
+    }
+
+    #[test]
+    fn every_sort_order_orders_the_merged_entries() {
+        let sizes = [(1, 32), (2, 64), (3, 16), (4, 48), (5, 16), (6, 8)];
+        let seeds = |seeds: &[u32]| -> Vec<Sha1Hash> {
+            let size = |seed| sizes.iter().find(|(s, _)| *s == seed).unwrap().1;
+            seeds.iter().map(|&seed| entry(seed, size(seed)).hash).collect()
+        };
+        let inputs = [
+            vec![entry(1, 32), entry(2, 64), entry(3, 16)],
+            vec![entry(4, 48), entry(2, 64), entry(5, 16)],
+            vec![entry(6, 8), entry(1, 32)]
+        ];
+        let sorted = |order| merge("sort-orders", ConfigBuilder::new().sort(order), &inputs);
+
+        // Entries found again in a later input keep their first place, and
+        // move to where that input has them by input.
+        assert_eq!(sorted(SortOrder::None), seeds(&[1, 2, 3, 4, 5, 6]));
+        assert_eq!(sorted(SortOrder::Input), seeds(&[3, 4, 2, 5, 6, 1]));
+        let mut by_hash = seeds(&[1, 2, 3, 4, 5, 6]);
+        by_hash.sort_unstable();
+        assert_eq!(sorted(SortOrder::Hash), by_hash);
+        assert_eq!(sorted(SortOrder::Size), seeds(&[6, 3, 5, 1, 4, 2]));
+    }
+
+    #[test]
+    fn version_mismatches_list_every_version() {
+        let dir = temp_dir("check-versions");
+        let versions = [17, 16, 17, 17, 15, 17, 17, 17, 17, 16];
+        let mut builder = ConfigBuilder::new().dry_run(true).regression_check(false);
+        for (i, version) in versions.iter().enumerate() {
+            let path = dir.join(format!("{}.dxvk-cache", i));
+            let header = DxvkStateCacheHeader {
+                magic:      MAGIC_STRING,
+                version:    *version,
+                entry_size: 0
+            };
+            let entries = [entry(i as u32, 32)];
+            format::write_cache(&path, &header, entries.iter(), DEFAULT_BUFFER_SIZE).unwrap();
+            builder = builder.add_file(path);
+        }
+        let Err(error) = merge_files(&builder.build().unwrap()) else {
+            panic!("Merged mismatched versions");
+        };
+        assert_eq!(error.kind(), ErrorKind::VersionMismatch { expected: 17, found: 16 });
+        assert_eq!(error.path(), Some(dir.join("1.dxvk-cache").as_path()));
+        let lines: Vec<&str> = error.message().lines().skip(1).collect();
+        let expected = [
+            "\t1 input is v15 (DXVK 1.10.2): 4.dxvk-cache",
+            "\t2 inputs are v16 (DXVK 2.0): 1.dxvk-cache, 9.dxvk-cache",
+            "\t7 inputs are v17 (DXVK 2.2): 0.dxvk-cache, 2.dxvk-cache, 3.dxvk-cache, \
+             5.dxvk-cache, 6.dxvk-cache, and 2 more"
+        ];
+        assert_eq!(lines, expected);
+    }
+
+    fn entry_map(entries: &[DxvkStateCacheEntry]) -> LinkedHashMap<Sha1Hash, DxvkStateCacheEntry> {
+        entries.iter().map(|e| (e.hash, e.clone())).collect()
+    }
+
+    #[test]
+    fn filter_hashes_applies_the_block_list_after_the_allow_list() {
+        let entries = entry_map(&[entry(1, 32), entry(2, 32), entry(3, 32)]);
+        let [first, second, third] = [1, 2, 3].map(|seed| entry(seed, 32).hash);
+        let keep = HashSet::from([first, second]);
+        let drop = HashSet::from([second]);
+        assert_eq!(filter_hashes(&entries, None, &HashSet::new()), Vec::<Sha1Hash>::new());
+        assert_eq!(filter_hashes(&entries, Some(&keep), &HashSet::new()), [third]);
+        assert_eq!(filter_hashes(&entries, None, &drop), [second]);
+        assert_eq!(filter_hashes(&entries, Some(&keep), &drop), [second, third]);
+        assert_eq!(entries.len(), 3);
+    }
+
+    #[test]
+    fn filter_stages_drops_the_other_stages() {
+        let entries = entry_map(&[masked(1, 32, 0x11), masked(2, 32, 0x20), masked(3, 32, 0x31)]);
+        let handling = LegacyStageHandling::default();
+        let hash = |seed| entry(seed, 32).hash;
+        let dropped = |stages| filter_stages(&entries, stages, handling).unwrap();
+        assert_eq!(dropped(Stages::Graphics), [hash(2), hash(3)]);
+        assert_eq!(dropped(Stages::Compute), [hash(1), hash(3)]);
+        assert_eq!(dropped(Stages::Ambiguous), [hash(1), hash(2)]);
+    }
+
+    #[test]
+    fn passes_run_in_a_fixed_order() {
+        let dir = temp_dir("pass-order");
+        let inputs = [
+            vec![masked(1, 32, 0x11), masked(2, 32, 0x20), masked(3, 64, 0x11), entry(4, 32)],
+            vec![masked(3, 64, 0x11), entry(5, 32), entry(6, 96), entry(7, 32)]
+        ];
+        let paths: Vec<PathBuf> = inputs
+            .iter()
+            .enumerate()
+            .map(|(i, entries)| {
+                let path = dir.join(format!("{}.dxvk-cache", i));
+                write(&path, entries);
+                path
+            })
+            .collect();
+        let hash = |seed, size| entry(seed, size).hash;
+        let drop = HashSet::from([hash(7, 32)]);
+        let keep = HashSet::from([hash(1, 32), hash(2, 32), hash(3, 64), hash(5, 32), hash(7, 32)]);
+        let builders = [
+            ConfigBuilder::new()
+                .keep_hashes(keep.clone())
+                .drop_hashes(drop.clone())
+                .only_stages(Stages::Graphics, LegacyStageHandling::default())
+                .max_entries(2)
+                .sort(SortOrder::Hash),
+            ConfigBuilder::new()
+                .sort(SortOrder::Hash)
+                .max_entries(2)
+                .only_stages(Stages::Graphics, LegacyStageHandling::default())
+                .drop_hashes(drop)
+                .keep_hashes(keep)
+        ];
+        for builder in builders {
+            let builder = paths.iter().fold(builder, |builder, path| builder.add_file(path));
+            let config = builder.dry_run(true).regression_check(false).build().unwrap();
+            let mut output = Vec::new();
+            let report = merge_files_with(&config, &mut |event| {
+                if let Event::Writing { entries, .. } = event {
+                    output = entries.keys().copied().collect();
+                }
+            })
+            .unwrap();
+            // The merge skips 3, the hash lists drop 4, 6 and 7, the stage
+            // filter 2, and the limit the oldest left, 1.
+            let passes = [("merge", 7), ("hash lists", 4), ("stages", 3), ("limits", 2)];
+            assert_eq!(report.passes, passes);
+            let mut expected = vec![hash(3, 64), hash(5, 32)];
+            expected.sort_unstable();
+            assert_eq!(output, expected);
+        }
+    }
+
+    #[test]
+    fn only_stages_treats_legacy_entries_by_the_handling() {
+        let path = temp_dir("only-stages-legacy").join("legacy.dxvk-cache");
+        let header = DxvkStateCacheHeader {
+            magic:      MAGIC_STRING,
+            version:    LEGACY_VERSION,
+            entry_size: (32 + HASH_SIZE) as u32
+        };
+        let entries: Vec<DxvkStateCacheEntry> = (1..=2)
+            .map(|i| {
+                let mut entry = DxvkStateCacheEntry::with_length(32 + HASH_SIZE);
+                entry.data.fill(i);
+                entry.hash = entry.compute_hash(HashKind::Sha1);
+                entry
+            })
+            .collect();
+        format::write_cache(&path, &header, entries.iter(), DEFAULT_BUFFER_SIZE).unwrap();
+        let kept = |stages, handling| {
+            let builder = ConfigBuilder::new()
+                .add_file(&path)
+                .dry_run(true)
+                .allow_empty(true)
+                .only_stages(stages, handling);
+            let mut kept = None;
+            merge_files_with(&builder.build().unwrap(), &mut |event| {
+                if let Event::Writing { entries, .. } = event {
+                    kept = Some(entries.len());
+                }
+            })
+            .map(|_| kept.unwrap())
+        };
+        let graphics = LegacyStageHandling::TreatAsGraphics;
+        assert_eq!(kept(Stages::Graphics, graphics).unwrap(), 2);
+        assert_eq!(kept(Stages::Compute, graphics).unwrap(), 0);
+        let unknown = LegacyStageHandling::TreatAsUnknown;
+        assert_eq!(kept(Stages::Graphics, unknown).unwrap(), 0);
+        assert_eq!(kept(Stages::Unknown, unknown).unwrap(), 2);
+        let error = kept(Stages::Graphics, LegacyStageHandling::Reject).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::InvalidData);
+    }
+
+    /// A cache of `version` holding `entries`, as its bytes.
+    fn cache_of(version: u32, entries: &[DxvkStateCacheEntry]) -> Vec<u8> {
+        let header = DxvkStateCacheHeader {
+            magic: MAGIC_STRING,
+            version,
+            entry_size: 0
+        };
+        let mut bytes = Vec::new();
+        format::write_cache_to(&mut bytes, &header, entries.iter()).unwrap();
+        bytes
+    }
+
+    #[test]
+    fn session_rejects_a_mismatched_version_mid_session() {
+        let dir = temp_dir("session-mismatch");
+        let (first, second) = (dir.join("first.dxvk-cache"), dir.join("second.dxvk-cache"));
+        fs::write(&first, cache_of(17, &[entry(1, 32), entry(2, 64)])).unwrap();
+        fs::write(&second, cache_of(15, &[entry(3, 32)])).unwrap();
+        let config = ConfigBuilder::new().dry_run(true).regression_check(false).build().unwrap();
+        let mut session = MergeSession::new(&config);
+        assert_eq!(session.preview_output_size(), HEADER_SIZE as u64);
+
+        assert_eq!(session.add_file(&first).unwrap().new, 2);
+        let size = session.preview_output_size();
+        assert_eq!(size, (HEADER_SIZE + 56 + 88) as u64);
+        let Err(error) = session.add_file(&second) else {
+            panic!("Added a mismatched version");
+        };
+        assert_eq!(error.kind(), ErrorKind::VersionMismatch { expected: 17, found: 15 });
+        assert_eq!(error.path(), Some(second.as_path()));
+        let Err(error) = session.add_reader(&second, cache_of(15, &[entry(3, 32)]).as_slice())
+        else {
+            panic!("Added a mismatched version");
+        };
+        assert_eq!(error.kind(), ErrorKind::VersionMismatch { expected: 17, found: 15 });
+
+        // The session goes on as if the mismatched input wasn't added.
+        assert_eq!(session.preview_output_size(), size);
+        assert_eq!(session.current_stats().entries, 2);
+        let third = cache_of(17, &[entry(4, 32)]);
+        let report = session.add_reader(Path::new("third"), third.as_slice());
+        assert_eq!(report.unwrap().new, 1);
+        assert_eq!(session.preview_output_size(), size + 56);
+        let mut written = None;
+        let report = session
+            .finish_with(&mut |event| {
+                if let Event::Writing { entries, size, .. } = event {
+                    written = Some((entries.len(), *size));
+                }
+            })
+            .unwrap();
+        assert_eq!(written, Some((3, size + 56)));
+        assert_eq!(report.inputs.len(), 2);
+    }
+
+    #[test]
+    fn entry_size_range_keeps_the_entries_within_it() {
+        let sizes = [[16, 32, 64, 128], [32, 256, 1024, 4]];
+        let inputs: Vec<Vec<u8>> = sizes
+            .iter()
+            .enumerate()
+            .map(|(i, sizes)| {
+                let entries: Vec<DxvkStateCacheEntry> = sizes
+                    .iter()
+                    .enumerate()
+                    .map(|(j, &size)| entry((10 * i + j) as u32, size))
+                    .collect();
+                cache_of(17, &entries)
+            })
+            .collect();
+        let kept = |builder: ConfigBuilder| -> (Vec<usize>, Vec<usize>) {
+            let config = builder.dry_run(true).regression_check(false).build().unwrap();
+            let mut session = MergeSession::new(&config);
+            let mut out_of_range = Vec::new();
+            for (i, input) in inputs.iter().enumerate() {
+                let name = PathBuf::from(format!("{}.dxvk-cache", i));
+                let report = session.add_reader(&name, input.as_slice()).unwrap();
+                out_of_range.push(report.out_of_range);
+            }
+            let mut kept = Vec::new();
+            session
+                .finish_with(&mut |event| {
+                    if let Event::Writing { entries, .. } = event {
+                        kept = entries.values().map(|e| e.data.len()).collect();
+                    }
+                })
+                .unwrap();
+            (kept, out_of_range)
+        };
+        let range = ConfigBuilder::new().min_entry_size(32).max_entry_size(128);
+        assert_eq!(kept(range), (vec![32, 64, 128, 32], vec![1, 3]));
+        let min = ConfigBuilder::new().min_entry_size(129);
+        assert_eq!(kept(min), (vec![256, 1024], vec![4, 2]));
+        let max = ConfigBuilder::new().max_entry_size(16);
+        assert_eq!(kept(max), (vec![16, 4], vec![3, 3]));
+        let exact = ConfigBuilder::new().min_entry_size(64).max_entry_size(64);
+        assert_eq!(kept(exact), (vec![64], vec![3, 4]));
+    }
+
+    #[test]
+    fn entry_size_range_must_not_be_empty() {
+        let Err(error) = ConfigBuilder::new().min_entry_size(65).max_entry_size(64).build() else {
+            panic!("Built an empty entry size range");
+        };
+        assert_eq!(error.kind(), ErrorKind::InvalidInput);
+        assert_eq!(error.message(), "Entry size range is empty: at least 65 bytes but at most 64");
+        assert!(ConfigBuilder::new().max_entry_size(0).build().is_err());
+    }
+
+    #[test]
+    fn dedup_by_data_replaces_stale_master_entries() {
+        let dir = temp_dir("dedup-by-data");
+        let master = dir.join("master.dxvk-cache");
+        let session = dir.join("session.dxvk-cache");
+        let mut stale = entry(1, 32);
+        stale.hash[0] ^= 1;
+        let merged = |key: DedupKey| -> (Vec<Sha1Hash>, usize, usize) {
+            write(&master, &[stale.clone(), entry(2, 32)]);
+            write(&session, &[entry(2, 32), entry(1, 32)]);
+            let config = ConfigBuilder::new()
+                .add_file(&master)
+                .add_file(&session)
+                .output(&master)
+                .dedup_by(key)
+                .dry_run(true)
+                .build()
+                .unwrap();
+            let mut hashes = Vec::new();
+            let report = merge_files_with(&config, &mut |event| {
+                if let Event::Writing { entries, .. } = event {
+                    hashes = entries.values().map(|e| e.hash).collect();
+                }
+            })
+            .unwrap();
+            let input = &report.inputs[1];
+            (hashes, input.duplicates, input.data_duplicates)
+        };
+        let by_hash = vec![stale.hash, entry(2, 32).hash, entry(1, 32).hash];
+        assert_eq!(merged(DedupKey::Hash), (by_hash, 1, 0));
+        let by_data = vec![entry(1, 32).hash, entry(2, 32).hash];
+        assert_eq!(merged(DedupKey::Data), (by_data, 2, 1));
+    }
+
+    #[test]
+    fn space_of_a_replaced_output_is_not_available() {
+        // The old output stays until the new one is renamed over it, so
+        // 600 bytes don't fit into 500 available even next to 1000 old ones.
+        let dir = temp_dir("space-replaced");
+        let output = dir.join("out.dxvk-cache");
+        fs::write(&output, vec![0; 1000]).unwrap();
+        let error = check_fits(&output, 600, 500).unwrap_err();
+        assert_eq!(error.kind(), ErrorKind::IoError(io::ErrorKind::Other));
+        assert!(error.message().contains("600 bytes needed, 500 bytes available"));
+        assert!(check_fits(&output, 500, 500).is_ok());
+    }
+
+    #[test]
+    fn checkpoints_append_the_entries_merged_since() {
+        let dir = temp_dir("checkpoint");
+        let inputs = [dir.join("a.dxvk-cache"), dir.join("b.dxvk-cache"), dir.join("c.dxvk-cache")];
+        let [a, b, c] = &inputs;
+        write(a, &[entry(1, 32), entry(2, 32)]);
+        write(b, &[entry(3, 32)]);
+        write(c, &[masked(1, 32, 0x10)]);
+        let config = ConfigBuilder::new().dry_run(true).build().unwrap();
+        let path = dir.join("journal");
+        let partial = dir.join("journal.dxvk-cache");
+        let mut journal = MergeJournal::new(&partial);
+        let mut session = MergeSession::new(&config);
+        let mut checkpoint = |session: &mut MergeSession, input: &Path| {
+            session.add_file(input).unwrap();
+            let stale = session.partial_stale;
+            session.checkpoint(&mut journal, &path, &mut vec![input], &mut |_| ()).unwrap();
+            stale
+        };
+
+        assert!(!checkpoint(&mut session, a));
+        assert_eq!(fs::read(&partial).unwrap(), cache_of(17, &[entry(1, 32), entry(2, 32)]));
+        assert!(!checkpoint(&mut session, b));
+        let appended = [entry(1, 32), entry(2, 32), entry(3, 32)];
+        assert_eq!(fs::read(&partial).unwrap(), cache_of(17, &appended));
+        // Replacing a written entry writes the partial output anew.
+        assert!(checkpoint(&mut session, c));
+        let replaced = [masked(1, 32, 0x10), entry(2, 32), entry(3, 32)];
+        assert_eq!(fs::read(&partial).unwrap(), cache_of(17, &replaced));
+
+        // Resuming cuts off what an interrupted checkpoint appended.
+        let mut bytes = fs::read(&partial).unwrap();
+        bytes.extend_from_slice(&[0; 20]);
+        fs::write(&partial, bytes).unwrap();
+        let mut session = MergeSession::new(&config);
+        let journal = open_journal(&path, &mut session, &mut |_| ()).unwrap();
+        assert!(inputs.iter().all(|input| journal.contains(input)));
+        assert_eq!(session.current_stats().entries, 3);
+        assert_eq!(session.checkpointed, 3);
+        assert_eq!(fs::read(&partial).unwrap(), cache_of(17, &replaced));
+    }
+}
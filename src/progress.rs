@@ -0,0 +1,55 @@
+use std::fmt;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Phase {
+    Reading,
+    Validating,
+    Writing
+}
+
+impl fmt::Display for Phase {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(match self {
+            Phase::Reading => "reading",
+            Phase::Validating => "validating",
+            Phase::Writing => "writing"
+        })
+    }
+}
+
+/// Progress of a whole run over several files.
+///
+/// `bytes_done` counts the bytes of all finished files plus the current
+/// position in the current one. `bytes_total` is `None` when the size of an
+/// input can't be determined up front, in which case no overall percentage
+/// is available. `entries_done` counts the entries processed in the current
+/// phase of the current file.
+pub struct Progress<'a> {
+    pub phase:        Phase,
+    pub file_index:   usize,
+    pub file_count:   usize,
+    pub file_name:    &'a str,
+    pub bytes_done:   u64,
+    pub bytes_total:  Option<u64>,
+    pub entries_done: usize
+}
+
+impl<'a> Progress<'a> {
+    pub fn percent(&self) -> Option<u64> {
+        match self.bytes_total {
+            Some(0) => Some(100),
+            Some(total) => Some((self.bytes_done.min(total) * 100) / total),
+            None => None
+        }
+    }
+}
+
+impl<'a> fmt::Display for Progress<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "[{}/{}]", self.file_index + 1, self.file_count)?;
+        if let Some(percent) = self.percent() {
+            write!(f, " {}% overall", percent)?;
+        }
+        write!(f, " {} {}", self.phase, self.file_name)
+    }
+}
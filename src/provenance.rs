@@ -0,0 +1,203 @@
+//! Provenance sidecars of merged caches. The state cache format has no room
+//! for metadata, so how a cache was made is written next to it, to
+//! `<cache>.meta.json`, for the recipients of merged caches. A cache never
+//! requires its sidecar.
+
+use std::convert::TryFrom;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::dxvk::{hash_to_hex, parse_hash, Sha1Hash};
+use crate::error::{Error, ErrorKind};
+use crate::json::{self, Value};
+use crate::resume::file_hash;
+use crate::util;
+
+/// Appended to the file name of a cache for the name of its sidecar.
+pub const SIDECAR_SUFFIX: &str = ".meta.json";
+
+/// Transformation of entries sorted by hash instead of in merge order,
+/// which tells the order the pipelines were compiled in.
+pub const SORT_BY_HASH: &str = "sort-by-hash";
+/// Transformation of entries grouped by input instead of in merge order.
+pub const SORT_BY_INPUT: &str = "sort-by-input";
+/// Transformation of entries sorted by size instead of in merge order.
+pub const SORT_BY_SIZE: &str = "sort-by-size";
+/// Transformation of input names replaced by `strip_input_names`.
+pub const STRIP_INPUT_NAMES: &str = "strip-input-names";
+/// Transformation of entries removed by `redact`.
+pub const REDACT: &str = "redact";
+
+/// How a cache was made: by which version of the tool, when and from which
+/// inputs, along with the SHA1 of the cache, telling whether it changed or
+/// was copied partially since. A sidecar is a JSON object with these fields
+/// and `schema_version`, see the `provenance` definition of
+/// `json::SCHEMA`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Provenance {
+    pub tool_version: String,
+    /// ISO 8601 UTC timestamp of the merge.
+    pub created:      String,
+    /// File names of the inputs with the SHA1 of their contents. Directories
+    /// are left out, as they tell about the machine of whoever merged.
+    pub inputs:       Vec<(String, Sha1Hash)>,
+    pub entries:      usize,
+    pub sha1:         Sha1Hash,
+    /// What was done besides merging, such as `sort-by-hash`, in order.
+    /// Empty in sidecars of earlier versions.
+    pub transformations: Vec<String>
+}
+
+impl Provenance {
+    /// Records the provenance of `output` as it is now, with `entries`
+    /// entries merged from `inputs`, hashed before they were merged, as the
+    /// output may replace one of them.
+    pub fn record<'a, I>(output: &Path, inputs: I, entries: usize) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = (&'a Path, Sha1Hash)>
+    {
+        Ok(Provenance {
+            tool_version: env!("CARGO_PKG_VERSION").to_string(),
+            created: util::format_timestamp(SystemTime::now()),
+            inputs: inputs
+                .into_iter()
+                .map(|(path, hash)| (util::file_name(path).into_owned(), hash))
+                .collect(),
+            entries,
+            sha1: file_hash(output).map_err(|e| Error::from(e).with_path(output))?,
+            transformations: Vec::new()
+        })
+    }
+
+    /// Replaces the names of the inputs by `input-1`, `input-2` and so on,
+    /// keeping only their hashes to tell them by, for sharing a cache
+    /// without the names of the mods and games it came from.
+    pub fn strip_input_names(&mut self) {
+        for (i, (name, _)) in self.inputs.iter_mut().enumerate() {
+            *name = format!("input-{}", i + 1);
+        }
+        self.transformations.push(STRIP_INPUT_NAMES.to_string());
+    }
+
+    /// Reads the sidecar of `cache`, returning `None` if there is none and
+    /// failing with `ErrorKind::InvalidData` if it is malformed.
+    pub fn read(cache: &Path) -> Result<Option<Self>, Error> {
+        let path = sidecar_path(cache);
+        let text = match fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(None),
+            Err(e) => return Err(Error::from(e).with_path(&path))
+        };
+        json::parse(&text)
+            .as_ref()
+            .and_then(Provenance::from_json)
+            .map(Some)
+            .ok_or_else(|| {
+                Error::new(ErrorKind::InvalidData, "Malformed provenance metadata").with_path(&path)
+            })
+    }
+
+    fn from_json(value: &Value) -> Option<Self> {
+        let hash = |value: &Value| value.as_str().and_then(|s| parse_hash(s).ok());
+        if value.get("schema_version")?.as_u64()? != u64::from(json::SCHEMA_VERSION) {
+            return None;
+        }
+        let inputs = value
+            .get("inputs")?
+            .as_array()?
+            .iter()
+            .map(|input| {
+                let name = input.get("name")?.as_str()?.to_string();
+                Some((name, hash(input.get("sha1")?)?))
+            })
+            .collect::<Option<_>>()?;
+        let transformations = match value.get("transformations") {
+            Some(list) => list
+                .as_array()?
+                .iter()
+                .map(|t| t.as_str().map(str::to_string))
+                .collect::<Option<_>>()?,
+            None => Vec::new()
+        };
+        Some(Provenance {
+            tool_version: value.get("tool_version")?.as_str()?.to_string(),
+            created: value.get("created")?.as_str()?.to_string(),
+            inputs,
+            entries: usize::try_from(value.get("entries")?.as_u64()?).ok()?,
+            sha1: hash(value.get("sha1")?)?,
+            transformations
+        })
+    }
+
+    /// Writes the sidecar of `cache`, replacing it at once.
+    pub fn write(&self, cache: &Path) -> Result<(), Error> {
+        let path = sidecar_path(cache);
+        let text = format!(
+            "{{\"schema_version\":{},{}}}\n",
+            json::SCHEMA_VERSION,
+            self.json_fields()
+        );
+        util::write_atomic(&path, text.as_bytes()).map_err(|e| Error::from(e).with_path(&path))
+    }
+
+    /// Whether `cache` still has the contents the sidecar was written for.
+    pub fn matches(&self, cache: &Path) -> Result<bool, Error> {
+        let hash = file_hash(cache).map_err(|e| Error::from(e).with_path(cache))?;
+        Ok(hash == self.sha1)
+    }
+
+    /// Formats the provenance as the members of a JSON object, for
+    /// embedding into other documents.
+    pub fn json_fields(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|(name, hash)| {
+                format!(
+                    "{{\"name\":{},\"sha1\":{}}}",
+                    json::string(name),
+                    json::string(&hash_to_hex(hash))
+                )
+            })
+            .collect();
+        let transformations: Vec<String> =
+            self.transformations.iter().map(|t| json::string(t)).collect();
+        format!(
+            "\"tool_version\":{},\"created\":{},\"inputs\":[{}],\"entries\":{},\"sha1\":{},\
+             \"transformations\":[{}]",
+            json::string(&self.tool_version),
+            json::string(&self.created),
+            inputs.join(","),
+            self.entries,
+            json::string(&hash_to_hex(&self.sha1)),
+            transformations.join(",")
+        )
+    }
+}
+
+/// Returns the path of the sidecar of `cache`.
+pub fn sidecar_path(cache: &Path) -> PathBuf {
+    let mut path = cache.as_os_str().to_owned();
+    path.push(SIDECAR_SUFFIX);
+    PathBuf::from(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_a_sidecar_of_an_earlier_version() {
+        let cache = Path::new("tests/fixtures/schema/v1/provenance.dxvk-cache");
+        let provenance = Provenance::read(cache).unwrap().unwrap();
+        assert_eq!(provenance.tool_version, "1.1.2");
+        assert_eq!(provenance.created, "2026-10-16T06:33:30Z");
+        let names: Vec<&str> = provenance.inputs.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, ["a.dxvk-cache", "bad.dxvk-cache"]);
+        assert_eq!(provenance.entries, 3);
+        assert_eq!(hash_to_hex(&provenance.sha1), "61f9f0a49135f39234c48b797a468ca99302a1aa");
+        assert!(provenance.transformations.is_empty());
+    }
+}
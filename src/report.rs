@@ -0,0 +1,372 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::bench::Workload;
+use crate::dxvk::{hash_to_hex, Sha1Hash};
+use crate::json;
+use crate::warning::{self, Warning};
+
+/// Outcome of merging a single input file.
+#[derive(Clone)]
+pub struct FileReport {
+    pub path:    PathBuf,
+    pub entries: usize,
+    /// Entries left after limiting the entries taken per input.
+    pub taken:   usize,
+    pub new:     usize,
+    /// Valid entries whose hash an earlier input or an earlier entry of the
+    /// same input already had.
+    pub duplicates: usize,
+    /// Duplicates whose stored hash differs from that of the entry they
+    /// duplicate, found with `ConfigBuilder::dedup_by` set to
+    /// `DedupKey::Data`. Deduplicating by hash would have kept both.
+    pub data_duplicates: usize,
+    pub invalid: usize,
+    /// Last entries dropped as still being written, by
+    /// `ConfigBuilder::drop_partial_tail`.
+    pub truncated: usize,
+    /// Valid entries skipped for a data size outside the range set by
+    /// `ConfigBuilder::min_entry_size` and `ConfigBuilder::max_entry_size`.
+    pub out_of_range: usize,
+    /// Transient I/O errors retried while reading.
+    pub retries: usize,
+    /// Entries evicted to fit into the limits after merging this input.
+    pub evicted: usize,
+    pub skipped: bool,
+    /// Earlier input that is the same file as this one.
+    pub duplicate_of: Option<PathBuf>
+}
+
+impl FileReport {
+    /// The report of an input skipped without merging any of its entries.
+    pub fn skipped(path: &Path) -> Self {
+        FileReport {
+            path:         path.to_path_buf(),
+            entries:      0,
+            taken:        0,
+            evicted:      0,
+            new:          0,
+            duplicates:   0,
+            data_duplicates: 0,
+            invalid:      0,
+            truncated:    0,
+            out_of_range: 0,
+            retries:      0,
+            skipped:      true,
+            duplicate_of: None
+        }
+    }
+}
+
+/// Outcome of a merge, printed with `--json`. On a dry run, the output is
+/// the one that would have been written.
+#[derive(Default)]
+pub struct MergeReport {
+    pub dry_run:     bool,
+    pub version:     u32,
+    pub inputs:      Vec<FileReport>,
+    pub evicted:     usize,
+    /// Entries dropped for being found in fewer inputs than required.
+    pub too_few_sources: usize,
+    /// Entries dropped by the hash lists and the stages kept.
+    pub filtered:    usize,
+    /// The passes of the merge that ran, in order, with the entries left
+    /// after each of them.
+    pub passes:      Vec<(&'static str, usize)>,
+    /// Transient I/O errors retried, reading inputs or writing the output.
+    pub retries:     usize,
+    /// Entries differing between inputs, resolved by the conflict policy.
+    pub conflicts:   usize,
+    /// Inputs left unmerged when the time limit passed.
+    pub pending:     Vec<PathBuf>,
+    pub entries:     usize,
+    pub output:      PathBuf,
+    pub output_size: u64,
+    /// The merged entries compared with a reference cache, if one is given.
+    pub comparison:  Option<Comparison>,
+    /// Time spent in each phase of the merge.
+    pub timings:     PhaseTimings,
+    pub warnings:    Vec<Warning>
+}
+
+/// Time a merge spends in each of its phases, summed over the inputs, as
+/// printed by `--profile`.
+#[derive(Clone, Copy, Default)]
+pub struct PhaseTimings {
+    /// Reading the headers of the inputs to check their versions.
+    pub header_reads: Duration,
+    /// Reading and parsing the entries of the inputs.
+    pub parsing:      Duration,
+    /// Checking the hashes of the entries.
+    pub validation:   Duration,
+    /// Adding the valid entries to the output, evicting and sorting them.
+    pub merging:      Duration,
+    pub writing:      Duration
+}
+
+impl PhaseTimings {
+    /// The phases by name, in the order they happen.
+    pub fn phases(&self) -> [(&'static str, Duration); 5] {
+        [
+            ("Header reads", self.header_reads),
+            ("Parsing", self.parsing),
+            ("SHA1 validation", self.validation),
+            ("Merging", self.merging),
+            ("Writing", self.writing)
+        ]
+    }
+}
+
+/// Hashes of valid entries found in only one of a merge and a reference
+/// cache, both sorted.
+pub struct Comparison {
+    pub reference:         PathBuf,
+    pub only_in_merge:     Vec<Sha1Hash>,
+    pub only_in_reference: Vec<Sha1Hash>
+}
+
+impl Comparison {
+    /// Whether the merge has exactly the entries of the reference.
+    pub fn is_equal(&self) -> bool {
+        self.only_in_merge.is_empty() && self.only_in_reference.is_empty()
+    }
+
+    fn to_json(&self) -> String {
+        let hashes = |hashes: &[Sha1Hash]| -> String {
+            let hashes: Vec<String> =
+                hashes.iter().map(|h| format!("\"{}\"", hash_to_hex(h))).collect();
+            hashes.join(",")
+        };
+        format!(
+            "{{\"reference\":{},\"only_in_merge\":[{}],\"only_in_reference\":[{}]}}",
+            json::path(&self.reference),
+            hashes(&self.only_in_merge),
+            hashes(&self.only_in_reference)
+        )
+    }
+}
+
+impl MergeReport {
+    pub fn to_json(&self) -> String {
+        let inputs: Vec<String> = self
+            .inputs
+            .iter()
+            .map(|f| {
+                format!(
+                    "{{\"path\":{},\"entries\":{},\"taken\":{},\"new\":{},\"duplicates\":{},\
+                     \"data_duplicates\":{},\"invalid\":{},\"truncated\":{},\"out_of_range\":{},\
+                     \"evicted\":{},\"retries\":{},\"skipped\":{},\"duplicate_of\":{}}}",
+                    json::path(&f.path),
+                    f.entries,
+                    f.taken,
+                    f.new,
+                    f.duplicates,
+                    f.data_duplicates,
+                    f.invalid,
+                    f.truncated,
+                    f.out_of_range,
+                    f.evicted,
+                    f.retries,
+                    f.skipped,
+                    f.duplicate_of
+                        .as_ref()
+                        .map_or("null".into(), |p| json::path(p))
+                )
+            })
+            .collect();
+        let pending: Vec<String> =
+            self.pending.iter().map(|p| json::path(p)).collect();
+        let passes: Vec<String> = self
+            .passes
+            .iter()
+            .map(|(pass, entries)| {
+                format!("{{\"pass\":{},\"entries\":{}}}", json::string(pass), entries)
+            })
+            .collect();
+        format!(
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"dry_run\":{},\"version\":{},\
+             \"inputs\":[{}],\"evicted\":{},\"too_few_sources\":{},\"filtered\":{},\
+             \"passes\":[{}],\"retries\":{},\"conflicts\":{},\"pending\":[{}],\"entries\":{},\
+             \"output\":{},\"output_size\":{},\"comparison\":{},\"warnings\":{}}}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            self.dry_run,
+            self.version,
+            inputs.join(","),
+            self.evicted,
+            self.too_few_sources,
+            self.filtered,
+            passes.join(","),
+            self.retries,
+            self.conflicts,
+            pending.join(","),
+            self.entries,
+            json::path(&self.output),
+            self.output_size,
+            self.comparison.as_ref().map_or("null".into(), Comparison::to_json),
+            warning::to_json(&self.warnings)
+        )
+    }
+}
+
+/// Formats merge reports in the Prometheus text format, for the textfile
+/// collector of node_exporter. The metrics of each report are labeled with
+/// its output.
+pub fn to_metrics(reports: &[MergeReport]) -> String {
+    type Metric = fn(&MergeReport) -> u64;
+    let metrics: [(&str, &str, Metric); 10] = [
+        ("dxvk_cache_version", "State cache version of the output.", |r| r.version.into()),
+        ("dxvk_cache_inputs_total", "Input files given.", |r| r.inputs.len() as u64),
+        (
+            "dxvk_cache_inputs_skipped_total",
+            "Inputs skipped as mismatched or the same file as another.",
+            |r| r.inputs.iter().filter(|f| f.skipped).count() as u64
+        ),
+        ("dxvk_cache_entries_total", "Entries in the output.", |r| r.entries as u64),
+        (
+            "dxvk_cache_invalid_total",
+            "Entries omitted for failing validation.",
+            |r| r.inputs.iter().map(|f| f.invalid as u64).sum()
+        ),
+        (
+            "dxvk_cache_duplicates_total",
+            "Entries already merged from an earlier input.",
+            |r| r.inputs.iter().map(|f| f.taken.saturating_sub(f.invalid + f.new) as u64).sum()
+        ),
+        (
+            "dxvk_cache_conflicts_total",
+            "Entries differing between inputs, resolved by the conflict policy.",
+            |r| r.conflicts as u64
+        ),
+        ("dxvk_cache_evicted_total", "Entries evicted to fit into the limits.", |r| {
+            r.evicted as u64
+        }),
+        (
+            "dxvk_cache_too_few_sources_total",
+            "Entries dropped for being found in too few inputs.",
+            |r| r.too_few_sources as u64
+        ),
+        ("dxvk_cache_output_bytes", "Size of the output file.", |r| r.output_size)
+    ];
+    let mut out = String::new();
+    for (name, help, metric) in metrics.iter() {
+        out.push_str(&format!("# HELP {} {}\n# TYPE {} gauge\n", name, help, name));
+        for report in reports {
+            let output = report
+                .output
+                .to_string_lossy()
+                .replace('\\', "\\\\")
+                .replace('"', "\\\"")
+                .replace('\n', "\\n");
+            out.push_str(&format!("{}{{output=\"{}\"}} {}\n", name, output, metric(report)));
+        }
+    }
+    out
+}
+
+/// An entry failing validation and the hash `repair` gives it.
+pub struct RepairedEntry {
+    pub stored:     Sha1Hash,
+    pub computed:   Sha1Hash,
+    pub size:       usize,
+    pub stage_mask: Option<u8>,
+    /// Whether the computed hash is already taken by another entry, in
+    /// which case the repaired entry is dropped as a duplicate.
+    pub collides:   bool
+}
+
+/// Outcome of `repair`, the same whether or not anything was written.
+pub struct RepairReport {
+    pub dry_run:  bool,
+    pub entries:  Vec<RepairedEntry>,
+    pub warnings: Vec<Warning>
+}
+
+impl RepairReport {
+    pub fn to_json(&self) -> String {
+        let entries: Vec<String> = self
+            .entries
+            .iter()
+            .map(|e| {
+                format!(
+                    "{{\"stored\":\"{}\",\"computed\":\"{}\",\"size\":{},\"stage_mask\":{},\
+                     \"collides\":{}}}",
+                    hash_to_hex(&e.stored),
+                    hash_to_hex(&e.computed),
+                    e.size,
+                    e.stage_mask.map_or("null".into(), |m| m.to_string()),
+                    e.collides
+                )
+            })
+            .collect();
+        format!(
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"dry_run\":{},\"entries\":[{}],\
+             \"warnings\":{}}}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            self.dry_run,
+            entries.join(","),
+            warning::to_json(&self.warnings)
+        )
+    }
+}
+
+/// Timing of a benchmark over the bytes it processes.
+pub struct Timing {
+    pub name:   &'static str,
+    pub bytes:  u64,
+    /// The fastest run.
+    pub min:    Duration,
+    pub median: Duration
+}
+
+impl Timing {
+    /// Throughput in megabytes, of a million bytes, per second over
+    /// `duration`.
+    pub fn throughput(&self, duration: Duration) -> f64 {
+        self.bytes as f64 / 1e6 / duration.max(Duration::from_nanos(1)).as_secs_f64()
+    }
+}
+
+/// Outcome of `bench`.
+pub struct BenchReport {
+    pub workload: Workload,
+    pub threads:  usize,
+    pub runs:     usize,
+    pub timings:  Vec<Timing>,
+    pub warnings: Vec<Warning>
+}
+
+impl BenchReport {
+    pub fn to_json(&self) -> String {
+        let timings: Vec<String> = self
+            .timings
+            .iter()
+            .map(|t| {
+                format!(
+                    "{{\"name\":{},\"bytes\":{},\"min_seconds\":{:.6},\"median_seconds\":{:.6},\
+                     \"best_mb_per_second\":{:.1},\"median_mb_per_second\":{:.1}}}",
+                    json::string(t.name),
+                    t.bytes,
+                    t.min.as_secs_f64(),
+                    t.median.as_secs_f64(),
+                    t.throughput(t.min),
+                    t.throughput(t.median)
+                )
+            })
+            .collect();
+        format!(
+            "{{\"schema_version\":{},\"workload\":{{\"entries\":{},\"entry_size\":{},\"seed\":{},\
+             \"version\":{}}},\"threads\":{},\"runs\":{},\"timings\":[{}],\"warnings\":{}}}",
+            json::SCHEMA_VERSION,
+            self.workload.entries,
+            self.workload.entry_size,
+            self.workload.seed,
+            self.workload.version(),
+            self.threads,
+            self.runs,
+            timings.join(","),
+            warning::to_json(&self.warnings)
+        )
+    }
+}
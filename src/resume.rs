@@ -0,0 +1,602 @@
+//! State of a merge stopped by its time limit or interrupted, for
+//! resuming it later.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::iter::Peekable;
+use std::path::{Path, PathBuf};
+
+use sha1::Sha1;
+
+use crate::dxvk::{hash_to_hex, parse_hash, Sha1Hash};
+use crate::error::{Error, ErrorKind};
+use crate::util::{self, PathStyle};
+
+/// First line of a state file, changed along with its layout.
+const STATE_MAGIC: &str = "dxvk-cache-tool merge state 1";
+/// First line of a journal, changed along with its layout.
+const JOURNAL_MAGIC: &str = "dxvk-cache-tool merge journal 2";
+/// First line of a journal of version 1, which records the partial output
+/// without its size, as it was written whole at every checkpoint.
+const JOURNAL_MAGIC_1: &str = "dxvk-cache-tool merge journal 1";
+
+/// The inputs merged into an output so far, identified by the SHA1 of
+/// their contents so that changes are noticed before resuming. A state
+/// file lists them a line each, after the output:
+///
+/// ```text
+/// dxvk-cache-tool merge state 1
+/// path_style <posix|windows>
+/// output <sha1> <path>
+/// input <sha1> <path>
+/// ```
+///
+/// Paths are absolute, so a merge can be resumed from another directory,
+/// and written with forward slashes. `path_style` tells the platform that
+/// wrote them, so that backslashes of Windows paths are read as separators
+/// on other platforms too.
+pub struct MergeState {
+    output: (PathBuf, Sha1Hash),
+    inputs: Vec<(PathBuf, Sha1Hash)>
+}
+
+impl MergeState {
+    /// Records `inputs` as merged into `output`, hashing both as they are
+    /// now.
+    pub fn record<'a, I>(output: &Path, inputs: I) -> Result<Self, Error>
+    where
+        I: IntoIterator<Item = &'a Path>
+    {
+        let hashed = |path: &Path| -> Result<(PathBuf, Sha1Hash), Error> {
+            let path = fs::canonicalize(path).map_err(|e| Error::from(e).with_path(path))?;
+            let hash = file_hash(&path).map_err(|e| Error::from(e).with_path(&path))?;
+            Ok((path, hash))
+        };
+        Ok(MergeState {
+            output: hashed(output)?,
+            inputs: inputs.into_iter().map(hashed).collect::<Result<_, _>>()?
+        })
+    }
+
+    /// Reads a state file, failing with `ErrorKind::InvalidData` if it is
+    /// malformed.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let lines = util::read_lines(path).map_err(|e| Error::from(e).with_path(path))?;
+        let invalid = |line: usize| {
+            Error::new(ErrorKind::InvalidData, format!("Malformed merge state at line {}", line))
+                .with_path(path)
+        };
+        let mut lines = lines.iter().map(String::as_str).peekable();
+        if lines.next() != Some(STATE_MAGIC) {
+            return Err(invalid(1));
+        }
+        let (style, first) = read_path_style(&mut lines, invalid)?;
+        let mut output = None;
+        let mut inputs = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let number = i + first;
+            let mut fields = line.splitn(3, ' ');
+            let (kind, hash, file) = match (fields.next(), fields.next(), fields.next()) {
+                (Some(kind), Some(hash), Some(file)) if !file.is_empty() => (kind, hash, file),
+                _ => return Err(invalid(number))
+            };
+            let hash = parse_hash(hash).map_err(|_| invalid(number))?;
+            let entry = (util::native_path(file, style), hash);
+            match kind {
+                "output" if output.is_none() => output = Some(entry),
+                "input" => inputs.push(entry),
+                _ => return Err(invalid(number))
+            }
+        }
+        let output = output.ok_or_else(|| invalid(1))?;
+        Ok(MergeState { output, inputs })
+    }
+
+    /// Writes the state file, replacing it at once.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut text = format!("{}\npath_style {}\n", STATE_MAGIC, PathStyle::NATIVE.as_str());
+        let line = |kind: &str, (file, hash): &(PathBuf, Sha1Hash)| {
+            format!("{} {} {}\n", kind, hash_to_hex(hash), util::portable_path(file))
+        };
+        text.push_str(&line("output", &self.output));
+        for input in &self.inputs {
+            text.push_str(&line("input", input));
+        }
+        util::write_atomic(path, text.as_bytes()).map_err(|e| Error::from(e).with_path(path))
+    }
+
+    /// The output the inputs are merged into.
+    pub fn output(&self) -> &Path {
+        &self.output.0
+    }
+
+    /// The inputs merged so far, in merge order.
+    pub fn inputs(&self) -> impl Iterator<Item = &Path> {
+        self.inputs.iter().map(|(path, _)| path.as_path())
+    }
+
+    /// Whether `path` is one of the inputs merged so far.
+    pub fn contains(&self, path: &Path) -> bool {
+        fs::canonicalize(path).is_ok_and(|path| self.inputs().any(|p| p == path))
+    }
+
+    /// Returns the output or the first input whose contents changed since
+    /// they were recorded, or that can't be read anymore.
+    pub fn changed(&self) -> Option<&Path> {
+        std::iter::once(&self.output)
+            .chain(&self.inputs)
+            .find(|(path, hash)| file_hash(path).ok().as_ref() != Some(hash))
+            .map(|(path, _)| path.as_path())
+    }
+}
+
+/// The inputs of an interrupted merge merged so far, and the cache holding
+/// their merged entries, the partial output, appended to at checkpoints
+/// along with the journal. Inputs are identified by the SHA1 of their
+/// contents and the bytes of them merged, their size, and the partial
+/// output by the SHA1 and size of the part written at the last checkpoint.
+/// A journal lists the inputs a line each, after the partial output:
+///
+/// ```text
+/// dxvk-cache-tool merge journal 2
+/// path_style <posix|windows>
+/// partial <sha1> <bytes> <path>
+/// input <sha1> <bytes> <path>
+/// ```
+///
+/// Paths are absolute and written as in a state file. Anything past the
+/// recorded bytes of the partial output is left by a checkpoint
+/// interrupted before writing the journal, and cut off on resuming.
+pub struct MergeJournal {
+    partial: (PathBuf, u64, Sha1Hash),
+    inputs:  Vec<(PathBuf, u64, Sha1Hash)>,
+    /// Hashes the partial output as it is appended to, once it was hashed
+    /// up to the recorded bytes.
+    hasher:  Option<Sha1>
+}
+
+impl MergeJournal {
+    /// An empty journal, whose partial output is `partial`.
+    pub fn new(partial: &Path) -> Self {
+        MergeJournal {
+            partial: (partial.to_path_buf(), 0, Sha1::default().digest().bytes()),
+            inputs:  Vec::new(),
+            hasher:  None
+        }
+    }
+
+    /// Reads a journal, failing with `ErrorKind::InvalidData` if it is
+    /// malformed.
+    pub fn read(path: &Path) -> Result<Self, Error> {
+        let lines = util::read_lines(path).map_err(|e| Error::from(e).with_path(path))?;
+        let invalid = |line: usize| {
+            Error::new(ErrorKind::InvalidData, format!("Malformed merge journal at line {}", line))
+                .with_path(path)
+        };
+        let mut lines = lines.iter().map(String::as_str).peekable();
+        let sized = match lines.next() {
+            Some(JOURNAL_MAGIC) => true,
+            Some(JOURNAL_MAGIC_1) => false,
+            _ => return Err(invalid(1))
+        };
+        let (style, first) = read_path_style(&mut lines, invalid)?;
+        let mut partial = None;
+        let mut inputs = Vec::new();
+        for (i, line) in lines.enumerate() {
+            let number = i + first;
+            let mut fields = line.splitn(2, ' ');
+            let (kind, rest) = match (fields.next(), fields.next()) {
+                (Some(kind), Some(rest)) => (kind, rest),
+                _ => return Err(invalid(number))
+            };
+            let fields_of = |n| -> Result<Vec<&str>, Error> {
+                let fields: Vec<&str> = rest.splitn(n, ' ').collect();
+                match fields.last() {
+                    Some(file) if fields.len() == n && !file.is_empty() => Ok(fields),
+                    _ => Err(invalid(number))
+                }
+            };
+            let hash = |s: &str| parse_hash(s).map_err(|_| invalid(number));
+            match (kind, partial.is_none()) {
+                ("partial", true) => {
+                    let fields = fields_of(if sized { 3 } else { 2 })?;
+                    let (sha1, bytes, file) = match fields.as_slice() {
+                        [sha1, bytes, file] => {
+                            (sha1, Some(bytes.parse().map_err(|_| invalid(number))?), file)
+                        },
+                        [sha1, file] => (sha1, None, file),
+                        _ => return Err(invalid(number))
+                    };
+                    let file = util::native_path(file, style);
+                    let bytes = bytes.unwrap_or_else(|| fs::metadata(&file).map_or(0, |m| m.len()));
+                    partial = Some((file, bytes, hash(sha1)?));
+                },
+                ("input", false) => match fields_of(3)?.as_slice() {
+                    [sha1, bytes, file] => {
+                        let bytes = bytes.parse().map_err(|_| invalid(number))?;
+                        inputs.push((util::native_path(file, style), bytes, hash(sha1)?));
+                    },
+                    _ => return Err(invalid(number))
+                },
+                _ => return Err(invalid(number))
+            }
+        }
+        let partial = partial.ok_or_else(|| invalid(1))?;
+        Ok(MergeJournal { partial, inputs, hasher: None })
+    }
+
+    /// Appends what `write` writes to the partial output, after cutting it
+    /// back to the recorded bytes, and flushes it to disk. With `restart`,
+    /// the partial output is written anew instead. The size and hash of
+    /// the partial output are recorded as they are then.
+    pub fn append_partial<F>(&mut self, restart: bool, write: F) -> Result<(), Error>
+    where
+        F: FnOnce(&mut dyn Write) -> Result<(), Error>
+    {
+        let path = self.partial.0.clone();
+        let with_path = |e: io::Error| Error::from(e).with_path(&path);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)
+            .map_err(with_path)?;
+        let bytes = if restart { 0 } else { self.partial.1 };
+        let mut hasher = match self.hasher.take() {
+            Some(hasher) if !restart => hasher,
+            _ => hash_reader((&file).take(bytes)).map_err(with_path)?
+        };
+        file.set_len(bytes).map_err(with_path)?;
+        file.seek(SeekFrom::Start(bytes)).map_err(with_path)?;
+        let mut writer = HashingWriter { inner: &file, hasher: &mut hasher, bytes: 0 };
+        write(&mut writer).map_err(|e| e.with_path(&path))?;
+        let appended = writer.bytes;
+        file.sync_all().map_err(with_path)?;
+        let path = fs::canonicalize(&path).map_err(with_path)?;
+        self.partial = (path, bytes + appended, hasher.digest().bytes());
+        self.hasher = Some(hasher);
+        Ok(())
+    }
+
+    /// Cuts the partial output back to the recorded bytes, dropping what a
+    /// checkpoint interrupted before writing the journal left past them.
+    pub fn cut_partial(&self) -> Result<(), Error> {
+        let (path, bytes, _) = &self.partial;
+        OpenOptions::new()
+            .write(true)
+            .open(path)
+            .and_then(|file| file.set_len(*bytes))
+            .map_err(|e| Error::from(e).with_path(path))
+    }
+
+    /// Records `inputs` as merged into the partial output as well, which
+    /// must have been appended with them, hashing them as they are now.
+    pub fn record<'a, I>(&mut self, inputs: I) -> Result<(), Error>
+    where
+        I: IntoIterator<Item = &'a Path>
+    {
+        for path in inputs {
+            let path = fs::canonicalize(path).map_err(|e| Error::from(e).with_path(path))?;
+            let bytes = fs::metadata(&path).map_err(|e| Error::from(e).with_path(&path))?.len();
+            let hash = file_hash(&path).map_err(|e| Error::from(e).with_path(&path))?;
+            self.inputs.push((path, bytes, hash));
+        }
+        Ok(())
+    }
+
+    /// Writes the journal, replacing it at once.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let mut text = format!("{}\npath_style {}\n", JOURNAL_MAGIC, PathStyle::NATIVE.as_str());
+        let (partial, bytes, hash) = &self.partial;
+        let partial = util::portable_path(partial);
+        text.push_str(&format!("partial {} {} {}\n", hash_to_hex(hash), bytes, partial));
+        for (file, bytes, hash) in &self.inputs {
+            let file = util::portable_path(file);
+            text.push_str(&format!("input {} {} {}\n", hash_to_hex(hash), bytes, file));
+        }
+        util::write_atomic(path, text.as_bytes()).map_err(|e| Error::from(e).with_path(path))
+    }
+
+    /// The partial output, which holds the entries of the inputs merged so
+    /// far.
+    pub fn partial(&self) -> &Path {
+        &self.partial.0
+    }
+
+    /// The inputs merged so far, in merge order.
+    pub fn inputs(&self) -> impl Iterator<Item = &Path> {
+        self.inputs.iter().map(|(path, ..)| path.as_path())
+    }
+
+    /// Whether `path` is one of the inputs merged so far.
+    pub fn contains(&self, path: &Path) -> bool {
+        fs::canonicalize(path).is_ok_and(|path| self.inputs().any(|p| p == path))
+    }
+
+    /// Returns the partial output or the first input whose size or
+    /// contents changed since they were recorded, or that can't be read
+    /// anymore. The partial output may have grown past the recorded bytes.
+    pub fn changed(&self) -> Option<&Path> {
+        let (partial, bytes, hash) = &self.partial;
+        let recorded = File::open(partial).and_then(|file| {
+            let long_enough = file.metadata()?.len() >= *bytes;
+            Ok(long_enough && hash_reader(file.take(*bytes))?.digest().bytes() == *hash)
+        });
+        if !recorded.unwrap_or(false) {
+            return Some(partial);
+        }
+        self.inputs
+            .iter()
+            .find(|(path, bytes, hash)| {
+                fs::metadata(path).ok().map(|m| m.len()) != Some(*bytes)
+                    || file_hash(path).ok().as_ref() != Some(hash)
+            })
+            .map(|(path, ..)| path.as_path())
+    }
+}
+
+/// Passes writes on to `inner`, hashing and counting the bytes written.
+struct HashingWriter<'h, W> {
+    inner:  W,
+    hasher: &'h mut Sha1,
+    bytes:  u64
+}
+
+impl<W: Write> Write for HashingWriter<'_, W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.hasher.update(buf.get(..n).unwrap_or_default());
+        self.bytes += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Reads the `path_style` line following the magic line of a state file
+/// or journal, returning the style and the number of the line after it.
+/// Files written before the line was added lack it; their paths are read
+/// as written.
+fn read_path_style<'a, I, F>(
+    lines: &mut Peekable<I>,
+    invalid: F
+) -> Result<(PathStyle, usize), Error>
+where
+    I: Iterator<Item = &'a str>,
+    F: Fn(usize) -> Error
+{
+    match lines.peek().and_then(|line| line.strip_prefix("path_style ")) {
+        Some(style) => {
+            let style = PathStyle::parse(style).ok_or_else(|| invalid(2))?;
+            lines.next();
+            Ok((style, 3))
+        },
+        None => Ok((PathStyle::NATIVE, 2))
+    }
+}
+
+/// Returns the SHA1 of the contents of a file.
+pub fn file_hash(path: &Path) -> io::Result<Sha1Hash> {
+    Ok(hash_reader(File::open(path)?)?.digest().bytes())
+}
+
+/// A hasher fed everything read from `reader`.
+fn hash_reader<R: Read>(mut reader: R) -> io::Result<Sha1> {
+    let mut hasher = Sha1::default();
+    let mut buffer = vec![0; 1 << 16];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => return Ok(hasher),
+            Ok(n) => hasher.update(buffer.get(..n).unwrap_or_default()),
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => (),
+            Err(e) => return Err(e)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::dxvk::HASH_SIZE;
+
+    fn temp_file(name: &str, bytes: &[u8]) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("dxvk-cache-tool-test-resume-{}", name));
+        fs::write(&path, bytes).unwrap();
+        path
+    }
+
+    /// Appends `bytes` to the partial output of `journal`, or writes it
+    /// anew with `restart`.
+    fn append(journal: &mut MergeJournal, restart: bool, bytes: &[u8]) {
+        journal.append_partial(restart, |writer| Ok(writer.write_all(bytes)?)).unwrap();
+    }
+
+    #[test]
+    fn journal_round_trips() {
+        let partial = temp_file("journal-partial", b"");
+        let first = temp_file("journal-first", b"first");
+        let second = temp_file("journal-second", b"second");
+        let mut journal = MergeJournal::new(&partial);
+        append(&mut journal, false, b"partial");
+        journal.record([first.as_path(), second.as_path()]).unwrap();
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-resume-journal");
+        journal.write(&path).unwrap();
+
+        let read = MergeJournal::read(&path).unwrap();
+        assert_eq!(read.partial(), fs::canonicalize(&partial).unwrap());
+        let inputs: Vec<&Path> = read.inputs().collect();
+        assert_eq!(inputs, [fs::canonicalize(&first).unwrap(), fs::canonicalize(&second).unwrap()]);
+        assert!(read.contains(&first) && read.contains(&second));
+        assert!(!read.contains(&partial));
+        assert_eq!(read.changed(), None);
+
+        fs::write(&second, b"changed").unwrap();
+        assert_eq!(read.changed(), Some(fs::canonicalize(&second).unwrap().as_path()));
+        fs::write(&partial, b"changed").unwrap();
+        assert_eq!(read.changed(), Some(fs::canonicalize(&partial).unwrap().as_path()));
+    }
+
+    #[test]
+    fn partial_outputs_are_appended_to_and_cut_back() {
+        let partial = temp_file("append-partial", b"stale");
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-resume-append");
+        let mut journal = MergeJournal::new(&partial);
+        append(&mut journal, false, b"first ");
+        journal.write(&path).unwrap();
+        // A checkpoint interrupted before writing the journal.
+        append(&mut journal, false, b"lost");
+        assert_eq!(fs::read(&partial).unwrap(), b"first lost");
+
+        let mut read = MergeJournal::read(&path).unwrap();
+        assert_eq!(read.changed(), None);
+        read.cut_partial().unwrap();
+        assert_eq!(fs::read(&partial).unwrap(), b"first ");
+        append(&mut read, false, b"second");
+        assert_eq!(fs::read(&partial).unwrap(), b"first second");
+        read.write(&path).unwrap();
+        assert_eq!(MergeJournal::read(&path).unwrap().changed(), None);
+
+        append(&mut read, true, b"anew");
+        assert_eq!(fs::read(&partial).unwrap(), b"anew");
+        read.write(&path).unwrap();
+        assert_eq!(MergeJournal::read(&path).unwrap().changed(), None);
+        fs::write(&partial, b"an").unwrap();
+        assert_eq!(read.changed(), Some(fs::canonicalize(&partial).unwrap().as_path()));
+    }
+
+    #[test]
+    fn journals_of_version_1_record_the_whole_partial_output() {
+        let partial = temp_file("version-1-partial", b"partial");
+        let text = format!(
+            "{}\npartial {} {}\n",
+            JOURNAL_MAGIC_1,
+            hash_to_hex(&file_hash(&partial).unwrap()),
+            util::portable_path(&partial)
+        );
+        let path = temp_file("version-1-journal", text.as_bytes());
+
+        let mut read = MergeJournal::read(&path).unwrap();
+        assert_eq!(read.changed(), None);
+        append(&mut read, false, b" and more");
+        assert_eq!(fs::read(&partial).unwrap(), b"partial and more");
+    }
+
+    #[test]
+    fn state_round_trips() {
+        let output = temp_file("state-output", b"output");
+        let input = temp_file("state-input", b"input");
+        let state = MergeState::record(&output, [input.as_path()]).unwrap();
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-resume-state");
+        state.write(&path).unwrap();
+
+        let read = MergeState::read(&path).unwrap();
+        assert_eq!(read.output(), fs::canonicalize(&output).unwrap());
+        assert_eq!(read.inputs().collect::<Vec<_>>(), [fs::canonicalize(&input).unwrap()]);
+        assert!(read.contains(&input));
+        assert_eq!(read.changed(), None);
+
+        fs::write(&output, b"changed").unwrap();
+        assert_eq!(read.changed(), Some(fs::canonicalize(&output).unwrap().as_path()));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn journal_keeps_backslashes_in_file_names() {
+        let partial = temp_file("backslash-partial", b"");
+        let input = temp_file("back\\slash", b"input");
+        let mut journal = MergeJournal::new(&partial);
+        append(&mut journal, false, b"partial");
+        journal.record([input.as_path()]).unwrap();
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-resume-backslash");
+        journal.write(&path).unwrap();
+
+        let read = MergeJournal::read(&path).unwrap();
+        assert!(read.contains(&input));
+        assert_eq!(read.changed(), None);
+    }
+
+    #[test]
+    fn malformed_journals_are_invalid_data() {
+        let hash = "0".repeat(2 * HASH_SIZE);
+        let journals = [
+            String::from("not a journal\n"),
+            format!("{}\n", JOURNAL_MAGIC),
+            format!("{}\ninput {} 5 /first\n", JOURNAL_MAGIC, hash),
+            format!("{}\npartial {} 7 /partial\ninput {} five /first\n", JOURNAL_MAGIC, hash, hash),
+            format!("{}\npartial {} 7 /partial\ninput {} 5\n", JOURNAL_MAGIC, hash, hash),
+            format!("{}\npartial nothex 7 /partial\n", JOURNAL_MAGIC),
+            format!("{}\npartial {} seven /partial\n", JOURNAL_MAGIC, hash),
+            format!("{}\npartial {} /partial\n", JOURNAL_MAGIC, hash),
+            format!("{}\npath_style dos\npartial {} 7 /partial\n", JOURNAL_MAGIC, hash)
+        ];
+        for (i, text) in journals.iter().enumerate() {
+            let path = temp_file(&format!("malformed-{}", i), text.as_bytes());
+            let Err(error) = MergeJournal::read(&path) else {
+                panic!("{:?} was read", text)
+            };
+            assert_eq!(error.kind(), ErrorKind::InvalidData, "{:?}", text);
+        }
+    }
+
+    /// `path` as a file written on Windows would have it, with backslashes.
+    fn windows_path(path: &Path) -> String {
+        util::portable_path(path).replace('/', "\\")
+    }
+
+    #[test]
+    fn journals_written_on_windows_read_on_posix() {
+        let partial = temp_file("windows-partial", b"partial");
+        let input = temp_file("windows-input", b"input");
+        let hash = |path: &Path| hash_to_hex(&file_hash(path).unwrap());
+        let text = format!(
+            "{}\r\npath_style windows\r\npartial {} 7 {}\r\ninput {} 5 {}\r\n",
+            JOURNAL_MAGIC,
+            hash(&partial),
+            windows_path(&partial),
+            hash(&input),
+            windows_path(&input)
+        );
+        let path = temp_file("windows-journal", text.as_bytes());
+
+        let read = MergeJournal::read(&path).unwrap();
+        assert_eq!(read.partial(), partial);
+        assert!(read.contains(&input));
+        assert_eq!(read.changed(), None);
+    }
+
+    #[test]
+    fn state_files_record_the_path_style_of_the_writer() {
+        let output = temp_file("style-output", b"output");
+        let input = temp_file("style-input", b"input");
+        let state = MergeState::record(&output, [input.as_path()]).unwrap();
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-resume-style");
+        state.write(&path).unwrap();
+        let text = fs::read_to_string(&path).unwrap();
+        let second = text.lines().nth(1).unwrap();
+        assert_eq!(second, format!("path_style {}", PathStyle::NATIVE.as_str()));
+
+        // Read as a file of the other platform, POSIX paths keep their
+        // slashes, and Windows paths get them.
+        let other = match PathStyle::NATIVE {
+            PathStyle::Posix => "windows",
+            PathStyle::Windows => "posix"
+        };
+        let converted = text.replace(second, &format!("path_style {}", other));
+        let read = MergeState::read(&temp_file("style-other", converted.as_bytes())).unwrap();
+        assert_eq!(read.output(), fs::canonicalize(&output).unwrap());
+        assert!(read.contains(&input));
+    }
+
+    #[test]
+    fn state_files_without_a_path_style_read_as_written() {
+        let output = temp_file("unstyled-output", b"output");
+        let hash = hash_to_hex(&file_hash(&output).unwrap());
+        let text = format!("{}\noutput {} {}\n", STATE_MAGIC, hash, util::portable_path(&output));
+        let read = MergeState::read(&temp_file("unstyled", text.as_bytes())).unwrap();
+        assert_eq!(read.output(), output);
+        assert_eq!(read.changed(), None);
+    }
+}
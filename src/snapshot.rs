@@ -0,0 +1,88 @@
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
+use std::time::{Duration, SystemTime};
+
+use crate::error::{Error, ErrorKind};
+
+const RETRIES: u32 = 10;
+const RETRY_DELAY: Duration = Duration::from_millis(100);
+#[cfg(windows)]
+const ERROR_SHARING_VIOLATION: i32 = 32;
+
+static COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+/// A private copy of a cache that may be in use by a running game. The copy
+/// is removed when the snapshot is dropped.
+pub struct Snapshot {
+    path:         PathBuf,
+    pub taken_at: SystemTime
+}
+
+impl Snapshot {
+    /// Copies `source` to the temporary directory. Copies that raced with a
+    /// writer, detected by the file size or modification time changing while
+    /// copying, and sharing violations on Windows are retried a few times.
+    ///
+    /// std opens files with FILE_SHARE_READ | FILE_SHARE_WRITE on Windows,
+    /// so the copy succeeds unless DXVK holds the file exclusively.
+    pub fn take(source: &Path) -> Result<Self, Error> {
+        let path = std::env::temp_dir().join(format!(
+            "dxvk-cache-tool-{}-{}.dxvk-cache",
+            process::id(),
+            COUNTER.fetch_add(1, Ordering::Relaxed)
+        ));
+        for _ in 0..RETRIES {
+            let before = stamp(source)?;
+            let taken_at = SystemTime::now();
+            match fs::copy(source, &path) {
+                Ok(_) => {},
+                Err(ref e) if is_sharing_violation(e) => {
+                    thread::sleep(RETRY_DELAY);
+                    continue;
+                },
+                Err(e) => {
+                    let _ = fs::remove_file(&path);
+                    return Err(e.into());
+                }
+            }
+            if stamp(source)? == before {
+                return Ok(Snapshot { path, taken_at });
+            }
+            thread::sleep(RETRY_DELAY);
+        }
+        let _ = fs::remove_file(&path);
+        Err(Error::new(
+            ErrorKind::IoError(io::ErrorKind::Other),
+            "File kept changing while taking a snapshot"
+        ))
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for Snapshot {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
+
+fn stamp(path: &Path) -> io::Result<(u64, Option<SystemTime>)> {
+    let metadata = fs::metadata(path)?;
+    Ok((metadata.len(), metadata.modified().ok()))
+}
+
+#[cfg(windows)]
+fn is_sharing_violation(e: &io::Error) -> bool {
+    e.raw_os_error() == Some(ERROR_SHARING_VIOLATION)
+}
+
+#[cfg(not(windows))]
+fn is_sharing_violation(_: &io::Error) -> bool {
+    false
+}
@@ -0,0 +1,269 @@
+//! Tables of the human readable output, aligned on a terminal and tab
+//! separated otherwise, so that piping them into `cut` or a spreadsheet
+//! works, or as CSV where asked for.
+
+use std::io::{self, IsTerminal};
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Align {
+    Left,
+    /// For numbers, so that their digits line up.
+    Right
+}
+
+/// How tables are printed.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    /// Indented, with padded columns and long cells cut short.
+    Aligned,
+    /// A line per row with the cells separated by tabs, nothing cut.
+    Tabs,
+    /// Comma separated values as of RFC 4180, with the header, nothing cut.
+    Csv
+}
+
+impl Style {
+    /// Aligned tables if stdout is a terminal, unless `--no-table` asks for
+    /// tabs anyway.
+    pub fn detect(no_table: bool) -> Self {
+        if no_table || !io::stdout().is_terminal() {
+            Style::Tabs
+        } else {
+            Style::Aligned
+        }
+    }
+}
+
+struct Column {
+    title:     &'static str,
+    align:     Align,
+    max_width: Option<usize>
+}
+
+/// A table built column by column, then row by row. The header is left out
+/// if every title is empty.
+pub struct Table {
+    columns: Vec<Column>,
+    rows:    Vec<Vec<String>>
+}
+
+impl Table {
+    pub fn new() -> Self {
+        Table { columns: Vec::new(), rows: Vec::new() }
+    }
+
+    pub fn column(mut self, title: &'static str, align: Align) -> Self {
+        self.columns.push(Column { title, align, max_width: None });
+        self
+    }
+
+    /// Cuts the cells of the last column added to `width` columns of the
+    /// terminal, ending them with an ellipsis.
+    pub fn max_width(mut self, width: usize) -> Self {
+        if let Some(column) = self.columns.last_mut() {
+            column.max_width = Some(width.max(1));
+        }
+        self
+    }
+
+    /// Adds a row, with missing cells left empty and extra cells dropped.
+    pub fn row(&mut self, cells: Vec<String>) {
+        self.rows.push(cells);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rows.is_empty()
+    }
+
+    /// Renders the table, without a newline after the last row.
+    pub fn render(&self, style: Style) -> String {
+        let header = self.columns.iter().any(|c| !c.title.is_empty());
+        let titles: Vec<String> = self.columns.iter().map(|c| c.title.to_string()).collect();
+        let rows = header.then_some(&titles).into_iter().chain(&self.rows).map(|row| {
+            self.columns
+                .iter()
+                .enumerate()
+                .map(|(i, column)| {
+                    let cell = row.get(i).map_or("", String::as_str);
+                    match (style, column.max_width) {
+                        (Style::Tabs, _) => cell.replace(['\t', '\n'], " "),
+                        (Style::Csv, _) => csv_field(cell),
+                        (Style::Aligned, Some(max)) => truncate(cell, max),
+                        (Style::Aligned, None) => cell.replace(char::is_control, " ")
+                    }
+                })
+                .collect::<Vec<_>>()
+        });
+        match style {
+            Style::Tabs => return rows.map(|row| row.join("\t")).collect::<Vec<_>>().join("\n"),
+            Style::Csv => return rows.map(|row| row.join(",")).collect::<Vec<_>>().join("\n"),
+            Style::Aligned => ()
+        }
+
+        let rows: Vec<Vec<String>> = rows.collect();
+        let mut widths = vec![0; self.columns.len()];
+        for row in &rows {
+            for (width, cell) in widths.iter_mut().zip(row) {
+                *width = (*width).max(display_width(cell));
+            }
+        }
+        let lines: Vec<String> = rows
+            .iter()
+            .map(|row| {
+                let mut line = String::from("\t");
+                let cells = self.columns.iter().zip(row).zip(&widths);
+                for (i, ((column, cell), width)) in cells.enumerate() {
+                    let padding = " ".repeat(width - display_width(cell));
+                    if i > 0 {
+                        line.push_str("  ");
+                    }
+                    match column.align {
+                        Align::Left => {
+                            line.push_str(cell);
+                            line.push_str(&padding);
+                        },
+                        Align::Right => {
+                            line.push_str(&padding);
+                            line.push_str(cell);
+                        }
+                    }
+                }
+                line.trim_end().to_string()
+            })
+            .collect();
+        lines.join("\n")
+    }
+}
+
+/// Quotes `cell` for CSV if it holds a separator, a quote or a line break.
+fn csv_field(cell: &str) -> String {
+    if cell.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", cell.replace('"', "\"\""))
+    } else {
+        cell.to_string()
+    }
+}
+
+/// Cuts `cell` to `max` columns of the terminal, ending it with an
+/// ellipsis if anything is cut.
+fn truncate(cell: &str, max: usize) -> String {
+    let cell = cell.replace(char::is_control, " ");
+    if display_width(&cell) <= max {
+        return cell;
+    }
+    let mut width = 0;
+    let mut cut = String::new();
+    for c in cell.chars() {
+        width += char_width(c);
+        if width > max - 1 {
+            break;
+        }
+        cut.push(c);
+    }
+    cut.push('\u{2026}');
+    cut
+}
+
+/// Columns `s` takes on a terminal. Close enough for names in common
+/// scripts, without the full Unicode tables: combining marks take none, and
+/// East Asian wide characters and emoji take two.
+pub fn display_width(s: &str) -> usize {
+    s.chars().map(char_width).sum()
+}
+
+fn char_width(c: char) -> usize {
+    match u32::from(c) {
+        0x0300..=0x036f
+        | 0x0483..=0x0489
+        | 0x0591..=0x05bd
+        | 0x0610..=0x061a
+        | 0x064b..=0x065f
+        | 0x0e31
+        | 0x0e34..=0x0e3a
+        | 0x0e47..=0x0e4e
+        | 0x1ab0..=0x1aff
+        | 0x1dc0..=0x1dff
+        | 0x200b..=0x200f
+        | 0x20d0..=0x20ff
+        | 0xfe00..=0xfe0f
+        | 0xfe20..=0xfe2f => 0,
+        0x1100..=0x115f
+        | 0x2e80..=0x303e
+        | 0x3041..=0x33ff
+        | 0x3400..=0x4dbf
+        | 0x4e00..=0x9fff
+        | 0xa000..=0xa4cf
+        | 0xac00..=0xd7a3
+        | 0xf900..=0xfaff
+        | 0xfe30..=0xfe4f
+        | 0xff00..=0xff60
+        | 0xffe0..=0xffe6
+        | 0x1f300..=0x1f64f
+        | 0x1f900..=0x1f9ff
+        | 0x20000..=0x3fffd => 2,
+        _ => 1
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn table() -> Table {
+        let mut table = Table::new()
+            .column("File", Align::Left)
+            .max_width(12)
+            .column("Entries", Align::Right);
+        table.row(vec!["game.dxvk-cache".to_string(), "1234".to_string()]);
+        table.row(vec!["ゲーム.dxvk-cache".to_string(), "5".to_string()]);
+        table.row(vec!["Café, \"beta\"".to_string(), "67".to_string()]);
+        table
+    }
+
+    #[test]
+    fn aligned_tables_pad_by_display_width() {
+        let expected = "\
+\tFile          Entries
+\tgame.dxvk-c\u{2026}     1234
+\tゲーム.dxvk\u{2026}        5
+\tCafé, \"beta\"       67";
+        assert_eq!(table().render(Style::Aligned), expected);
+    }
+
+    #[test]
+    fn tab_separated_tables_cut_nothing() {
+        let expected = "\
+File\tEntries
+game.dxvk-cache\t1234
+ゲーム.dxvk-cache\t5
+Café, \"beta\"\t67";
+        assert_eq!(table().render(Style::Tabs), expected);
+    }
+
+    #[test]
+    fn csv_tables_quote_separators_and_quotes() {
+        let expected = "\
+File,Entries
+game.dxvk-cache,1234
+ゲーム.dxvk-cache,5
+\"Café, \"\"beta\"\"\",67";
+        assert_eq!(table().render(Style::Csv), expected);
+    }
+
+    #[test]
+    fn tables_without_titles_have_no_header() {
+        let mut table = Table::new().column("", Align::Left).column("", Align::Right);
+        table.row(vec!["a\tb".to_string()]);
+        assert_eq!(table.render(Style::Tabs), "a b\t");
+        assert_eq!(table.render(Style::Aligned), "\ta b");
+    }
+
+    #[test]
+    fn display_width_counts_wide_and_combining_characters() {
+        assert_eq!(display_width("abc"), 3);
+        assert_eq!(display_width("ゲーム"), 6);
+        assert_eq!(display_width("e\u{301}"), 1);
+        assert_eq!(truncate("ゲーム", 5), "ゲー\u{2026}");
+        assert_eq!(truncate("ゲーム", 6), "ゲーム");
+    }
+}
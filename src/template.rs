@@ -0,0 +1,166 @@
+//! Output file names expanded from a template once a merge is done.
+
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+use std::time::SystemTime;
+
+use crate::error::{Error, ErrorKind};
+use crate::util;
+
+/// Placeholders a template may contain, for the help and error messages.
+pub const PLACEHOLDERS: &str = "{stem}, {appid}, {game}, {version}, {date}, {entries}";
+
+#[derive(Clone)]
+enum Segment {
+    Literal(String),
+    Stem,
+    AppId,
+    Game,
+    Version,
+    Date,
+    Entries
+}
+
+/// A template like `{game}-v{version}-{date}.dxvk-cache` for the output
+/// path. Input placeholders are taken from the first input:
+///
+/// - `{stem}`: its file name without the extension, which DXVK takes from
+///   the executable.
+/// - `{appid}`: the Steam app ID from its `shadercache/<appid>` or
+///   `compatdata/<appid>` directory.
+/// - `{game}`: the name from the Steam app manifest, or `{stem}` without
+///   one.
+///
+/// `{version}` and `{entries}` describe the output, and `{date}` is the
+/// UTC date of the merge as `YYYYMMDD`.
+#[derive(Clone)]
+pub struct OutputTemplate {
+    segments: Vec<Segment>
+}
+
+impl OutputTemplate {
+    /// Parses `template`, failing with `ErrorKind::InvalidInput` on unknown
+    /// or unclosed placeholders.
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let invalid = |message: String| Error::new(ErrorKind::InvalidInput, message);
+        let mut segments = Vec::new();
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let (literal, tail) = rest.split_at(start);
+            if !literal.is_empty() {
+                segments.push(Segment::Literal(literal.into()));
+            }
+            let end = tail.find('}').ok_or_else(|| {
+                invalid(format!("Unclosed placeholder in output template: {}", template))
+            })?;
+            let name = tail.get(1..end).unwrap_or_default();
+            segments.push(match name {
+                "stem" => Segment::Stem,
+                "appid" => Segment::AppId,
+                "game" => Segment::Game,
+                "version" => Segment::Version,
+                "date" => Segment::Date,
+                "entries" => Segment::Entries,
+                _ => {
+                    return Err(invalid(format!(
+                        "Unknown placeholder {{{}}} in output template, expected one of {}",
+                        name, PLACEHOLDERS
+                    )))
+                },
+            });
+            rest = tail.get(end + 1..).unwrap_or_default();
+        }
+        if !rest.is_empty() {
+            segments.push(Segment::Literal(rest.into()));
+        }
+        if segments.is_empty() {
+            return Err(invalid("Empty output template".into()));
+        }
+        Ok(OutputTemplate { segments })
+    }
+
+    /// Whether the template contains `{version}`, so that outputs of
+    /// different versions get different names.
+    pub fn has_version(&self) -> bool {
+        self.segments.iter().any(|s| matches!(s, Segment::Version))
+    }
+
+    /// Checks that the placeholders taken from `first_input` can be
+    /// expanded, so that a merge doesn't fail only when writing.
+    pub fn check(&self, first_input: &Path) -> Result<(), Error> {
+        self.expand(first_input, 0, 0, SystemTime::now()).map(|_| ())
+    }
+
+    /// Returns the output path for a merge of `entries` entries of
+    /// `version`, made at `time`.
+    pub fn expand(
+        &self,
+        first_input: &Path,
+        version: u32,
+        entries: usize,
+        time: SystemTime
+    ) -> Result<PathBuf, Error> {
+        let stem = first_input.file_stem().unwrap_or_default().to_string_lossy();
+        let mut path = String::new();
+        for segment in &self.segments {
+            match segment {
+                Segment::Literal(s) => path.push_str(s),
+                Segment::Stem => path.push_str(&stem),
+                Segment::AppId => path.push_str(&steam_app_id(first_input)?.1),
+                Segment::Game => match game_name(first_input) {
+                    Some(name) => path.push_str(&name),
+                    None => path.push_str(&stem)
+                },
+                Segment::Version => path.push_str(&version.to_string()),
+                Segment::Date => {
+                    let timestamp = util::format_timestamp(time);
+                    path.push_str(&timestamp.get(..10).unwrap_or_default().replace('-', ""))
+                },
+                Segment::Entries => path.push_str(&entries.to_string())
+            }
+        }
+        Ok(util::expand_path(&path))
+    }
+}
+
+/// Finds the Steam app ID in the path of a cache, along with the
+/// `steamapps` directory containing it.
+fn steam_app_id(path: &Path) -> Result<(PathBuf, String), Error> {
+    let absolute = fs::canonicalize(path).unwrap_or_else(|_| path.to_path_buf());
+    let mut steamapps = PathBuf::new();
+    let mut components = absolute.components();
+    while let Some(component) = components.next() {
+        if let Component::Normal(name) = component {
+            if name == "shadercache" || name == "compatdata" {
+                let id = components.next().map(|c| c.as_os_str().to_string_lossy());
+                if let Some(id) = id.filter(|id| id.bytes().all(|b| b.is_ascii_digit())) {
+                    return Ok((steamapps, id.into_owned()));
+                }
+            }
+        }
+        steamapps.push(component);
+    }
+    Err(Error::new(
+        ErrorKind::InvalidInput,
+        "No Steam app ID for {appid} in the path of the first input"
+    )
+    .with_path(path))
+}
+
+/// Reads the game name from the Steam app manifest of a cache, replacing
+/// characters that aren't allowed in file names.
+fn game_name(path: &Path) -> Option<String> {
+    let (steamapps, id) = steam_app_id(path).ok()?;
+    let manifest = util::read_lines(&steamapps.join(format!("appmanifest_{}.acf", id))).ok()?;
+    manifest.iter().find_map(|line| {
+        let mut fields = line.split('"').skip(1).step_by(2);
+        match (fields.next(), fields.next()) {
+            (Some("name"), Some(name)) if !name.is_empty() => Some(
+                name.chars()
+                    .map(|c| if "/\\:*?\"<>|".contains(c) { '_' } else { c })
+                    .collect()
+            ),
+            _ => None
+        }
+    })
+}
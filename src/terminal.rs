@@ -0,0 +1,85 @@
+use std::collections::VecDeque;
+use std::io::{self, IsTerminal, Write};
+use std::time::{Duration, Instant};
+
+use dxvk_cache_tool::progress::{Phase, Progress};
+
+/// Time the entries per second are measured over, so that the rate shows
+/// a slowdown within seconds rather than averaging it into the phase.
+const RATE_WINDOW: Duration = Duration::from_secs(5);
+
+/// Renders progress on a single terminal line, along with the entries per
+/// second processed in the current phase over the last `RATE_WINDOW`. Does
+/// nothing unless enabled and stderr is a terminal, so redirected output
+/// stays clean.
+pub struct ProgressLine {
+    enabled: bool,
+    visible: bool,
+    current: Option<(Phase, usize)>,
+    /// Entries done in the current phase by the time of each update within
+    /// the window, and of the last one before it to measure from.
+    samples: VecDeque<(Instant, usize)>
+}
+
+impl ProgressLine {
+    pub fn new(enabled: bool) -> Self {
+        ProgressLine {
+            enabled: enabled && io::stderr().is_terminal(),
+            visible: false,
+            current: None,
+            samples: VecDeque::new()
+        }
+    }
+
+    pub fn update(&mut self, progress: &Progress) {
+        if self.enabled {
+            let now = Instant::now();
+            let current = Some((progress.phase, progress.file_index));
+            if self.current != current {
+                self.current = current;
+                self.samples.clear();
+                self.samples.push_back((now, 0));
+            }
+            self.samples.push_back((now, progress.entries_done));
+            while self.samples.get(1).is_some_and(|&(time, _)| now - time >= RATE_WINDOW) {
+                self.samples.pop_front();
+            }
+            eprint!("\r\x1b[K{}", progress);
+            if let Some(&(since, entries)) = self.samples.front() {
+                let elapsed = (now - since).as_secs_f64();
+                if progress.entries_done > 0 && elapsed > 0.0 {
+                    let entries = progress.entries_done.saturating_sub(entries);
+                    eprint!(" ({:.0} entries/s)", entries as f64 / elapsed);
+                }
+            }
+            let _ = io::stderr().flush();
+            self.visible = true;
+        }
+    }
+
+    pub fn clear(&mut self) {
+        if self.visible {
+            eprint!("\r\x1b[K");
+            self.visible = false;
+        }
+    }
+}
+
+/// Restores the default action for SIGPIPE, so that output piped into a
+/// command like `head` ends the process quietly instead of panicking.
+#[cfg(unix)]
+pub fn reset_sigpipe() {
+    use std::os::raw::c_int;
+
+    const SIGPIPE: c_int = 13;
+    const SIG_DFL: usize = 0;
+    extern "C" {
+        fn signal(signum: c_int, handler: usize) -> usize;
+    }
+    unsafe {
+        signal(SIGPIPE, SIG_DFL);
+    }
+}
+
+#[cfg(not(unix))]
+pub fn reset_sigpipe() {}
@@ -0,0 +1,630 @@
+use std::borrow::Cow;
+use std::env;
+use std::ffi::OsStr;
+use std::fs;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Formats `time` as an ISO 8601 UTC timestamp, e.g. `2020-01-31T12:00:00Z`.
+pub fn format_timestamp(time: SystemTime) -> String {
+    let secs = time.duration_since(UNIX_EPOCH).map_or(0, |d| d.as_secs());
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let rem = secs % 86400;
+    format!(
+        "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        rem / 3600,
+        rem / 60 % 60,
+        rem % 60
+    )
+}
+
+/// Days from the Unix epoch to `time` in UTC, 0 for earlier times.
+pub fn days_since_epoch(time: SystemTime) -> i64 {
+    time.duration_since(UNIX_EPOCH).map_or(0, |d| (d.as_secs() / 86400) as i64)
+}
+
+/// Converts days since the Unix epoch to a proleptic Gregorian date.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+/// Converts a proleptic Gregorian date to days since the Unix epoch, the
+/// inverse of `civil_from_days`.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from((month + 9) % 12);
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146_097 + doe - 719_468
+}
+
+/// Returns the space available to unprivileged users on the file system
+/// containing `path`, or `None` where it can't be determined.
+#[cfg(all(target_os = "linux", target_pointer_width = "64"))]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use std::ffi::CString;
+    use std::os::raw::{c_char, c_int, c_ulong};
+    use std::os::unix::ffi::OsStrExt;
+
+    #[repr(C)]
+    struct StatVfs {
+        f_bsize:   c_ulong,
+        f_frsize:  c_ulong,
+        f_blocks:  c_ulong,
+        f_bfree:   c_ulong,
+        f_bavail:  c_ulong,
+        f_files:   c_ulong,
+        f_ffree:   c_ulong,
+        f_favail:  c_ulong,
+        f_fsid:    c_ulong,
+        f_flag:    c_ulong,
+        f_namemax: c_ulong,
+        f_spare:   [c_int; 6]
+    }
+
+    extern "C" {
+        fn statvfs(path: *const c_char, buf: *mut StatVfs) -> c_int;
+    }
+
+    let path = CString::new(path.as_os_str().as_bytes()).ok()?;
+    let mut stat = std::mem::MaybeUninit::<StatVfs>::uninit();
+    if unsafe { statvfs(path.as_ptr(), stat.as_mut_ptr()) } != 0 {
+        return None;
+    }
+    let stat = unsafe { stat.assume_init() };
+    Some(stat.f_bavail * stat.f_frsize)
+}
+
+#[cfg(windows)]
+pub fn available_space(path: &Path) -> Option<u64> {
+    use std::os::windows::ffi::OsStrExt;
+
+    extern "system" {
+        fn GetDiskFreeSpaceExW(
+            directory: *const u16,
+            free_bytes_available: *mut u64,
+            total_bytes: *mut u64,
+            total_free_bytes: *mut u64
+        ) -> i32;
+    }
+
+    let path: Vec<u16> = path.as_os_str().encode_wide().chain(Some(0)).collect();
+    let mut available = 0;
+    let ok = unsafe {
+        GetDiskFreeSpaceExW(
+            path.as_ptr(),
+            &mut available,
+            std::ptr::null_mut(),
+            std::ptr::null_mut()
+        )
+    };
+    if ok != 0 {
+        Some(available)
+    } else {
+        None
+    }
+}
+
+#[cfg(not(any(all(target_os = "linux", target_pointer_width = "64"), windows)))]
+pub fn available_space(_: &Path) -> Option<u64> {
+    None
+}
+
+/// Identity of a file, the same for every path leading to it.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct FileId(u64, u64);
+
+/// Returns the device and inode of the file at `path`.
+#[cfg(unix)]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::unix::fs::MetadataExt;
+
+    let metadata = std::fs::metadata(path).ok()?;
+    Some(FileId(metadata.dev(), metadata.ino()))
+}
+
+/// Returns the volume serial number and file index of the file at `path`.
+#[cfg(windows)]
+pub fn file_id(path: &Path) -> Option<FileId> {
+    use std::os::windows::io::AsRawHandle;
+
+    #[repr(C)]
+    struct ByHandleFileInformation {
+        file_attributes:      u32,
+        creation_time:        [u32; 2],
+        last_access_time:     [u32; 2],
+        last_write_time:      [u32; 2],
+        volume_serial_number: u32,
+        file_size_high:       u32,
+        file_size_low:        u32,
+        number_of_links:      u32,
+        file_index_high:      u32,
+        file_index_low:       u32
+    }
+
+    extern "system" {
+        fn GetFileInformationByHandle(
+            file: std::os::windows::io::RawHandle,
+            info: *mut ByHandleFileInformation
+        ) -> i32;
+    }
+
+    let file = std::fs::File::open(path).ok()?;
+    let mut info = std::mem::MaybeUninit::<ByHandleFileInformation>::uninit();
+    if unsafe { GetFileInformationByHandle(file.as_raw_handle(), info.as_mut_ptr()) } == 0 {
+        return None;
+    }
+    let info = unsafe { info.assume_init() };
+    Some(FileId(
+        u64::from(info.volume_serial_number),
+        u64::from(info.file_index_high) << 32 | u64::from(info.file_index_low)
+    ))
+}
+
+#[cfg(not(any(unix, windows)))]
+pub fn file_id(_: &Path) -> Option<FileId> {
+    None
+}
+
+/// Whether processes have a file open, as far as can be told.
+pub enum FileUse {
+    Unused,
+    /// Open by the processes listed, by name and PID where known.
+    InUse(Vec<String>),
+    /// Can't be told, for the reason given.
+    Unknown(String)
+}
+
+/// Tells whether processes other than this one have the file at `path` open,
+/// by looking for it among the open files in `/proc`. Processes of other
+/// users are skipped where they can't be inspected; those of the same user
+/// that can't be make the outcome unknown unless others have it open.
+#[cfg(target_os = "linux")]
+pub fn file_use(path: &Path) -> FileUse {
+    use std::os::unix::fs::MetadataExt;
+
+    let target = match file_id(path) {
+        Some(id) => id,
+        None => return FileUse::Unused
+    };
+    let (processes, uid) = match (fs::read_dir("/proc"), fs::metadata("/proc/self")) {
+        (Ok(processes), Ok(own)) => (processes, own.uid()),
+        _ => return FileUse::Unknown("/proc can't be read".to_string())
+    };
+    let mut users = Vec::new();
+    let mut hidden = 0;
+    for process in processes.flatten() {
+        let pid = match process.file_name().to_str().and_then(|s| s.parse::<u32>().ok()) {
+            Some(pid) if pid != std::process::id() => pid,
+            _ => continue
+        };
+        let fds = match fs::read_dir(process.path().join("fd")) {
+            Ok(fds) => fds,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => continue,
+            Err(_) => {
+                if process.metadata().is_ok_and(|m| m.uid() == uid) {
+                    hidden += 1;
+                }
+                continue;
+            }
+        };
+        if fds.flatten().any(|fd| file_id(&fd.path()) == Some(target)) {
+            let name = fs::read_to_string(process.path().join("comm")).unwrap_or_default();
+            users.push(format!("{} ({})", name.trim_end(), pid));
+        }
+    }
+    match (users.is_empty(), hidden) {
+        (false, _) => FileUse::InUse(users),
+        (true, 0) => FileUse::Unused,
+        (true, n) => FileUse::Unknown(format!("the open files of {} processes can't be read", n))
+    }
+}
+
+/// Tells whether another process has the file at `path` open, by opening it
+/// without sharing it, which fails while it is open elsewhere.
+#[cfg(windows)]
+pub fn file_use(path: &Path) -> FileUse {
+    use std::os::windows::fs::OpenOptionsExt;
+
+    const ERROR_SHARING_VIOLATION: i32 = 32;
+    match fs::OpenOptions::new().read(true).share_mode(0).open(path) {
+        Ok(_) => FileUse::Unused,
+        Err(e) if e.raw_os_error() == Some(ERROR_SHARING_VIOLATION) => FileUse::InUse(Vec::new()),
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => FileUse::Unused,
+        Err(e) => FileUse::Unknown(e.to_string())
+    }
+}
+
+#[cfg(not(any(target_os = "linux", windows)))]
+pub fn file_use(_: &Path) -> FileUse {
+    FileUse::Unknown("open files can't be listed on this platform".to_string())
+}
+
+/// Returns the permission bits of the file at `path`.
+#[cfg(unix)]
+pub fn file_mode(path: &Path) -> std::io::Result<u32> {
+    use std::os::unix::fs::PermissionsExt;
+
+    Ok(fs::metadata(path)?.permissions().mode() & 0o7777)
+}
+
+/// Sets the permission bits of the file at `path`.
+#[cfg(unix)]
+pub fn set_file_mode(path: &Path, mode: u32) -> std::io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+
+    fs::set_permissions(path, fs::Permissions::from_mode(mode))
+}
+
+/// Flushes the directory entries of the directory holding `path` to disk,
+/// so that a file renamed to `path` survives a crash.
+#[cfg(unix)]
+pub fn sync_parent_dir(path: &Path) -> std::io::Result<()> {
+    let dir = match path.parent() {
+        Some(dir) if !dir.as_os_str().is_empty() => dir,
+        _ => Path::new(".")
+    };
+    fs::File::open(dir)?.sync_all()
+}
+
+/// Directories can't be opened to flush them here; renames are written
+/// through by the file system.
+#[cfg(not(unix))]
+pub fn sync_parent_dir(_path: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+/// Returns the file name of `path` for messages, or the whole path if it
+/// has none, replacing invalid UTF-8.
+pub fn file_name(path: &Path) -> Cow<'_, str> {
+    path.file_name().unwrap_or(path.as_os_str()).to_string_lossy()
+}
+
+/// Replaces a leading `~` of `path` with the home directory, for paths no
+/// shell expanded, such as `~/game/cache.dxvk-cache` in a script. `~user`
+/// is left as it is, and so is `~` if the home directory is unknown.
+pub fn expand_path<S: AsRef<OsStr> + ?Sized>(path: &S) -> PathBuf {
+    let path = Path::new(path);
+    match (path.strip_prefix("~"), home_dir()) {
+        (Ok(rest), Some(home)) if rest.as_os_str().is_empty() => home,
+        (Ok(rest), Some(home)) => home.join(rest),
+        _ => path.to_path_buf()
+    }
+}
+
+/// The home directory from `HOME`, or `USERPROFILE` on Windows.
+fn home_dir() -> Option<PathBuf> {
+    let var = if cfg!(windows) { "USERPROFILE" } else { "HOME" };
+    env::var_os(var).filter(|home| !home.is_empty()).map(PathBuf::from)
+}
+
+/// Returns an unused path next to `path` for writing it. Inputs exist, so
+/// this never picks one of them.
+pub fn temp_path(path: &Path) -> PathBuf {
+    let name = file_name(path);
+    (0..)
+        .map(|n| path.with_file_name(format!(".{}.{}-{}.tmp", name, std::process::id(), n)))
+        .find(|p| fs::symlink_metadata(p).is_err())
+        .unwrap_or_else(|| path.with_extension("tmp"))
+}
+
+/// Writes `bytes` to a temporary file next to `path` that then replaces
+/// it, so that readers never see a partial file. The file and the rename
+/// are flushed to disk, so that after a crash `path` holds either the old
+/// or the new contents. The temporary file never outlives a failure.
+pub fn write_atomic(path: &Path, bytes: &[u8]) -> io::Result<()> {
+    let temp = temp_path(path);
+    let result = fs::File::create(&temp)
+        .and_then(|mut file| {
+            file.write_all(bytes)?;
+            file.sync_all()
+        })
+        .and_then(|()| fs::rename(&temp, path));
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result?;
+    sync_parent_dir(path)
+}
+
+/// Reads the lines of a text file as written on any platform, such as hash
+/// lists: a UTF-8 BOM is skipped, and each line is trimmed, dropping the
+/// `\r` of CRLF line endings and whitespace left by editors. Empty lines
+/// are kept, so that the position of a line is its line number. Text other
+/// than UTF-8 fails with `io::ErrorKind::InvalidData`, telling UTF-16 from
+/// Windows editors apart.
+pub fn read_lines(path: &Path) -> io::Result<Vec<String>> {
+    let bytes = fs::read(path)?;
+    if bytes.starts_with(&[0xff, 0xfe]) || bytes.starts_with(&[0xfe, 0xff]) {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Text is UTF-16, save it as UTF-8 instead"
+        ));
+    }
+    let text = String::from_utf8(bytes).map_err(|e| {
+        let offset = e.utf8_error().valid_up_to();
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Text isn't valid UTF-8 at byte {}", offset)
+        )
+    })?;
+    let text = text.strip_prefix('\u{feff}').unwrap_or(&text);
+    Ok(text.lines().map(|line| line.trim().to_string()).collect())
+}
+
+/// Platform convention of the paths of a list or document. On Windows, a
+/// backslash is a separator as well as a slash, and paths may start with a
+/// drive letter. Elsewhere, a backslash is part of a file name.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PathStyle {
+    Posix,
+    Windows
+}
+
+impl PathStyle {
+    /// The convention of the platform the tool runs on.
+    pub const NATIVE: PathStyle = if cfg!(windows) { PathStyle::Windows } else { PathStyle::Posix };
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            PathStyle::Posix => "posix",
+            PathStyle::Windows => "windows"
+        }
+    }
+
+    pub fn parse(s: &str) -> Option<Self> {
+        match s {
+            "posix" => Some(PathStyle::Posix),
+            "windows" => Some(PathStyle::Windows),
+            _ => None
+        }
+    }
+}
+
+/// Writes `path` with forward slashes, as machine-readable outputs do so
+/// that they read the same on every platform. Only Windows separators are
+/// replaced, as a backslash is part of a file name elsewhere.
+pub fn portable_path(path: &Path) -> String {
+    let path = path.to_string_lossy();
+    if cfg!(windows) {
+        path.replace('\\', "/")
+    } else {
+        path.into_owned()
+    }
+}
+
+/// Reads a path of a list written in `style`, on this platform.
+pub fn native_path(s: &str, style: PathStyle) -> PathBuf {
+    PathBuf::from(convert_path(s, style, PathStyle::NATIVE))
+}
+
+/// Converts a path from the convention `from` to `to`. Backslashes of
+/// Windows paths become slashes elsewhere. Slashes are separators on both,
+/// so POSIX paths are kept as they are, backslashes in file names included,
+/// which Windows can't represent anyway.
+fn convert_path(s: &str, from: PathStyle, to: PathStyle) -> String {
+    match (from, to) {
+        (PathStyle::Windows, PathStyle::Posix) => s.replace('\\', "/"),
+        _ => s.to_string()
+    }
+}
+
+/// Formats `n` with thousands separators, e.g. `12,340`.
+pub fn format_count(n: u64) -> String {
+    let digits = n.to_string();
+    let mut out = String::with_capacity(digits.len() + digits.len() / 3);
+    for (i, c) in digits.chars().enumerate() {
+        if i > 0 && (digits.len() - i) % 3 == 0 {
+            out.push(',');
+        }
+        out.push(c);
+    }
+    out
+}
+
+/// Formats `bytes` 16 to a line as the offset, the bytes in hex and the
+/// bytes as ASCII, with `.` for those not printable, e.g.
+/// `00000000  44 58 56 4b 11 00 00 00  |DXVK....|`.
+pub fn hex_dump(bytes: &[u8]) -> String {
+    let mut out = String::new();
+    for (i, line) in bytes.chunks(16).enumerate() {
+        out.push_str(&format!("{:08x} ", i * 16));
+        for j in 0..16 {
+            if j % 8 == 0 {
+                out.push(' ');
+            }
+            match line.get(j) {
+                Some(b) => out.push_str(&format!("{:02x} ", b)),
+                None => out.push_str("   ")
+            }
+        }
+        let ascii: String = line
+            .iter()
+            .map(|&b| if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' })
+            .collect();
+        out.push_str(&format!(" |{}|\n", ascii));
+    }
+    out.pop();
+    out
+}
+
+/// Formats a byte count with a binary unit, e.g. `45.2 MiB`.
+pub fn format_bytes(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format!("{} bytes", bytes);
+    }
+    let mut size = bytes as f64 / 1024.0;
+    let mut unit = "KiB";
+    for next in ["MiB", "GiB", "TiB"] {
+        // Compared as rounded to one decimal, so that no size shows as 1024.0.
+        if (size * 10.0).round() < 10240.0 {
+            break;
+        }
+        size /= 1024.0;
+        unit = next;
+    }
+    format!("{:.1} {}", size, unit)
+}
+
+/// Like `format_bytes`, followed by the exact count for sizes of a KiB and
+/// more, e.g. `45.2 MiB (47,395,635 bytes)`.
+pub fn format_bytes_exact(bytes: u64) -> String {
+    if bytes < 1024 {
+        return format_bytes(bytes);
+    }
+    format!("{} ({} bytes)", format_bytes(bytes), format_count(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_name_falls_back_to_the_path() {
+        assert_eq!(file_name(Path::new("dir/game.dxvk-cache")), "game.dxvk-cache");
+        assert_eq!(file_name(Path::new("..")), "..");
+        assert_eq!(file_name(Path::new("")), "");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn file_name_replaces_invalid_utf8() {
+        use std::ffi::OsStr;
+        use std::os::unix::ffi::OsStrExt;
+
+        let path = Path::new(OsStr::from_bytes(b"dir/\xff.dxvk-cache"));
+        assert_eq!(file_name(path), "\u{fffd}.dxvk-cache");
+    }
+
+    #[test]
+    fn format_count_groups_thousands() {
+        assert_eq!(format_count(0), "0");
+        assert_eq!(format_count(999), "999");
+        assert_eq!(format_count(1000), "1,000");
+        assert_eq!(format_count(123_456), "123,456");
+        assert_eq!(format_count(1_234_567), "1,234,567");
+        assert_eq!(format_count(u64::MAX), "18,446,744,073,709,551,615");
+    }
+
+    #[test]
+    fn format_bytes_switches_units_at_the_boundaries() {
+        assert_eq!(format_bytes(0), "0 bytes");
+        assert_eq!(format_bytes(1023), "1023 bytes");
+        assert_eq!(format_bytes(1024), "1.0 KiB");
+        assert_eq!(format_bytes(1024 * 1024 - 1), "1.0 MiB");
+        assert_eq!(format_bytes(1024 * 1024), "1.0 MiB");
+        assert_eq!(format_bytes(3 * 1024 * 1024), "3.0 MiB");
+        assert_eq!(format_bytes(1023 * 1024 * 1024), "1023.0 MiB");
+        assert_eq!(format_bytes(1024 * 1024 * 1024), "1.0 GiB");
+        assert_eq!(format_bytes(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn format_bytes_exact_adds_the_count_from_a_kib() {
+        assert_eq!(format_bytes_exact(1023), "1023 bytes");
+        assert_eq!(format_bytes_exact(1024), "1.0 KiB (1,024 bytes)");
+        assert_eq!(format_bytes_exact(5 * 1024 * 1024), "5.0 MiB (5,242,880 bytes)");
+    }
+
+    fn lines_of(name: &str, bytes: &[u8]) -> io::Result<Vec<String>> {
+        let path = std::env::temp_dir().join(format!("dxvk-cache-tool-test-util-{}", name));
+        fs::write(&path, bytes).unwrap();
+        read_lines(&path)
+    }
+
+    #[test]
+    fn read_lines_skips_the_bom_and_trims_lines() {
+        let lines = lines_of("bom", b"\xef\xbb\xbfone\r\n  two\t\r\n\r\nthree").unwrap();
+        assert_eq!(lines, ["one", "two", "", "three"]);
+        assert_eq!(lines_of("empty", b"").unwrap(), Vec::<String>::new());
+        assert_eq!(lines_of("bom-only", b"\xef\xbb\xbf").unwrap(), Vec::<String>::new());
+    }
+
+    #[test]
+    fn read_lines_rejects_utf16() {
+        let texts = [("utf16le", b"\xff\xfeo\0n\0e\0"), ("utf16be", b"\xfe\xff\0o\0n\0e")];
+        for (name, bytes) in texts {
+            let error = lines_of(name, bytes).unwrap_err();
+            assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+            assert!(error.to_string().contains("UTF-16"), "{}", error);
+        }
+    }
+
+    #[test]
+    fn read_lines_rejects_invalid_utf8_at_its_offset() {
+        let error = lines_of("latin1", b"one\ncaf\xe9\n").unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+        assert_eq!(error.to_string(), "Text isn't valid UTF-8 at byte 7");
+    }
+
+    #[test]
+    fn write_atomic_replaces_the_file_and_leaves_nothing_else() {
+        let dir = env::temp_dir().join("dxvk-cache-tool-test-util-atomic");
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("metrics.prom");
+        write_atomic(&path, b"old").unwrap();
+        write_atomic(&path, b"new").unwrap();
+        assert_eq!(fs::read(&path).unwrap(), b"new");
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+
+        let missing = dir.join("missing").join("metrics.prom");
+        assert!(write_atomic(&missing, b"new").is_err());
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn native_path_reads_back_portable_paths() {
+        for path in ["/games/game.dxvk-cache", "dir/game.dxvk-cache", "game name.dxvk-cache"] {
+            let read = native_path(&portable_path(Path::new(path)), PathStyle::NATIVE);
+            assert_eq!(read, Path::new(path));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn native_path_keeps_backslashes_in_posix_file_names() {
+        let path = Path::new("/games/back\\slash.dxvk-cache");
+        assert_eq!(portable_path(path), "/games/back\\slash.dxvk-cache");
+        assert_eq!(native_path(&portable_path(path), PathStyle::Posix), path);
+    }
+
+    #[test]
+    fn windows_paths_convert_to_posix() {
+        let path = r"C:\games\dir\game.dxvk-cache";
+        let converted = convert_path(path, PathStyle::Windows, PathStyle::Posix);
+        assert_eq!(converted, "C:/games/dir/game.dxvk-cache");
+        let posix = convert_path("C:/games/game.dxvk-cache", PathStyle::Windows, PathStyle::Posix);
+        assert_eq!(posix, "C:/games/game.dxvk-cache");
+    }
+
+    #[test]
+    fn posix_paths_convert_to_windows_as_written() {
+        for path in ["/games/dir/game.dxvk-cache", "dir/game.dxvk-cache", "back\\slash"] {
+            assert_eq!(convert_path(path, PathStyle::Posix, PathStyle::Windows), path);
+        }
+    }
+
+    #[test]
+    fn path_styles_round_trip() {
+        for style in [PathStyle::Posix, PathStyle::Windows] {
+            assert_eq!(PathStyle::parse(style.as_str()), Some(style));
+        }
+        assert_eq!(PathStyle::parse("dos"), None);
+    }
+}
@@ -0,0 +1,75 @@
+//! What a build of the tool is, for bug reports.
+
+use std::fmt;
+
+use crate::dxvk::{dxvk_release, CURRENT_VERSION, VERSIONS};
+use crate::json;
+
+/// Describes the build of the tool, as `--version` prints it, so that a
+/// bug report tells everything about it in one block.
+pub struct VersionInfo {
+    pub name:           &'static str,
+    pub version:        &'static str,
+    /// Git commit the tool was built from, unless built from a source
+    /// tarball.
+    pub commit:         Option<&'static str>,
+    /// Target triple, such as `x86_64-unknown-linux-gnu`.
+    pub target:         &'static str,
+    /// Cargo features enabled, such as `zstd`.
+    pub features:       &'static [&'static str],
+    /// Oldest and newest state cache versions known, those of `VERSIONS`.
+    pub cache_versions: (u32, u32)
+}
+
+impl VersionInfo {
+    /// The build running.
+    pub fn current() -> Self {
+        let first = VERSIONS.first().map_or(CURRENT_VERSION, |(v, _)| *v);
+        VersionInfo {
+            name:           env!("CARGO_PKG_NAME"),
+            version:        env!("CARGO_PKG_VERSION"),
+            commit:         option_env!("DXVK_CACHE_TOOL_COMMIT"),
+            target:         option_env!("DXVK_CACHE_TOOL_TARGET").unwrap_or("unknown"),
+            features:       if cfg!(feature = "zstd") { &["zstd"] } else { &[] },
+            cache_versions: (first, CURRENT_VERSION)
+        }
+    }
+
+    pub fn to_json(&self) -> String {
+        let features: Vec<String> = self.features.iter().map(|f| json::string(f)).collect();
+        format!(
+            "{{\"schema_version\":{},\"name\":{},\"version\":{},\"commit\":{},\"target\":{},\
+             \"features\":[{}],\"cache_versions\":{{\"first\":{},\"last\":{}}}}}",
+            json::SCHEMA_VERSION,
+            json::string(self.name),
+            json::string(self.version),
+            self.commit.map_or("null".into(), json::string),
+            json::string(self.target),
+            features.join(","),
+            self.cache_versions.0,
+            self.cache_versions.1
+        )
+    }
+}
+
+impl fmt::Display for VersionInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let version = |v: u32| match dxvk_release(v) {
+            Some(release) => format!("v{} (DXVK {})", v, release),
+            None => format!("v{}", v)
+        };
+        writeln!(f, "{} {}", self.name, self.version)?;
+        writeln!(f, "Commit: {}", self.commit.unwrap_or("unknown"))?;
+        writeln!(f, "Target: {}", self.target)?;
+        match self.features {
+            [] => writeln!(f, "Features: none")?,
+            features => writeln!(f, "Features: {}", features.join(", "))?
+        }
+        write!(
+            f,
+            "State cache versions: {} to {}",
+            version(self.cache_versions.0),
+            version(self.cache_versions.1)
+        )
+    }
+}
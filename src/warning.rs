@@ -0,0 +1,201 @@
+//! Warnings a run completes with, apart from errors and progress. Each has
+//! a stable identifier, so that scripts can tell a run that completed with
+//! warnings from a clean one, and deny some of them like compiler lints.
+
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+use crate::error::{Error, ErrorKind};
+use crate::json;
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum WarningId {
+    /// `DXVK_STATE_CACHE=0` is set, so DXVK ignores the caches written.
+    StateCacheDisabled,
+    /// Bytes after the last entry of an input don't make up an entry.
+    TrailingGarbage,
+    /// An input is older than `MIN_USEFUL_VERSION`.
+    VersionOutdated,
+    /// An input is newer than the versions the tool knows of.
+    VersionUnknown,
+    /// Entries failing validation are left out.
+    InvalidEntries,
+    /// Inputs hold different entries under the same hash, resolved by the
+    /// conflict policy.
+    ConflictsResolved,
+    /// The output replaces a cache with more entries.
+    OutputRegression,
+    /// An input of a merge to resume changed since the merge stopped.
+    InputChanged,
+    /// Entries with an ambiguous stage mask are written as graphics.
+    AmbiguousStages,
+    /// Entries without a stage mask are written to a cache of their own.
+    UnknownStages,
+    /// An imported entry replaces the entry with the same hash.
+    EntryReplaced,
+    /// Options setting the mode of the output don't apply to the platform.
+    ModeIgnored,
+    /// The provenance sidecar of a cache can't be read or doesn't match it.
+    ProvenanceMismatch,
+    /// Whether a running game has the cache an install replaces open can't
+    /// be told.
+    LiveReplaceUnchecked
+}
+
+impl WarningId {
+    pub const ALL: &'static [WarningId] = &[
+        WarningId::StateCacheDisabled,
+        WarningId::TrailingGarbage,
+        WarningId::VersionOutdated,
+        WarningId::VersionUnknown,
+        WarningId::InvalidEntries,
+        WarningId::ConflictsResolved,
+        WarningId::OutputRegression,
+        WarningId::InputChanged,
+        WarningId::AmbiguousStages,
+        WarningId::UnknownStages,
+        WarningId::EntryReplaced,
+        WarningId::ModeIgnored,
+        WarningId::ProvenanceMismatch,
+        WarningId::LiveReplaceUnchecked
+    ];
+
+    /// The identifier printed with the warning, never changed once released.
+    pub fn code(self) -> &'static str {
+        match self {
+            WarningId::StateCacheDisabled => "W_STATE_CACHE_DISABLED",
+            WarningId::TrailingGarbage => "W_TRAILING_GARBAGE",
+            WarningId::VersionOutdated => "W_VERSION_OUTDATED",
+            WarningId::VersionUnknown => "W_VERSION_UNKNOWN",
+            WarningId::InvalidEntries => "W_INVALID_ENTRIES",
+            WarningId::ConflictsResolved => "W_CONFLICTS_RESOLVED",
+            WarningId::OutputRegression => "W_OUTPUT_REGRESSION",
+            WarningId::InputChanged => "W_INPUT_CHANGED",
+            WarningId::AmbiguousStages => "W_AMBIGUOUS_STAGES",
+            WarningId::UnknownStages => "W_UNKNOWN_STAGES",
+            WarningId::EntryReplaced => "W_ENTRY_REPLACED",
+            WarningId::ModeIgnored => "W_MODE_IGNORED",
+            WarningId::ProvenanceMismatch => "W_PROVENANCE_MISMATCH",
+            WarningId::LiveReplaceUnchecked => "W_LIVE_REPLACE_UNCHECKED"
+        }
+    }
+
+    /// Looks up an identifier by its code, ignoring case.
+    pub fn from_code(code: &str) -> Option<Self> {
+        WarningId::ALL.iter().copied().find(|id| id.code().eq_ignore_ascii_case(code))
+    }
+}
+
+impl fmt::Display for WarningId {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str(self.code())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Warning {
+    pub id:      WarningId,
+    pub message: String,
+    pub path:    Option<PathBuf>,
+    pub offset:  Option<u64>
+}
+
+impl Warning {
+    pub fn new<S: Into<String>>(id: WarningId, message: S) -> Self {
+        Warning { id, message: message.into(), path: None, offset: None }
+    }
+
+    pub fn with_path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn with_offset(mut self, offset: u64) -> Self {
+        self.offset = Some(offset);
+        self
+    }
+
+    pub fn to_json(&self) -> String {
+        let mut s = format!(
+            "{{\"id\":{},\"message\":{}",
+            json::string(self.id.code()),
+            json::string(&self.message)
+        );
+        if let Some(path) = &self.path {
+            s.push_str(&format!(",\"path\":{}", json::path(path)));
+        }
+        if let Some(offset) = self.offset {
+            s.push_str(&format!(",\"offset\":{}", offset));
+        }
+        s.push('}');
+        s
+    }
+}
+
+/// Formats like an error, followed by the identifier.
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(path) = &self.path {
+            write!(f, "{}: ", path.display())?;
+        }
+        write!(f, "{}", self.message)?;
+        if let Some(offset) = self.offset {
+            write!(f, " at offset {}", offset)?;
+        }
+        write!(f, " [{}]", self.id)
+    }
+}
+
+/// A denied warning, failing with `ErrorKind::DeniedWarning`.
+impl From<Warning> for Error {
+    fn from(warning: Warning) -> Self {
+        let message = format!("{} [{} is denied]", warning.message, warning.id);
+        let mut error = Error::new(ErrorKind::DeniedWarning(warning.id), message);
+        if let Some(path) = &warning.path {
+            error = error.with_path(path);
+        }
+        if let Some(offset) = warning.offset {
+            error = error.with_offset(offset);
+        }
+        error
+    }
+}
+
+/// Formats warnings as a JSON array.
+pub fn to_json(warnings: &[Warning]) -> String {
+    let warnings: Vec<String> = warnings.iter().map(Warning::to_json).collect();
+    format!("[{}]", warnings.join(","))
+}
+
+/// The warnings turned into errors, none by default.
+#[derive(Clone, Default)]
+pub struct Deny {
+    all: bool,
+    ids: Vec<WarningId>
+}
+
+impl Deny {
+    /// Denies every warning.
+    pub fn all() -> Self {
+        Deny { all: true, ids: Vec::new() }
+    }
+
+    pub fn add(&mut self, id: WarningId) {
+        if !self.ids.contains(&id) {
+            self.ids.push(id);
+        }
+    }
+
+    pub fn denies(&self, id: WarningId) -> bool {
+        self.all || self.ids.contains(&id)
+    }
+
+    /// Passes `warning` through unless it is denied, in which case it is
+    /// returned as an error.
+    pub fn check(&self, warning: Warning) -> Result<Warning, Error> {
+        if self.denies(warning.id) {
+            return Err(warning.into());
+        }
+        Ok(warning)
+    }
+}
@@ -0,0 +1,384 @@
+//! Watch mode: merging inputs into a master cache again whenever they
+//! change, for running the tool as a service. Changes are found by polling
+//! the size and modification time of the inputs, which needs no support for
+//! file notifications from the platform.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+
+use crate::error::Error;
+use crate::json;
+use crate::util;
+
+/// How often inputs are checked for changes unless told otherwise.
+pub const DEFAULT_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Source of time of the watch loop.
+pub trait Clock {
+    /// Time passed since the watch started.
+    fn elapsed(&self) -> Duration;
+    /// The wall clock time, as recorded in the health file.
+    fn now(&self) -> SystemTime;
+    fn sleep(&mut self, duration: Duration);
+}
+
+/// The clock of the system.
+pub struct SystemClock {
+    started: Instant
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        SystemClock { started: Instant::now() }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        self.started.elapsed()
+    }
+
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+
+    fn sleep(&mut self, duration: Duration) {
+        std::thread::sleep(duration);
+    }
+}
+
+/// Source of the events the watch loop merges on.
+pub trait Events {
+    /// Whether the inputs changed since last asked, or ever on the first
+    /// call.
+    fn changed(&mut self) -> bool;
+}
+
+/// Finds changed files by their size and modification time. A file
+/// appearing or disappearing is a change too.
+pub struct PollFiles {
+    files:  Vec<PathBuf>,
+    stamps: Option<Vec<Option<(u64, SystemTime)>>>
+}
+
+impl PollFiles {
+    pub fn new(files: Vec<PathBuf>) -> Self {
+        PollFiles { files, stamps: None }
+    }
+}
+
+impl Events for PollFiles {
+    fn changed(&mut self) -> bool {
+        let stamps = self
+            .files
+            .iter()
+            .map(|path| fs::metadata(path).ok().and_then(|m| Some((m.len(), m.modified().ok()?))))
+            .collect();
+        self.stamps.replace(stamps) != self.stamps
+    }
+}
+
+/// How the watch loop runs.
+#[derive(Clone, Debug)]
+pub struct Options {
+    /// Time between checks for changes.
+    pub interval:    Duration,
+    /// Time after which the loop ends, or `None` to run until killed.
+    pub max_runtime: Option<Duration>,
+    /// File the health is written to after every check.
+    pub health_file: Option<PathBuf>
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options { interval: DEFAULT_INTERVAL, max_runtime: None, health_file: None }
+    }
+}
+
+/// State of the watch loop, written to the health file for monitoring to
+/// alert when it goes stale or merges fail.
+#[derive(Debug, Default)]
+pub struct Health {
+    /// When the inputs were last checked for changes.
+    pub checked:    Option<SystemTime>,
+    /// When the last merge ran, and whether it succeeded.
+    pub last_run:   Option<(SystemTime, bool)>,
+    /// Entries in the master after the last merge that succeeded.
+    pub entries:    Option<usize>,
+    pub merges:     usize,
+    pub failures:   usize,
+    /// The last merge that failed, kept after merges succeed again.
+    pub last_error: Option<(SystemTime, Error)>
+}
+
+impl Health {
+    /// Records a merge run at `time`, which merged `result` entries into the
+    /// master or failed.
+    pub fn record(&mut self, time: SystemTime, result: Result<usize, Error>) {
+        self.merges += 1;
+        self.last_run = Some((time, result.is_ok()));
+        match result {
+            Ok(entries) => self.entries = Some(entries),
+            Err(e) => {
+                self.failures += 1;
+                self.last_error = Some((time, e));
+            }
+        }
+    }
+
+    /// The health as a JSON document, with timestamps in ISO 8601 UTC and
+    /// `null` for what didn't happen yet.
+    pub fn to_json(&self) -> String {
+        let timestamp = |time: Option<SystemTime>| {
+            time.map_or("null".into(), |t| json::string(&util::format_timestamp(t)))
+        };
+        let result = match self.last_run {
+            Some((_, true)) => "\"merged\"",
+            Some((_, false)) => "\"failed\"",
+            None => "null"
+        };
+        format!(
+            "{{\"schema_version\":{},\"path_style\":\"{}\",\"checked\":{},\"last_run\":{},\
+             \"result\":{},\"entries\":{},\"merges\":{},\"failures\":{},\"last_error_at\":{},\
+             \"last_error\":{}}}",
+            json::SCHEMA_VERSION,
+            json::PATH_STYLE,
+            timestamp(self.checked),
+            timestamp(self.last_run.map(|(time, _)| time)),
+            result,
+            self.entries.map_or("null".into(), |n| n.to_string()),
+            self.merges,
+            self.failures,
+            timestamp(self.last_error.as_ref().map(|(time, _)| *time)),
+            self.last_error.as_ref().map_or("null".into(), |(_, e)| e.details_json())
+        )
+    }
+
+    /// Replaces the health file at `path` at once, flushed to disk, so that
+    /// monitoring never reads it half written, even after a crash.
+    pub fn write(&self, path: &Path) -> Result<(), Error> {
+        let text = self.to_json() + "\n";
+        util::write_atomic(path, text.as_bytes()).map_err(|e| Error::from(e).with_path(path))
+    }
+}
+
+/// Checks `events` for changes every interval and calls `merge`, which
+/// returns the entries in the master, whenever there are, until the
+/// maximum runtime passes. A merge failing is recorded in the health and
+/// retried on the next change, so it never ends the loop; failing to write
+/// the health file does.
+pub fn watch<C, E, M>(
+    options: &Options,
+    clock: &mut C,
+    events: &mut E,
+    mut merge: M
+) -> Result<Health, Error>
+where
+    C: Clock,
+    E: Events,
+    M: FnMut() -> Result<usize, Error>
+{
+    let mut health = Health::default();
+    loop {
+        let left = options.max_runtime.map(|max| max.saturating_sub(clock.elapsed()));
+        if left == Some(Duration::ZERO) {
+            return Ok(health);
+        }
+        if events.changed() {
+            let result = merge();
+            health.record(clock.now(), result);
+        }
+        health.checked = Some(clock.now());
+        if let Some(path) = &options.health_file {
+            health.write(path)?;
+        }
+        let left = options.max_runtime.map(|max| max.saturating_sub(clock.elapsed()));
+        clock.sleep(left.map_or(options.interval, |left| left.min(options.interval)));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::ErrorKind;
+    use crate::json::Value;
+    use std::collections::VecDeque;
+    use std::time::UNIX_EPOCH;
+
+    /// A clock that only moves when slept on, starting at `start` seconds.
+    struct FakeClock {
+        start:   u64,
+        elapsed: Duration
+    }
+
+    impl Clock for FakeClock {
+        fn elapsed(&self) -> Duration {
+            self.elapsed
+        }
+
+        fn now(&self) -> SystemTime {
+            UNIX_EPOCH + Duration::from_secs(self.start) + self.elapsed
+        }
+
+        fn sleep(&mut self, duration: Duration) {
+            self.elapsed += duration;
+        }
+    }
+
+    fn clock() -> FakeClock {
+        // 2020-01-31T12:00:00Z
+        FakeClock { start: 1_580_472_000, elapsed: Duration::ZERO }
+    }
+
+    /// Changes on the checks given, counting from 0, and on no others.
+    struct FakeEvents {
+        checks:  usize,
+        changes: Vec<usize>
+    }
+
+    impl Events for FakeEvents {
+        fn changed(&mut self) -> bool {
+            self.checks += 1;
+            self.changes.contains(&(self.checks - 1))
+        }
+    }
+
+    fn options(interval: u64, max_runtime: u64) -> Options {
+        Options {
+            interval:    Duration::from_secs(interval),
+            max_runtime: Some(Duration::from_secs(max_runtime)),
+            health_file: None
+        }
+    }
+
+    #[test]
+    fn merges_on_changes_until_the_maximum_runtime() {
+        let mut clock = clock();
+        let mut events = FakeEvents { checks: 0, changes: vec![0, 3] };
+        let mut merges = 0;
+        let health = watch(&options(10, 55), &mut clock, &mut events, || {
+            merges += 1;
+            Ok(merges * 100)
+        })
+        .unwrap();
+        // Checks at 0, 10, 20, 30, 40 and 50 seconds, and the last sleep
+        // stops at the maximum runtime.
+        assert_eq!(events.checks, 6);
+        assert_eq!(clock.elapsed, Duration::from_secs(55));
+        assert_eq!(merges, 2);
+        assert_eq!(health.merges, 2);
+        assert_eq!(health.entries, Some(200));
+        assert_eq!(health.failures, 0);
+        assert_eq!(health.checked, Some(UNIX_EPOCH + Duration::from_secs(1_580_472_050)));
+    }
+
+    #[test]
+    fn failed_merges_are_recorded_and_retried_on_the_next_change() {
+        let mut clock = clock();
+        let mut events = FakeEvents { checks: 0, changes: vec![0, 1, 4] };
+        let mut results: VecDeque<Result<usize, Error>> = VecDeque::from([
+            Ok(5),
+            Err(Error::new(ErrorKind::InvalidData, "Truncated entry").with_path("a.dxvk-cache")),
+            Ok(7)
+        ]);
+        let health = watch(&options(1, 10), &mut clock, &mut events, || {
+            results.pop_front().unwrap()
+        })
+        .unwrap();
+        assert!(results.is_empty());
+        assert_eq!(health.merges, 3);
+        assert_eq!(health.failures, 1);
+        assert_eq!(health.entries, Some(7));
+        let (_, merged) = health.last_run.unwrap();
+        assert!(merged);
+        let (at, error) = health.last_error.as_ref().unwrap();
+        assert_eq!(*at, UNIX_EPOCH + Duration::from_secs(1_580_472_001));
+        assert_eq!(error.message(), "Truncated entry");
+    }
+
+    #[test]
+    fn stops_without_merging_once_past_the_maximum_runtime() {
+        let mut clock = clock();
+        clock.elapsed = Duration::from_secs(60);
+        let mut events = FakeEvents { checks: 0, changes: vec![0] };
+        let health =
+            watch(&options(10, 60), &mut clock, &mut events, || panic!("Merged")).unwrap();
+        assert_eq!(events.checks, 0);
+        assert_eq!(health.merges, 0);
+    }
+
+    #[test]
+    fn writes_the_health_file_after_every_check() {
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-watch-health.json");
+        let _ = fs::remove_file(&path);
+        let mut options = options(10, 30);
+        options.health_file = Some(path.clone());
+        let mut clock = clock();
+        let mut events = FakeEvents { checks: 0, changes: vec![0, 2] };
+        let mut failed = false;
+        watch(&options, &mut clock, &mut events, || {
+            failed = !failed;
+            match failed {
+                true => Err(Error::new(ErrorKind::InvalidData, "Bad header").with_path("a")),
+                false => Ok(12)
+            }
+        })
+        .unwrap();
+
+        let document = json::parse(&fs::read_to_string(&path).unwrap()).unwrap();
+        let field = |name: &str| document.get(name).unwrap();
+        assert_eq!(field("schema_version").as_u64(), Some(1));
+        assert_eq!(field("checked").as_str(), Some("2020-01-31T12:00:20Z"));
+        assert_eq!(field("last_run").as_str(), Some("2020-01-31T12:00:20Z"));
+        assert_eq!(field("result").as_str(), Some("merged"));
+        assert_eq!(field("entries").as_u64(), Some(12));
+        assert_eq!(field("merges").as_u64(), Some(2));
+        assert_eq!(field("failures").as_u64(), Some(1));
+        assert_eq!(field("last_error_at").as_str(), Some("2020-01-31T12:00:00Z"));
+        let error = field("last_error");
+        assert_eq!(error.get("kind").and_then(Value::as_str), Some("invalid_data"));
+        assert_eq!(error.get("path").and_then(Value::as_str), Some("a"));
+        let dir = path.parent().unwrap();
+        let name = path.file_name().unwrap().to_string_lossy().into_owned();
+        let leftovers = fs::read_dir(dir)
+            .unwrap()
+            .filter_map(|e| e.ok())
+            .filter(|e| e.file_name().to_string_lossy().starts_with(&format!(".{}", name)))
+            .count();
+        assert_eq!(leftovers, 0);
+    }
+
+    #[test]
+    fn health_before_any_merge_is_null() {
+        let health = Health { checked: Some(UNIX_EPOCH), ..Health::default() };
+        let document = json::parse(&health.to_json()).unwrap();
+        for name in ["last_run", "result", "entries", "last_error_at", "last_error"] {
+            assert_eq!(document.get(name), Some(&Value::Null), "{}", name);
+        }
+        assert_eq!(document.get("checked").and_then(Value::as_str), Some("1970-01-01T00:00:00Z"));
+    }
+
+    #[test]
+    fn poll_files_notices_changed_appearing_and_removed_files() {
+        let path = std::env::temp_dir().join("dxvk-cache-tool-test-watch-poll");
+        let _ = fs::remove_file(&path);
+        let mut events = PollFiles::new(vec![path.clone()]);
+        assert!(events.changed());
+        assert!(!events.changed());
+        fs::write(&path, b"a").unwrap();
+        assert!(events.changed());
+        assert!(!events.changed());
+        fs::write(&path, b"ab").unwrap();
+        assert!(events.changed());
+        fs::remove_file(&path).unwrap();
+        assert!(events.changed());
+        assert!(!events.changed());
+    }
+}
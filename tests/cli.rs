@@ -0,0 +1,732 @@
+//! Runs the tool on caches written to a temporary directory, checking its
+//! exit statuses and what it reports.
+
+use std::ffi::OsStr;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use dxvk_cache_tool::format::{self, Limits};
+use sha1::Sha1;
+
+const LEGACY_VERSION: u32 = 7;
+
+/// Runs the tool with `args` in `dir`.
+fn run<I, S>(dir: &Path, args: I) -> Output
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>
+{
+    Command::new(env!("CARGO_BIN_EXE_dxvk-cache-tool"))
+        .args(args)
+        .current_dir(dir)
+        .output()
+        .unwrap()
+}
+
+/// An empty directory of its own for the test `name`.
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("dxvk-cache-tool-test-{}", name));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A valid entry of `version` whose data is `size` bytes of `byte`, as it
+/// is stored in a cache.
+fn entry(version: u32, byte: u8, size: usize) -> Vec<u8> {
+    entry_of(version, vec![byte; size])
+}
+
+/// A valid entry of `version` holding `data`, as it is stored in a cache.
+fn entry_of(version: u32, data: Vec<u8>) -> Vec<u8> {
+    let size = data.len();
+    let mut hasher = Sha1::default();
+    hasher.update(&data);
+    if version > LEGACY_VERSION {
+        let mut bytes = (0x11 | (size as u32) << 8).to_le_bytes().to_vec();
+        bytes.extend_from_slice(&hasher.digest().bytes());
+        bytes.extend_from_slice(&data);
+        bytes
+    } else {
+        hasher.update(&Sha1::default().digest().bytes());
+        let mut bytes = data;
+        bytes.extend_from_slice(&hasher.digest().bytes());
+        bytes
+    }
+}
+
+/// Writes a cache of `version` holding `entries`.
+fn write_cache(path: &Path, version: u32, entries: &[Vec<u8>]) {
+    let entry_size = match entries.first() {
+        Some(entry) if version <= LEGACY_VERSION => entry.len() as u32,
+        _ => 0
+    };
+    let mut bytes = b"DXVK".to_vec();
+    bytes.extend_from_slice(&version.to_le_bytes());
+    bytes.extend_from_slice(&entry_size.to_le_bytes());
+    entries.iter().for_each(|entry| bytes.extend_from_slice(entry));
+    fs::write(path, bytes).unwrap();
+}
+
+/// The `error` object of the JSON error document printed on stderr.
+fn json_error(output: &Output) -> String {
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    let document = stderr.trim();
+    assert!(document.starts_with('{') && document.ends_with('}'), "Not JSON: {}", document);
+    assert_eq!(field(document, "schema_version").as_deref(), Some("1"));
+    let start = document.find("\"error\":{").unwrap_or_else(|| panic!("No error object"));
+    document[start + 8..document.len() - 1].to_string()
+}
+
+/// The value of the string or number `key` of the flat JSON object `json`.
+fn field(json: &str, key: &str) -> Option<String> {
+    let start = json.find(&format!("\"{}\":", key))? + key.len() + 3;
+    let rest = &json[start..];
+    match rest.strip_prefix('"') {
+        Some(rest) => {
+            let mut value = String::new();
+            let mut chars = rest.chars();
+            while let Some(c) = chars.next() {
+                match c {
+                    '"' => return Some(value),
+                    '\\' => value.push(chars.next()?),
+                    c => value.push(c)
+                }
+            }
+            None
+        },
+        None => Some(rest.chars().take_while(char::is_ascii_digit).collect())
+    }
+}
+
+fn kind(error: &str) -> Option<String> {
+    field(error, "kind")
+}
+
+#[test]
+fn missing_input_is_an_io_error() {
+    let dir = temp_dir("missing-input");
+    let output = run(&dir, ["--json", "-o", "out.dxvk-cache", "missing.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("io_error"));
+    assert_eq!(field(&error, "path").as_deref(), Some("missing.dxvk-cache"));
+}
+
+#[test]
+fn garbage_input_is_invalid_data() {
+    let dir = temp_dir("garbage-input");
+    fs::write(dir.join("garbage.dxvk-cache"), b"not a cache at all").unwrap();
+    let output = run(&dir, ["--json", "-o", "out.dxvk-cache", "garbage.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_data"));
+}
+
+#[test]
+fn unknown_option_is_invalid_input() {
+    let dir = temp_dir("unknown-option");
+    let output = run(&dir, ["--json", "--no-such-option", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_input"));
+}
+
+#[test]
+fn mismatched_versions_are_a_version_mismatch() {
+    let dir = temp_dir("mismatched-versions");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    write_cache(&dir.join("b.dxvk-cache"), 15, &[entry(15, 2, 64)]);
+    let output = run(&dir, ["--json", "-o", "out.dxvk-cache", "a.dxvk-cache", "b.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("version_mismatch"));
+    assert_eq!(field(&error, "expected").as_deref(), Some("17"));
+    assert_eq!(field(&error, "found").as_deref(), Some("15"));
+    assert!(!dir.join("out.dxvk-cache").exists());
+}
+
+/// The cache at `path` as the library reads it and writes it back.
+fn to_bytes(path: &Path) -> Vec<u8> {
+    let file = fs::File::open(path).unwrap();
+    let (header, entries) = format::read_cache_from(file, &Limits::default()).unwrap();
+    let mut bytes = Vec::new();
+    format::write_cache_to(&mut bytes, &header, entries.iter()).unwrap();
+    bytes
+}
+
+#[test]
+fn thread_count_leaves_the_output_unchanged() {
+    let dir = temp_dir("thread-count");
+    // Two inputs of 3000 distinct entries sharing half of them, a fifth of
+    // them invalid, so that validation is split across every thread.
+    for input in 0..2u32 {
+        let entries: Vec<Vec<u8>> = (input * 1500..input * 1500 + 3000)
+            .map(|seed| {
+                let size = 32 + seed as usize % 128;
+                let data = (0..size).map(|i| seed.to_le_bytes()[i % 4]).collect();
+                let mut entry = entry_of(17, data);
+                if seed % 5 == 0 {
+                    entry[4] ^= 0x80;
+                }
+                entry
+            })
+            .collect();
+        write_cache(&dir.join(format!("{}.dxvk-cache", input)), 17, &entries);
+    }
+    let outputs: Vec<(Vec<u8>, Vec<u8>)> = ["1", "2", "8"]
+        .iter()
+        .map(|threads| {
+            let args = ["-j", threads, "-o", "out.dxvk-cache", "0.dxvk-cache", "1.dxvk-cache"];
+            let output = run(&dir, args);
+            assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+            let path = dir.join("out.dxvk-cache");
+            (fs::read(&path).unwrap(), to_bytes(&path))
+        })
+        .collect();
+    let (_, entries) = format::read_cache_from(&*outputs[0].0, &Limits::default()).unwrap();
+    assert_eq!(entries.len(), 3600);
+    assert!(outputs.iter().all(|output| *output == outputs[0]));
+}
+
+/// Sets the entry size in the header of the cache at `path`.
+fn set_entry_size(path: &Path, entry_size: u32) {
+    let mut bytes = fs::read(path).unwrap();
+    bytes[8..12].copy_from_slice(&entry_size.to_le_bytes());
+    fs::write(path, bytes).unwrap();
+}
+
+#[test]
+fn modern_inputs_may_declare_other_entry_sizes() {
+    let dir = temp_dir("modern-entry-size");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 32), entry(17, 2, 48)]);
+    write_cache(&dir.join("b.dxvk-cache"), 17, &[entry(17, 2, 48), entry(17, 3, 64)]);
+    set_entry_size(&dir.join("b.dxvk-cache"), 1234);
+    let output = run(&dir, ["-v", "-o", "out.dxvk-cache", "a.dxvk-cache", "b.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("b.dxvk-cache declares entry size 1234 instead of 0"), "{}", stdout);
+    let expected = [entry(17, 1, 32), entry(17, 2, 48), entry(17, 3, 64)].concat();
+    assert_eq!(fs::read(dir.join("out.dxvk-cache")).unwrap()[12..], expected[..]);
+}
+
+#[test]
+fn legacy_inputs_must_declare_the_same_entry_size() {
+    let dir = temp_dir("legacy-entry-size-mismatch");
+    write_cache(&dir.join("a.dxvk-cache"), 7, &[entry(7, 1, 32)]);
+    write_cache(&dir.join("b.dxvk-cache"), 7, &[entry(7, 2, 48)]);
+    let args = ["--json", "-o", "out.dxvk-cache", "a.dxvk-cache", "b.dxvk-cache"];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("invalid_data"));
+    let message = field(&error, "message");
+    assert_eq!(message.as_deref(), Some("Entry size mismatch: expected 52, found 68"));
+
+    write_cache(&dir.join("b.dxvk-cache"), 7, &[entry(7, 2, 32)]);
+    let output = run(&dir, ["-o", "out.dxvk-cache", "a.dxvk-cache", "b.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = [entry(7, 1, 32), entry(7, 2, 32)].concat();
+    assert_eq!(fs::read(dir.join("out.dxvk-cache")).unwrap()[12..], expected[..]);
+}
+
+#[test]
+fn ambiguous_hash_prefix_is_invalid_input() {
+    let dir = temp_dir("ambiguous-prefix");
+    // Both hashes start with 0cfc0b.
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 5, 147), entry(17, 12, 6)]);
+    let output = run(&dir, ["build-index", "a.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&dir, ["--json", "has-hash", "a.idx", "0cfc0b"]);
+    assert_eq!(output.status.code(), Some(2));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("invalid_input"));
+    let message = field(&error, "message").unwrap();
+    assert!(message.starts_with("Hash prefix 0cfc0b is ambiguous"), "{}", message);
+    assert!(message.contains("0cfc0b9d6bf1a8de5ce9aee092edb73f2ed951f0"), "{}", message);
+    assert!(message.contains("0cfc0bd50984c0db91cf1b8431f5e0f9ecfad9b3"), "{}", message);
+
+    let output = run(&dir, ["has-hash", "a.idx", "0cfc0bd"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("0cfc0bd50984c0db91cf1b8431f5e0f9ecfad9b3 found"), "{}", stdout);
+}
+
+#[test]
+fn ambiguous_print_entry_prefix_is_invalid_input() {
+    let dir = temp_dir("ambiguous-print-entry");
+    // Both hashes start with 0cfc0b.
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 5, 147), entry(17, 12, 6)]);
+    let output = run(&dir, ["info", "--print-entry", "0cfc0b", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(output.stdout.is_empty());
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Error: Hash prefix 0cfc0b is ambiguous"), "{}", stderr);
+    assert!(stderr.contains("0cfc0b9d6bf1a8de5ce9aee092edb73f2ed951f0"), "{}", stderr);
+    assert!(stderr.contains("0cfc0bd50984c0db91cf1b8431f5e0f9ecfad9b3"), "{}", stderr);
+
+    let output = run(&dir, ["info", "--print-entry", "0cfc0b9", "a.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.starts_with("Hash: 0cfc0b9d6bf1a8de5ce9aee092edb73f2ed951f0\n"), "{}", stdout);
+}
+
+#[cfg(unix)]
+#[test]
+fn inputs_linked_to_an_earlier_input_are_skipped() {
+    let dir = temp_dir("linked-inputs");
+    write_cache(&dir.join("game.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    std::os::unix::fs::symlink("game.dxvk-cache", dir.join("link.dxvk-cache")).unwrap();
+    let args = ["-o", "out.dxvk-cache", "game.dxvk-cache", "./game.dxvk-cache", "link.dxvk-cache"];
+    let output = run(&dir, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Skipping game.dxvk-cache (2/3): same file as game.dxvk-cache"));
+    assert!(stdout.contains("Skipping link.dxvk-cache (3/3): same file as game.dxvk-cache"));
+    assert_eq!(fs::read(dir.join("out.dxvk-cache")).unwrap()[12..], entry(17, 1, 64)[..]);
+}
+
+/// File names on Windows compare without case, so another spelling of an
+/// input is the same file.
+#[cfg(windows)]
+#[test]
+fn inputs_differing_in_case_are_skipped() {
+    let dir = temp_dir("case-inputs");
+    write_cache(&dir.join("game.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    let output = run(&dir, ["-o", "out.dxvk-cache", "game.dxvk-cache", "GAME.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Skipping GAME.dxvk-cache (2/2): same file as game.dxvk-cache"));
+}
+
+/// Writes a v17 cache of two entries whose header claims version 3, which
+/// would be read as legacy entries. Returns the cache as it was before.
+fn write_corrupt_version(path: &Path) -> Vec<u8> {
+    write_cache(path, 17, &[entry(17, 1, 64), entry(17, 2, 96)]);
+    let bytes = fs::read(path).unwrap();
+    let mut corrupt = bytes.clone();
+    corrupt[4..8].copy_from_slice(&3u32.to_le_bytes());
+    fs::write(path, corrupt).unwrap();
+    bytes
+}
+
+#[test]
+fn assume_version_reads_a_corrupt_header_version() {
+    let dir = temp_dir("assume-version");
+    let bytes = write_corrupt_version(&dir.join("corrupt.dxvk-cache"));
+
+    let output = run(&dir, ["--json", "-o", "out.dxvk-cache", "corrupt.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_data"));
+
+    let args = ["--assume-version", "17", "-o", "out.dxvk-cache", "corrupt.dxvk-cache"];
+    let output = run(&dir, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert_eq!(fs::read(dir.join("out.dxvk-cache")).unwrap(), bytes);
+}
+
+#[test]
+fn assume_version_conflicts_with_another_output_version() {
+    let dir = temp_dir("assume-output-version");
+    write_corrupt_version(&dir.join("corrupt.dxvk-cache"));
+    let args = [
+        "--json",
+        "--assume-version",
+        "17",
+        "--output-version",
+        "15",
+        "-o",
+        "out.dxvk-cache",
+        "corrupt.dxvk-cache"
+    ];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_input"));
+    assert!(!dir.join("out.dxvk-cache").exists());
+}
+
+#[test]
+fn trailing_output_option_is_invalid_input() {
+    let dir = temp_dir("trailing-output");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    let output = run(&dir, ["--json", "a.dxvk-cache", "-o"]);
+    assert_eq!(output.status.code(), Some(2));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("invalid_input"));
+    assert_eq!(field(&error, "message").as_deref(), Some("Option -o requires a value"));
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_arguments_are_invalid_input() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = temp_dir("non-utf8-arguments");
+    let args = [
+        OsStr::new("--json"),
+        OsStr::new("--output-version"),
+        OsStr::from_bytes(b"\xff"),
+        OsStr::new("-o"),
+        OsStr::new("out.dxvk-cache"),
+        OsStr::new("a.dxvk-cache")
+    ];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_input"));
+
+    let args = [OsStr::new("--json"), OsStr::new("info"), OsStr::from_bytes(b"\xff.dxvk-cache")];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("io_error"));
+}
+
+#[test]
+fn legacy_entry_size_up_to_a_hash_is_invalid_data() {
+    let dir = temp_dir("legacy-entry-size");
+    for size in [0u32, 5, 20] {
+        let mut bytes = b"DXVK".to_vec();
+        bytes.extend_from_slice(&2u32.to_le_bytes());
+        bytes.extend_from_slice(&size.to_le_bytes());
+        bytes.extend_from_slice(&[0; 64]);
+        fs::write(dir.join("legacy.dxvk-cache"), bytes).unwrap();
+        let output = run(&dir, ["info", "--json", "legacy.dxvk-cache"]);
+        assert_eq!(output.status.code(), Some(1));
+        let error = json_error(&output);
+        assert_eq!(kind(&error).as_deref(), Some("invalid_data"));
+        let message = field(&error, "message").unwrap();
+        assert!(message.starts_with("Invalid entry size"), "{}", message);
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn non_utf8_file_names_are_printed_lossily() {
+    use std::os::unix::ffi::OsStrExt;
+
+    let dir = temp_dir("non-utf8-file-name");
+    let name = OsStr::from_bytes(b"\xff.dxvk-cache");
+    write_cache(&dir.join(name), 17, &[entry(17, 1, 64)]);
+    let args = [OsStr::new("--verbose"), OsStr::new("-o"), OsStr::new("out.dxvk-cache"), name];
+    let output = run(&dir, args);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("\u{fffd}.dxvk-cache"));
+}
+
+#[test]
+fn repeated_output_must_agree() {
+    let dir = temp_dir("repeated-output");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+
+    let args = ["--json", "-o", "x.dxvk-cache", "a.dxvk-cache", "--output", "y.dxvk-cache"];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(2));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("invalid_input"));
+    let message = field(&error, "message").unwrap();
+    assert_eq!(message, "Conflicting outputs x.dxvk-cache and y.dxvk-cache, give -o once");
+    assert!(!dir.join("x.dxvk-cache").exists() && !dir.join("y.dxvk-cache").exists());
+
+    let output = run(&dir, ["-o", "x.dxvk-cache", "a.dxvk-cache", "-o", "x.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    assert!(dir.join("x.dxvk-cache").exists());
+}
+
+/// Writes a v17 cache of entries with the given data bytes and stage masks,
+/// last modified `secs` after the epoch.
+fn write_session(path: &Path, entries: &[(u8, u8)], secs: u64) {
+    let entries: Vec<Vec<u8>> = entries
+        .iter()
+        .map(|&(byte, mask)| {
+            let mut entry = entry(17, byte, 64);
+            entry[0] = mask;
+            entry
+        })
+        .collect();
+    write_cache(path, 17, &entries);
+    let file = fs::File::options().write(true).open(path).unwrap();
+    file.set_modified(std::time::UNIX_EPOCH + std::time::Duration::from_secs(secs)).unwrap();
+}
+
+#[test]
+fn stats_timeline_counts_what_each_bucket_added() {
+    let dir = temp_dir("stats-timeline");
+    // 2024-01-01 is a Monday.
+    let monday = 1_704_067_200;
+    write_session(&dir.join("s1.dxvk-cache"), &[(1, 0x11), (2, 0x20)], monday + 36_000);
+    write_session(&dir.join("s2.dxvk-cache"), &[(1, 0x11), (3, 0x01)], monday + 82_800);
+    write_session(&dir.join("s3.dxvk-cache"), &[(3, 0x01), (4, 0x21)], monday + 86_400);
+    fs::write(dir.join("notes.txt"), "not a cache").unwrap();
+
+    let output = run(&dir, ["stats-timeline", "--csv", "."]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let expected = "\
+Bucket,Files,Added,Graphics,Compute,Other,Total
+2024-01-01,2,3,2,1,0,3
+2024-01-02,1,1,0,0,1,4
+";
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+
+    let output = run(&dir, ["stats-timeline", "--csv", "--group-by", "mtime:week", "."]);
+    let expected = "\
+Bucket,Files,Added,Graphics,Compute,Other,Total
+2024-W01,3,4,2,1,1,4
+";
+    assert_eq!(String::from_utf8_lossy(&output.stdout), expected);
+}
+
+#[test]
+fn stats_timeline_needs_caches() {
+    let dir = temp_dir("stats-timeline-empty");
+    let output = run(&dir, ["stats-timeline", "--json", "."]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_input"));
+}
+
+#[test]
+fn require_version_accepts_the_allowed_versions() {
+    let dir = temp_dir("require-version-accepted");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    for args in [["--require-version", "15..=17"], ["--require-dxvk", "2.3"]] {
+        let output = run(&dir, ["info", args[0], args[1], "a.dxvk-cache"]);
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    }
+}
+
+#[test]
+fn require_version_rejects_other_versions() {
+    let dir = temp_dir("require-version-rejected");
+    write_cache(&dir.join("a.dxvk-cache"), 15, &[entry(15, 1, 64)]);
+    let args = [
+        "--json",
+        "--require-version",
+        "16..18",
+        "--require-version",
+        "10",
+        "-o",
+        "out.dxvk-cache",
+        "a.dxvk-cache"
+    ];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("version_mismatch"));
+    assert_eq!(field(&error, "expected").as_deref(), Some("16"));
+    assert_eq!(field(&error, "found").as_deref(), Some("15"));
+    let message = field(&error, "message");
+    let expected = "State cache version v15 is not allowed, expected v10 or v16 to v17";
+    assert_eq!(message.as_deref(), Some(expected));
+    assert!(!dir.join("out.dxvk-cache").exists());
+
+    let args = ["--json", "--require-dxvk", "2.0", "info", "a.dxvk-cache"];
+    let output = run(&dir, args);
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("version_mismatch"));
+
+    let output = run(&dir, ["--json", "--require-version", "17..=15", "info", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("invalid_input"));
+}
+
+#[cfg(not(feature = "zstd"))]
+#[test]
+fn compressibility_is_not_enabled() {
+    let dir = temp_dir("compressibility");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+
+    let output = run(&dir, ["info", "--compressibility", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("zstd"));
+
+    let output = run(&dir, ["info", "--json", "--compressibility", "--exact", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(1));
+    assert_eq!(kind(&json_error(&output)).as_deref(), Some("feature_not_enabled"));
+    assert!(output.stdout.is_empty());
+}
+
+#[cfg(feature = "zstd")]
+#[test]
+fn compressibility_breaks_down_by_stage_mask_and_age() {
+    let dir = temp_dir("compressibility-zstd");
+    let entries: Vec<Vec<u8>> = (1..=8).map(|i| entry(17, i, 4096)).collect();
+    write_cache(&dir.join("a.dxvk-cache"), 17, &entries);
+
+    let output = run(&dir, ["info", "--json", "--compressibility", "--exact", "a.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let info = String::from_utf8_lossy(&output.stdout).into_owned();
+    let start = info.find("\"compressibility\":{").unwrap();
+    let compressibility = &info[start..];
+    assert_eq!(field(compressibility, "entries").as_deref(), Some("8"));
+    assert_eq!(field(compressibility, "data_bytes").as_deref(), Some("32768"));
+    let estimated: u64 = field(compressibility, "estimated_bytes").unwrap().parse().unwrap();
+    assert!(estimated > 0 && estimated < 32768 / 10, "{}", estimated);
+    assert_eq!(compressibility.matches("\"stage_mask\":17").count(), 1);
+    assert_eq!(compressibility.matches("{\"entries\":2,").count(), 4);
+
+    let output = run(&dir, ["info", "--compressibility", "a.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("Compressibility: all data compressed, zstd level 1"), "{}", stdout);
+    assert!(stdout.contains("Stage mask 0x11") && stdout.contains("Oldest quarter"));
+}
+
+#[test]
+fn fail_on_invalid_reports_the_count_and_lists_hashes_verbosely() {
+    let dir = temp_dir("fail-on-invalid");
+    let mut corrupt = entry(17, 2, 64);
+    *corrupt.last_mut().unwrap() ^= 1;
+    let hash: String = corrupt[4..24].iter().map(|b| format!("{:02x}", b)).collect();
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64), corrupt]);
+
+    let args = ["--json", "--fail-on-invalid", "-o", "out.dxvk-cache", "a.dxvk-cache"];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(1));
+    let error = json_error(&output);
+    assert_eq!(kind(&error).as_deref(), Some("invalid_data"));
+    assert_eq!(field(&error, "message").as_deref(), Some("1 entries failed SHA1 validation"));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains(&hash));
+
+    let args = ["-v", "--fail-on-invalid", "-o", "out.dxvk-cache", "a.dxvk-cache"];
+    let output = run(&dir, args);
+    assert_eq!(output.status.code(), Some(1));
+    assert!(String::from_utf8_lossy(&output.stdout).contains(&hash));
+    assert!(!String::from_utf8_lossy(&output.stderr).contains(&hash));
+    assert!(!dir.join("out.dxvk-cache").exists());
+}
+
+/// A v17 cache of two graphics entries, stage mask 0x1f, and a compute
+/// entry, stage mask 0x20, with 48 bytes of data each.
+fn stages_fixture(dir: &Path) -> PathBuf {
+    let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/stages.dxvk-cache");
+    let path = dir.join("stages.dxvk-cache");
+    fs::copy(fixture, &path).unwrap();
+    path
+}
+
+#[test]
+fn info_counts_graphics_and_compute_entries() {
+    let dir = temp_dir("info-stages");
+    stages_fixture(&dir);
+    let output = run(&dir, ["info", "--json", "stages.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let info = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(field(&info, "entries").as_deref(), Some("3"));
+    assert_eq!(field(&info, "graphics").as_deref(), Some("2"));
+    assert_eq!(field(&info, "compute").as_deref(), Some("1"));
+    assert_eq!(field(&info, "ambiguous").as_deref(), Some("0"));
+}
+
+#[test]
+fn merging_keeps_graphics_and_compute_entries() {
+    let dir = temp_dir("merge-stages");
+    stages_fixture(&dir);
+    write_cache(&dir.join("game.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+    let output = run(&dir, ["-o", "out.dxvk-cache", "stages.dxvk-cache", "game.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+
+    let output = run(&dir, ["info", "--json", "out.dxvk-cache"]);
+    let info = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(field(&info, "graphics").as_deref(), Some("3"));
+    assert_eq!(field(&info, "compute").as_deref(), Some("1"));
+}
+
+#[test]
+fn repair_check_reports_what_repair_does() {
+    let dir = temp_dir("repair-check");
+    let mut stale = entry(17, 2, 64);
+    stale[4] ^= 1;
+    // The same data as the first entry under another hash, which collides
+    // once repaired.
+    let mut duplicate = entry(17, 1, 64);
+    duplicate[4] ^= 1;
+    let entries = [entry(17, 1, 64), stale, entry(17, 3, 32), duplicate];
+    write_cache(&dir.join("corrupt.dxvk-cache"), 17, &entries);
+
+    let output = run(&dir, ["repair", "--check", "--json", "corrupt.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let check = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert!(!dir.join("output.dxvk-cache").exists());
+    let output = run(&dir, ["repair", "--json", "corrupt.dxvk-cache"]);
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let repair = String::from_utf8_lossy(&output.stdout).into_owned();
+
+    assert!(check.contains("\"dry_run\":true") && repair.contains("\"dry_run\":false"));
+    assert_eq!(check.replace("\"dry_run\":true", "\"dry_run\":false"), repair);
+    assert_eq!(repair.matches("\"stored\"").count(), 2);
+    assert_eq!(repair.matches("\"collides\":true").count(), 1);
+
+    let output = run(&dir, ["info", "--json", "output.dxvk-cache"]);
+    let info = String::from_utf8_lossy(&output.stdout).into_owned();
+    assert_eq!(field(&info, "entries").as_deref(), Some("3"));
+    assert_eq!(field(&info, "invalid").as_deref(), Some("0"));
+}
+
+#[test]
+fn dedup_by_data_drops_stale_master_entries() {
+    let dir = temp_dir("dedup-by-data");
+    let mut stale = entry(17, 1, 64);
+    stale[4] ^= 1;
+    let args = ["-o", "master.dxvk-cache", "session.dxvk-cache", "master.dxvk-cache"];
+    for (key, entries, invalid) in [("hash", "3", "1"), ("data", "2", "0")] {
+        write_cache(&dir.join("master.dxvk-cache"), 17, &[stale.clone(), entry(17, 2, 64)]);
+        write_cache(&dir.join("session.dxvk-cache"), 17, &[entry(17, 1, 64)]);
+        let output = run(&dir, ["--dedup-by", key].iter().chain(&args));
+        assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+        let stdout = String::from_utf8_lossy(&output.stdout);
+        let note = "1 duplicates have the data of an entry with another hash";
+        assert_eq!(stdout.contains(note), key == "data", "{}", stdout);
+
+        let output = run(&dir, ["info", "--json", "master.dxvk-cache"]);
+        let info = String::from_utf8_lossy(&output.stdout).into_owned();
+        assert_eq!(field(&info, "entries").as_deref(), Some(entries));
+        assert_eq!(field(&info, "invalid").as_deref(), Some(invalid));
+    }
+
+    let output = run(&dir, ["--dedup-by", "stage", "-o", "out.dxvk-cache", "session.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("Unknown dedup key: stage"));
+}
+
+#[test]
+fn watch_merges_until_the_maximum_runtime_and_reports_health() {
+    let dir = temp_dir("watch");
+    write_cache(&dir.join("a.dxvk-cache"), 17, &[entry(17, 1, 64), entry(17, 2, 64)]);
+    let args = ["--interval", "1", "--max-runtime", "1", "--health-file", "health.json"];
+    let inputs = ["watch", "-o", "master.dxvk-cache", "a.dxvk-cache"];
+    let output = run(&dir, args.iter().chain(&inputs));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let health = fs::read_to_string(dir.join("health.json")).unwrap();
+    assert_eq!(field(&health, "result").as_deref(), Some("merged"));
+    assert_eq!(field(&health, "entries").as_deref(), Some("2"));
+    let output = run(&dir, ["info", "--json", "master.dxvk-cache"]);
+    assert_eq!(field(&String::from_utf8_lossy(&output.stdout), "entries").as_deref(), Some("2"));
+
+    // A failed merge is recorded and doesn't end the watch.
+    fs::write(dir.join("b.dxvk-cache"), b"garbage").unwrap();
+    let inputs = ["watch", "-o", "master.dxvk-cache", "a.dxvk-cache", "b.dxvk-cache"];
+    let output = run(&dir, args.iter().chain(&inputs));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let health = fs::read_to_string(dir.join("health.json")).unwrap();
+    assert_eq!(field(&health, "result").as_deref(), Some("failed"));
+    assert_eq!(field(&health, "failures").as_deref(), Some("1"));
+    assert!(health.contains("\"path\":\"b.dxvk-cache\""), "{}", health);
+
+    let output = run(&dir, ["watch", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+    let output = run(&dir, ["--max-runtime", "1", "-o", "master.dxvk-cache", "a.dxvk-cache"]);
+    assert_eq!(output.status.code(), Some(2));
+}
+
+#[test]
+fn merge_by_version_summarizes_outputs_in_a_table() {
+    let dir = temp_dir("merge-by-version-table");
+    write_cache(&dir.join("new.dxvk-cache"), 17, &[entry(17, 1, 64), entry(17, 2, 64)]);
+    write_cache(&dir.join("old.dxvk-cache"), 15, &[entry(15, 3, 64)]);
+    let inputs = ["new.dxvk-cache", "old.dxvk-cache"];
+    let output = run(&dir, ["--merge-by-version", "--no-table", "-o", "out"].iter().chain(&inputs));
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let rows: Vec<&str> = stdout.lines().skip_while(|l| !l.starts_with("Version\t")).collect();
+    assert_eq!(rows[0], "Version\tInputs\tEntries\tSize\tOutput");
+    assert!(rows[1].starts_with("v15\t1\t1\t"), "{}", stdout);
+    assert!(rows[1].ends_with("merged.v15.dxvk-cache"), "{}", stdout);
+    assert!(rows[2].starts_with("v17\t1\t2\t"), "{}", stdout);
+}